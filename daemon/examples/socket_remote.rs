@@ -0,0 +1,92 @@
+//! Controls a synth over the daemon socket without an audio stream, the way
+//! `synthctl` would: plays a note, sets and reads parameters, saves and loads
+//! a patch and sends invalid requests, printing each response.
+//!
+//! `cargo run -p daemon --example socket_remote`
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+};
+
+use anyhow::{anyhow, Result};
+use daemon::{
+    protocol::{Request, Response},
+    server::{Controller, StreamInfo},
+};
+use serde_json::json;
+use synth::{
+    params::{ParamId, ParamStore},
+    waves::{Wave, Waveform},
+    Note,
+};
+
+fn main() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("synthd-example-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let socket = dir.join("synth.sock");
+
+    let params = ParamStore::from_wave(&Wave::new(Waveform::Sin, Note::A4, 0.3));
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let (s_shutdown, r_shutdown) = crossbeam_channel::bounded(1);
+    let controller = Controller::new(params.clone(), tx, StreamInfo::default(), s_shutdown);
+    controller.serve(UnixListener::bind(&socket)?);
+
+    let stream = UnixStream::connect(&socket)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut send_line = |line: &str| -> Result<Response> {
+        writeln!(writer, "{line}")?;
+        let mut response = String::new();
+        reader.read_line(&mut response)?;
+        println!("{line}\n  -> {}", response.trim());
+        Ok(serde_json::from_str(&response)?)
+    };
+    let patch = dir.join("example.toml");
+    let requests = [
+        ("note_on", json!({ "note": "C4", "velocity": 0.8 })),
+        ("note_off", json!({ "note": 60 })),
+        ("set_param", json!({ "id": "waveform", "value": "saw" })),
+        ("set_param", json!({ "id": "amp", "value": 0.5 })),
+        ("save_patch", json!({ "path": patch })),
+        ("set_param", json!({ "id": "amp", "value": 0.1 })),
+        ("load_patch", json!({ "path": patch })),
+        ("get_param", json!({ "id": "amp" })),
+        ("status", json!(null)),
+    ];
+    for (id, (method, params)) in requests.into_iter().enumerate() {
+        let request = serde_json::to_string(&Request::new(id as u64, method, params))?;
+        send_line(&request)?.into_result()?;
+    }
+
+    let invalid = [
+        "{not json",
+        r#"{"jsonrpc":"2.0","method":"dance","id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"set_param","params":{"id":"volume","value":1},"id":2}"#,
+        r#"{"jsonrpc":"2.0","method":"note_on","params":{"note":"H9"},"id":3}"#,
+    ];
+    for line in invalid {
+        if send_line(line)?.into_result().is_ok() {
+            return Err(anyhow!("{line} should have failed"));
+        }
+    }
+    send_line(&serde_json::to_string(&Request::new(
+        9,
+        "shutdown",
+        json!(null),
+    ))?)?;
+    r_shutdown.recv()?;
+
+    for event in rx.try_iter() {
+        println!("event {event:?}");
+    }
+    let expected = [(ParamId::Waveform, 2.0), (ParamId::Amp, 0.5)];
+    for (id, value) in expected {
+        if params.get(id) != value {
+            return Err(anyhow!("{id} is {}, expected {value}", params.get(id)));
+        }
+    }
+    std::fs::remove_dir_all(&dir)?;
+    println!("parameters set");
+    Ok(())
+}
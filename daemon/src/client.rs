@@ -0,0 +1,122 @@
+//! Sends one command to `synthd` and prints the result.
+//!
+//! `synthctl [--socket path] <command> [args]`
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context, Result};
+use daemon::protocol::{default_socket_path, Request, Response};
+use serde_json::{json, Value};
+
+const USAGE: &str = "Usage: synthctl [--socket path] <command> [args]
+
+Commands:
+    note-on NOTE [VELOCITY]   Play a note, by name (A4) or MIDI number
+    note-off NOTE             Release a note
+    set ID VALUE              Set a parameter
+    get ID                    Print a parameter
+    params                    Print every parameter
+    load PATH                 Load a patch
+    save PATH [NAME]          Save the current sound as a patch
+    undo                      Undo the last change or patch load
+    redo                      Redo the last change undone
+    status                    Print the device, stream and transport
+    meters                    Print the output level and gain reductions
+    shutdown                  Stop the daemon";
+
+fn main() -> Result<()> {
+    let mut socket = default_socket_path();
+    let mut args = Vec::new();
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--socket" => {
+                socket = PathBuf::from(argv.next().ok_or_else(|| anyhow!("{USAGE}"))?);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    let (method, params) = parse_command(&args)?;
+    let mut stream = UnixStream::connect(&socket).with_context(|| {
+        format!(
+            "Couldn't connect to synthd on {}, is it running?",
+            socket.display()
+        )
+    })?;
+    let request = serde_json::to_string(&Request::new(1, method, params))?;
+    writeln!(stream, "{request}")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(anyhow!("synthd closed the connection"));
+    }
+    let result = serde_json::from_str::<Response>(&line)?.into_result()?;
+    if !result.is_null() {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    }
+    Ok(())
+}
+
+fn parse_command(args: &[String]) -> Result<(&'static str, Value)> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Ok(match args.as_slice() {
+        ["note-on", note] => ("note_on", json!({ "note": note_arg(note) })),
+        ["note-on", note, velocity] => {
+            let velocity: f32 = velocity
+                .parse()
+                .with_context(|| format!("Invalid velocity {velocity:?}"))?;
+            (
+                "note_on",
+                json!({ "note": note_arg(note), "velocity": velocity }),
+            )
+        }
+        ["note-off", note] => ("note_off", json!({ "note": note_arg(note) })),
+        ["set", id, value] => ("set_param", json!({ "id": id, "value": value_arg(value) })),
+        ["get", id] => ("get_param", json!({ "id": id })),
+        ["params"] => ("params", Value::Null),
+        ["load", path] => ("load_patch", json!({ "path": absolute(path)? })),
+        ["save", path] => ("save_patch", json!({ "path": absolute(path)? })),
+        ["save", path, name] => (
+            "save_patch",
+            json!({ "path": absolute(path)?, "name": name }),
+        ),
+        ["undo"] => ("undo", Value::Null),
+        ["redo"] => ("redo", Value::Null),
+        ["status"] => ("status", Value::Null),
+        ["meters"] => ("meters", Value::Null),
+        ["shutdown"] => ("shutdown", Value::Null),
+        _ => return Err(anyhow!("{USAGE}")),
+    })
+}
+
+/// MIDI numbers are sent as numbers, anything else as a note name.
+fn note_arg(note: &str) -> Value {
+    note.parse::<u8>().map_or_else(|_| note.into(), Value::from)
+}
+
+/// Values are sent as a bool or number when they parse as one, so choices
+/// like `saw` stay strings.
+fn value_arg(value: &str) -> Value {
+    if let Ok(value) = value.parse::<bool>() {
+        return value.into();
+    }
+    match value.parse::<f64>() {
+        Ok(number) => number.into(),
+        Err(_) => value.into(),
+    }
+}
+
+/// Paths are resolved here, the daemon may run from another directory.
+fn absolute(path: &str) -> Result<PathBuf> {
+    std::path::absolute(path).with_context(|| format!("Invalid path {path:?}"))
+}
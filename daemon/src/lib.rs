@@ -0,0 +1,5 @@
+//! Headless synth controlled over a Unix socket: `synthd` owns the audio
+//! stream, `synthctl` sends it commands.
+
+pub mod protocol;
+pub mod server;
@@ -0,0 +1,142 @@
+//! Headless synth, controlled over a Unix socket with `synthctl`.
+//!
+//! `synthd [--socket path] [patch.toml]`
+
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use daemon::{
+    protocol::default_socket_path,
+    server::{Controller, Meters, StreamInfo},
+};
+use synth::{
+    effects::{
+        distortion::{Bitcrusher, Curve, Waveshaper, MAX_CRUSH_BITS, MAX_CRUSH_RATE},
+        dynamics::{Compressor, Limiter, PeakMeter},
+        eq::Equalizer,
+        Chain,
+    },
+    params::ParamStore,
+    patch::Patch,
+    transport::Transport,
+    waves::{Wave, Waveform},
+    AudioDevice, BufferSize, Note, StreamTrait, Synth,
+};
+
+const USAGE: &str = "Usage: synthd [--socket path] [patch.toml]";
+
+fn main() -> Result<()> {
+    let mut socket = default_socket_path();
+    let mut patch = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => {
+                socket = PathBuf::from(args.next().ok_or_else(|| anyhow!("{USAGE}"))?);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            option if option.starts_with('-') => {
+                return Err(anyhow!("Unknown option {option:?}\n{USAGE}"));
+            }
+            _ if patch.is_some() => return Err(anyhow!("Only one patch can be given\n{USAGE}")),
+            path => {
+                patch = Some(
+                    Patch::open(path).with_context(|| format!("Couldn't open the patch {path}"))?,
+                );
+            }
+        }
+    }
+
+    let mut wave = Wave::new(Waveform::Sin, Note::A4, 0.3);
+    if let Some(patch) = &patch {
+        patch.apply_to_wave(&mut wave)?;
+    }
+    let params = ParamStore::from_wave(&wave);
+
+    let effects = patch
+        .as_ref()
+        .and_then(|patch| patch.effects.clone())
+        .unwrap_or_default();
+    let waveshaper = Waveshaper::new(Curve::SoftClip, 1.0).settings(effects.waveshaper.clone());
+    let bitcrusher = Bitcrusher::new(MAX_CRUSH_BITS, MAX_CRUSH_RATE).settings(effects.bitcrusher);
+    let eq = Equalizer::new(effects.eq.clone());
+    let compressor = Compressor::new().settings(effects.compressor);
+    let limiter = Limiter::new().settings(effects.limiter);
+    let peak = PeakMeter::new();
+    let meters = Meters {
+        output: peak.level(),
+        compressor: compressor.gain_reduction(),
+        limiter: limiter.gain_reduction(),
+    };
+    let transport = Transport::new();
+    let transport_handle = transport.handle();
+
+    let (s_events, r_events) = crossbeam_channel::unbounded();
+    let device = AudioDevice::default()?;
+    let mut synth = Synth::new(device)?
+        .effects(
+            Chain::new()
+                .with(waveshaper)
+                .with(bitcrusher)
+                .with(eq)
+                .with(compressor)
+                .with(limiter)
+                .with(peak),
+        )
+        .events(r_events)
+        .transport(transport)
+        .params(params.clone())
+        .silent();
+    let config = synth.config();
+    let stream_info = StreamInfo {
+        device: synth.device().name(),
+        sample_rate: config.sample_rate.0,
+        channels: config.channels,
+        buffer_size: match config.buffer_size {
+            BufferSize::Fixed(frames) => Some(frames),
+            BufferSize::Default => None,
+        },
+    };
+    let temperament = wave.tuning.temperament.clone();
+    let (s_waves, r_waves) = crossbeam_channel::unbounded();
+    let stream = synth.new_output_stream_chan::<f32>(wave.clone(), r_waves)?;
+    stream.play()?;
+
+    let listener = bind(&socket)?;
+    let (s_shutdown, r_shutdown) = crossbeam_channel::bounded(1);
+    let controller = Controller::new(params, s_events, stream_info, s_shutdown)
+        .meters(meters)
+        .transport(transport_handle)
+        .temperament(temperament)
+        .waves(wave, s_waves)
+        .effects(effects);
+    if let Some(patch) = &patch {
+        controller.set_patch_name(&patch.name);
+    }
+    controller.serve(listener);
+    eprintln!("synthd listening on {}", socket.display());
+
+    let _ = r_shutdown.recv();
+    drop(stream);
+    std::fs::remove_file(&socket)?;
+    Ok(())
+}
+
+/// Listens on `path`, replacing the socket left by a daemon that didn't
+/// shut down cleanly.
+fn bind(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!("synthd is already running on {}", path.display()));
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Couldn't remove the stale socket {}", path.display()))?;
+    }
+    UnixListener::bind(path).with_context(|| format!("Couldn't listen on {}", path.display()))
+}
@@ -0,0 +1,122 @@
+//! JSON-RPC 2.0 over a Unix socket, one request or response per line.
+//!
+//! | Method | Params | Result |
+//! |---|---|---|
+//! | `note_on` | `note` (name or MIDI number), `velocity` (0 to 1, optional) | `null` |
+//! | `note_off` | `note` | `null` |
+//! | `set_param` | `id` (parameter key), `value` | the value set |
+//! | `get_param` | `id` | the value |
+//! | `params` | | every parameter by key |
+//! | `load_patch` | `path` | the patch name |
+//! | `undo` | | the change undone, fails when there is none |
+//! | `redo` | | the change redone, fails when there is none |
+//! | `save_patch` | `path`, `name` (optional) | `null` |
+//! | `status` | | device, stream and transport |
+//! | `meters` | | output level and gain reductions in dB |
+//! | `shutdown` | | `null`, then the daemon exits |
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+/// Socket used when `SYNTH_SOCKET` isn't set, in `XDG_RUNTIME_DIR` or the
+/// temporary directory.
+pub const SOCKET_NAME: &str = "rust-synth.sock";
+pub const SOCKET_ENV: &str = "SYNTH_SOCKET";
+
+// Error codes from the JSON-RPC specification.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was valid but the synth couldn't do it, such as a missing patch.
+pub const SYNTH_ERROR: i64 = -32000;
+
+pub fn default_socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(SOCKET_NAME)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+    /// Requests without an id are notifications and get no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params,
+            id: Some(id.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result,
+            error,
+            id,
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.result.unwrap_or_default()),
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use crossbeam_channel::Sender;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Map, Value};
+use synth::{
+    effects::EffectSettings,
+    events::SynthEvent,
+    history::History,
+    meter::Meter,
+    params::{ParamId, ParamStore},
+    patch::{ParamValue, Patch},
+    transport::TransportHandle,
+    tuning::Temperament,
+    waves::Wave,
+    Note,
+};
+
+use crate::protocol::{
+    Request, Response, RpcError, INVALID_PARAMS, INVALID_REQUEST, JSONRPC_VERSION,
+    METHOD_NOT_FOUND, PARSE_ERROR, SYNTH_ERROR,
+};
+
+/// Levels measured by the audio thread, in dB.
+#[derive(Debug, Clone, Default)]
+pub struct Meters {
+    pub output: Meter,
+    pub compressor: Meter,
+    pub limiter: Meter,
+}
+
+/// Stream settings reported by `status`.
+#[derive(Debug, Clone, Default)]
+pub struct StreamInfo {
+    pub device: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Frames per buffer, `None` when the device picks it.
+    pub buffer_size: Option<u32>,
+}
+
+/// Runs the requests of every connection on the synth.
+#[derive(Debug, Clone)]
+pub struct Controller {
+    params: ParamStore,
+    events: Sender<SynthEvent>,
+    meters: Meters,
+    stream: StreamInfo,
+    transport: Option<TransportHandle>,
+    /// Name of the last patch loaded or saved.
+    patch: Arc<Mutex<Option<String>>>,
+    /// Parameter changes and patch loads, undone by `undo`.
+    history: Arc<Mutex<History>>,
+    /// Custom or mapped temperament the synth started with or last loaded,
+    /// saved with the patches as the parameters only name it.
+    temperament: Arc<Mutex<Temperament>>,
+    /// Wave the synth started with and the oscilator's wave channel, which
+    /// takes the tables of the patches loaded.
+    waves: Option<(Wave, Sender<Wave>)>,
+    /// Effects the synth started with, saved with the patches.
+    effects: EffectSettings,
+    shutdown: Sender<()>,
+}
+
+/// A note given by name, like `"A4"`, or MIDI number.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum NoteArg {
+    Midi(u8),
+    Name(String),
+}
+
+impl NoteArg {
+    fn note(&self) -> Result<Note, RpcError> {
+        match self {
+            NoteArg::Midi(key) => Note::from_midi(*key)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Invalid MIDI note {key}"))),
+            NoteArg::Name(name) => name
+                .parse()
+                .map_err(|_| RpcError::new(INVALID_PARAMS, format!("Invalid note {name:?}"))),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NoteParams {
+    note: NoteArg,
+    velocity: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParamParams {
+    id: String,
+    value: Option<ParamValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PatchParams {
+    path: PathBuf,
+    name: Option<String>,
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|err| RpcError::new(INVALID_PARAMS, format!("Invalid params: {err}")))
+}
+
+fn synth_error(err: anyhow::Error) -> RpcError {
+    RpcError::new(SYNTH_ERROR, format!("{err:#}"))
+}
+
+impl Controller {
+    pub fn new(
+        params: ParamStore,
+        events: Sender<SynthEvent>,
+        stream: StreamInfo,
+        shutdown: Sender<()>,
+    ) -> Self {
+        Self {
+            params,
+            events,
+            meters: Meters::default(),
+            stream,
+            transport: None,
+            patch: Arc::new(Mutex::new(None)),
+            history: Arc::default(),
+            temperament: Arc::new(Mutex::new(Temperament::Equal)),
+            waves: None,
+            effects: EffectSettings::default(),
+            shutdown,
+        }
+    }
+
+    pub fn meters(mut self, meters: Meters) -> Self {
+        self.meters = meters;
+        self
+    }
+
+    pub fn transport(mut self, transport: TransportHandle) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn temperament(mut self, temperament: Temperament) -> Self {
+        self.temperament = Arc::new(Mutex::new(temperament));
+        self
+    }
+
+    /// Sends the tuning tables of the patches loaded to the oscilator, as
+    /// `wave` with the parameters applied.
+    pub fn waves(mut self, wave: Wave, waves: Sender<Wave>) -> Self {
+        self.waves = Some((wave, waves));
+        self
+    }
+
+    pub fn effects(mut self, effects: EffectSettings) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    /// Sets the name reported by `status`, for a patch loaded at startup.
+    pub fn set_patch_name(&self, name: &str) {
+        *self.patch.lock().unwrap() = Some(name.to_string());
+    }
+
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "note_on" => {
+                let NoteParams { note, velocity } = parse_params(params)?;
+                self.send(SynthEvent::NoteOn {
+                    note: note.note()?,
+                    velocity: velocity.unwrap_or(1.0).clamp(0.0, 1.0),
+                })
+            }
+            "note_off" => {
+                let NoteParams { note, .. } = parse_params(params)?;
+                self.send(SynthEvent::NoteOff { note: note.note()? })
+            }
+            "set_param" => {
+                let (id, value) = self.param(params)?;
+                let value = value
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing value"))?
+                    .decode(id)
+                    .map_err(|err| RpcError::new(INVALID_PARAMS, format!("{err:#}")))?;
+                self.history.lock().unwrap().set(&self.params, id, value);
+                Ok(self.param_value(id))
+            }
+            "get_param" => {
+                let (id, _) = self.param(params)?;
+                Ok(self.param_value(id))
+            }
+            "params" => Ok(Value::Object(
+                ParamId::ALL
+                    .into_iter()
+                    .map(|id| (id.key().to_string(), self.param_value(id)))
+                    .collect::<Map<_, _>>(),
+            )),
+            "load_patch" => {
+                let PatchParams { path, .. } = parse_params(params)?;
+                let patch = Patch::open(&path).map_err(synth_error)?;
+                self.history
+                    .lock()
+                    .unwrap()
+                    .record(&self.params, |params| patch.apply(params))
+                    .map_err(synth_error)?;
+                self.load_tuning(&patch)?;
+                self.set_patch_name(&patch.name);
+                Ok(patch.name.into())
+            }
+            "save_patch" => {
+                let PatchParams { path, name } = parse_params(params)?;
+                let name = name.unwrap_or_else(|| {
+                    path.file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                });
+                Patch::new(&name, &self.params)
+                    .temperament(&self.temperament.lock().unwrap())
+                    .effects(self.effects.clone())
+                    .save(&path)
+                    .map_err(synth_error)?;
+                self.set_patch_name(&name);
+                Ok(Value::Null)
+            }
+            "undo" => {
+                let mut history = self.history.lock().unwrap();
+                let edit = history
+                    .undo()
+                    .ok_or_else(|| RpcError::new(SYNTH_ERROR, "Nothing to undo"))?;
+                edit.revert(&self.params);
+                Ok(edit.to_string().into())
+            }
+            "redo" => {
+                let mut history = self.history.lock().unwrap();
+                let edit = history
+                    .redo()
+                    .ok_or_else(|| RpcError::new(SYNTH_ERROR, "Nothing to redo"))?;
+                edit.apply(&self.params);
+                Ok(edit.to_string().into())
+            }
+            "status" => Ok(self.status()),
+            "meters" => Ok(json!({
+                "output_db": self.meters.output.get(),
+                "compressor_gain_reduction_db": self.meters.compressor.get(),
+                "limiter_gain_reduction_db": self.meters.limiter.get(),
+            })),
+            "shutdown" => {
+                let _ = self.shutdown.send(());
+                Ok(Value::Null)
+            }
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method:?}"),
+            )),
+        }
+    }
+
+    fn send(&self, event: SynthEvent) -> Result<Value, RpcError> {
+        self.events
+            .send(event)
+            .map_err(|_| RpcError::new(SYNTH_ERROR, "The audio stream stopped"))?;
+        Ok(Value::Null)
+    }
+
+    fn param(&self, params: Value) -> Result<(ParamId, Option<ParamValue>), RpcError> {
+        let ParamParams { id, value } = parse_params(params)?;
+        let id = ParamId::from_key(&id)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Unknown parameter {id:?}")))?;
+        Ok((id, value))
+    }
+
+    /// Keeps the custom or mapped table of `patch` for saving and hands it to
+    /// the oscilator, which only gets the values through the parameters.
+    fn load_tuning(&self, patch: &Patch) -> Result<(), RpcError> {
+        let index = self.params.get(ParamId::Temperament) as usize;
+        let Some(table) = patch
+            .tuning
+            .as_ref()
+            .filter(|_| Temperament::ALL.get(index).is_none())
+        else {
+            return Ok(());
+        };
+        let temperament = table.temperament().map_err(synth_error)?;
+        *self.temperament.lock().unwrap() = temperament.clone();
+        if let Some((wave, waves)) = &self.waves {
+            let mut wave = self.params.wave(wave.clone());
+            wave.tuning.temperament = temperament;
+            waves
+                .send(wave)
+                .map_err(|_| RpcError::new(SYNTH_ERROR, "Audio thread stopped"))?;
+        }
+        Ok(())
+    }
+
+    fn param_value(&self, id: ParamId) -> Value {
+        serde_json::to_value(ParamValue::encode(id, self.params.get(id))).unwrap_or_default()
+    }
+
+    fn status(&self) -> Value {
+        let transport = self.transport.as_ref().map(|transport| {
+            let position = transport.position();
+            json!({
+                "playing": position.playing,
+                "bar": position.bar() + 1,
+                "beat": position.beat_in_bar() + 1.0,
+                "bpm": position.bpm,
+            })
+        });
+        json!({
+            "device": self.stream.device,
+            "sample_rate": self.stream.sample_rate,
+            "channels": self.stream.channels,
+            "buffer_size": self.stream.buffer_size,
+            "patch": *self.patch.lock().unwrap(),
+            "transport": transport,
+        })
+    }
+
+    /// Answers one line of JSON, `None` for notifications.
+    pub fn handle_line(&self, line: &str) -> Option<String> {
+        let request = serde_json::from_str::<Value>(line)
+            .map_err(|err| RpcError::new(PARSE_ERROR, format!("Invalid JSON: {err}")))
+            .and_then(|value| {
+                serde_json::from_value::<Request>(value).map_err(|err| {
+                    RpcError::new(INVALID_REQUEST, format!("Invalid request: {err}"))
+                })
+            });
+        let response = match request {
+            Err(err) => Response::new(Value::Null, Err(err)),
+            Ok(request) if request.jsonrpc != JSONRPC_VERSION => Response::new(
+                request.id.unwrap_or_default(),
+                Err(RpcError::new(
+                    INVALID_REQUEST,
+                    format!("Unsupported JSON-RPC version {:?}", request.jsonrpc),
+                )),
+            ),
+            Ok(request) => {
+                let result = self.call(&request.method, request.params);
+                Response::new(request.id?, result)
+            }
+        };
+        serde_json::to_string(&response).ok()
+    }
+
+    fn serve_connection(&self, stream: UnixStream) -> std::io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle_line(&line) {
+                writeln!(writer, "{response}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Accepts connections from a new thread, each served from its own.
+    pub fn serve(self, listener: UnixListener) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let controller = self.clone();
+                std::thread::spawn(move || controller.serve_connection(stream));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Request, Response};
+    use synth::waves::{Oscilator, Waveform};
+
+    fn controller(params: &ParamStore) -> Controller {
+        let (events, _events_rx) = crossbeam_channel::unbounded();
+        let (shutdown, _shutdown_rx) = crossbeam_channel::unbounded();
+        Controller::new(params.clone(), events, StreamInfo::default(), shutdown)
+    }
+
+    #[test]
+    fn answers_requests_over_a_unix_socket() {
+        let dir = std::env::temp_dir().join(format!("synthd-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("synth.sock");
+        let _ = std::fs::remove_file(&path);
+        let params = ParamStore::new();
+        controller(&params).serve(UnixListener::bind(&path).unwrap());
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut call = |line: String| {
+            writeln!(writer, "{line}").unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            serde_json::from_str::<Response>(&response).unwrap()
+        };
+        let request = |id, method, params| serde_json::to_string(&Request::new(id, method, params));
+
+        let set =
+            call(request(1, "set_param", json!({ "id": "waveform", "value": "saw" })).unwrap());
+        assert_eq!(set.id, json!(1));
+        assert_eq!(set.into_result().unwrap(), json!("Saw"));
+        assert_eq!(params.get(ParamId::Waveform), 2.0);
+
+        // Notifications get no response, the line read answers the request after it.
+        let notification =
+            r#"{"jsonrpc":"2.0","method":"set_param","params":{"id":"amp","value":0.1}}"#;
+        let get = request(2, "get_param", json!({ "id": "amp" })).unwrap();
+        let get = call(format!("{notification}\n{get}"));
+        assert_eq!(get.id, json!(2));
+        assert_eq!(get.into_result().unwrap(), json!(0.1));
+
+        let unknown = call(request(3, "nope", Value::Null).unwrap());
+        assert_eq!(unknown.into_result().unwrap_err().code, METHOD_NOT_FOUND);
+        let invalid = call("{".to_string());
+        assert_eq!(invalid.id, Value::Null);
+        assert_eq!(invalid.into_result().unwrap_err().code, PARSE_ERROR);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undoes_parameter_changes_and_patch_loads() {
+        let params = ParamStore::new();
+        let controller = controller(&params);
+        let path = std::env::temp_dir().join(format!("synthd-undo-{}.toml", std::process::id()));
+        params.set(ParamId::Amp, 0.8);
+        Patch::new("Loud", &params).save(&path).unwrap();
+        params.set(ParamId::Amp, 0.5);
+
+        controller
+            .call("set_param", json!({ "id": "amp", "value": 0.2 }))
+            .unwrap();
+        controller
+            .call("load_patch", json!({ "path": path }))
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(params.get(ParamId::Amp), 0.8);
+        controller.call("undo", Value::Null).unwrap();
+        assert_eq!(params.get(ParamId::Amp), 0.2);
+        controller.call("undo", Value::Null).unwrap();
+        assert_eq!(params.get(ParamId::Amp), 0.5);
+        let err = controller.call("undo", Value::Null).unwrap_err();
+        assert_eq!(err.code, SYNTH_ERROR);
+        controller.call("redo", Value::Null).unwrap();
+        assert_eq!(params.get(ParamId::Amp), 0.2);
+    }
+
+    #[test]
+    fn loads_custom_temperaments_into_the_oscilator() {
+        let offsets = [
+            0.0, -29.3, 3.9, 15.6, -13.7, -2.0, -31.3, 2.0, -27.4, -15.6, 17.6, -11.7,
+        ];
+        let start = Wave::new(Waveform::Sin, Note::A4, 0.5);
+        let mut tuned = start.clone();
+        tuned.tuning.temperament = Temperament::Custom(offsets);
+        let dir = std::env::temp_dir().join(format!("synthd-tuning-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("werckmeister.toml");
+        Patch::from_wave("Werckmeister", &tuned)
+            .save(&path)
+            .unwrap();
+
+        let params = ParamStore::from_wave(&start);
+        let (waves, rx) = crossbeam_channel::unbounded();
+        let controller = controller(&params).waves(start.clone(), waves);
+        let mut oscilator = Oscilator::new(48000, start);
+        oscilator.add_receiver(rx);
+        oscilator.add_params(params.clone());
+        controller
+            .call("load_patch", json!({ "path": path }))
+            .unwrap();
+        oscilator.start_block();
+        assert_eq!(
+            oscilator.wave().tuning.temperament,
+            Temperament::Custom(offsets)
+        );
+
+        let saved = dir.join("saved.toml");
+        controller
+            .call("save_patch", json!({ "path": saved }))
+            .unwrap();
+        let mut loaded = Wave::new(Waveform::Sin, Note::A4, 0.5);
+        Patch::open(&saved)
+            .unwrap()
+            .apply_to_wave(&mut loaded)
+            .unwrap();
+        assert_eq!(loaded.tuning.temperament, Temperament::Custom(offsets));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
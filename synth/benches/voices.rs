@@ -0,0 +1,204 @@
+//! Rendering speed of the original per-sample oscilator (`baseline`), against
+//! the oscilator one sample at a time and in whole blocks, and of the
+//! oscilator bank at each SIMD level the CPU supports.
+//!
+//! Throughput is in samples per second, divided by the 48 kHz sample rate it
+//! gives the voices a single core renders in real time.
+//!
+//! `cargo bench -p synth --bench voices`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crossbeam_channel::Receiver;
+use synth::{
+    effects::{
+        dynamics::{Compressor, Limiter},
+        eq::{Band, BandKind, Equalizer, Slope},
+        Chain, Effect,
+    },
+    engine::Engine,
+    events::SynthEvent,
+    processor::AudioProcessor,
+    waves::{
+        bank::{OscilatorBank, SimdLevel},
+        Oscilator, Wave, Waveform,
+    },
+    Note,
+};
+
+const SAMPLE_RATE: u32 = 48000;
+/// Frames in a block, a common audio callback size.
+const BLOCK: usize = 512;
+/// Blocks rendered per iteration, about a tenth of a second.
+const BLOCKS: usize = 10;
+/// Voices in the oscilator bank, a dense unison.
+const BANK_VOICES: usize = 64;
+
+/// The oscilator before block processing: it polls its channel and
+/// computes the time of every sample.
+struct Baseline {
+    sample_rate: f32,
+    curr_sample: f32,
+    wave: Wave,
+    rx: Receiver<Wave>,
+}
+
+impl Baseline {
+    fn new(waveform: Waveform) -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE as f32,
+            curr_sample: 0.0,
+            wave: Wave::new(waveform, Note::A4, 0.3),
+            rx: crossbeam_channel::never(),
+        }
+    }
+
+    fn sample(&mut self) -> f32 {
+        if let Ok(wave) = self.rx.try_recv() {
+            self.wave = wave;
+        }
+        self.curr_sample += 1.0;
+        self.curr_sample %= self.wave.note.freq().recip() * self.sample_rate;
+        self.wave.sample(self.curr_sample / self.sample_rate)
+    }
+}
+
+fn oscilator(waveform: Waveform) -> Oscilator {
+    let mut oscilator = Oscilator::new(SAMPLE_RATE, Wave::new(waveform, Note::A4, 0.3));
+    oscilator.handle_event(SynthEvent::NoteOn {
+        note: Note::A4,
+        velocity: 1.0,
+    });
+    oscilator
+}
+
+fn effects() -> Chain {
+    let eq = Equalizer::default()
+        .with_band(Band::new(BandKind::LowCut(Slope::Db24), 30.0, 0.0, 0.7))
+        .with_band(Band::new(BandKind::Peaking, 2500.0, -1.5, 1.0))
+        .with_band(Band::new(BandKind::HighCut(Slope::Db12), 18000.0, 0.0, 0.7));
+    Chain::new()
+        .with(eq)
+        .with(Compressor::new())
+        .with(Limiter::new())
+}
+
+fn engine() -> Engine {
+    Engine::new(SAMPLE_RATE, Wave::new(Waveform::Saw, Note::A4, 0.3)).effects(effects())
+}
+
+fn oscilators(c: &mut Criterion) {
+    let mut group = c.benchmark_group("oscilator");
+    group.throughput(Throughput::Elements((BLOCK * BLOCKS) as u64));
+    for waveform in [
+        Waveform::Sin,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Triangle,
+    ] {
+        let name = format!("{waveform:?}");
+        group.bench_function(BenchmarkId::new("baseline", &name), |b| {
+            let mut oscilator = Baseline::new(waveform);
+            let mut buffer = [0.0; BLOCK];
+            b.iter(|| {
+                for _ in 0..BLOCKS {
+                    buffer
+                        .iter_mut()
+                        .for_each(|sample| *sample = oscilator.sample());
+                }
+                buffer[BLOCK - 1]
+            })
+        });
+        group.bench_function(BenchmarkId::new("per_sample", &name), |b| {
+            let mut oscilator = oscilator(waveform);
+            let mut buffer = [0.0; BLOCK];
+            b.iter(|| {
+                for _ in 0..BLOCKS {
+                    buffer
+                        .iter_mut()
+                        .for_each(|sample| *sample = oscilator.sample());
+                }
+                buffer[BLOCK - 1]
+            })
+        });
+        group.bench_function(BenchmarkId::new("block", &name), |b| {
+            let mut oscilator = oscilator(waveform);
+            let mut buffer = [0.0; BLOCK];
+            b.iter(|| {
+                for _ in 0..BLOCKS {
+                    oscilator.process(&mut buffer);
+                }
+                buffer[BLOCK - 1]
+            })
+        });
+    }
+    group.finish();
+}
+
+fn engines(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.throughput(Throughput::Elements((BLOCK * BLOCKS) as u64));
+    group.bench_function("baseline", |b| {
+        let mut oscilator = Baseline::new(Waveform::Saw);
+        let mut effects = effects();
+        effects.prepare(SAMPLE_RATE as f32);
+        let mut buffer = [0.0; BLOCK];
+        b.iter(|| {
+            for _ in 0..BLOCKS {
+                buffer
+                    .iter_mut()
+                    .for_each(|sample| *sample = effects.process(oscilator.sample()));
+            }
+            buffer[BLOCK - 1]
+        })
+    });
+    group.bench_function("per_sample", |b| {
+        let mut engine = engine();
+        let mut buffer = [0.0; BLOCK];
+        b.iter(|| {
+            for _ in 0..BLOCKS {
+                engine.start_block(None);
+                buffer
+                    .iter_mut()
+                    .for_each(|sample| *sample = engine.next_sample());
+            }
+            buffer[BLOCK - 1]
+        })
+    });
+    group.bench_function("block", |b| {
+        let mut engine = engine();
+        let mut buffer = [0.0; BLOCK];
+        b.iter(|| {
+            for _ in 0..BLOCKS {
+                engine.start_block(None);
+                engine.process(&mut buffer);
+            }
+            buffer[BLOCK - 1]
+        })
+    });
+    group.finish();
+}
+
+fn banks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bank");
+    group.throughput(Throughput::Elements((BLOCK * BLOCKS * BANK_VOICES) as u64));
+    for waveform in [Waveform::Sin, Waveform::Saw] {
+        for simd in SimdLevel::ALL.into_iter().filter(SimdLevel::is_supported) {
+            let id = BenchmarkId::new(format!("{simd:?}"), format!("{waveform:?}"));
+            group.bench_function(id, |b| {
+                let mut bank = OscilatorBank::new(SAMPLE_RATE, waveform).simd(simd);
+                bank.add_unison(110.0, BANK_VOICES, 30.0, 1.0);
+                let mut buffer = [0.0; BLOCK];
+                b.iter(|| {
+                    for _ in 0..BLOCKS {
+                        bank.render(&mut buffer);
+                    }
+                    buffer[BLOCK - 1]
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, oscilators, engines, banks);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+//! Slaves a transport to a simulated MIDI clock with jittery ticks, and
+//! prints how closely it follows.
+//!
+//! `cargo run --example clock_sync -- [bpm] [jitter ms]`
+
+use std::time::Duration;
+
+use anyhow::Result;
+use synth::{
+    midi::clock::{ClockFollower, SimulatedClock, PPQN},
+    transport::{TempoMap, Transport},
+};
+
+const SAMPLE_RATE: u32 = 48000;
+/// Frames the transport moves between updates, like an audio block.
+const BLOCK: u64 = 256;
+const BEATS: u32 = 64;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let bpm = args
+        .next()
+        .map(|bpm| bpm.parse())
+        .transpose()?
+        .unwrap_or(128.0);
+    let jitter = args.next().map(|ms| ms.parse()).transpose()?.unwrap_or(2.0);
+
+    let mut transport = Transport::new().tempo_map(TempoMap::new(100.0));
+    transport.prepare(SAMPLE_RATE as f32);
+    let mut follower = ClockFollower::new(transport.handle());
+    let mut clock = SimulatedClock::new(bpm, Duration::from_secs_f64(jitter / 1000.0));
+
+    follower.handle(clock.start())?;
+    let mut frame = 0;
+    for tick in 1..=BEATS * PPQN {
+        let event = clock.tick();
+        // Run the audio side until the tick arrives.
+        let due = event.micros * SAMPLE_RATE as u64 / 1_000_000;
+        while frame < due {
+            transport.update(None);
+            transport.advance(BLOCK);
+            frame += BLOCK;
+        }
+        transport.update(None);
+        follower.handle(event)?;
+
+        if tick % (4 * PPQN) == 0 {
+            let position = transport.position();
+            println!(
+                "beat {:>3}: clock {:>7.3} BPM, transport {:>7.3} BPM, {:+.4} beats behind",
+                tick / PPQN,
+                follower.bpm().unwrap_or_default(),
+                position.bpm,
+                follower.beat() - position.beat
+            );
+        }
+    }
+    Ok(())
+}
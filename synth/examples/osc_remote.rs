@@ -0,0 +1,71 @@
+//! Controls the synth over OSC on loopback UDP, the way a remote tool
+//! would: subscribes, plays a note, sets parameters and sends an invalid
+//! message, printing what the synth received and what it sent back.
+//!
+//! `cargo run --example osc_remote`
+
+use std::{net::UdpSocket, time::Duration};
+
+use anyhow::{anyhow, Result};
+use synth::{
+    osc::{
+        packet::{OscMessage, OscPacket},
+        OscRouter, OscServer,
+    },
+    params::{ParamId, ParamStore},
+    waves::{Wave, Waveform},
+    Note,
+};
+
+fn main() -> Result<()> {
+    let params = ParamStore::from_wave(&Wave::new(Waveform::Sin, Note::A4, 0.3));
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let server = OscServer::bind("127.0.0.1:0", OscRouter::new(params.clone(), tx))?;
+    let server_addr = server.local_addr()?;
+    server.spawn();
+
+    let client = UdpSocket::bind("127.0.0.1:0")?;
+    client.set_read_timeout(Some(Duration::from_millis(200)))?;
+    let send = |message: OscMessage| client.send_to(&message.encode(), server_addr);
+
+    send(OscMessage::new("/synth/subscribe"))?;
+    let state = receive(&client)?;
+    println!("subscribed, {} parameters received", state.len());
+
+    send(OscMessage::new("/synth/note_on").arg("C4").arg(0.8f32))?;
+    send(OscMessage::new("/synth/note_off").arg(60))?;
+    send(OscMessage::new("/synth/waveform").arg("saw"))?;
+    send(OscMessage::new("/synth/param/amp").arg(0.5f32))?;
+    send(OscMessage::new("/synth/param/mono").arg(true))?;
+    send(OscMessage::new("/synth/param/arp_pattern").arg("Sideways"))?;
+
+    for reply in receive(&client)? {
+        println!("received {} {:?}", reply.address, reply.args);
+    }
+    for event in rx.try_iter() {
+        println!("event {event:?}");
+    }
+
+    let expected = [
+        (ParamId::Waveform, 2.0),
+        (ParamId::Amp, 0.5),
+        (ParamId::Mono, 1.0),
+    ];
+    for (id, value) in expected {
+        if params.get(id) != value {
+            return Err(anyhow!("{id} is {}, expected {value}", params.get(id)));
+        }
+    }
+    println!("parameters set");
+    Ok(())
+}
+
+/// Messages received until nothing comes for a while.
+fn receive(socket: &UdpSocket) -> Result<Vec<OscMessage>> {
+    let mut messages = Vec::new();
+    let mut buffer = [0; 1024];
+    while let Ok(len) = socket.recv(&mut buffer) {
+        messages.extend(OscPacket::decode(&buffer[..len])?.messages());
+    }
+    Ok(messages)
+}
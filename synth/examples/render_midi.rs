@@ -0,0 +1,35 @@
+//! Renders a Standard MIDI File to a WAV file without an audio device.
+//!
+//! `cargo run --example render_midi -- song.mid song.wav`
+
+use anyhow::{anyhow, Result};
+use synth::{
+    effects::{dynamics::Limiter, Chain},
+    engine::Engine,
+    midi::smf::{MidiFile, SmfPlayer},
+    wav::write_wav,
+    waves::{Wave, Waveform},
+    Note,
+};
+
+const SAMPLE_RATE: u32 = 44100;
+/// Extra time rendered after the last event.
+const TAIL_SECONDS: f64 = 1.0;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(input), Some(output)) = (args.next(), args.next()) else {
+        return Err(anyhow!("Usage: render_midi <input.mid> <output.wav>"));
+    };
+
+    let file = MidiFile::open(input)?;
+    let frames = ((file.duration() + TAIL_SECONDS) * SAMPLE_RATE as f64) as usize;
+
+    let wave = Wave::new(Waveform::Triangle, Note::A4, 0.3);
+    let mut engine = Engine::new(SAMPLE_RATE, wave)
+        .effects(Chain::new().with(Limiter::new()))
+        .source(SmfPlayer::new(file))
+        .silent();
+
+    write_wav(output, &engine.render(frames), SAMPLE_RATE)
+}
@@ -0,0 +1,494 @@
+use crate::{events::SynthEvent, notes::Note};
+
+/// Most notes the arpeggiator plays at once, further ones are ignored.
+const MAX_NOTES: usize = 16;
+pub const MAX_OCTAVES: u8 = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArpPattern {
+    #[default]
+    Up,
+    Down,
+    /// Up then down, without repeating the top and bottom notes.
+    UpDown,
+    Random,
+    /// In the order the notes were pressed.
+    AsPlayed,
+}
+
+impl ArpPattern {
+    pub const ALL: [ArpPattern; 5] = [
+        ArpPattern::Up,
+        ArpPattern::Down,
+        ArpPattern::UpDown,
+        ArpPattern::Random,
+        ArpPattern::AsPlayed,
+    ];
+}
+
+/// Length of each step, synced to the tempo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArpRate {
+    Quarter,
+    Eighth,
+    EighthTriplet,
+    #[default]
+    Sixteenth,
+    SixteenthTriplet,
+    ThirtySecond,
+}
+
+impl ArpRate {
+    pub const ALL: [ArpRate; 6] = [
+        ArpRate::Quarter,
+        ArpRate::Eighth,
+        ArpRate::EighthTriplet,
+        ArpRate::Sixteenth,
+        ArpRate::SixteenthTriplet,
+        ArpRate::ThirtySecond,
+    ];
+
+    /// Length of a step in beats (quarter notes).
+    pub fn beats(&self) -> f32 {
+        match self {
+            ArpRate::Quarter => 1.0,
+            ArpRate::Eighth => 1.0 / 2.0,
+            ArpRate::EighthTriplet => 1.0 / 3.0,
+            ArpRate::Sixteenth => 1.0 / 4.0,
+            ArpRate::SixteenthTriplet => 1.0 / 6.0,
+            ArpRate::ThirtySecond => 1.0 / 8.0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArpRate::Quarter => "1/4",
+            ArpRate::Eighth => "1/8",
+            ArpRate::EighthTriplet => "1/8T",
+            ArpRate::Sixteenth => "1/16",
+            ArpRate::SixteenthTriplet => "1/16T",
+            ArpRate::ThirtySecond => "1/32",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArpSettings {
+    pub enabled: bool,
+    pub pattern: ArpPattern,
+    /// Octaves spanned, from 1 to `MAX_OCTAVES`.
+    pub octaves: u8,
+    pub rate: ArpRate,
+    /// Tempo in beats per minute.
+    pub bpm: f32,
+    /// Fraction of each step the note is held, 1.0 ties the steps together.
+    pub gate: f32,
+    /// Delays every other step by this fraction of a step, 0.0 is straight
+    /// and 1/3 gives a triplet shuffle.
+    pub swing: f32,
+    /// Keeps playing after the keys are released, until new ones are pressed.
+    pub latch: bool,
+}
+
+impl Default for ArpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pattern: ArpPattern::default(),
+            octaves: 1,
+            rate: ArpRate::default(),
+            bpm: 120.0,
+            gate: 0.5,
+            swing: 0.0,
+            latch: false,
+        }
+    }
+}
+
+impl ArpSettings {
+    pub fn new(pattern: ArpPattern, rate: ArpRate, bpm: f32) -> Self {
+        Self {
+            enabled: true,
+            pattern,
+            rate,
+            bpm,
+            ..Default::default()
+        }
+    }
+
+    /// Length of a step in samples, before swing.
+    pub fn step_length(&self, sample_rate: f32) -> f32 {
+        60.0 / self.bpm.max(1.0) * self.rate.beats() * sample_rate
+    }
+}
+
+/// Turns held notes into a sequence of note events.
+#[derive(Debug, Clone)]
+pub struct Arpeggiator {
+    sample_rate: f32,
+    /// Keys physically down.
+    pressed: Vec<Note>,
+    /// Notes being arpeggiated, in the order they were pressed.
+    notes: Vec<Note>,
+    /// `notes` sorted from low to high.
+    sorted: Vec<Note>,
+    velocity: f32,
+    step: usize,
+    /// Samples left until the next step.
+    countdown: f32,
+    /// Samples left until the sounding note is released.
+    gate_countdown: f32,
+    sounding: Option<Note>,
+    rng: u32,
+    /// Tempo played at instead of the one of the settings.
+    tempo: Option<f32>,
+}
+
+impl Arpeggiator {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            pressed: Vec::with_capacity(MAX_NOTES),
+            notes: Vec::with_capacity(MAX_NOTES),
+            sorted: Vec::with_capacity(MAX_NOTES),
+            velocity: 1.0,
+            step: 0,
+            countdown: 1.0,
+            gate_countdown: 0.0,
+            sounding: None,
+            rng: 0x9E37_79B9,
+            tempo: None,
+        }
+    }
+
+    /// Plays at `bpm` rather than at the tempo of the settings, until it is
+    /// given `None`.
+    pub fn follow_tempo(&mut self, bpm: Option<f32>) {
+        self.tempo = bpm;
+    }
+
+    /// Notes being arpeggiated, in the order they were pressed.
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub fn press(&mut self, note: Note, velocity: f32, settings: &ArpSettings) {
+        if settings.latch && self.pressed.is_empty() {
+            // A new chord after everything was released replaces the latched one.
+            self.notes.clear();
+        }
+        if self.pressed.is_empty() && self.sounding.is_none() {
+            // Start right away instead of waiting for the next step.
+            self.step = 0;
+            self.countdown = 1.0;
+        }
+        if !self.pressed.contains(&note) && self.pressed.len() < MAX_NOTES {
+            self.pressed.push(note);
+        }
+        if !self.notes.contains(&note) && self.notes.len() < MAX_NOTES {
+            self.notes.push(note);
+            self.sort();
+        }
+        self.velocity = velocity;
+    }
+
+    pub fn release(&mut self, note: Note, settings: &ArpSettings) {
+        self.pressed.retain(|pressed| *pressed != note);
+        if !settings.latch {
+            self.notes.retain(|held| *held != note);
+            self.sort();
+        }
+    }
+
+    /// Forgets every note, returns the note off for the sounding one.
+    pub fn stop(&mut self) -> Option<SynthEvent> {
+        self.pressed.clear();
+        self.notes.clear();
+        self.sorted.clear();
+        self.sounding
+            .take()
+            .map(|note| SynthEvent::NoteOff { note })
+    }
+
+    fn sort(&mut self) {
+        self.sorted.clear();
+        self.sorted.extend_from_slice(&self.notes);
+        self.sorted.sort();
+    }
+
+    fn random(&mut self) -> u32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    /// Note played at the current step.
+    fn note_at_step(&mut self, settings: &ArpSettings) -> Option<Note> {
+        let count = self.notes.len();
+        if count == 0 {
+            return None;
+        }
+        let octaves = settings.octaves.clamp(1, MAX_OCTAVES) as usize;
+        let length = count * octaves;
+        let index = match settings.pattern {
+            ArpPattern::Up | ArpPattern::AsPlayed => self.step % length,
+            ArpPattern::Down => length - 1 - self.step % length,
+            ArpPattern::UpDown if length > 1 => {
+                let position = self.step % (2 * length - 2);
+                if position < length {
+                    position
+                } else {
+                    2 * length - 2 - position
+                }
+            }
+            ArpPattern::UpDown => 0,
+            ArpPattern::Random => self.random() as usize % length,
+        };
+        let base = match settings.pattern {
+            ArpPattern::AsPlayed => self.notes[index % count],
+            _ => self.sorted[index % count],
+        };
+        let octave = (index / count) as i32;
+        Some(base.transpose(12 * octave).unwrap_or(base))
+    }
+
+    /// Advances one sample, returns the note events to play at this sample.
+    pub fn advance(&mut self, settings: &ArpSettings) -> [Option<SynthEvent>; 2] {
+        let mut events = [None, None];
+        if !settings.latch && self.notes.len() != self.pressed.len() {
+            // Latch was just turned off, drop the notes no longer held.
+            self.notes.retain(|note| self.pressed.contains(note));
+            self.sort();
+        }
+
+        if let Some(note) = self.sounding {
+            self.gate_countdown -= 1.0;
+            if self.gate_countdown <= 0.0 || self.notes.is_empty() {
+                events[0] = Some(SynthEvent::NoteOff { note });
+                self.sounding = None;
+            }
+        }
+
+        self.countdown -= 1.0;
+        if self.countdown > 0.0 {
+            return events;
+        }
+        let Some(note) = self.note_at_step(settings) else {
+            self.countdown = 1.0;
+            return events;
+        };
+
+        let bpm = self.tempo.unwrap_or(settings.bpm);
+        let step_length = ArpSettings { bpm, ..*settings }.step_length(self.sample_rate);
+        let swing = settings.swing.clamp(0.0, 0.9);
+        let length = if self.step.is_multiple_of(2) {
+            step_length * (1.0 + swing)
+        } else {
+            step_length * (1.0 - swing)
+        };
+        self.countdown += length;
+        self.step += 1;
+
+        if let Some(previous) = self.sounding.take() {
+            events[0] = Some(SynthEvent::NoteOff { note: previous });
+        }
+        let gate = settings.gate.clamp(0.0, 1.0);
+        // A full gate holds the note until the next step replaces it.
+        self.gate_countdown = if gate >= 1.0 {
+            f32::INFINITY
+        } else {
+            (length * gate).max(1.0)
+        };
+        self.sounding = Some(note);
+        events[1] = Some(SynthEvent::NoteOn {
+            note,
+            velocity: self.velocity,
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sixteenths at 150 BPM and 1 kHz are 100 samples long.
+    fn sixteenths(pattern: ArpPattern) -> ArpSettings {
+        ArpSettings::new(pattern, ArpRate::Sixteenth, 150.0)
+    }
+
+    fn pressed(notes: &[Note], settings: &ArpSettings) -> Arpeggiator {
+        let mut arp = Arpeggiator::new(1000.0);
+        for note in notes {
+            arp.press(*note, 1.0, settings);
+        }
+        arp
+    }
+
+    /// Events of the next `samples` samples, with the sample they land on.
+    fn run(
+        arp: &mut Arpeggiator,
+        settings: &ArpSettings,
+        samples: usize,
+    ) -> Vec<(usize, SynthEvent)> {
+        (0..samples)
+            .flat_map(|sample| {
+                arp.advance(settings)
+                    .into_iter()
+                    .flatten()
+                    .map(move |event| (sample, event))
+            })
+            .collect()
+    }
+
+    fn note_ons(events: &[(usize, SynthEvent)]) -> Vec<(usize, Note)> {
+        events
+            .iter()
+            .filter_map(|(sample, event)| match event {
+                SynthEvent::NoteOn { note, .. } => Some((*sample, *note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn notes(arp: &mut Arpeggiator, settings: &ArpSettings, steps: usize) -> Vec<Note> {
+        let events = run(arp, settings, steps * 100);
+        note_ons(&events)
+            .into_iter()
+            .map(|(_, note)| note)
+            .collect()
+    }
+
+    #[test]
+    fn down_plays_from_the_top() {
+        let settings = sixteenths(ArpPattern::Down);
+        let mut arp = pressed(&[Note::E4, Note::C4, Note::G4], &settings);
+        assert_eq!(
+            notes(&mut arp, &settings, 4),
+            [Note::G4, Note::E4, Note::C4, Note::G4]
+        );
+    }
+
+    #[test]
+    fn up_down_doesnt_repeat_the_end_notes() {
+        let settings = sixteenths(ArpPattern::UpDown);
+        let mut arp = pressed(&[Note::C4, Note::E4, Note::G4], &settings);
+        assert_eq!(
+            notes(&mut arp, &settings, 7),
+            [
+                Note::C4,
+                Note::E4,
+                Note::G4,
+                Note::E4,
+                Note::C4,
+                Note::E4,
+                Note::G4
+            ]
+        );
+    }
+
+    #[test]
+    fn random_stays_in_the_held_notes() {
+        let settings = sixteenths(ArpPattern::Random);
+        let held = [Note::C4, Note::E4, Note::G4];
+        let mut arp = pressed(&held, &settings);
+        let played = notes(&mut arp, &settings, 60);
+        assert_eq!(played.len(), 60);
+        assert!(played.iter().all(|note| held.contains(note)));
+        assert!(held.iter().all(|note| played.contains(note)));
+    }
+
+    #[test]
+    fn spans_one_to_four_octaves() {
+        for octaves in 1..=MAX_OCTAVES {
+            let settings = ArpSettings {
+                octaves,
+                ..sixteenths(ArpPattern::Up)
+            };
+            let mut arp = pressed(&[Note::C4, Note::E4], &settings);
+            let steps = 2 * octaves as usize;
+            let played = notes(&mut arp, &settings, steps + 1);
+            let top = Note::E4.transpose(12 * (octaves as i32 - 1)).unwrap();
+            assert_eq!(played[steps - 1], top, "{octaves} octaves");
+            assert_eq!(played[steps], Note::C4, "{octaves} octaves");
+        }
+    }
+
+    #[test]
+    fn swing_delays_every_other_step() {
+        let settings = ArpSettings {
+            swing: 1.0 / 3.0,
+            ..sixteenths(ArpPattern::Up)
+        };
+        let mut arp = pressed(&[Note::C4], &settings);
+        let onsets: Vec<usize> = note_ons(&run(&mut arp, &settings, 450))
+            .into_iter()
+            .map(|(sample, _)| sample)
+            .collect();
+        // Long and short steps, a sample either way for rounding.
+        assert_eq!(onsets.len(), 5);
+        for (onset, expected) in onsets.iter().zip([0, 133, 200, 333, 400]) {
+            assert!(onset.abs_diff(expected) <= 1, "{onsets:?}");
+        }
+    }
+
+    #[test]
+    fn gate_sets_how_long_each_note_is_held() {
+        for (gate, off) in [(0.25, 25), (0.5, 50), (1.0, 100)] {
+            let settings = ArpSettings {
+                gate,
+                ..sixteenths(ArpPattern::Up)
+            };
+            let mut arp = pressed(&[Note::C4, Note::E4], &settings);
+            let events = run(&mut arp, &settings, 101);
+            let first_off = events
+                .iter()
+                .find(|(_, event)| matches!(event, SynthEvent::NoteOff { .. }));
+            assert_eq!(
+                first_off,
+                Some(&(off, SynthEvent::NoteOff { note: Note::C4 })),
+                "gate {gate}"
+            );
+        }
+    }
+
+    #[test]
+    fn latch_keeps_playing_until_a_new_chord() {
+        let settings = ArpSettings {
+            latch: true,
+            ..sixteenths(ArpPattern::Up)
+        };
+        let mut arp = pressed(&[Note::C4, Note::E4], &settings);
+        arp.release(Note::C4, &settings);
+        arp.release(Note::E4, &settings);
+        assert_eq!(
+            notes(&mut arp, &settings, 3),
+            [Note::C4, Note::E4, Note::C4]
+        );
+
+        arp.press(Note::G4, 1.0, &settings);
+        assert_eq!(arp.notes(), [Note::G4]);
+
+        // Without latch, releasing the keys stops the notes.
+        let settings = sixteenths(ArpPattern::Up);
+        let mut arp = pressed(&[Note::C4], &settings);
+        arp.release(Note::C4, &settings);
+        assert!(notes(&mut arp, &settings, 3).is_empty());
+    }
+
+    #[test]
+    fn follows_a_tempo_other_than_its_own() {
+        let settings = sixteenths(ArpPattern::Up);
+        let mut arp = pressed(&[Note::C4], &settings);
+        arp.follow_tempo(Some(300.0));
+        let onsets: Vec<usize> = note_ons(&run(&mut arp, &settings, 200))
+            .into_iter()
+            .map(|(sample, _)| sample)
+            .collect();
+        assert_eq!(onsets, [0, 50, 100, 150]);
+        arp.follow_tempo(None);
+        assert_eq!(note_ons(&run(&mut arp, &settings, 200)).len(), 2);
+    }
+}
@@ -0,0 +1,122 @@
+pub mod biquad;
+pub mod distortion;
+pub mod dynamics;
+pub mod eq;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    effects::{
+        distortion::{BitcrusherSettings, WaveshaperSettings},
+        dynamics::{CompressorSettings, LimiterSettings},
+        eq::Band,
+    },
+    transport::Position,
+};
+
+/// Quietest level the effects work with, avoids `-inf` when converting silence to dB.
+pub const MIN_DB: f32 = -120.0;
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    (20.0 * gain.abs().log10()).max(MIN_DB)
+}
+
+/// Settings of the waveshaper, bitcrusher, EQ, compressor and limiter after
+/// the oscilators, saved with patches. The defaults leave the sound
+/// unchanged below the ceiling.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EffectSettings {
+    pub waveshaper: WaveshaperSettings,
+    pub bitcrusher: BitcrusherSettings,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub eq: Vec<Band>,
+    pub compressor: CompressorSettings,
+    pub limiter: LimiterSettings,
+}
+
+/// A mono audio processor placed between the oscilator and the output device.
+pub trait Effect: Send {
+    /// Called once before the effect starts processing, and whenever the sample rate changes.
+    fn prepare(&mut self, _sample_rate: f32) {}
+
+    fn process(&mut self, input: f32) -> f32;
+
+    /// Processes a block in place, one dynamic call for the whole block.
+    fn process_block(&mut self, buffer: &mut [f32]) {
+        buffer
+            .iter_mut()
+            .for_each(|sample| *sample = self.process(*sample));
+    }
+
+    /// Clears any internal state (filter memories, held samples, ...).
+    fn reset(&mut self) {}
+
+    /// Called before each block with the transport position at its start,
+    /// for effects synced to the tempo or following settings changed while
+    /// they play.
+    fn sync(&mut self, _position: &Position) {}
+}
+
+/// Effects applied one after the other, in insertion order.
+#[derive(Default)]
+pub struct Chain {
+    effects: Vec<Box<dyn Effect>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<E: Effect + 'static>(mut self, effect: E) -> Self {
+        self.push(effect);
+        self
+    }
+
+    pub fn push<E: Effect + 'static>(&mut self, effect: E) {
+        self.effects.push(Box::new(effect));
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
+
+impl Effect for Chain {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.effects
+            .iter_mut()
+            .for_each(|effect| effect.prepare(sample_rate));
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.effects
+            .iter_mut()
+            .fold(input, |sample, effect| effect.process(sample))
+    }
+
+    fn process_block(&mut self, buffer: &mut [f32]) {
+        self.effects
+            .iter_mut()
+            .for_each(|effect| effect.process_block(buffer));
+    }
+
+    fn reset(&mut self) {
+        self.effects.iter_mut().for_each(|effect| effect.reset());
+    }
+
+    fn sync(&mut self, position: &Position) {
+        self.effects
+            .iter_mut()
+            .for_each(|effect| effect.sync(position));
+    }
+}
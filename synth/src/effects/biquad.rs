@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+
+/// Normalized biquad coefficients, following the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl Coefficients {
+    pub const IDENTITY: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    fn normalize(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+        }
+    }
+
+    pub fn lowpass(sample_rate: f32, freq: f32, q: f32) -> Self {
+        let (cos, alpha) = omega(sample_rate, freq, q);
+        let b1 = 1.0 - cos;
+        Self::normalize(
+            [b1 / 2.0, b1, b1 / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn highpass(sample_rate: f32, freq: f32, q: f32) -> Self {
+        let (cos, alpha) = omega(sample_rate, freq, q);
+        let b1 = 1.0 + cos;
+        Self::normalize(
+            [b1 / 2.0, -b1, b1 / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn peaking(sample_rate: f32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, alpha) = omega(sample_rate, freq, q);
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        Self::normalize(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn low_shelf(sample_rate: f32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, alpha) = omega(sample_rate, freq, q);
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+        Self::normalize(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + beta),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + beta,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: f32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, alpha) = omega(sample_rate, freq, q);
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+        Self::normalize(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + beta),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + beta,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    /// Magnitude of the frequency response at `freq`, as a linear gain.
+    pub fn magnitude(&self, sample_rate: f32, freq: f32) -> f32 {
+        let w = 2.0 * PI * freq / sample_rate;
+        let (cos1, sin1) = (w.cos(), w.sin());
+        let (cos2, sin2) = ((2.0 * w).cos(), (2.0 * w).sin());
+        let norm = |c0: f32, c1: f32, c2: f32| {
+            let re = c0 + c1 * cos1 + c2 * cos2;
+            let im = c1 * sin1 + c2 * sin2;
+            re.hypot(im)
+        };
+        norm(self.b0, self.b1, self.b2) / norm(1.0, self.a1, self.a2)
+    }
+}
+
+/// Returns `cos(w0)` and `alpha` for the given cutoff and Q.
+fn omega(sample_rate: f32, freq: f32, q: f32) -> (f32, f32) {
+    let freq = freq.clamp(1.0, sample_rate * 0.499);
+    let w0 = 2.0 * PI * freq / sample_rate;
+    (w0.cos(), w0.sin() / (2.0 * q))
+}
+
+/// Transposed direct form II biquad section.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    coefficients: Coefficients,
+    z1: f32,
+    z2: f32,
+}
+
+impl Default for Biquad {
+    fn default() -> Self {
+        Self::new(Coefficients::IDENTITY)
+    }
+}
+
+impl Biquad {
+    pub fn new(coefficients: Coefficients) -> Self {
+        Self {
+            coefficients,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn set_coefficients(&mut self, coefficients: Coefficients) {
+        self.coefficients = coefficients;
+    }
+
+    pub fn coefficients(&self) -> Coefficients {
+        self.coefficients
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let Coefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        let output = b0.mul_add(input, self.z1);
+        self.z1 = b1.mul_add(input, self.z2) - a1 * output;
+        self.z2 = b2 * input - a2 * output;
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
@@ -0,0 +1,593 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    effects::{
+        biquad::{Biquad, Coefficients},
+        Effect,
+    },
+    transport::Position,
+};
+
+/// How much harder the negative half of the tube curve saturates.
+const TUBE_ASYMMETRY: f32 = 2.0;
+
+/// Butterworth Q, used by each of the two anti-aliasing sections.
+const ANTI_ALIAS_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Bitcrusher rate at which no sample is held at any output rate.
+pub const MAX_CRUSH_RATE: f32 = 192_000.0;
+/// Bit depth at and above which the bitcrusher doesn't quantize.
+pub const MAX_CRUSH_BITS: u32 = 24;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    #[default]
+    /// `tanh` saturation.
+    SoftClip,
+    /// Clamps to `[-1.0, 1.0]`.
+    HardClip,
+    /// Reflects anything past `±1.0` back into range.
+    Foldback,
+    /// Soft clip that saturates earlier on the negative half, adding even harmonics.
+    Tube,
+    /// Transfer function sampled uniformly over `[-1.0, 1.0]`, linearly interpolated.
+    Table(Vec<f32>),
+}
+
+impl Curve {
+    /// Curves that can be picked while the synth plays, a table is only set
+    /// when the waveshaper is built.
+    pub const BUILT_IN: [Curve; 4] = [
+        Curve::SoftClip,
+        Curve::HardClip,
+        Curve::Foldback,
+        Curve::Tube,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::SoftClip => "Soft clip",
+            Curve::HardClip => "Hard clip",
+            Curve::Foldback => "Foldback",
+            Curve::Tube => "Tube",
+            Curve::Table(_) => "Table",
+        }
+    }
+
+    pub fn shape(&self, x: f32) -> f32 {
+        match self {
+            Curve::SoftClip => x.tanh(),
+            Curve::HardClip => x.clamp(-1.0, 1.0),
+            Curve::Foldback => ((x - 1.0).rem_euclid(4.0) - 2.0).abs() - 1.0,
+            Curve::Tube => {
+                if x >= 0.0 {
+                    x.tanh()
+                } else {
+                    (x * TUBE_ASYMMETRY).tanh() / TUBE_ASYMMETRY
+                }
+            }
+            Curve::Table(table) => lookup(table, x),
+        }
+    }
+}
+
+fn lookup(table: &[f32], x: f32) -> f32 {
+    match table.len() {
+        0 => x,
+        1 => table[0],
+        len => {
+            let position = (x.clamp(-1.0, 1.0) + 1.0) * 0.5 * (len - 1) as f32;
+            let index = (position as usize).min(len - 2);
+            let frac = position - index as f32;
+            (table[index + 1] - table[index]).mul_add(frac, table[index])
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Oversampling {
+    #[default]
+    None,
+    X2,
+    X4,
+    X8,
+}
+
+impl Oversampling {
+    pub const ALL: [Oversampling; 4] = [
+        Oversampling::None,
+        Oversampling::X2,
+        Oversampling::X4,
+        Oversampling::X8,
+    ];
+
+    pub fn factor(&self) -> usize {
+        match self {
+            Oversampling::None => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+            Oversampling::X8 => 8,
+        }
+    }
+}
+
+/// Settings of a [`Waveshaper`]. The default mix leaves the sound dry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaveshaperSettings {
+    pub curve: Curve,
+    pub drive: f32,
+    /// Share of the shaped signal, from 0.0 to 1.0.
+    pub mix: f32,
+    pub oversampling: Oversampling,
+}
+
+impl Default for WaveshaperSettings {
+    fn default() -> Self {
+        Self {
+            curve: Curve::SoftClip,
+            drive: 1.0,
+            mix: 0.0,
+            oversampling: Oversampling::None,
+        }
+    }
+}
+
+/// Settings of a [`Bitcrusher`]. The defaults neither quantize nor hold.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BitcrusherSettings {
+    pub bits: u32,
+    /// Rate in Hz at which new samples are taken.
+    pub rate: f32,
+}
+
+impl Default for BitcrusherSettings {
+    fn default() -> Self {
+        Self {
+            bits: MAX_CRUSH_BITS,
+            rate: MAX_CRUSH_RATE,
+        }
+    }
+}
+
+/// Waveshaper and bitcrusher settings changed from the UI while they play,
+/// picked up by the audio thread before each block. Only numbers are shared,
+/// a table curve stays the one the waveshaper was built with.
+#[derive(Debug, Clone)]
+pub struct DistortionControl(Arc<[AtomicU32; 6]>);
+
+impl DistortionControl {
+    const CURVE: usize = 0;
+    const DRIVE: usize = 1;
+    const MIX: usize = 2;
+    const OVERSAMPLING: usize = 3;
+    const BITS: usize = 4;
+    const RATE: usize = 5;
+
+    pub fn new(waveshaper: &WaveshaperSettings, bitcrusher: &BitcrusherSettings) -> Self {
+        let control = Self(Arc::new(Default::default()));
+        control.set_waveshaper(waveshaper);
+        control.set_bitcrusher(bitcrusher);
+        control
+    }
+
+    pub fn set_waveshaper(&self, settings: &WaveshaperSettings) {
+        let curve = Curve::BUILT_IN
+            .iter()
+            .position(|curve| *curve == settings.curve)
+            .unwrap_or(Curve::BUILT_IN.len());
+        let oversampling = Oversampling::ALL
+            .iter()
+            .position(|oversampling| *oversampling == settings.oversampling)
+            .unwrap_or_default();
+        self.store(Self::CURVE, curve as u32);
+        self.store(Self::DRIVE, settings.drive.to_bits());
+        self.store(Self::MIX, settings.mix.to_bits());
+        self.store(Self::OVERSAMPLING, oversampling as u32);
+    }
+
+    pub fn set_bitcrusher(&self, settings: &BitcrusherSettings) {
+        self.store(Self::BITS, settings.bits);
+        self.store(Self::RATE, settings.rate.to_bits());
+    }
+
+    fn store(&self, index: usize, value: u32) {
+        self.0[index].store(value, Ordering::Relaxed);
+    }
+
+    fn load(&self, index: usize) -> u32 {
+        self.0[index].load(Ordering::Relaxed)
+    }
+}
+
+/// Drives the input into a [`Curve`], optionally running the curve at a higher
+/// sample rate so the harmonics it creates don't fold back as aliasing.
+pub struct Waveshaper {
+    pub curve: Curve,
+    pub drive: f32,
+    pub mix: f32,
+    oversampling: Oversampling,
+    sample_rate: f32,
+    prev_input: f32,
+    anti_alias: [Biquad; 2],
+    /// Table curve it was built with, kept here while a built-in one is used.
+    table: Option<Vec<f32>>,
+    control: Option<DistortionControl>,
+}
+
+impl Waveshaper {
+    pub fn new(curve: Curve, drive: f32) -> Self {
+        Self {
+            curve,
+            drive,
+            mix: 1.0,
+            oversampling: Oversampling::None,
+            sample_rate: 44100.0,
+            prev_input: 0.0,
+            anti_alias: Default::default(),
+            table: None,
+            control: None,
+        }
+    }
+
+    pub fn settings(mut self, settings: WaveshaperSettings) -> Self {
+        self.curve = settings.curve;
+        self.drive = settings.drive;
+        self.mix = settings.mix;
+        self.oversampling(settings.oversampling)
+    }
+
+    /// Follows the waveshaper settings of `control`.
+    pub fn control(mut self, control: DistortionControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    pub fn oversampling(mut self, oversampling: Oversampling) -> Self {
+        self.set_oversampling(oversampling);
+        self
+    }
+
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        self.oversampling = oversampling;
+        self.update_filters();
+    }
+
+    fn update_filters(&mut self) {
+        let factor = self.oversampling.factor() as f32;
+        let coefficients = Coefficients::lowpass(
+            self.sample_rate * factor,
+            self.sample_rate * 0.45,
+            ANTI_ALIAS_Q,
+        );
+        self.anti_alias
+            .iter_mut()
+            .for_each(|filter| filter.set_coefficients(coefficients));
+    }
+
+    fn shape(&self, x: f32) -> f32 {
+        self.curve.shape(x * self.drive)
+    }
+}
+
+impl Effect for Waveshaper {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_filters();
+        self.reset();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let factor = self.oversampling.factor();
+        let wet = if factor == 1 {
+            self.shape(input)
+        } else {
+            // Linear interpolation up to the oversampled rate, then band-limit
+            // back down before keeping only the last sub-sample.
+            let mut output = 0.0;
+            for step in 1..=factor {
+                let t = step as f32 / factor as f32;
+                let x = (input - self.prev_input).mul_add(t, self.prev_input);
+                let shaped = self.shape(x);
+                output = self
+                    .anti_alias
+                    .iter_mut()
+                    .fold(shaped, |sample, filter| filter.process(sample));
+            }
+            output
+        };
+        self.prev_input = input;
+        (wet - input).mul_add(self.mix, input)
+    }
+
+    fn reset(&mut self) {
+        self.prev_input = 0.0;
+        self.anti_alias.iter_mut().for_each(Biquad::reset);
+    }
+
+    fn sync(&mut self, _position: &Position) {
+        let Some(control) = &self.control else {
+            return;
+        };
+        self.drive = f32::from_bits(control.load(DistortionControl::DRIVE));
+        self.mix = f32::from_bits(control.load(DistortionControl::MIX));
+        // The table is moved between the curve and `table`, never cloned or
+        // dropped, so the audio thread doesn't allocate.
+        let curve = control.load(DistortionControl::CURVE) as usize;
+        match Curve::BUILT_IN.get(curve) {
+            Some(built_in) if self.curve != *built_in => {
+                if let Curve::Table(table) = std::mem::replace(&mut self.curve, built_in.clone()) {
+                    self.table = Some(table);
+                }
+            }
+            None => {
+                if let Some(table) = self.table.take() {
+                    self.curve = Curve::Table(table);
+                }
+            }
+            _ => (),
+        }
+        let oversampling = control.load(DistortionControl::OVERSAMPLING) as usize;
+        let oversampling = Oversampling::ALL
+            .get(oversampling)
+            .copied()
+            .unwrap_or_default();
+        if oversampling != self.oversampling {
+            self.set_oversampling(oversampling);
+        }
+    }
+}
+
+/// Reduces bit depth and sample rate by quantizing and holding samples.
+pub struct Bitcrusher {
+    pub bits: u32,
+    /// Rate in Hz at which new samples are taken, the rest are held.
+    pub rate: f32,
+    sample_rate: f32,
+    phase: f32,
+    held: f32,
+    control: Option<DistortionControl>,
+}
+
+impl Bitcrusher {
+    pub fn new(bits: u32, rate: f32) -> Self {
+        Self {
+            bits,
+            rate,
+            sample_rate: 44100.0,
+            phase: 1.0,
+            held: 0.0,
+            control: None,
+        }
+    }
+
+    pub fn settings(mut self, settings: BitcrusherSettings) -> Self {
+        self.bits = settings.bits;
+        self.rate = settings.rate;
+        self
+    }
+
+    /// Follows the bitcrusher settings of `control`.
+    pub fn control(mut self, control: DistortionControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    fn quantize(&self, x: f32) -> f32 {
+        if self.bits >= MAX_CRUSH_BITS {
+            return x;
+        }
+        // `bits` bits give 2^bits levels evenly spread over [-1.0, 1.0].
+        let steps = ((1u32 << self.bits.max(1)) - 1) as f32;
+        ((x.clamp(-1.0, 1.0) + 1.0) * 0.5 * steps).round() / steps * 2.0 - 1.0
+    }
+}
+
+impl Effect for Bitcrusher {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.phase += (self.rate / self.sample_rate).min(1.0);
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.held = self.quantize(input);
+        }
+        self.held
+    }
+
+    fn reset(&mut self) {
+        self.phase = 1.0;
+        self.held = 0.0;
+    }
+
+    fn sync(&mut self, _position: &Position) {
+        if let Some(control) = &self.control {
+            self.bits = control.load(DistortionControl::BITS);
+            self.rate = f32::from_bits(control.load(DistortionControl::RATE));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::Chain;
+
+    /// Sine at 441 Hz through `chain`, synced before every block of 64 samples.
+    fn render(chain: &mut Chain) -> Vec<f32> {
+        chain.prepare(44100.0);
+        let mut output: Vec<f32> = (0..1024)
+            .map(|n| 0.8 * (n as f32 * std::f32::consts::TAU / 100.0).sin())
+            .collect();
+        for block in output.chunks_mut(64) {
+            chain.sync(&Position::default());
+            chain.process_block(block);
+        }
+        output
+    }
+
+    fn levels(bits: u32) -> Vec<f32> {
+        let crusher = Bitcrusher::new(bits, 44100.0);
+        let mut levels: Vec<f32> = (-100..=100)
+            .map(|x| crusher.quantize(x as f32 / 100.0))
+            .collect();
+        levels.dedup();
+        levels
+    }
+
+    #[test]
+    fn bitcrusher_has_two_to_the_bits_levels() {
+        assert_eq!(levels(1), [-1.0, 1.0]);
+        assert_eq!(levels(2).len(), 4);
+        assert_eq!(levels(3).len(), 8);
+        assert_eq!(levels(4).len(), 16);
+    }
+
+    #[test]
+    fn bitcrusher_holds_samples_between_updates() {
+        let mut crusher = Bitcrusher::new(24, 11025.0);
+        crusher.prepare(44100.0);
+        let output: Vec<f32> = (1..=8).map(|x| crusher.process(x as f32 / 10.0)).collect();
+        assert_eq!(output, [0.1, 0.1, 0.1, 0.4, 0.4, 0.4, 0.4, 0.8]);
+    }
+
+    #[test]
+    fn curves_stay_in_range() {
+        let curves = [
+            Curve::SoftClip,
+            Curve::HardClip,
+            Curve::Foldback,
+            Curve::Tube,
+            Curve::Table(vec![-1.0, 0.0, 1.0]),
+        ];
+        for curve in curves {
+            for x in -50..=50 {
+                let y = curve.shape(x as f32 / 5.0);
+                assert!((-1.0..=1.0).contains(&y), "{curve:?} gives {y} for {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn foldback_reflects_past_the_limits() {
+        assert!((Curve::Foldback.shape(0.5) - 0.5).abs() < 1e-6);
+        assert!((Curve::Foldback.shape(1.5) - 0.5).abs() < 1e-6);
+        assert!((Curve::Foldback.shape(-1.5) + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn table_interpolates_linearly() {
+        let curve = Curve::Table(vec![0.0, 1.0, 0.0]);
+        assert!((curve.shape(-0.5) - 0.5).abs() < 1e-6);
+        assert!((curve.shape(0.0) - 1.0).abs() < 1e-6);
+        assert_eq!(Curve::Table(vec![]).shape(0.3), 0.3);
+    }
+
+    #[test]
+    fn oversampled_waveshaper_stays_bounded() {
+        let mut shaper = Waveshaper::new(Curve::HardClip, 4.0).oversampling(Oversampling::X4);
+        shaper.prepare(44100.0);
+        for n in 0..4410 {
+            let x = (n as f32 * 0.05).sin();
+            assert!(shaper.process(x).abs() < 1.2);
+        }
+    }
+
+    #[test]
+    fn chain_follows_the_distortion_control() {
+        let input = render(&mut Chain::new());
+        let waveshaper = WaveshaperSettings::default();
+        let bitcrusher = BitcrusherSettings::default();
+        let control = DistortionControl::new(&waveshaper, &bitcrusher);
+        let mut chain = Chain::new()
+            .with(
+                Waveshaper::new(Curve::HardClip, 8.0)
+                    .settings(waveshaper.clone())
+                    .control(control.clone()),
+            )
+            .with(
+                Bitcrusher::new(4, 8000.0)
+                    .settings(bitcrusher)
+                    .control(control.clone()),
+            );
+        // The default settings leave the sound unchanged.
+        assert_eq!(render(&mut chain), input);
+
+        control.set_waveshaper(&WaveshaperSettings {
+            curve: Curve::HardClip,
+            drive: 4.0,
+            mix: 1.0,
+            oversampling: Oversampling::X2,
+        });
+        let clipped = render(&mut chain);
+        assert!(clipped.iter().all(|x| x.abs() < 1.2));
+        assert!(clipped.iter().filter(|x| x.abs() > 0.95).count() > input.len() / 4);
+
+        control.set_waveshaper(&waveshaper);
+        control.set_bitcrusher(&BitcrusherSettings {
+            bits: 2,
+            rate: MAX_CRUSH_RATE,
+        });
+        let mut levels = render(&mut chain);
+        levels.sort_by(f32::total_cmp);
+        levels.dedup();
+        assert_eq!(levels.len(), 4);
+    }
+
+    #[test]
+    fn table_curve_comes_back_after_a_built_in_one() {
+        let table = WaveshaperSettings {
+            curve: Curve::Table(vec![0.5, 0.5]),
+            mix: 1.0,
+            ..Default::default()
+        };
+        let control = DistortionControl::new(&table, &BitcrusherSettings::default());
+        let mut shaper = Waveshaper::new(Curve::SoftClip, 1.0)
+            .settings(table.clone())
+            .control(control.clone());
+        shaper.prepare(44100.0);
+        shaper.sync(&Position::default());
+        assert_eq!(shaper.process(-0.3), 0.5);
+
+        control.set_waveshaper(&WaveshaperSettings {
+            curve: Curve::HardClip,
+            ..table.clone()
+        });
+        shaper.sync(&Position::default());
+        assert_eq!(shaper.process(-0.3), -0.3);
+
+        control.set_waveshaper(&table);
+        shaper.sync(&Position::default());
+        assert_eq!(shaper.process(-0.3), 0.5);
+    }
+
+    #[test]
+    fn settings_are_read_from_a_patch() {
+        let effects: crate::effects::EffectSettings = toml::from_str(
+            r#"
+            [waveshaper]
+            curve = "Tube"
+            drive = 4
+            mix = 0.5
+            oversampling = "X4"
+
+            [bitcrusher]
+            bits = 8
+            "#,
+        )
+        .unwrap();
+        assert_eq!(effects.waveshaper.curve, Curve::Tube);
+        assert_eq!(effects.waveshaper.oversampling, Oversampling::X4);
+        assert_eq!(effects.bitcrusher.bits, 8);
+        assert_eq!(effects.bitcrusher.rate, MAX_CRUSH_RATE);
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    effects::{db_to_gain, gain_to_db, Effect},
+    meter::Meter,
+};
+
+/// One-pole smoothing coefficient reaching ~63% of a step after `ms` milliseconds.
+fn time_coefficient(ms: f32, sample_rate: f32) -> f32 {
+    if ms <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (ms * 0.001 * sample_rate)).exp()
+    }
+}
+
+/// Settings of a [`Compressor`], levels in dB and times in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressorSettings {
+    pub threshold: f32,
+    pub ratio: f32,
+    pub knee: f32,
+    pub attack: f32,
+    pub release: f32,
+    pub makeup: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 0.0,
+            ratio: 1.0,
+            knee: 6.0,
+            attack: 10.0,
+            release: 100.0,
+            makeup: 0.0,
+        }
+    }
+}
+
+/// Feed-forward compressor with a soft knee. The level is detected on the
+/// input itself, or on an external key passed to [`Compressor::process_sidechain`].
+/// The default ratio is 1:1, so it leaves the audio unchanged until set up.
+pub struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    knee: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    sample_rate: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    envelope: f32,
+    gain_reduction: Meter,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        let settings = CompressorSettings::default();
+        Self {
+            threshold: settings.threshold,
+            ratio: settings.ratio,
+            knee: settings.knee,
+            attack: settings.attack,
+            release: settings.release,
+            makeup: settings.makeup,
+            sample_rate: 44100.0,
+            attack_coef: 0.0,
+            release_coef: 0.0,
+            envelope: 0.0,
+            gain_reduction: Meter::new(),
+        }
+        .with_coefficients()
+    }
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn settings(self, settings: CompressorSettings) -> Self {
+        self.threshold(settings.threshold)
+            .ratio(settings.ratio)
+            .knee(settings.knee)
+            .attack(settings.attack)
+            .release(settings.release)
+            .makeup(settings.makeup)
+    }
+
+    /// Level in dBFS above which the signal gets compressed.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn ratio(mut self, ratio: f32) -> Self {
+        self.ratio = ratio.max(1.0);
+        self
+    }
+
+    /// Width in dB of the transition around the threshold.
+    pub fn knee(mut self, knee: f32) -> Self {
+        self.knee = knee.max(0.0);
+        self
+    }
+
+    pub fn attack(mut self, attack_ms: f32) -> Self {
+        self.attack = attack_ms;
+        self.with_coefficients()
+    }
+
+    pub fn release(mut self, release_ms: f32) -> Self {
+        self.release = release_ms;
+        self.with_coefficients()
+    }
+
+    /// Gain in dB applied after compression.
+    pub fn makeup(mut self, makeup: f32) -> Self {
+        self.makeup = makeup;
+        self
+    }
+
+    /// Current gain reduction in dB, as a positive number.
+    pub fn gain_reduction(&self) -> Meter {
+        self.gain_reduction.clone()
+    }
+
+    fn with_coefficients(mut self) -> Self {
+        self.update_coefficients();
+        self
+    }
+
+    fn update_coefficients(&mut self) {
+        self.attack_coef = time_coefficient(self.attack, self.sample_rate);
+        self.release_coef = time_coefficient(self.release, self.sample_rate);
+    }
+
+    /// Static curve, returns the gain change in dB for a level in dB.
+    fn gain_computer(&self, level: f32) -> f32 {
+        let over = level - self.threshold;
+        let slope = self.ratio.recip() - 1.0;
+        if 2.0 * over < -self.knee {
+            0.0
+        } else if 2.0 * over.abs() <= self.knee {
+            slope * (over + self.knee / 2.0).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
+        }
+    }
+
+    pub fn process_sidechain(&mut self, input: f32, sidechain: f32) -> f32 {
+        let target = self.gain_computer(gain_to_db(sidechain));
+        let coef = if target < self.envelope {
+            self.attack_coef
+        } else {
+            self.release_coef
+        };
+        self.envelope = coef.mul_add(self.envelope - target, target);
+        self.gain_reduction.set(-self.envelope);
+        input * db_to_gain(self.envelope + self.makeup)
+    }
+}
+
+impl Effect for Compressor {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_coefficients();
+        self.reset();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.process_sidechain(input, input)
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.gain_reduction.set(0.0);
+    }
+}
+
+/// Settings of a [`Limiter`], the ceiling in dBFS and times in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimiterSettings {
+    pub ceiling: f32,
+    pub lookahead: f32,
+    pub release: f32,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            ceiling: -1.0,
+            lookahead: 5.0,
+            release: 50.0,
+        }
+    }
+}
+
+/// Brickwall limiter. The output is delayed by the lookahead time so the gain
+/// is already down when a peak reaches the output, and it never exceeds the ceiling.
+pub struct Limiter {
+    ceiling: f32,
+    lookahead: f32,
+    release: f32,
+    release_coef: f32,
+    delay: VecDeque<f32>,
+    /// Candidates for the minimum required gain within the lookahead window.
+    minimum: VecDeque<(usize, f32)>,
+    /// Ring buffer averaging the held gain over the lookahead window.
+    average: VecDeque<f32>,
+    average_sum: f32,
+    held: f32,
+    gain: f32,
+    history: [f32; 4],
+    position: usize,
+    gain_reduction: Meter,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        let settings = LimiterSettings::default();
+        let mut limiter = Self {
+            ceiling: db_to_gain(settings.ceiling),
+            lookahead: settings.lookahead,
+            release: settings.release,
+            release_coef: 0.0,
+            delay: VecDeque::new(),
+            minimum: VecDeque::new(),
+            average: VecDeque::new(),
+            average_sum: 0.0,
+            held: 1.0,
+            gain: 1.0,
+            history: [0.0; 4],
+            position: 0,
+            gain_reduction: Meter::new(),
+        };
+        limiter.prepare(44100.0);
+        limiter
+    }
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn settings(self, settings: LimiterSettings) -> Self {
+        self.ceiling(settings.ceiling)
+            .lookahead(settings.lookahead)
+            .release(settings.release)
+    }
+
+    /// Highest true-peak level in dBFS allowed at the output.
+    pub fn ceiling(mut self, ceiling: f32) -> Self {
+        self.ceiling = db_to_gain(ceiling.min(0.0));
+        self
+    }
+
+    pub fn lookahead(mut self, lookahead_ms: f32) -> Self {
+        self.lookahead = lookahead_ms.max(0.0);
+        self
+    }
+
+    pub fn release(mut self, release_ms: f32) -> Self {
+        self.release = release_ms;
+        self
+    }
+
+    /// Current gain reduction in dB, as a positive number.
+    pub fn gain_reduction(&self) -> Meter {
+        self.gain_reduction.clone()
+    }
+
+    /// Estimates the peak between samples by evaluating a Catmull-Rom spline
+    /// through the last four samples at 4x the sample rate.
+    fn true_peak(&mut self, input: f32) -> f32 {
+        self.history.rotate_left(1);
+        self.history[3] = input;
+        let [x0, x1, x2, x3] = self.history;
+        [0.25, 0.5, 0.75]
+            .iter()
+            .map(|t| {
+                let a = -0.5 * x0 + 1.5 * x1 - 1.5 * x2 + 0.5 * x3;
+                let b = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+                let c = -0.5 * x0 + 0.5 * x2;
+                ((a * t + b) * t + c) * t + x1
+            })
+            .fold(input.abs(), |peak, x| peak.max(x.abs()))
+    }
+
+    /// Lowest gain required over the last `lookahead + 1` samples: the one
+    /// leaving the delay line now and every one still in it.
+    fn window_minimum(&mut self, required: f32) -> f32 {
+        let window = self.delay.len() + 1;
+        while matches!(self.minimum.back(), Some((_, gain)) if *gain >= required) {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((self.position, required));
+        while matches!(self.minimum.front(), Some((index, _)) if index + window <= self.position) {
+            self.minimum.pop_front();
+        }
+        self.position += 1;
+        self.minimum.front().map_or(required, |(_, gain)| *gain)
+    }
+}
+
+impl Effect for Limiter {
+    fn prepare(&mut self, sample_rate: f32) {
+        let length = (self.lookahead * 0.001 * sample_rate).round() as usize;
+        self.delay = VecDeque::from(vec![0.0; length]);
+        self.average = VecDeque::from(vec![1.0; length]);
+        self.minimum = VecDeque::with_capacity(length + 2);
+        self.release_coef = time_coefficient(self.release, sample_rate);
+        self.reset();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let peak = self.true_peak(input);
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        // Drops instantly and recovers with the release time. Staying at or
+        // under the window minimum keeps the averaged gain low enough in time.
+        let minimum = self.window_minimum(required);
+        self.held = if minimum < self.held {
+            minimum
+        } else {
+            self.release_coef.mul_add(self.held - minimum, minimum)
+        };
+
+        let delayed = match self.delay.pop_front() {
+            Some(delayed) => {
+                self.delay.push_back(input);
+                self.average_sum += self.held - self.average.pop_front().unwrap_or(1.0);
+                self.average.push_back(self.held);
+                self.gain = (self.average_sum / self.average.len() as f32).min(1.0);
+                delayed
+            }
+            None => {
+                self.gain = self.held;
+                input
+            }
+        };
+
+        self.gain_reduction.set(-gain_to_db(self.gain));
+        (delayed * self.gain).clamp(-self.ceiling, self.ceiling)
+    }
+
+    fn reset(&mut self) {
+        self.delay.iter_mut().for_each(|sample| *sample = 0.0);
+        self.average.iter_mut().for_each(|gain| *gain = 1.0);
+        self.average_sum = self.average.len() as f32;
+        self.minimum.clear();
+        self.held = 1.0;
+        self.gain = 1.0;
+        self.history = [0.0; 4];
+        self.position = 0;
+        self.gain_reduction.set(0.0);
+    }
+}
+
+/// Leaves the audio unchanged and measures its peak level in dB, falling
+/// back over the release time, for output level meters.
+pub struct PeakMeter {
+    release: f32,
+    release_coef: f32,
+    envelope: f32,
+    level: Meter,
+}
+
+impl Default for PeakMeter {
+    fn default() -> Self {
+        Self {
+            release: 300.0,
+            release_coef: 0.0,
+            envelope: 0.0,
+            level: Meter::new(),
+        }
+    }
+}
+
+impl PeakMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Milliseconds for the level to fall by ~63% after a peak.
+    pub fn release(mut self, ms: f32) -> Self {
+        self.release = ms.max(0.0);
+        self
+    }
+
+    /// Current peak level in dB, [`MIN_DB`](crate::effects::MIN_DB) when silent.
+    pub fn level(&self) -> Meter {
+        self.level.clone()
+    }
+}
+
+impl Effect for PeakMeter {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.release_coef = time_coefficient(self.release, sample_rate);
+        self.reset();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.envelope = input.abs().max(self.envelope * self.release_coef);
+        self.level.set(gain_to_db(self.envelope));
+        input
+    }
+
+    fn process_block(&mut self, buffer: &mut [f32]) {
+        for sample in buffer.iter() {
+            self.envelope = sample.abs().max(self.envelope * self.release_coef);
+        }
+        self.level.set(gain_to_db(self.envelope));
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.level.set(gain_to_db(0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_compressor_is_transparent() {
+        let mut compressor = Compressor::new();
+        compressor.prepare(44100.0);
+        for n in 0..4410 {
+            let x = (n as f32 * 0.03).sin();
+            assert!((compressor.process(x) - x).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn compressor_reduces_gain_above_threshold() {
+        let mut compressor = Compressor::new().threshold(-12.0).ratio(4.0).knee(0.0);
+        compressor.prepare(44100.0);
+        for _ in 0..44100 {
+            compressor.process(1.0);
+        }
+        // 12 dB over the threshold at 4:1 comes out 3 dB over it.
+        assert!((compressor.gain_reduction().get() - 9.0).abs() < 0.01);
+    }
+
+    /// Loud bursts and lone spikes after quiet parts, the hardest cases for
+    /// the lookahead.
+    fn bursts() -> Vec<f32> {
+        (0..44100)
+            .map(|n| match (n / 1000) % 3 {
+                0 => 0.1 * (n as f32 * 0.2).sin(),
+                1 => (1.0 + (n % 7) as f32) * (n as f32 * 0.2).sin(),
+                _ if n % 500 == 0 => 4.0,
+                _ => 0.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limiter_gain_keeps_peaks_under_the_ceiling() {
+        for release in [50.0, 0.0] {
+            let mut limiter = Limiter::new().ceiling(-3.0).release(release);
+            limiter.prepare(44100.0);
+            let input = bursts();
+            let length = limiter.delay.len();
+            for (n, x) in input.iter().enumerate() {
+                let output = limiter.process(*x);
+                assert!(output.abs() <= limiter.ceiling);
+                // Checked before the final clamp, which should never have to act.
+                if let Some(delayed) = n.checked_sub(length).map(|n| input[n]) {
+                    assert!(
+                        (delayed * limiter.gain).abs() <= limiter.ceiling * 1.0001,
+                        "sample {n} over the ceiling with {release} ms release"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn limiter_leaves_quiet_audio_alone() {
+        let mut limiter = Limiter::new();
+        limiter.prepare(44100.0);
+        let length = limiter.delay.len();
+        let input: Vec<f32> = (0..4410).map(|n| 0.5 * (n as f32 * 0.1).sin()).collect();
+        for (n, x) in input.iter().enumerate() {
+            let output = limiter.process(*x);
+            let expected = n.checked_sub(length).map_or(0.0, |n| input[n]);
+            assert!((output - expected).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::effects::{
+    biquad::{Biquad, Coefficients},
+    gain_to_db, Effect,
+};
+
+/// Attenuation per octave of the low and high cut bands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Slope {
+    #[default]
+    Db12,
+    Db24,
+    Db36,
+    Db48,
+}
+
+impl Slope {
+    /// Number of second order sections needed for the slope.
+    pub fn sections(&self) -> usize {
+        match self {
+            Slope::Db12 => 1,
+            Slope::Db24 => 2,
+            Slope::Db36 => 3,
+            Slope::Db48 => 4,
+        }
+    }
+
+    /// Q of each section so the cascade has a Butterworth response.
+    fn butterworth_q(&self) -> impl Iterator<Item = f32> {
+        let sections = self.sections();
+        let order = 2 * sections;
+        (1..=sections).map(move |k| {
+            let angle = (2 * k - 1) as f32 * PI / (2 * order) as f32;
+            (2.0 * angle.cos()).recip()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BandKind {
+    LowShelf,
+    HighShelf,
+    Peaking,
+    LowCut(Slope),
+    HighCut(Slope),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Band {
+    pub kind: BandKind,
+    pub freq: f32,
+    /// Boost or cut in dB, ignored by the low and high cut bands.
+    pub gain: f32,
+    /// Ignored by the low and high cut bands, which are always Butterworth.
+    pub q: f32,
+    pub enabled: bool,
+}
+
+impl Band {
+    pub fn new(kind: BandKind, freq: f32, gain: f32, q: f32) -> Self {
+        Self {
+            kind,
+            freq,
+            gain,
+            q,
+            enabled: true,
+        }
+    }
+
+    fn coefficients(&self, sample_rate: f32) -> Vec<Coefficients> {
+        match self.kind {
+            BandKind::LowShelf => vec![Coefficients::low_shelf(
+                sample_rate,
+                self.freq,
+                self.q,
+                self.gain,
+            )],
+            BandKind::HighShelf => vec![Coefficients::high_shelf(
+                sample_rate,
+                self.freq,
+                self.q,
+                self.gain,
+            )],
+            BandKind::Peaking => vec![Coefficients::peaking(
+                sample_rate,
+                self.freq,
+                self.q,
+                self.gain,
+            )],
+            BandKind::LowCut(slope) => slope
+                .butterworth_q()
+                .map(|q| Coefficients::highpass(sample_rate, self.freq, q))
+                .collect(),
+            BandKind::HighCut(slope) => slope
+                .butterworth_q()
+                .map(|q| Coefficients::lowpass(sample_rate, self.freq, q))
+                .collect(),
+        }
+    }
+}
+
+/// Parametric EQ made of any number of bands in series.
+#[derive(Debug, Clone)]
+pub struct Equalizer {
+    bands: Vec<Band>,
+    filters: Vec<Vec<Biquad>>,
+    sample_rate: f32,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl Equalizer {
+    pub fn new(bands: Vec<Band>) -> Self {
+        let mut eq = Self {
+            bands,
+            filters: Vec::new(),
+            sample_rate: 44100.0,
+        };
+        eq.update_filters();
+        eq
+    }
+
+    pub fn with_band(mut self, band: Band) -> Self {
+        self.bands.push(band);
+        self.update_filters();
+        self
+    }
+
+    pub fn bands(&self) -> &[Band] {
+        &self.bands
+    }
+
+    pub fn set_band(&mut self, index: usize, band: Band) {
+        if let Some(old) = self.bands.get_mut(index) {
+            *old = band;
+            self.update_filters();
+        }
+    }
+
+    fn update_filters(&mut self) {
+        let sample_rate = self.sample_rate;
+        self.filters = self
+            .bands
+            .iter()
+            .map(|band| {
+                band.coefficients(sample_rate)
+                    .into_iter()
+                    .map(Biquad::new)
+                    .collect()
+            })
+            .collect();
+    }
+
+    /// Combined magnitude response in dB of every enabled band at `freq`.
+    pub fn magnitude_db(&self, sample_rate: f32, freq: f32) -> f32 {
+        self.bands
+            .iter()
+            .filter(|band| band.enabled)
+            .flat_map(|band| band.coefficients(sample_rate))
+            .map(|coefficients| gain_to_db(coefficients.magnitude(sample_rate, freq)))
+            .sum()
+    }
+}
+
+impl Effect for Equalizer {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_filters();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.bands
+            .iter()
+            .zip(self.filters.iter_mut())
+            .filter(|(band, _)| band.enabled)
+            .flat_map(|(_, sections)| sections.iter_mut())
+            .fold(input, |sample, section| section.process(sample))
+    }
+
+    fn reset(&mut self) {
+        self.filters
+            .iter_mut()
+            .flatten()
+            .for_each(|section| section.reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_flat() {
+        let mut eq = Equalizer::default();
+        eq.prepare(48000.0);
+        for freq in [20.0, 1000.0, 20000.0] {
+            assert_eq!(eq.magnitude_db(48000.0, freq), 0.0);
+        }
+        for n in 0..480 {
+            let x = (n as f32 * 0.1).sin();
+            assert_eq!(eq.process(x), x);
+        }
+    }
+
+    #[test]
+    fn peaking_band_boosts_its_frequency() {
+        let eq = Equalizer::default().with_band(Band::new(BandKind::Peaking, 1000.0, 6.0, 1.0));
+        assert!((eq.magnitude_db(48000.0, 1000.0) - 6.0).abs() < 0.01);
+        assert!(eq.magnitude_db(48000.0, 50.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn cut_slopes_add_up() {
+        for (slope, db) in [
+            (Slope::Db12, 12.0),
+            (Slope::Db24, 24.0),
+            (Slope::Db48, 48.0),
+        ] {
+            let eq = Equalizer::default().with_band(Band::new(
+                BandKind::LowCut(slope),
+                1000.0,
+                0.0,
+                0.7,
+            ));
+            let octave_down = eq.magnitude_db(48000.0, 125.0) - eq.magnitude_db(48000.0, 250.0);
+            assert!(
+                (octave_down + db).abs() < 1.0,
+                "{octave_down} dB per octave"
+            );
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+
+use crate::{
+    effects::{Chain, Effect},
+    events::{EventSource, SynthEvent},
+    processor::AudioProcessor,
+    sequencer::pattern::MAX_TRACKS,
+    transport::Transport,
+    tuning::Temperament,
+    waves::{Oscilator, Wave},
+};
+
+/// Frames rendered offline between two block starts, where parameters are read.
+const RENDER_BLOCK: usize = 256;
+/// Events a single frame usually produces, more only allocate once.
+const PENDING_EVENTS: usize = 64;
+/// Voices for the sources playing several parts, one per sequencer track.
+const VOICES: usize = MAX_TRACKS;
+/// Frames of a voice mixed at once, from a buffer on the stack.
+const MIX_BLOCK: usize = 64;
+
+/// Everything that runs in the audio thread to produce samples. It doesn't
+/// need an audio device, so it can also render offline.
+pub struct Engine {
+    sample_rate: u32,
+    oscilator: Oscilator,
+    /// Voices of the sources playing several parts at once, following the
+    /// settings of the main oscilator.
+    voices: Vec<Oscilator>,
+    effects: Chain,
+    sources: Vec<Box<dyn EventSource>>,
+    transport: Transport,
+    /// Events polled from the sources during the current frame.
+    pending: Vec<(Option<usize>, SynthEvent)>,
+}
+
+impl Engine {
+    pub fn new(sample_rate: u32, wave: Wave) -> Self {
+        let mut transport = Transport::new();
+        transport.prepare(sample_rate as f32);
+        let voices = (0..VOICES)
+            .map(|_| {
+                let mut voice = Oscilator::new(sample_rate, wave.clone());
+                voice.stop();
+                voice
+            })
+            .collect();
+        Self {
+            sample_rate,
+            oscilator: Oscilator::new(sample_rate, wave),
+            voices,
+            effects: Chain::new(),
+            sources: Vec::new(),
+            transport,
+            pending: Vec::with_capacity(PENDING_EVENTS),
+        }
+    }
+
+    pub fn effects(mut self, mut effects: Chain) -> Self {
+        effects.prepare(self.sample_rate as f32);
+        self.effects = effects;
+        self
+    }
+
+    pub fn source<S: EventSource + 'static>(self, source: S) -> Self {
+        self.boxed_source(Box::new(source))
+    }
+
+    pub fn boxed_source(mut self, mut source: Box<dyn EventSource>) -> Self {
+        source.prepare(self.sample_rate as f32);
+        self.sources.push(source);
+        self
+    }
+
+    /// Starts with no note playing, for engines played by their sources or
+    /// events rather than holding the note of the wave.
+    pub fn silent(mut self) -> Self {
+        self.oscilator.stop();
+        self
+    }
+
+    /// Sends the mapped temperaments the oscilators stop using to `tx`, to
+    /// free their tables outside the audio thread.
+    pub fn retired_sender(mut self, tx: Sender<Temperament>) -> Self {
+        for voice in self.voices.iter_mut() {
+            voice.add_retired_sender(tx.clone());
+        }
+        self.oscilator.add_retired_sender(tx);
+        self
+    }
+
+    pub fn transport(mut self, mut transport: Transport) -> Self {
+        transport.prepare(self.sample_rate as f32);
+        self.transport = transport;
+        self
+    }
+
+    pub fn transport_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn oscilator_mut(&mut self) -> &mut Oscilator {
+        &mut self.oscilator
+    }
+
+    /// Called by the audio callback before each block, `latency` is the
+    /// time until the block is heard.
+    pub fn start_block(&mut self, latency: Option<Duration>) {
+        self.transport.update(latency);
+        // Arpeggiators follow the transport tempo while it plays.
+        let position = self.transport.position();
+        let tempo = position.playing.then_some(position.bpm as f32);
+        self.oscilator.follow_tempo(tempo);
+        self.oscilator.start_block();
+        for voice in self.voices.iter_mut() {
+            voice.follow(self.oscilator.wave());
+            voice.follow_tempo(tempo);
+        }
+    }
+
+    /// Renders a single sample, syncing the effects to it. Prefer
+    /// [`process`](AudioProcessor::process), which renders whole blocks.
+    pub fn next_sample(&mut self) -> f32 {
+        let position = self.transport.position();
+        let oscilator = &mut self.oscilator;
+        let voices = &mut self.voices;
+        for source in self.sources.iter_mut() {
+            source.sync(&position);
+            source.poll_voices(&mut |voice, event| {
+                Self::voice(oscilator, voices, voice).handle_event(event)
+            });
+        }
+        self.effects.sync(&position);
+        let playing = voices.iter_mut().filter(|voice| !voice.is_idle());
+        let sample = playing.fold(oscilator.sample(), |sample, voice| sample + voice.sample());
+        let sample = self.effects.process(sample);
+        self.transport.advance(1);
+        sample
+    }
+
+    /// Renders `frames` mono samples as fast as possible.
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; frames];
+        for block in samples.chunks_mut(RENDER_BLOCK) {
+            self.start_block(None);
+            self.process(block);
+        }
+        // Publishes where rendering stopped.
+        self.transport.update(None);
+        samples
+    }
+
+    /// Oscilator playing `voice`, the main one for `None` and for voices
+    /// past the ones the engine has.
+    fn voice<'a>(
+        oscilator: &'a mut Oscilator,
+        voices: &'a mut [Oscilator],
+        voice: Option<usize>,
+    ) -> &'a mut Oscilator {
+        match voice.and_then(|voice| voices.get_mut(voice)) {
+            Some(voice) => voice,
+            None => oscilator,
+        }
+    }
+
+    /// Renders the main oscilator and mixes in the voices playing.
+    fn render_voices(oscilator: &mut Oscilator, voices: &mut [Oscilator], output: &mut [f32]) {
+        oscilator.process(output);
+        let mut buffer = [0.0; MIX_BLOCK];
+        for voice in voices.iter_mut().filter(|voice| !voice.is_idle()) {
+            for chunk in output.chunks_mut(MIX_BLOCK) {
+                let buffer = &mut buffer[..chunk.len()];
+                voice.process(buffer);
+                for (sample, value) in chunk.iter_mut().zip(buffer.iter()) {
+                    *sample += value;
+                }
+            }
+        }
+    }
+}
+
+impl AudioProcessor for Engine {
+    /// Events from the sources still land on their exact frame, the
+    /// oscilator renders the stretches between them as blocks.
+    fn process(&mut self, output: &mut [f32]) {
+        let start = self.transport.position();
+        let oscilator = &mut self.oscilator;
+        let voices = &mut self.voices;
+        if self.sources.is_empty() {
+            oscilator.process(output);
+            self.transport.advance(output.len() as u64);
+        } else {
+            let mut rendered = 0;
+            for frame in 0..output.len() {
+                let position = self.transport.position();
+                let pending = &mut self.pending;
+                for source in self.sources.iter_mut() {
+                    source.sync(&position);
+                    source.poll_voices(&mut |voice, event| pending.push((voice, event)));
+                }
+                if !pending.is_empty() {
+                    Self::render_voices(oscilator, voices, &mut output[rendered..frame]);
+                    rendered = frame;
+                    for (voice, event) in pending.drain(..) {
+                        Self::voice(oscilator, voices, voice).handle_event(event);
+                    }
+                }
+                self.transport.advance(1);
+            }
+            Self::render_voices(oscilator, voices, &mut output[rendered..]);
+        }
+        self.effects.sync(&start);
+        self.effects.process_block(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        notes::Note,
+        sequencer::{
+            pattern::{Pattern, Step, Track},
+            Sequencer,
+        },
+        waves::Waveform,
+    };
+
+    #[test]
+    fn sequencer_tracks_sound_together() {
+        let mut pattern = Pattern {
+            bpm: 120.0,
+            tracks: vec![Track::default(), Track::default()],
+        };
+        for track in pattern.tracks.iter_mut() {
+            track.steps_mut()[0] = Step::new(Note::A4);
+        }
+        let mut engine = Engine::new(48000, Wave::new(Waveform::Sin, Note::A4, 0.5))
+            .silent()
+            .source(Sequencer::new(pattern).playing(true));
+        let peak = engine
+            .render(2400)
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        // Both tracks at velocity 0.8, one alone would peak at 0.4.
+        assert!(peak > 0.7, "{peak}");
+        assert!(engine.oscilator_mut().is_idle());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{notes::Note, transport::Position};
+
+/// Performance events that drive the synth, independent of where they come from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SynthEvent {
+    /// `velocity` goes from `0.0` to `1.0`.
+    NoteOn {
+        note: Note,
+        velocity: f32,
+    },
+    NoteOff {
+        note: Note,
+    },
+    /// Bend amount from `-1.0` to `1.0`, scaled by the oscilator bend range.
+    PitchBend(f32),
+    /// Semitones reached by a full pitch bend in either direction.
+    BendRange(f32),
+    /// Fine tuning in cents, kept across notes.
+    Detune(f32),
+    /// Plays a raw frequency in Hz instead of the note, `None` goes back to the note.
+    Frequency(Option<f32>),
+    /// Modulation amount from `0.0` to `1.0`.
+    ModWheel(f32),
+    Sustain(bool),
+}
+
+/// Generates events from inside the audio thread, so they land on exact samples.
+pub trait EventSource: Send {
+    /// Called before the first poll, and whenever the sample rate changes.
+    fn prepare(&mut self, _sample_rate: f32) {}
+
+    /// Called before each poll with the transport position, for sources
+    /// following the song tempo.
+    fn sync(&mut self, _position: &Position) {}
+
+    /// Called once per frame, `emit` receives the events due on that frame.
+    fn poll(&mut self, emit: &mut dyn FnMut(SynthEvent));
+
+    /// Like [`poll`](Self::poll), for sources playing several parts at once
+    /// such as the sequencer tracks. Events come with the voice playing them,
+    /// numbered from 0, or `None` for the main oscilator also played by the
+    /// keyboard and MIDI. Other sources play everything on the main oscilator.
+    fn poll_voices(&mut self, emit: &mut dyn FnMut(Option<usize>, SynthEvent)) {
+        self.poll(&mut |event| emit(None, event));
+    }
+}
@@ -0,0 +1,217 @@
+/// Distance left, relative to the whole glide, when an exponential glide snaps to its target.
+const EXPONENTIAL_THRESHOLD: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GlideCurve {
+    /// Moves at a steady rate in pitch.
+    #[default]
+    Linear,
+    /// Moves fast at first and slows down near the target, like an analog RC circuit.
+    Exponential,
+}
+
+impl GlideCurve {
+    pub const ALL: [GlideCurve; 2] = [GlideCurve::Linear, GlideCurve::Exponential];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GlideMode {
+    /// Every glide takes the glide time, whatever the interval.
+    #[default]
+    ConstantTime,
+    /// The glide time is per octave, wider intervals take longer.
+    ConstantRate,
+}
+
+impl GlideMode {
+    pub const ALL: [GlideMode; 2] = [GlideMode::ConstantTime, GlideMode::ConstantRate];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GlideSettings {
+    /// Milliseconds, `0.0` disables glide.
+    pub time: f32,
+    pub curve: GlideCurve,
+    pub mode: GlideMode,
+    /// Only glide into notes played while another one is still held.
+    pub legato_only: bool,
+}
+
+impl GlideSettings {
+    pub fn new(time: f32) -> Self {
+        Self {
+            time,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.time > 0.0
+    }
+}
+
+/// Moves a pitch, in octaves (log2 of the frequency), towards a target over time.
+#[derive(Debug, Clone, Copy)]
+pub struct Glide {
+    pub settings: GlideSettings,
+    sample_rate: f32,
+    start: f32,
+    current: f32,
+    target: f32,
+    elapsed: f32,
+    duration: f32,
+    coef: f32,
+}
+
+impl Glide {
+    pub fn new(sample_rate: f32, pitch: f32) -> Self {
+        Self {
+            settings: GlideSettings::default(),
+            sample_rate,
+            start: pitch,
+            current: pitch,
+            target: pitch,
+            elapsed: 0.0,
+            duration: 0.0,
+            coef: 0.0,
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.elapsed < self.duration
+    }
+
+    /// Starts gliding towards `target`. Jumps instead when glide is off, or
+    /// when it is legato only and the previous note isn't held (`legato`).
+    pub fn set_target(&mut self, target: f32, legato: bool) {
+        self.target = target;
+        let silent = !target.is_finite() || !self.current.is_finite();
+        if silent || !self.settings.is_enabled() || (self.settings.legato_only && !legato) {
+            self.jump(target);
+            return;
+        }
+        let length = match self.settings.mode {
+            GlideMode::ConstantTime => self.settings.time,
+            GlideMode::ConstantRate => self.settings.time * (target - self.current).abs(),
+        };
+        self.start = self.current;
+        self.elapsed = 0.0;
+        self.duration = (length * 0.001 * self.sample_rate).max(1.0);
+        self.coef = (EXPONENTIAL_THRESHOLD.ln() / self.duration).exp();
+    }
+
+    /// Moves straight to `pitch`.
+    pub fn jump(&mut self, pitch: f32) {
+        self.start = pitch;
+        self.current = pitch;
+        self.target = pitch;
+        self.elapsed = 0.0;
+        self.duration = 0.0;
+    }
+
+    /// Advances one sample, returns the pitch in octaves.
+    pub fn advance(&mut self) -> f32 {
+        if !self.is_gliding() {
+            self.current = self.target;
+            return self.current;
+        }
+        self.elapsed += 1.0;
+        self.current = match self.settings.curve {
+            GlideCurve::Linear => {
+                let progress = self.elapsed / self.duration;
+                (self.target - self.start).mul_add(progress, self.start)
+            }
+            GlideCurve::Exponential => self.coef.mul_add(self.current - self.target, self.target),
+        };
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Glide at 1 kHz, so each millisecond is one sample.
+    fn glide(settings: GlideSettings) -> Glide {
+        let mut glide = Glide::new(1000.0, 0.0);
+        glide.settings = settings;
+        glide
+    }
+
+    /// Samples until the glide ends.
+    fn length(glide: &mut Glide) -> usize {
+        let mut samples = 0;
+        while glide.is_gliding() {
+            glide.advance();
+            samples += 1;
+        }
+        samples
+    }
+
+    #[test]
+    fn linear_glide_reaches_the_target_at_the_glide_time() {
+        let mut glide = glide(GlideSettings::new(100.0));
+        glide.set_target(1.0, true);
+        for _ in 0..50 {
+            glide.advance();
+        }
+        assert!((glide.current - 0.5).abs() < 1e-5);
+        assert_eq!(length(&mut glide), 50);
+        assert!((glide.current - 1.0).abs() < 1e-5);
+        assert_eq!(glide.advance(), 1.0);
+    }
+
+    #[test]
+    fn exponential_glide_snaps_to_the_target_at_the_glide_time() {
+        let mut glide = glide(GlideSettings {
+            curve: GlideCurve::Exponential,
+            ..GlideSettings::new(100.0)
+        });
+        glide.set_target(-2.0, true);
+        // Three quarters of the way are covered in the first fifth of the time.
+        for _ in 0..20 {
+            glide.advance();
+        }
+        assert!(glide.current < -1.4);
+        assert_eq!(length(&mut glide), 80);
+        assert!((glide.current + 2.0).abs() <= 2.0 * EXPONENTIAL_THRESHOLD + 1e-5);
+        assert_eq!(glide.advance(), -2.0);
+    }
+
+    #[test]
+    fn constant_rate_takes_longer_for_wider_intervals() {
+        let rate = GlideSettings {
+            mode: GlideMode::ConstantRate,
+            ..GlideSettings::new(100.0)
+        };
+        for (mode, octave, two_octaves) in [
+            (GlideMode::ConstantRate, 100, 200),
+            (GlideMode::ConstantTime, 100, 100),
+        ] {
+            let mut glide = glide(GlideSettings { mode, ..rate });
+            glide.set_target(1.0, true);
+            assert_eq!(length(&mut glide), octave, "{mode:?}");
+            glide.set_target(-1.0, true);
+            assert_eq!(length(&mut glide), two_octaves, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn legato_only_jumps_between_detached_notes() {
+        let mut glide = glide(GlideSettings {
+            legato_only: true,
+            ..GlideSettings::new(100.0)
+        });
+        glide.set_target(1.0, false);
+        assert!(!glide.is_gliding());
+        assert_eq!(glide.advance(), 1.0);
+
+        glide.set_target(2.0, true);
+        assert!(glide.is_gliding());
+        assert!(glide.advance() < 2.0);
+    }
+}
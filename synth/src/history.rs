@@ -0,0 +1,427 @@
+//! Undo and redo of parameter edits. Each edit records the parameters it
+//! changed, from and to which value, so it can be reverted on a
+//! [`ParamStore`] or a [`Wave`]. Quick repeated edits of the same
+//! parameters, like holding a key, are merged into one.
+//!
+//! The temperament parameter only names a custom or mapped temperament, so
+//! edits between waves also keep the whole tunings to restore their tables.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    params::{ParamId, ParamStore},
+    tuning::Tuning,
+    waves::Wave,
+};
+
+/// Edits of the same parameters closer than this are merged.
+pub const COALESCE_TIME: Duration = Duration::from_millis(500);
+/// Edits kept by default, the oldest ones are dropped.
+pub const HISTORY_LIMIT: usize = 256;
+
+/// A parameter going from one value to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    pub id: ParamId,
+    pub from: f32,
+    pub to: f32,
+}
+
+/// A tuning going from one temperament to another, tables included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningChange {
+    pub from: Tuning,
+    pub to: Tuning,
+}
+
+/// Parameters changed together, by one key press or loading a patch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub changes: Vec<Change>,
+    /// Set when the temperament changed between waves.
+    pub tuning: Option<Box<TuningChange>>,
+    pub time: Instant,
+}
+
+impl Edit {
+    pub fn new(changes: Vec<Change>) -> Self {
+        Self {
+            changes,
+            tuning: None,
+            time: Instant::now(),
+        }
+    }
+
+    /// Parameters that differ between the two waves, and their tunings
+    /// when the temperament changed.
+    pub fn between(before: &Wave, after: &Wave) -> Self {
+        let mut edit = Self::new(
+            ParamId::ALL
+                .into_iter()
+                .map(|id| Change {
+                    id,
+                    from: id.get(before),
+                    to: id.get(after),
+                })
+                .filter(|change| change.from != change.to)
+                .collect(),
+        );
+        if before.tuning.temperament != after.tuning.temperament {
+            edit.tuning = Some(Box::new(TuningChange {
+                from: before.tuning.clone(),
+                to: after.tuning.clone(),
+            }));
+        }
+        edit
+    }
+
+    /// Parameters of `params` that differ from their values in `before`,
+    /// indexed like [`ParamId::ALL`].
+    pub fn since(before: &[f32], params: &ParamStore) -> Self {
+        Self::new(
+            ParamId::ALL
+                .into_iter()
+                .zip(before)
+                .map(|(id, from)| Change {
+                    id,
+                    from: *from,
+                    to: params.get(id),
+                })
+                .filter(|change| change.from != change.to)
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.tuning.is_none()
+    }
+
+    pub fn apply(&self, params: &ParamStore) {
+        for change in &self.changes {
+            params.set(change.id, change.to);
+        }
+    }
+
+    pub fn revert(&self, params: &ParamStore) {
+        for change in self.changes.iter().rev() {
+            params.set(change.id, change.from);
+        }
+    }
+
+    pub fn apply_to_wave(&self, wave: &mut Wave) {
+        for change in &self.changes {
+            change.id.apply(change.to, wave);
+        }
+        if let Some(tuning) = &self.tuning {
+            wave.tuning = tuning.to.clone();
+        }
+    }
+
+    pub fn revert_wave(&self, wave: &mut Wave) {
+        for change in self.changes.iter().rev() {
+            change.id.apply(change.from, wave);
+        }
+        if let Some(tuning) = &self.tuning {
+            wave.tuning = tuning.from.clone();
+        }
+    }
+
+    /// Whether `other` changes the same parameters, so they can be merged.
+    fn same_params(&self, other: &Edit) -> bool {
+        self.tuning.is_some() == other.tuning.is_some()
+            && self.changes.len() == other.changes.len()
+            && self
+                .changes
+                .iter()
+                .zip(&other.changes)
+                .all(|(change, other)| change.id == other.id)
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.changes.as_slice(), &self.tuning) {
+            ([], Some(tuning)) => write!(
+                f,
+                "Temperament {} → {}",
+                tuning.from.temperament.name(),
+                tuning.to.temperament.name()
+            ),
+            ([], None) => write!(f, "Nothing"),
+            ([change], _) => {
+                let info = change.id.info();
+                write!(
+                    f,
+                    "{} {} → {}",
+                    info.name,
+                    info.format(change.from),
+                    info.format(change.to)
+                )
+            }
+            (changes, _) => write!(f, "{} parameters", changes.len()),
+        }
+    }
+}
+
+/// Edits that can be undone, and the undone ones that can be redone until
+/// a new edit is made.
+#[derive(Debug, Clone)]
+pub struct History {
+    done: Vec<Edit>,
+    undone: Vec<Edit>,
+    coalesce: Duration,
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            done: Vec::new(),
+            undone: Vec::new(),
+            coalesce: COALESCE_TIME,
+            limit: HISTORY_LIMIT,
+        }
+    }
+
+    /// Merges edits of the same parameters closer than `coalesce`, zero
+    /// keeps every edit.
+    pub fn coalesce(mut self, coalesce: Duration) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Keeps at most `limit` edits.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Edits that can be undone, oldest first.
+    pub fn done(&self) -> &[Edit] {
+        &self.done
+    }
+
+    /// Edits that can be redone, the next one to redo last.
+    pub fn undone(&self) -> &[Edit] {
+        &self.undone
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Records an edit that was already made, empty ones are ignored. The
+    /// undone edits can't be redone anymore.
+    pub fn push(&mut self, edit: Edit) {
+        if edit.is_empty() {
+            return;
+        }
+        // After an undo the last edit isn't the one just made anymore.
+        let coalesce = self.undone.is_empty();
+        self.undone.clear();
+        if let Some(last) = self.done.last_mut().filter(|_| coalesce) {
+            if last.same_params(&edit) && edit.time.duration_since(last.time) < self.coalesce {
+                for (change, new) in last.changes.iter_mut().zip(edit.changes) {
+                    change.to = new.to;
+                }
+                if let (Some(tuning), Some(new)) = (&mut last.tuning, edit.tuning) {
+                    tuning.to = new.to;
+                }
+                last.time = edit.time;
+                // Back where it started, such as Up then Down.
+                if last.changes.iter().all(|change| change.from == change.to)
+                    && last
+                        .tuning
+                        .as_ref()
+                        .is_none_or(|tuning| tuning.from.temperament == tuning.to.temperament)
+                {
+                    self.done.pop();
+                }
+                return;
+            }
+        }
+        self.keep(edit);
+    }
+
+    fn keep(&mut self, edit: Edit) {
+        self.done.push(edit);
+        if self.done.len() > self.limit {
+            self.done.remove(0);
+        }
+    }
+
+    /// Sets a parameter of `params` and records it.
+    pub fn set(&mut self, params: &ParamStore, id: ParamId, value: f32) {
+        let from = params.get(id);
+        params.set(id, value);
+        self.push(Edit::new(vec![Change {
+            id,
+            from,
+            to: params.get(id),
+        }]));
+    }
+
+    /// Makes `edit` on `params` and records every parameter it changed as
+    /// one edit, such as loading a patch. It is never merged with the last.
+    pub fn record<T>(&mut self, params: &ParamStore, edit: impl FnOnce(&ParamStore) -> T) -> T {
+        let before = ParamId::ALL.map(|id| params.get(id));
+        let result = edit(params);
+        let edit = Edit::since(&before, params);
+        if !edit.is_empty() {
+            self.undone.clear();
+            self.keep(edit);
+        }
+        result
+    }
+
+    /// Moves the last edit to the undone ones and returns it, to be reverted.
+    pub fn undo(&mut self) -> Option<&Edit> {
+        let edit = self.done.pop()?;
+        self.undone.push(edit);
+        self.undone.last()
+    }
+
+    /// Moves the last undone edit back and returns it, to be applied again.
+    pub fn redo(&mut self) -> Option<&Edit> {
+        let edit = self.undone.pop()?;
+        self.done.push(edit);
+        self.done.last()
+    }
+
+    /// Undoes the last edit on `params`.
+    pub fn undo_params(&mut self, params: &ParamStore) -> bool {
+        self.undo().map(|edit| edit.revert(params)).is_some()
+    }
+
+    /// Redoes the last undone edit on `params`.
+    pub fn redo_params(&mut self, params: &ParamStore) -> bool {
+        self.redo().map(|edit| edit.apply(params)).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        notes::Note,
+        tuning::{Temperament, MIDI_KEYS},
+        waves::Waveform,
+    };
+
+    #[test]
+    fn undoing_a_temperament_change_restores_the_mapped_table() {
+        let mut before = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        before.tuning.temperament = Temperament::Mapped(Arc::new([220.0; MIDI_KEYS]));
+        let mut after = before.clone();
+        after.tuning.temperament = Temperament::Just;
+
+        let mut history = History::new();
+        history.push(Edit::between(&before, &after));
+        let mut wave = after.clone();
+        history.undo().unwrap().revert_wave(&mut wave);
+        assert_eq!(wave.tuning, before.tuning);
+        history.redo().unwrap().apply_to_wave(&mut wave);
+        assert_eq!(wave.tuning, after.tuning);
+    }
+
+    /// Edit of a single parameter made `ms` milliseconds after `start`.
+    fn edit(id: ParamId, from: f32, to: f32, start: Instant, ms: u64) -> Edit {
+        Edit {
+            time: start + Duration::from_millis(ms),
+            ..Edit::new(vec![Change { id, from, to }])
+        }
+    }
+
+    #[test]
+    fn merges_quick_edits_of_the_same_parameter() {
+        let start = Instant::now();
+        let mut history = History::new();
+        history.push(edit(ParamId::Amp, 0.5, 0.6, start, 0));
+        history.push(edit(ParamId::Amp, 0.6, 0.7, start, 300));
+        // The window starts again from the last edit merged.
+        history.push(edit(ParamId::Amp, 0.7, 0.8, start, 600));
+        assert_eq!(history.done().len(), 1);
+        let change = history.done()[0].changes[0];
+        assert_eq!((change.from, change.to), (0.5, 0.8));
+    }
+
+    #[test]
+    fn keeps_edits_of_other_parameters_or_further_apart() {
+        let start = Instant::now();
+        let mut history = History::new();
+        history.push(edit(ParamId::Amp, 0.5, 0.6, start, 0));
+        history.push(edit(ParamId::Detune, 0.0, 1.0, start, 100));
+        history.push(edit(ParamId::Detune, 1.0, 2.0, start, 700));
+        assert_eq!(history.done().len(), 3);
+
+        // Nor is an edit made after an undo merged into the one before.
+        history.undo();
+        history.push(edit(ParamId::Detune, 1.0, 3.0, start, 800));
+        assert_eq!(history.done().len(), 3);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn edits_back_to_the_start_cancel_out() {
+        let start = Instant::now();
+        let mut history = History::new();
+        history.push(edit(ParamId::Note, 69.0, 60.0, start, 0));
+        history.push(edit(ParamId::Amp, 0.5, 0.51, start, 100));
+        history.push(edit(ParamId::Amp, 0.51, 0.5, start, 200));
+        assert_eq!(history.done().len(), 1);
+        assert_eq!(history.done()[0].changes[0].id, ParamId::Note);
+    }
+
+    #[test]
+    fn drops_the_oldest_edits_past_the_limit() {
+        let params = ParamStore::new();
+        let mut history = History::new().limit(2);
+        history.set(&params, ParamId::Amp, 0.1);
+        history.set(&params, ParamId::Detune, 10.0);
+        history.set(&params, ParamId::Unison, 3.0);
+        let ids: Vec<ParamId> = history
+            .done()
+            .iter()
+            .map(|edit| edit.changes[0].id)
+            .collect();
+        assert_eq!(ids, [ParamId::Detune, ParamId::Unison]);
+    }
+
+    #[test]
+    fn records_a_patch_load_as_one_edit() {
+        let params = ParamStore::new();
+        let mut history = History::new();
+        history.set(&params, ParamId::Amp, 0.2);
+        history.record(&params, |params| {
+            params.set(ParamId::Amp, 0.8);
+            params.set(ParamId::Unison, 3.0);
+        });
+        assert_eq!(history.done().len(), 2);
+        assert!(history.undo_params(&params));
+        assert_eq!(params.get(ParamId::Amp), 0.2);
+        assert_eq!(params.get(ParamId::Unison), 1.0);
+        assert!(history.undo_params(&params));
+        assert_eq!(params.get(ParamId::Amp), 0.5);
+        assert!(!history.undo_params(&params));
+    }
+}
@@ -1,20 +1,53 @@
+pub mod arp;
+pub mod effects;
+pub mod engine;
+pub mod events;
+pub mod glide;
+pub mod history;
+pub mod meter;
+pub mod midi;
+pub mod mono;
 pub mod notes;
+pub mod osc;
+pub mod params;
+pub mod patch;
+pub mod processor;
+pub mod scala;
+pub mod sequencer;
+pub mod transport;
+pub mod tuning;
+pub mod wav;
 pub mod waves;
+#[cfg(feature = "web")]
+pub mod web;
 
+use crate::effects::Chain;
+use crate::engine::Engine;
+use crate::events::{EventSource, SynthEvent};
+use crate::meter::Meter;
 pub use crate::notes::Note;
+use crate::params::ParamStore;
+use crate::processor::AudioProcessor;
+use crate::transport::Transport;
 use crate::waves::Wave;
 
 use anyhow::{anyhow, Error, Result};
 use core::hash::Hash;
-pub use cpal::traits::StreamTrait;
+pub use cpal::{traits::StreamTrait, BufferSize, StreamConfig};
 use cpal::{
     traits::{DeviceTrait, HostTrait},
-    BufferSize, Device, FromSample, OutputCallbackInfo, Sample, SampleFormat, SizedSample, Stream,
-    StreamConfig, SupportedBufferSize, SupportedOutputConfigs,
+    Device, FromSample, OutputCallbackInfo, Sample, SampleFormat, SizedSample, Stream,
+    SupportedBufferSize, SupportedOutputConfigs,
 };
 use crossbeam_channel::Receiver;
 use std::collections::HashSet;
-use waves::Oscilator;
+
+/// Frames rendered at once by the audio callback, allocated with the stream
+/// so the callback doesn't. Longer callbacks are rendered in several goes.
+const CALLBACK_BLOCK: usize = 1024;
+/// Mapped temperaments the audio thread can hand over at once to be freed,
+/// one per oscilator changing tables with room to spare.
+const RETIRED_TABLES: usize = 64;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct SupportedSampleFormat(SampleFormat);
@@ -42,8 +75,8 @@ pub struct SupportedConfigs {
 impl SupportedConfigs {
     fn new(configs: SupportedOutputConfigs) -> Self {
         let mut max_channels = 0;
-        let mut sample_rate_range = (0, 0);
-        let mut buffer_size_range = None;
+        let mut sample_rate_range = (u32::MAX, 0);
+        let mut buffer_size_range: Option<(u32, u32)> = None;
         let mut supported_sample_formats = HashSet::new();
         configs.for_each(|config| {
             let channels = config.channels();
@@ -52,16 +85,28 @@ impl SupportedConfigs {
             let buffer_size = config.buffer_size();
             let sample_format = config.sample_format();
 
+            // Ranges span every configuration of the device.
             max_channels = max_channels.max(channels);
-            sample_rate_range = (min_sample_rate.0, max_sample_rate.0);
+            sample_rate_range = (
+                sample_rate_range.0.min(min_sample_rate.0),
+                sample_rate_range.1.max(max_sample_rate.0),
+            );
             match *buffer_size {
-                SupportedBufferSize::Range { min, max } => buffer_size_range = Some((min, max)),
+                SupportedBufferSize::Range { min, max } => {
+                    buffer_size_range = Some(match buffer_size_range {
+                        Some((low, high)) => (low.min(min), high.max(max)),
+                        None => (min, max),
+                    })
+                }
                 SupportedBufferSize::Unknown => (),
             };
 
             supported_sample_formats.insert(sample_format.into());
         });
 
+        if sample_rate_range.0 > sample_rate_range.1 {
+            sample_rate_range = (0, 0);
+        }
         Self {
             max_channels,
             sample_rate_range,
@@ -78,22 +123,57 @@ pub struct AudioDevice {
 }
 
 impl AudioDevice {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self> {
-        let host = cpal::default_host();
-        // println!("Host: {:?}", host.id().name());
+        Self::open(None, None)
+    }
+
+    /// Output device called `device` of the audio host called `host`, such
+    /// as ALSA or JACK, ignoring case. The defaults are used for `None`.
+    pub fn open(host: Option<&str>, device: Option<&str>) -> Result<Self> {
+        let host = match host {
+            Some(name) => {
+                let id = cpal::available_hosts()
+                    .into_iter()
+                    .find(|id| id.name().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Unknown audio host {name:?}, available hosts: {}",
+                            Self::hosts().join(", ")
+                        )
+                    })?;
+                cpal::host_from_id(id)
+                    .map_err(|err| anyhow!("The audio host {} isn't available: {err}", id.name()))?
+            }
+            None => cpal::default_host(),
+        };
 
         let input_device = host.default_input_device();
-        // println!(
-        //     "Input Device: {:?}",
-        //     input_device
-        //         .as_ref()
-        //         .map(|device| device.name().unwrap_or("No Name".to_string()))
-        // );
-
-        let output_device = host
-            .default_output_device()
-            .ok_or(anyhow!("No default output device found."))?;
-        // println!("Output Device: {:?}", output_device.name().ok());
+
+        let output_device = match device {
+            Some(name) => host
+                .output_devices()?
+                .find(|device| {
+                    device
+                        .name()
+                        .is_ok_and(|device| device.eq_ignore_ascii_case(name))
+                })
+                .ok_or_else(|| {
+                    let devices = Self::output_devices(host.id().name()).unwrap_or_default();
+                    anyhow!(
+                        "Unknown output device {name:?} for {}, available devices: {}",
+                        host.id().name(),
+                        if devices.is_empty() {
+                            "none".to_string()
+                        } else {
+                            devices.join(", ")
+                        }
+                    )
+                })?,
+            None => host
+                .default_output_device()
+                .ok_or(anyhow!("No default output device found."))?,
+        };
 
         let supported_output_configs =
             SupportedConfigs::new(output_device.supported_output_configs()?);
@@ -104,14 +184,48 @@ impl AudioDevice {
             supported_output_configs,
         })
     }
+
+    /// Names of the audio hosts available on this platform.
+    pub fn hosts() -> Vec<&'static str> {
+        cpal::available_hosts()
+            .into_iter()
+            .map(|id| id.name())
+            .collect()
+    }
+
+    /// Names of the output devices of the audio host called `host`.
+    pub fn output_devices(host: &str) -> Result<Vec<String>> {
+        let id = cpal::available_hosts()
+            .into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(host))
+            .ok_or_else(|| anyhow!("Unknown audio host {host:?}"))?;
+        Ok(cpal::host_from_id(id)?
+            .output_devices()?
+            .filter_map(|device| device.name().ok())
+            .collect())
+    }
+
+    pub fn name(&self) -> String {
+        self.output_device
+            .name()
+            .unwrap_or_else(|_| "Unknown".to_string())
+    }
 }
 
 pub struct Synth {
     device: AudioDevice,
     config: StreamConfig,
+    effects: Chain,
+    events: Option<Receiver<SynthEvent>>,
+    sources: Vec<Box<dyn EventSource>>,
+    transport: Option<Transport>,
+    params: Option<ParamStore>,
+    frequency: Option<Meter>,
+    silent: bool,
 }
 
 impl Synth {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self> {
         let device = AudioDevice::default()?;
         Self::new(device)
@@ -120,29 +234,41 @@ impl Synth {
     pub fn new(device: AudioDevice) -> Result<Self> {
         let config = device.output_device.default_output_config()?;
         let config = config.into();
-        Ok(Self { device, config })
+        Ok(Self {
+            device,
+            config,
+            effects: Chain::new(),
+            events: None,
+            sources: Vec::new(),
+            transport: None,
+            params: None,
+            frequency: None,
+            silent: false,
+        })
     }
 
     pub fn channels(mut self, channels: u16) -> Result<Self> {
         let device_channels = self.device.supported_output_configs.max_channels;
-        if channels < device_channels {
+        if (1..=device_channels).contains(&channels) {
             self.config.channels = channels;
             Ok(self)
         } else {
             Err(anyhow!(
-                "The device supports up to {device_channels} channels"
+                "{channels} channels aren't supported by {}, it has up to {device_channels}",
+                self.device.name()
             ))
         }
     }
 
     pub fn sample_rate(mut self, sample_rate: u32) -> Result<Self> {
         let rate_range = self.device.supported_output_configs.sample_rate_range;
-        if (rate_range.0..rate_range.1).contains(&sample_rate) {
+        if (rate_range.0..=rate_range.1).contains(&sample_rate) {
             self.config.sample_rate.0 = sample_rate;
             Ok(self)
         } else {
             Err(anyhow!(
-                "The device supports sample rates between {} and {}",
+                "A sample rate of {sample_rate} Hz isn't supported by {}, it accepts {} to {} Hz",
+                self.device.name(),
                 rate_range.0,
                 rate_range.1,
             ))
@@ -155,32 +281,117 @@ impl Synth {
             .supported_output_configs
             .buffer_size_range
             .ok_or(anyhow!(
-                "The accepted buffer sizes for the device are unknown"
+                "The accepted buffer sizes for {} are unknown, leave it to the device",
+                self.device.name()
             ))?;
-        if (buffer_range.0..buffer_range.1).contains(&buffer_size) {
+        if (buffer_range.0..=buffer_range.1).contains(&buffer_size) {
             self.config.buffer_size = BufferSize::Fixed(buffer_size);
             Ok(self)
         } else {
             Err(anyhow!(
-                "The devices supports buffers size between {} and {}",
+                "A buffer of {buffer_size} frames isn't supported by {}, it accepts {} to {} frames",
+                self.device.name(),
                 buffer_range.0,
                 buffer_range.1
             ))
         }
     }
 
+    pub fn device(&self) -> &AudioDevice {
+        &self.device
+    }
+
+    /// Configuration of the next stream created.
+    pub fn config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    /// Effects applied to the oscilator output of the next stream created.
+    pub fn effects(mut self, effects: Chain) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    /// Performance events (notes, pitch bend, ...) received by the streams
+    /// created, at the start of the audio block after they arrive.
+    pub fn events(mut self, rx: Receiver<SynthEvent>) -> Self {
+        self.events = Some(rx);
+        self
+    }
+
+    /// Adds a source of events played sample accurately by the next stream created.
+    pub fn source<S: EventSource + 'static>(mut self, source: S) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Transport driven by the next stream created, keep a
+    /// [`handle`](Transport::handle) to control it.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Parameters followed by the streams created, set them to control the synth.
+    pub fn params(mut self, params: ParamStore) -> Self {
+        self.params = Some(params);
+        self
+    }
+
+    /// Frequency played by the streams created, bend, vibrato and detune included.
+    pub fn frequency(mut self, meter: Meter) -> Self {
+        self.frequency = Some(meter);
+        self
+    }
+
+    /// Streams created start with no note playing instead of holding the
+    /// note of their wave, to only hear the sources and events.
+    pub fn silent(mut self) -> Self {
+        self.silent = true;
+        self
+    }
+
+    /// Moves the effects, sources and transport into a new engine for a stream.
+    fn engine(&mut self, wave: Wave) -> Engine {
+        let mut engine =
+            Engine::new(self.config.sample_rate.0, wave).effects(std::mem::take(&mut self.effects));
+        if let Some(transport) = self.transport.take() {
+            engine = engine.transport(transport);
+        }
+        for source in self.sources.drain(..) {
+            engine = engine.boxed_source(source);
+        }
+        if let Some(events) = &self.events {
+            engine.oscilator_mut().add_event_receiver(events.clone());
+        }
+        if let Some(params) = &self.params {
+            engine.oscilator_mut().add_params(params.clone());
+        }
+        if let Some(meter) = &self.frequency {
+            engine.oscilator_mut().add_frequency_meter(meter.clone());
+        }
+        if self.silent {
+            engine = engine.silent();
+        }
+        // Tables the audio thread stops using are freed by a thread of their
+        // own, which ends with the engine.
+        let (s_retired, r_retired) = crossbeam_channel::bounded(RETIRED_TABLES);
+        std::thread::spawn(move || r_retired.iter().for_each(drop));
+        engine.retired_sender(s_retired)
+    }
+
     pub fn new_output_stream<T>(&mut self, wave: Wave) -> Result<Stream>
     where
         T: SizedSample + FromSample<f32>,
     {
-        let channels = self.config.channels as u16;
-        let sample_rate = self.config.sample_rate.0;
+        let channels = self.config.channels;
         let err_fn = |err| eprintln!("{}", err);
 
-        let mut oscilator = Oscilator::new(sample_rate, wave);
+        let mut engine = self.engine(wave);
+        let mut buffer = vec![0.0; CALLBACK_BLOCK];
 
         let data_callback = move |data: &mut [T], callback_info: &OutputCallbackInfo| {
-            Self::write_data(data, &mut oscilator, channels, callback_info)
+            Self::write_data(data, &mut buffer, &mut engine, channels, callback_info)
         };
 
         self.device
@@ -193,15 +404,15 @@ impl Synth {
     where
         T: SizedSample + FromSample<f32>,
     {
-        let channels = self.config.channels as u16;
-        let sample_rate = self.config.sample_rate.0;
+        let channels = self.config.channels;
         let err_fn = |err| eprintln!("{}", err);
 
-        let mut oscilator = Oscilator::new(sample_rate, wave);
-        oscilator.add_receiver(rx);
+        let mut engine = self.engine(wave);
+        let mut buffer = vec![0.0; CALLBACK_BLOCK];
+        engine.oscilator_mut().add_receiver(rx);
 
         let data_callback = move |data: &mut [T], callback_info: &OutputCallbackInfo| {
-            Self::write_data(data, &mut oscilator, channels, callback_info)
+            Self::write_data(data, &mut buffer, &mut engine, channels, callback_info)
         };
 
         self.device
@@ -212,16 +423,24 @@ impl Synth {
 
     fn write_data<T>(
         output: &mut [T],
-        oscilator: &mut Oscilator,
+        buffer: &mut [f32],
+        engine: &mut Engine,
         channels: u16,
-        _callback_info: &OutputCallbackInfo,
+        callback_info: &OutputCallbackInfo,
     ) where
         T: Sample + FromSample<f32>,
     {
-        for frame in output.chunks_mut(channels as usize) {
-            let value = oscilator.sample().to_sample();
-            for sample in frame.iter_mut() {
-                *sample = value;
+        let timestamp = callback_info.timestamp();
+        engine.start_block(timestamp.playback.duration_since(&timestamp.callback));
+        let channels = channels as usize;
+        for chunk in output.chunks_mut(buffer.len() * channels) {
+            let buffer = &mut buffer[..chunk.len() / channels];
+            engine.process(buffer);
+            for (frame, value) in chunk.chunks_mut(channels).zip(buffer.iter()) {
+                let value = value.to_sample();
+                for sample in frame.iter_mut() {
+                    *sample = value;
+                }
             }
         }
     }
@@ -0,0 +1,478 @@
+pub mod clock;
+pub mod smf;
+
+use std::{thread::JoinHandle, time::Instant};
+
+use anyhow::{anyhow, Error, Result};
+use crossbeam_channel::{Receiver, Sender};
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+use crate::{
+    events::SynthEvent,
+    midi::clock::{ClockEvent, ClockMessage},
+    notes::Note,
+};
+
+const CLIENT_NAME: &str = "rust-synth";
+
+const CC_MOD_WHEEL: u8 = 1;
+const CC_SUSTAIN: u8 = 64;
+const PITCH_BEND_CENTER: u16 = 8192;
+
+/// Channel voice messages the synth understands, channels go from 0 to 15.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOff {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// 14 bit value, 8192 is the center.
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+}
+
+impl MidiMessage {
+    /// Parses a single complete message, unsupported messages return `None`.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = status & 0x0F;
+        let data1 = *data.first()? & 0x7F;
+        let data2 = data.get(1).map(|byte| byte & 0x7F);
+        match status & 0xF0 {
+            0x80 => Some(Self::NoteOff {
+                channel,
+                key: data1,
+                velocity: data2?,
+            }),
+            0x90 => Some(Self::NoteOn {
+                channel,
+                key: data1,
+                velocity: data2?,
+            }),
+            0xB0 => Some(Self::ControlChange {
+                channel,
+                controller: data1,
+                value: data2?,
+            }),
+            0xE0 => Some(Self::PitchBend {
+                channel,
+                value: (data2? as u16) << 7 | data1 as u16,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn from_midly(channel: u8, message: midly::MidiMessage) -> Option<Self> {
+        match message {
+            midly::MidiMessage::NoteOff { key, vel } => Some(Self::NoteOff {
+                channel,
+                key: key.as_int(),
+                velocity: vel.as_int(),
+            }),
+            midly::MidiMessage::NoteOn { key, vel } => Some(Self::NoteOn {
+                channel,
+                key: key.as_int(),
+                velocity: vel.as_int(),
+            }),
+            midly::MidiMessage::Controller { controller, value } => Some(Self::ControlChange {
+                channel,
+                controller: controller.as_int(),
+                value: value.as_int(),
+            }),
+            midly::MidiMessage::PitchBend { bend } => Some(Self::PitchBend {
+                channel,
+                value: bend.0.as_int(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        match *self {
+            Self::NoteOff { channel, .. }
+            | Self::NoteOn { channel, .. }
+            | Self::ControlChange { channel, .. }
+            | Self::PitchBend { channel, .. } => channel,
+        }
+    }
+
+    /// Converts to a synth event. Notes outside of the [`Note`] range and
+    /// unmapped controllers return `None`.
+    pub fn to_event(&self) -> Option<SynthEvent> {
+        match *self {
+            Self::NoteOn { key, velocity, .. } if velocity > 0 => Some(SynthEvent::NoteOn {
+                note: Note::from_midi(key)?,
+                velocity: velocity as f32 / 127.0,
+            }),
+            Self::NoteOn { key, .. } | Self::NoteOff { key, .. } => Some(SynthEvent::NoteOff {
+                note: Note::from_midi(key)?,
+            }),
+            Self::ControlChange {
+                controller: CC_MOD_WHEEL,
+                value,
+                ..
+            } => Some(SynthEvent::ModWheel(value as f32 / 127.0)),
+            Self::ControlChange {
+                controller: CC_SUSTAIN,
+                value,
+                ..
+            } => Some(SynthEvent::Sustain(value >= 64)),
+            Self::ControlChange { .. } => None,
+            Self::PitchBend { value, .. } => Some(SynthEvent::PitchBend(
+                (value as f32 - PITCH_BEND_CENTER as f32) / PITCH_BEND_CENTER as f32,
+            )),
+        }
+    }
+}
+
+/// Turns raw MIDI bytes into synth events and forwards them to the engine.
+#[derive(Debug, Clone)]
+pub struct MidiRouter {
+    tx: Sender<SynthEvent>,
+    channel: Option<u8>,
+    clock: Option<Sender<ClockEvent>>,
+    created: Instant,
+}
+
+impl MidiRouter {
+    /// Listens on every channel.
+    pub fn new(tx: Sender<SynthEvent>) -> Self {
+        Self {
+            tx,
+            channel: None,
+            clock: None,
+            created: Instant::now(),
+        }
+    }
+
+    /// Only listens to `channel`, from 0 to 15.
+    pub fn channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Also routes clock messages, with their arrival time, to `tx`.
+    pub fn clock(mut self, tx: Sender<ClockEvent>) -> Self {
+        self.clock = Some(tx);
+        self
+    }
+
+    /// Handles one message, returns whether it produced an event.
+    pub fn handle(&self, bytes: &[u8]) -> Result<bool> {
+        let micros = self.created.elapsed().as_micros() as u64;
+        self.handle_at(micros, bytes)
+    }
+
+    /// Handles one message received at `micros`, as timestamped by the MIDI driver.
+    pub fn handle_at(&self, micros: u64, bytes: &[u8]) -> Result<bool> {
+        if let Some(message) = ClockMessage::parse(bytes) {
+            return match &self.clock {
+                Some(clock) => {
+                    clock.send(ClockEvent { micros, message })?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            };
+        }
+        let Some(message) = MidiMessage::parse(bytes) else {
+            return Ok(false);
+        };
+        if self
+            .channel
+            .is_some_and(|channel| channel != message.channel())
+        {
+            return Ok(false);
+        }
+        match message.to_event() {
+            Some(event) => {
+                self.tx.send(event)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Open connection to a MIDI input, messages are routed while this is alive.
+pub struct MidiInputPort {
+    _connection: MidiInputConnection<()>,
+    pub name: String,
+}
+
+impl MidiInputPort {
+    /// Names of the MIDI inputs currently available.
+    pub fn available() -> Result<Vec<String>> {
+        let input = MidiInput::new(CLIENT_NAME)?;
+        Ok(input
+            .ports()
+            .iter()
+            .filter_map(|port| input.port_name(port).ok())
+            .collect())
+    }
+
+    /// Connects to the `index`th available input.
+    pub fn open(index: usize, router: MidiRouter) -> Result<Self> {
+        let input = MidiInput::new(CLIENT_NAME)?;
+        let ports = input.ports();
+        let port = ports
+            .get(index)
+            .ok_or(anyhow!("No MIDI input found at index {index}"))?;
+        let name = input.port_name(port)?;
+        let connection = input
+            .connect(
+                port,
+                CLIENT_NAME,
+                move |micros, bytes, _| {
+                    let _ = router.handle_at(micros, bytes);
+                },
+                (),
+            )
+            .map_err(|err| anyhow!("Couldn't connect to MIDI input {name}: {err}"))?;
+        Ok(Self {
+            _connection: connection,
+            name,
+        })
+    }
+
+    /// Creates a virtual input other applications can connect to.
+    #[cfg(unix)]
+    pub fn open_virtual(name: &str, router: MidiRouter) -> Result<Self> {
+        use midir::os::unix::VirtualInput;
+
+        let input = MidiInput::new(CLIENT_NAME)?;
+        let connection = input
+            .create_virtual(
+                name,
+                move |micros, bytes, _| {
+                    let _ = router.handle_at(micros, bytes);
+                },
+                (),
+            )
+            .map_err(|err| Error::msg(err.to_string()))?;
+        Ok(Self {
+            _connection: connection,
+            name: name.to_string(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open_virtual(_name: &str, _router: MidiRouter) -> Result<Self> {
+        Err(anyhow!(
+            "Virtual MIDI ports are not supported on this platform"
+        ))
+    }
+}
+
+/// Open connection to a MIDI output.
+pub struct MidiOutputPort {
+    connection: MidiOutputConnection,
+    pub name: String,
+}
+
+impl MidiOutputPort {
+    /// Names of the MIDI outputs currently available.
+    pub fn available() -> Result<Vec<String>> {
+        let output = MidiOutput::new(CLIENT_NAME)?;
+        Ok(output
+            .ports()
+            .iter()
+            .filter_map(|port| output.port_name(port).ok())
+            .collect())
+    }
+
+    /// Connects to the `index`th available output.
+    pub fn open(index: usize) -> Result<Self> {
+        let output = MidiOutput::new(CLIENT_NAME)?;
+        let ports = output.ports();
+        let port = ports
+            .get(index)
+            .ok_or(anyhow!("No MIDI output found at index {index}"))?;
+        let name = output.port_name(port)?;
+        let connection = output
+            .connect(port, CLIENT_NAME)
+            .map_err(|err| anyhow!("Couldn't connect to MIDI output {name}: {err}"))?;
+        Ok(Self { connection, name })
+    }
+
+    /// Creates a virtual output other applications can connect to.
+    #[cfg(unix)]
+    pub fn open_virtual(name: &str) -> Result<Self> {
+        use midir::os::unix::VirtualOutput;
+
+        let output = MidiOutput::new(CLIENT_NAME)?;
+        let connection = output
+            .create_virtual(name)
+            .map_err(|err| Error::msg(err.to_string()))?;
+        Ok(Self {
+            connection,
+            name: name.to_string(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open_virtual(_name: &str) -> Result<Self> {
+        Err(anyhow!(
+            "Virtual MIDI ports are not supported on this platform"
+        ))
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.connection.send(bytes).map_err(Error::from)
+    }
+
+    /// Sends the clock messages received on `rx` from a new thread.
+    pub fn forward_clock(mut self, rx: Receiver<ClockMessage>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            for message in rx {
+                let (bytes, len) = message.bytes();
+                if self.send(&bytes[..len]).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        processor::AudioProcessor,
+        waves::{Oscilator, Wave, Waveform},
+    };
+
+    #[test]
+    fn parses_channel_messages() {
+        assert_eq!(
+            MidiMessage::parse(&[0x93, 60, 100]),
+            Some(MidiMessage::NoteOn {
+                channel: 3,
+                key: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xE0, 0x00, 0x40]),
+            Some(MidiMessage::PitchBend {
+                channel: 0,
+                value: 8192
+            })
+        );
+        assert_eq!(MidiMessage::parse(&[0x90, 60]), None);
+        assert_eq!(MidiMessage::parse(&[0xC0, 5]), None);
+    }
+
+    #[test]
+    fn converts_to_events() {
+        let event = |bytes: &[u8]| MidiMessage::parse(bytes).and_then(|m| m.to_event());
+        assert_eq!(
+            event(&[0x90, 69, 127]),
+            Some(SynthEvent::NoteOn {
+                note: Note::A4,
+                velocity: 1.0
+            })
+        );
+        // Note on with no velocity is a note off.
+        assert_eq!(
+            event(&[0x90, 69, 0]),
+            Some(SynthEvent::NoteOff { note: Note::A4 })
+        );
+        assert_eq!(event(&[0xE0, 0x00, 0x40]), Some(SynthEvent::PitchBend(0.0)));
+        assert_eq!(event(&[0xB0, 64, 127]), Some(SynthEvent::Sustain(true)));
+        assert_eq!(event(&[0xB0, 7, 127]), None);
+    }
+
+    #[test]
+    fn router_filters_channels() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let router = MidiRouter::new(tx).channel(1);
+        assert!(!router.handle(&[0x90, 60, 100]).unwrap());
+        assert!(router.handle(&[0x91, 60, 100]).unwrap());
+        assert!(!router.handle(&[0xF8]).unwrap());
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    fn peak(oscilator: &mut Oscilator) -> f32 {
+        let mut block = [0.0; 512];
+        oscilator.process(&mut block);
+        block
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn plays_notes_from_midi_bytes() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let router = MidiRouter::new(tx);
+        let mut oscilator = Oscilator::new(48000, Wave::new(Waveform::Sin, Note::A4, 1.0));
+        oscilator.add_event_receiver(rx);
+
+        router.handle(&[0x90, 60, 127]).unwrap();
+        assert!(peak(&mut oscilator) > 0.5);
+        assert_eq!(oscilator.note(), Note::C4);
+        router.handle(&[0x80, 60, 0]).unwrap();
+        peak(&mut oscilator);
+        assert_eq!(peak(&mut oscilator), 0.0);
+    }
+
+    #[test]
+    fn keyboard_plays_again_after_a_midi_note_off() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let (waves, received) = crossbeam_channel::unbounded();
+        let router = MidiRouter::new(tx);
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        let mut oscilator = Oscilator::new(48000, wave.clone());
+        oscilator.add_event_receiver(rx);
+        oscilator.add_receiver(received);
+
+        router.handle(&[0x90, 60, 127]).unwrap();
+        router.handle(&[0x80, 60, 0]).unwrap();
+        peak(&mut oscilator);
+        assert_eq!(peak(&mut oscilator), 0.0);
+
+        wave.note = Note::B4;
+        waves.send(wave.clone()).unwrap();
+        oscilator.start_block();
+        assert!(peak(&mut oscilator) > 0.5);
+    }
+
+    #[test]
+    fn parameters_play_again_after_a_midi_note_off() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let router = MidiRouter::new(tx);
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        let params = crate::params::ParamStore::from_wave(&wave);
+        let mut oscilator = Oscilator::new(48000, wave.clone());
+        oscilator.add_event_receiver(rx);
+        oscilator.add_params(params.clone());
+        oscilator.start_block();
+
+        router.handle(&[0x90, 60, 127]).unwrap();
+        router.handle(&[0x80, 60, 0]).unwrap();
+        peak(&mut oscilator);
+        assert_eq!(peak(&mut oscilator), 0.0);
+
+        // Only the note brings the sound back, not the other parameters.
+        wave.waveform = Waveform::Saw;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        assert_eq!(peak(&mut oscilator), 0.0);
+        wave.note = Note::E4;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        assert!(peak(&mut oscilator) > 0.5);
+    }
+}
@@ -0,0 +1,475 @@
+use std::{collections::VecDeque, thread::JoinHandle, time::Duration};
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{
+    events::{EventSource, SynthEvent},
+    transport::{Position, TransportHandle},
+};
+
+/// MIDI clock ticks per quarter note.
+pub const PPQN: u32 = 24;
+/// Song position pointer counts in MIDI beats, sixteenth notes.
+const SONG_POSITION_PER_BEAT: f64 = 4.0;
+
+const CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const SONG_POSITION: u8 = 0xF2;
+
+/// Weight of each new measurement in the tempo estimate, lower is smoother.
+const SMOOTHING: f64 = 0.05;
+/// Tick intervals are measured over this many ticks, so the jitter of a
+/// single tick is spread over all of them.
+const WINDOW_TICKS: usize = PPQN as usize;
+/// Intervals further than this ratio from the estimate are treated as dropouts.
+const MAX_INTERVAL_RATIO: f64 = 2.0;
+/// Tempo is nudged by this fraction per beat of phase error.
+const PHASE_GAIN: f64 = 0.1;
+/// Largest tempo nudge used to catch up with the clock.
+const MAX_PHASE_CORRECTION: f64 = 0.02;
+/// Phase errors past this many beats jump straight to the clock position.
+const RESYNC_BEATS: f64 = 0.25;
+/// Messages a bounded channel for [`ClockGenerator`] should hold, a few
+/// beats worth of ticks.
+pub const CLOCK_QUEUE: usize = 4 * PPQN as usize;
+
+/// System real-time and common messages used to sync to a clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockMessage {
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    /// Position in sixteenth notes from the start of the song.
+    SongPosition(u16),
+}
+
+impl ClockMessage {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        match *bytes {
+            [CLOCK, ..] => Some(Self::Clock),
+            [START, ..] => Some(Self::Start),
+            [CONTINUE, ..] => Some(Self::Continue),
+            [STOP, ..] => Some(Self::Stop),
+            [SONG_POSITION, lsb, msb, ..] => Some(Self::SongPosition(
+                (msb as u16 & 0x7F) << 7 | lsb as u16 & 0x7F,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Raw message, only the first `len` bytes are used.
+    pub fn bytes(&self) -> ([u8; 3], usize) {
+        match *self {
+            Self::Clock => ([CLOCK, 0, 0], 1),
+            Self::Start => ([START, 0, 0], 1),
+            Self::Continue => ([CONTINUE, 0, 0], 1),
+            Self::Stop => ([STOP, 0, 0], 1),
+            Self::SongPosition(position) => (
+                [
+                    SONG_POSITION,
+                    (position & 0x7F) as u8,
+                    (position >> 7 & 0x7F) as u8,
+                ],
+                3,
+            ),
+        }
+    }
+}
+
+/// Clock message with the time it arrived, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockEvent {
+    pub micros: u64,
+    pub message: ClockMessage,
+}
+
+/// Slaves a transport to an incoming MIDI clock. The transport runs ahead of
+/// the clock by the output latency, so what is heard lines up with it.
+#[derive(Debug)]
+pub struct ClockFollower {
+    transport: TransportHandle,
+    /// Smoothed time between ticks in seconds, `None` until two ticks arrived.
+    interval: Option<f64>,
+    /// Arrival times of the last ticks, oldest first.
+    ticks_micros: VecDeque<u64>,
+    /// Beat of the last start or song position pointer.
+    start_beat: f64,
+    ticks: u64,
+    running: bool,
+}
+
+impl ClockFollower {
+    pub fn new(transport: TransportHandle) -> Self {
+        Self {
+            transport,
+            interval: None,
+            ticks_micros: VecDeque::with_capacity(WINDOW_TICKS + 1),
+            start_beat: 0.0,
+            ticks: 0,
+            running: false,
+        }
+    }
+
+    /// Tempo of the incoming clock, `None` until it has been measured.
+    pub fn bpm(&self) -> Option<f64> {
+        self.interval
+            .map(|interval| 60.0 / (interval * PPQN as f64))
+    }
+
+    /// Beat the clock is at.
+    pub fn beat(&self) -> f64 {
+        self.start_beat + self.ticks as f64 / PPQN as f64
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Beats the transport is ahead of the clock, played during the output latency.
+    fn lead(&self) -> f64 {
+        self.bpm().map_or(0.0, |bpm| {
+            self.transport.latency().as_secs_f64() * bpm / 60.0
+        })
+    }
+
+    pub fn handle(&mut self, event: ClockEvent) -> Result<()> {
+        match event.message {
+            ClockMessage::Start => {
+                self.start_beat = 0.0;
+                self.ticks = 0;
+                self.running = true;
+                self.transport.locate(self.lead())?;
+                self.transport.play()?;
+            }
+            ClockMessage::Continue => {
+                self.running = true;
+                self.transport.play()?;
+            }
+            ClockMessage::Stop => {
+                self.running = false;
+                self.transport.stop()?;
+                // The tempo map is back in charge until the clock starts again.
+                self.transport.follow_bpm(None)?;
+            }
+            ClockMessage::SongPosition(position) => {
+                self.start_beat = position as f64 / SONG_POSITION_PER_BEAT;
+                self.ticks = 0;
+                self.transport.locate(self.start_beat + self.lead())?;
+            }
+            ClockMessage::Clock => self.tick(event.micros)?,
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, micros: u64) -> Result<()> {
+        if let Some(&last) = self.ticks_micros.back() {
+            let measured = micros.saturating_sub(last) as f64 / 1e6;
+            // Ticks going back in time are treated like a dropout too.
+            let dropout = micros < last
+                || self.interval.is_some_and(|interval| {
+                    measured > interval * MAX_INTERVAL_RATIO
+                        || measured < interval / MAX_INTERVAL_RATIO
+                });
+            if dropout {
+                // Start measuring again instead of averaging over the gap.
+                self.ticks_micros.clear();
+            }
+        }
+        if self.ticks_micros.len() > WINDOW_TICKS {
+            self.ticks_micros.pop_front();
+        }
+        self.ticks_micros.push_back(micros);
+        if let (Some(&first), true) = (self.ticks_micros.front(), self.ticks_micros.len() > 1) {
+            let measured =
+                micros.saturating_sub(first) as f64 / 1e6 / (self.ticks_micros.len() - 1) as f64;
+            self.interval = Some(match self.interval {
+                Some(interval) => interval + (measured - interval) * SMOOTHING,
+                None => measured,
+            });
+        }
+        if !self.running {
+            return Ok(());
+        }
+        self.ticks += 1;
+        if !self.ticks.is_multiple_of(PPQN as u64) {
+            return Ok(());
+        }
+
+        // Once per beat, follow the tempo and pull the transport towards the clock.
+        let Some(bpm) = self.bpm() else {
+            return Ok(());
+        };
+        let target = self.beat() + self.lead();
+        let error = target - self.transport.position().beat;
+        if error.abs() > RESYNC_BEATS {
+            self.transport.locate(target)?;
+            return self.transport.follow_bpm(Some(bpm));
+        }
+        let correction = (error * PHASE_GAIN).clamp(-MAX_PHASE_CORRECTION, MAX_PHASE_CORRECTION);
+        self.transport.follow_bpm(Some(bpm * (1.0 + correction)))
+    }
+
+    /// Follows the clock events received on `rx` from a new thread.
+    pub fn spawn(mut self, rx: Receiver<ClockEvent>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            for event in rx {
+                if self.handle(event).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// Sends MIDI clock following the transport, making it the clock master.
+/// Ticks are only sent while the transport plays, delayed by the output
+/// latency so they line up with what is heard. Give it a bounded channel
+/// of [`CLOCK_QUEUE`] messages, so sending from the audio thread never
+/// blocks or allocates.
+#[derive(Debug)]
+pub struct ClockGenerator {
+    tx: Sender<ClockMessage>,
+    position: Position,
+    playing: bool,
+    /// Beat the transport started playing from, until it is heard.
+    start: Option<f64>,
+    tick: Option<u64>,
+}
+
+impl ClockGenerator {
+    pub fn new(tx: Sender<ClockMessage>) -> Self {
+        Self {
+            tx,
+            position: Position::default(),
+            playing: false,
+            start: None,
+            tick: None,
+        }
+    }
+
+    fn send(&self, message: ClockMessage) {
+        // Nobody listening isn't an error for a clock master, and a full
+        // queue drops the message rather than holding up the audio.
+        let _ = self.tx.try_send(message);
+    }
+}
+
+impl EventSource for ClockGenerator {
+    fn sync(&mut self, position: &Position) {
+        self.position = *position;
+    }
+
+    fn poll(&mut self, _emit: &mut dyn FnMut(SynthEvent)) {
+        let position = self.position;
+        if position.playing && !self.playing {
+            self.start = Some(position.beat);
+            self.tick = None;
+        } else if !position.playing && self.playing && self.start.take().is_none() {
+            self.send(ClockMessage::Stop);
+        }
+        self.playing = position.playing;
+        if !position.playing {
+            return;
+        }
+
+        let heard = position.beat - position.latency_beats();
+        if let Some(start) = self.start {
+            if heard < start {
+                return;
+            }
+            self.start = None;
+            if start == 0.0 {
+                self.send(ClockMessage::Start);
+            } else {
+                let sixteenths = (start * SONG_POSITION_PER_BEAT) as u16;
+                self.send(ClockMessage::SongPosition(sixteenths));
+                self.send(ClockMessage::Continue);
+            }
+        }
+        let tick = (heard * PPQN as f64) as u64;
+        if self.tick != Some(tick) {
+            self.tick = Some(tick);
+            self.send(ClockMessage::Clock);
+        }
+    }
+}
+
+/// Clock source with random timing errors, standing in for a drum machine
+/// when trying out clock sync.
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    pub bpm: f64,
+    /// Largest timing error of a tick, either way.
+    pub jitter: Duration,
+    micros: f64,
+    rng: u32,
+}
+
+impl SimulatedClock {
+    pub fn new(bpm: f64, jitter: Duration) -> Self {
+        Self {
+            bpm,
+            jitter,
+            micros: 0.0,
+            rng: 0x1234_5678,
+        }
+    }
+
+    fn random(&mut self) -> f64 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f64 / u32::MAX as f64
+    }
+
+    /// Start message at the current time.
+    pub fn start(&self) -> ClockEvent {
+        ClockEvent {
+            micros: self.micros as u64,
+            message: ClockMessage::Start,
+        }
+    }
+
+    /// Next tick, late or early by up to the jitter.
+    pub fn tick(&mut self) -> ClockEvent {
+        self.micros += 60e6 / (self.bpm * PPQN as f64);
+        let jitter = (self.random() * 2.0 - 1.0) * self.jitter.as_micros() as f64;
+        ClockEvent {
+            micros: (self.micros + jitter).max(0.0) as u64,
+            message: ClockMessage::Clock,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{TempoMap, Transport};
+
+    const LATENCY: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn follower_leads_the_clock_by_the_latency() {
+        let mut transport = Transport::new();
+        transport.update(Some(LATENCY));
+        let mut follower = ClockFollower::new(transport.handle());
+        let mut clock = SimulatedClock::new(120.0, Duration::ZERO);
+        follower.handle(clock.start()).unwrap();
+        for _ in 0..PPQN {
+            follower.handle(clock.tick()).unwrap();
+        }
+        transport.update(Some(LATENCY));
+        // A beat in at 120 BPM, plus the 0.2 beats heard after the latency.
+        let position = transport.position();
+        assert!(position.playing);
+        assert!((position.beat - 1.2).abs() < 1e-4, "{}", position.beat);
+    }
+
+    #[test]
+    fn generator_waits_for_the_start_to_be_heard() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut generator = ClockGenerator::new(tx);
+        let mut poll = |beat: f64| {
+            generator.sync(&Position {
+                playing: true,
+                beat,
+                bpm: 120.0,
+                latency: LATENCY,
+                ..Default::default()
+            });
+            generator.poll(&mut |_| ());
+            rx.try_iter().collect::<Vec<_>>()
+        };
+        assert_eq!(poll(0.0), []);
+        assert_eq!(poll(0.1), []);
+        assert_eq!(poll(0.2), [ClockMessage::Start, ClockMessage::Clock]);
+        assert_eq!(poll(0.21), []);
+        assert_eq!(poll(0.3), [ClockMessage::Clock]);
+    }
+
+    #[test]
+    fn ticks_going_back_in_time_restart_the_measurement() {
+        let mut follower = ClockFollower::new(Transport::new().handle());
+        for micros in [50_000, 10_000, 30_000, 50_000] {
+            follower
+                .handle(ClockEvent {
+                    micros,
+                    message: ClockMessage::Clock,
+                })
+                .unwrap();
+        }
+        let interval = 60.0 / (follower.bpm().unwrap() * PPQN as f64);
+        assert!((interval - 0.02).abs() < 1e-9, "{interval}");
+    }
+
+    /// Runs a transport at 48 kHz in blocks of 256 frames, following
+    /// `clock` for `beats`.
+    fn follow(transport: &mut Transport, clock: &mut SimulatedClock, beats: u32) -> ClockFollower {
+        const SAMPLE_RATE: u64 = 48000;
+        transport.prepare(SAMPLE_RATE as f32);
+        let mut follower = ClockFollower::new(transport.handle());
+        follower.handle(clock.start()).unwrap();
+        let mut frame = 0;
+        for _ in 0..beats * PPQN {
+            let event = clock.tick();
+            while frame < event.micros * SAMPLE_RATE / 1_000_000 {
+                transport.update(None);
+                transport.advance(256);
+                frame += 256;
+            }
+            transport.update(None);
+            follower.handle(event).unwrap();
+        }
+        transport.update(None);
+        follower
+    }
+
+    #[test]
+    fn follower_converges_despite_jitter() {
+        let tempo_map = TempoMap::new(100.0).with_change(8.0, 90.0);
+        let mut transport = Transport::new().tempo_map(tempo_map.clone());
+        let mut clock = SimulatedClock::new(128.0, Duration::from_millis(2));
+        let follower = follow(&mut transport, &mut clock, 64);
+
+        let bpm = follower.bpm().unwrap();
+        assert!((bpm - 128.0).abs() < 0.5, "{bpm}");
+        let position = transport.position();
+        assert!((position.bpm - 128.0).abs() < 2.0, "{}", position.bpm);
+        let behind = follower.beat() - position.beat;
+        assert!(behind.abs() < 0.05, "{behind}");
+
+        // Stopping the clock hands the tempo back to the tempo map.
+        let mut follower = follower;
+        follower
+            .handle(ClockEvent {
+                micros: 0,
+                message: ClockMessage::Stop,
+            })
+            .unwrap();
+        transport.update(None);
+        let position = transport.position();
+        assert_eq!(position.bpm, tempo_map.bpm_at(position.beat));
+        assert_eq!(position.bpm, 90.0);
+    }
+
+    #[test]
+    fn generator_drops_clock_when_the_queue_is_full() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let mut generator = ClockGenerator::new(tx);
+        for tick in 0..4 * PPQN {
+            generator.sync(&Position {
+                playing: true,
+                beat: tick as f64 / PPQN as f64,
+                bpm: 120.0,
+                ..Default::default()
+            });
+            generator.poll(&mut |_| ());
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [ClockMessage::Start]);
+    }
+}
@@ -0,0 +1,291 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use midly::{Format, MetaMessage, Smf, Timing, TrackEventKind};
+
+use crate::{
+    events::{EventSource, SynthEvent},
+    midi::MidiMessage,
+};
+
+/// Tempo assumed until the first tempo change, 120 BPM.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TimedMessage {
+    seconds: f64,
+    message: MidiMessage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScheduledEvent {
+    /// Frame, counted from the start of the file, on which the event is due.
+    pub frame: u64,
+    pub event: SynthEvent,
+}
+
+/// Events of a Standard MIDI File with their times already resolved through the tempo map.
+#[derive(Debug, Clone, Default)]
+pub struct MidiFile {
+    messages: Vec<TimedMessage>,
+    duration: f64,
+}
+
+impl MidiFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::parse(&bytes)
+    }
+
+    /// Parses type 0 and 1 files, every track is merged into a single timeline.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let smf = Smf::parse(bytes)?;
+        if smf.header.format == Format::Sequential {
+            return Err(anyhow!("Sequential (type 2) MIDI files are not supported"));
+        }
+
+        // (tick, track, event) so simultaneous events keep the track order.
+        let mut events = smf
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(track, events)| {
+                events.iter().scan(0u64, move |tick, event| {
+                    *tick += event.delta.as_int() as u64;
+                    Some((*tick, track, event.kind))
+                })
+            })
+            .collect::<Vec<_>>();
+        events.sort_by_key(|(tick, track, _)| (*tick, *track));
+
+        let mut tempo = DEFAULT_TEMPO;
+        let mut last_tick = 0;
+        let mut seconds = 0.0;
+        let mut messages = Vec::new();
+        for (tick, _, kind) in events {
+            seconds += (tick - last_tick) as f64 * tick_length(smf.header.timing, tempo);
+            last_tick = tick;
+            match kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(new_tempo)) => tempo = new_tempo.as_int(),
+                TrackEventKind::Midi { channel, message } => {
+                    if let Some(message) = MidiMessage::from_midly(channel.as_int(), message) {
+                        messages.push(TimedMessage { seconds, message });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Self {
+            messages,
+            duration: seconds,
+        })
+    }
+
+    /// Length in seconds, up to the last event of any track.
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Synth events sorted by the frame they are due on.
+    pub fn schedule(&self, sample_rate: f32) -> Vec<ScheduledEvent> {
+        self.messages
+            .iter()
+            .filter_map(|timed| {
+                Some(ScheduledEvent {
+                    frame: (timed.seconds * sample_rate as f64).round() as u64,
+                    event: timed.message.to_event()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Seconds per tick for the given tempo in microseconds per beat.
+fn tick_length(timing: Timing, tempo: u32) -> f64 {
+    match timing {
+        Timing::Metrical(ticks_per_beat) => {
+            tempo as f64 * 1e-6 / ticks_per_beat.as_int().max(1) as f64
+        }
+        Timing::Timecode(fps, subframes) => 1.0 / (fps.as_f32() as f64 * subframes.max(1) as f64),
+    }
+}
+
+/// Plays a [`MidiFile`] from the audio thread, one frame at a time.
+pub struct SmfPlayer {
+    file: MidiFile,
+    schedule: Vec<ScheduledEvent>,
+    next: usize,
+    frame: u64,
+    sample_rate: f32,
+    looping: bool,
+}
+
+impl SmfPlayer {
+    pub fn new(file: MidiFile) -> Self {
+        let mut player = Self {
+            file,
+            schedule: Vec::new(),
+            next: 0,
+            frame: 0,
+            sample_rate: 44100.0,
+            looping: false,
+        };
+        player.prepare(player.sample_rate);
+        player
+    }
+
+    /// Starts over from the beginning once the file ends.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn rewind(&mut self) {
+        self.next = 0;
+        self.frame = 0;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.next >= self.schedule.len()
+    }
+
+    /// Number of frames needed to play the whole file.
+    pub fn length(&self) -> u64 {
+        (self.file.duration() * self.sample_rate as f64).ceil() as u64
+    }
+
+    /// Current position in seconds.
+    pub fn position(&self) -> f64 {
+        self.frame as f64 / self.sample_rate as f64
+    }
+}
+
+impl EventSource for SmfPlayer {
+    fn prepare(&mut self, sample_rate: f32) {
+        let position = self.position();
+        self.sample_rate = sample_rate;
+        self.schedule = self.file.schedule(sample_rate);
+        self.frame = (position * sample_rate as f64) as u64;
+        self.next = self
+            .schedule
+            .partition_point(|scheduled| scheduled.frame < self.frame);
+    }
+
+    fn poll(&mut self, emit: &mut dyn FnMut(SynthEvent)) {
+        while let Some(scheduled) = self.schedule.get(self.next) {
+            if scheduled.frame > self.frame {
+                break;
+            }
+            emit(scheduled.event);
+            self.next += 1;
+        }
+        self.frame += 1;
+        if self.looping && self.frame >= self.length().max(1) {
+            self.rewind();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::Engine,
+        notes::Note,
+        waves::{Wave, Waveform},
+    };
+
+    /// Standard MIDI File with 96 ticks per beat and one chunk per track.
+    fn smf(format: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = b"MThd\0\0\0\x06".to_vec();
+        bytes.extend(format.to_be_bytes());
+        bytes.extend((tracks.len() as u16).to_be_bytes());
+        bytes.extend(96u16.to_be_bytes());
+        for track in tracks {
+            bytes.extend(b"MTrk");
+            bytes.extend((track.len() as u32 + 4).to_be_bytes());
+            bytes.extend(*track);
+            bytes.extend([0x00, 0xFF, 0x2F, 0x00]);
+        }
+        bytes
+    }
+
+    /// A4 for a beat, then a tempo change to 60 BPM and C4 for a beat.
+    const MELODY: &[u8] = &[
+        0x00, 0x90, 69, 100, // note on A4
+        0x60, 0x80, 69, 0, // a beat later, note off
+        0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, // 1 000 000 µs per beat
+        0x00, 0x90, 60, 100, // note on C4
+        0x60, 0x90, 60, 0, // a beat later, note on with no velocity
+    ];
+
+    #[test]
+    fn resolves_times_through_tempo_changes() {
+        let file = MidiFile::parse(&smf(0, &[MELODY])).unwrap();
+        assert!((file.duration() - 1.5).abs() < 1e-9);
+        let schedule = file.schedule(1000.0);
+        let frames: Vec<u64> = schedule.iter().map(|event| event.frame).collect();
+        assert_eq!(frames, [0, 500, 500, 1500]);
+        assert_eq!(schedule[3].event, SynthEvent::NoteOff { note: Note::C4 });
+    }
+
+    #[test]
+    fn merges_tracks_in_order() {
+        let tempo: &[u8] = &[0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40];
+        let notes: &[u8] = &[0x00, 0x91, 64, 127, 0x60, 0x81, 64, 0];
+        let file = MidiFile::parse(&smf(1, &[tempo, notes])).unwrap();
+        let schedule = file.schedule(1000.0);
+        assert_eq!(schedule.len(), 2);
+        // The tempo of the first track applies to the second.
+        assert_eq!(schedule[1].frame, 1000);
+    }
+
+    #[test]
+    fn rejects_sequential_and_broken_files() {
+        assert!(MidiFile::parse(&smf(2, &[MELODY])).is_err());
+        assert!(MidiFile::parse(b"MThd").is_err());
+    }
+
+    #[test]
+    fn player_emits_events_on_their_frame() {
+        let file = MidiFile::parse(&smf(0, &[MELODY])).unwrap();
+        let mut player = SmfPlayer::new(file);
+        player.prepare(1000.0);
+        let mut emitted = Vec::new();
+        for frame in 0..2000 {
+            player.poll(&mut |event| emitted.push((frame, event)));
+        }
+        let frames: Vec<u64> = emitted.iter().map(|(frame, _)| *frame).collect();
+        assert_eq!(frames, [0, 500, 500, 1500]);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn plays_through_the_waveform_of_the_engine() {
+        let file = MidiFile::parse(&smf(0, &[MELODY])).unwrap();
+        let mut engine = Engine::new(1000, Wave::new(Waveform::Square, Note::A4, 0.5))
+            .source(SmfPlayer::new(file))
+            .silent();
+        let samples = engine.render(2000);
+        let peak = |range: std::ops::Range<usize>| {
+            samples[range]
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        };
+        assert!(peak(10..490) > 0.3);
+        assert!(peak(600..1490) > 0.3);
+        assert_eq!(peak(1600..2000), 0.0);
+    }
+
+    #[test]
+    fn silent_engine_waits_for_the_first_note() {
+        let file = MidiFile::parse(&smf(0, &[&[0x60, 0x90, 69, 100]])).unwrap();
+        let mut engine = Engine::new(1000, Wave::new(Waveform::Square, Note::A4, 0.5))
+            .source(SmfPlayer::new(file))
+            .silent();
+        let samples = engine.render(1000);
+        assert!(samples[..500].iter().all(|sample| *sample == 0.0));
+        assert!(samples[600..].iter().any(|sample| sample.abs() > 0.3));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::notes::Note;
+
+/// Most notes remembered while held, the oldest one is forgotten past it.
+const MAX_HELD_NOTES: usize = 16;
+
+/// Which of the held notes sounds in mono mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotePriority {
+    /// The most recently pressed note.
+    #[default]
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub const ALL: [NotePriority; 3] = [NotePriority::Last, NotePriority::Low, NotePriority::High];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonoSettings {
+    /// Off keeps the single note behavior, where the last note on always
+    /// sounds and releasing it stops the oscilator.
+    pub enabled: bool,
+    pub priority: NotePriority,
+    /// Changing notes while one is held keeps the phase and level of the
+    /// first note, otherwise each new note restarts from its own velocity.
+    pub legato: bool,
+}
+
+impl Default for MonoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            priority: NotePriority::default(),
+            legato: true,
+        }
+    }
+}
+
+impl MonoSettings {
+    pub fn new(priority: NotePriority) -> Self {
+        Self {
+            enabled: true,
+            priority,
+            ..Default::default()
+        }
+    }
+}
+
+/// Notes held down, in the order they were pressed, with their velocity.
+#[derive(Debug, Clone)]
+pub struct NoteStack {
+    notes: Vec<(Note, f32)>,
+}
+
+impl Default for NoteStack {
+    fn default() -> Self {
+        Self {
+            notes: Vec::with_capacity(MAX_HELD_NOTES),
+        }
+    }
+}
+
+impl NoteStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn press(&mut self, note: Note, velocity: f32) {
+        self.release(note);
+        if self.notes.len() == MAX_HELD_NOTES {
+            self.notes.remove(0);
+        }
+        self.notes.push((note, velocity));
+    }
+
+    pub fn release(&mut self, note: Note) {
+        self.notes.retain(|(held, _)| *held != note);
+    }
+
+    pub fn clear(&mut self) {
+        self.notes.clear();
+    }
+
+    /// Note that should sound with its velocity, `None` when nothing is held.
+    pub fn select(&self, priority: NotePriority) -> Option<(Note, f32)> {
+        let notes = self.notes.iter().copied();
+        match priority {
+            NotePriority::Last => notes.last(),
+            NotePriority::Low => notes.min_by_key(|(note, _)| *note),
+            NotePriority::High => notes.max_by_key(|(note, _)| *note),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(notes: &[Note]) -> NoteStack {
+        let mut stack = NoteStack::new();
+        for (index, note) in notes.iter().enumerate() {
+            stack.press(*note, index as f32 / 10.0);
+        }
+        stack
+    }
+
+    fn selected(stack: &NoteStack, priority: NotePriority) -> Option<Note> {
+        stack.select(priority).map(|(note, _)| note)
+    }
+
+    #[test]
+    fn picks_the_lowest_or_highest_note_whatever_the_order() {
+        let stack = held(&[Note::E4, Note::C4, Note::G4]);
+        assert_eq!(stack.select(NotePriority::Low), Some((Note::C4, 0.1)));
+        assert_eq!(stack.select(NotePriority::High), Some((Note::G4, 0.2)));
+        assert_eq!(stack.select(NotePriority::Last), Some((Note::G4, 0.2)));
+        assert_eq!(selected(&NoteStack::new(), NotePriority::Low), None);
+    }
+
+    #[test]
+    fn releasing_a_silent_note_keeps_the_sounding_one() {
+        for (priority, sounding) in [
+            (NotePriority::Last, Note::G4),
+            (NotePriority::Low, Note::C4),
+            (NotePriority::High, Note::G4),
+        ] {
+            let mut stack = held(&[Note::C4, Note::E4, Note::G4]);
+            stack.release(Note::E4);
+            stack.release(Note::A4);
+            assert_eq!(selected(&stack, priority), Some(sounding), "{priority:?}");
+            assert_eq!(stack.len(), 2);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_previous_held_note() {
+        let mut stack = held(&[Note::C4, Note::G4, Note::E4]);
+        stack.release(Note::E4);
+        assert_eq!(selected(&stack, NotePriority::Last), Some(Note::G4));
+        stack.release(Note::G4);
+        assert_eq!(selected(&stack, NotePriority::Last), Some(Note::C4));
+        stack.release(Note::C4);
+        assert!(stack.is_empty());
+
+        // Pressing a held note again makes it the last one, held once.
+        let mut stack = held(&[Note::C4, Note::E4]);
+        stack.press(Note::C4, 1.0);
+        assert_eq!(stack.select(NotePriority::Last), Some((Note::C4, 1.0)));
+        stack.release(Note::C4);
+        assert_eq!(selected(&stack, NotePriority::Last), Some(Note::E4));
+    }
+}
@@ -1,16 +1,43 @@
-use std::mem::transmute;
+use std::{
+    fmt::{self, Display},
+    mem::transmute,
+    ops::{Add, Sub},
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Error};
 
+#[allow(clippy::excessive_precision)]
 const C0: f32 = 16.35160;
 
-fn note_frequency(note: u32) -> f32 {
+pub(crate) const SHARP_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+const FLAT_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B",
+];
+
+fn note_frequency(note: i32) -> f32 {
     C0 * f32::powf(2.0, note as f32 / 12.0)
 }
 
-#[repr(u32)]
-#[derive(Debug, Clone, Copy)]
+/// Notes from C-1 to G9, the range covered by MIDI note numbers. Octave -1
+/// is spelled with an `m`, so `Dm1b` is D♭-1.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Note {
+    Cm1 = -12,
+    Dm1b,
+    Dm1,
+    Em1b,
+    Em1,
+    Fm1,
+    Gm1b,
+    Gm1,
+    Am1b,
+    Am1,
+    Bm1b,
+    Bm1,
     C0,
     D0b,
     D0,
@@ -119,6 +146,14 @@ pub enum Note {
     A8,
     B8b,
     B8,
+    C9,
+    D9b,
+    D9,
+    E9b,
+    E9,
+    F9,
+    G9b,
+    G9,
 }
 
 impl TryFrom<u32> for Note {
@@ -127,7 +162,7 @@ impl TryFrom<u32> for Note {
         let min = Self::A0 as u32;
         let max = Self::B8 as u32;
         if (min..=max).contains(&value) {
-            let note = unsafe { transmute(value) };
+            let note = unsafe { transmute::<i32, Note>(value as i32) };
             Ok(note)
         } else {
             Err(anyhow!("Number not a valid note"))
@@ -135,18 +170,223 @@ impl TryFrom<u32> for Note {
     }
 }
 
+/// Which accidental to use when naming black keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Accidental {
+    #[default]
+    Sharp,
+    Flat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Unison,
+    MinorSecond,
+    MajorSecond,
+    MinorThird,
+    MajorThird,
+    PerfectFourth,
+    Tritone,
+    PerfectFifth,
+    MinorSixth,
+    MajorSixth,
+    MinorSeventh,
+    MajorSeventh,
+    Octave,
+}
+
+impl Interval {
+    pub fn semitones(&self) -> i32 {
+        *self as i32
+    }
+}
+
 impl Note {
+    /// MIDI note number of [`Note::C0`], MIDI starts counting from C-1.
+    pub const MIDI_OFFSET: u8 = 12;
+
+    /// Semitones from C0, `None` when it falls outside of C-1 to G9.
+    pub fn from_semitones(value: i32) -> Option<Self> {
+        if (Self::Cm1 as i32..=Self::G9 as i32).contains(&value) {
+            Some(unsafe { transmute::<i32, Note>(value) })
+        } else {
+            None
+        }
+    }
+
+    /// Converts a MIDI note number, `None` when it is over 127.
+    pub fn from_midi(key: u8) -> Option<Self> {
+        Self::from_semitones(key as i32 - Self::MIDI_OFFSET as i32)
+    }
+
+    pub fn midi(&self) -> u8 {
+        (*self as i32 + Self::MIDI_OFFSET as i32) as u8
+    }
+
+    /// Octave in scientific pitch notation, C4 being middle C.
+    pub fn octave(&self) -> i32 {
+        (*self as i32).div_euclid(12)
+    }
+
+    /// Position within the octave, 0 is C and 11 is B.
+    pub fn pitch_class(&self) -> usize {
+        (*self as i32).rem_euclid(12) as usize
+    }
+
+    /// Name such as `C#4` or `Db4`, depending on the accidental.
+    pub fn name(&self, accidental: Accidental) -> String {
+        let names = match accidental {
+            Accidental::Sharp => SHARP_NAMES,
+            Accidental::Flat => FLAT_NAMES,
+        };
+        format!("{}{}", names[self.pitch_class()], self.octave())
+    }
+
+    pub fn transpose(&self, semitones: i32) -> Option<Self> {
+        (*self as i32)
+            .checked_add(semitones)
+            .and_then(Self::from_semitones)
+    }
+
+    pub fn checked_add(&self, interval: Interval) -> Option<Self> {
+        self.transpose(interval.semitones())
+    }
+
+    pub fn checked_sub(&self, interval: Interval) -> Option<Self> {
+        self.transpose(-interval.semitones())
+    }
+
     pub fn freq(&self) -> f32 {
-        note_frequency(*self as u32)
+        note_frequency(*self as i32)
     }
     pub fn next_note(&self) -> Self {
-        let min = Self::A0 as u32;
-        let max = Self::B8 as u32;
-        (*self as u32 + 1).clamp(min, max).try_into().unwrap()
+        let min = Self::A0 as i32;
+        let max = Self::B8 as i32;
+        ((*self as i32 + 1).clamp(min, max) as u32)
+            .try_into()
+            .unwrap()
     }
     pub fn prev_note(&self) -> Self {
-        let min = Self::A0 as u32;
-        let max = Self::B8 as u32;
-        (*self as u32 - 1).clamp(min, max).try_into().unwrap()
+        let min = Self::A0 as i32;
+        let max = Self::B8 as i32;
+        ((*self as i32 - 1).clamp(min, max) as u32)
+            .try_into()
+            .unwrap()
+    }
+}
+
+/// Moves up by the interval, stopping at G9.
+impl Add<Interval> for Note {
+    type Output = Note;
+    fn add(self, interval: Interval) -> Self::Output {
+        self.checked_add(interval).unwrap_or(Note::G9)
+    }
+}
+
+/// Moves down by the interval, stopping at C-1.
+impl Sub<Interval> for Note {
+    type Output = Note;
+    fn sub(self, interval: Interval) -> Self::Output {
+        self.checked_sub(interval).unwrap_or(Note::Cm1)
+    }
+}
+
+/// Uses sharps, or flats with the alternate flag (`{:#}`).
+impl Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let accidental = if f.alternate() {
+            Accidental::Flat
+        } else {
+            Accidental::Sharp
+        };
+        f.pad(&self.name(accidental))
+    }
+}
+
+/// Parses names like `C#4`, `Db4`, `A-1` or `e♭3`. Any number of sharps or
+/// flats is accepted, as long as the result stays within C-1 to G9.
+impl FromStr for Note {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid note name {s:?}");
+        let mut chars = s.trim().chars().peekable();
+        let letter = chars.next().ok_or_else(invalid)?;
+        let pitch_class = match letter.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return Err(invalid()),
+        };
+        let mut accidentals = 0;
+        while let Some(accidental) = chars.peek() {
+            match accidental {
+                '#' | '♯' => accidentals += 1,
+                'b' | '♭' => accidentals -= 1,
+                _ => break,
+            }
+            chars.next();
+        }
+        let octave: i32 = chars.collect::<String>().parse().map_err(|_| invalid())?;
+        octave
+            .checked_mul(12)
+            .and_then(|semitones| semitones.checked_add(pitch_class + accidentals))
+            .and_then(Self::from_semitones)
+            .ok_or_else(|| anyhow!("Note {s:?} is outside of C-1 to G9"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("C#4".parse::<Note>().unwrap(), Note::D4b);
+        assert_eq!("Db4".parse::<Note>().unwrap(), Note::D4b);
+        assert_eq!("e♭3".parse::<Note>().unwrap(), Note::E3b);
+        assert_eq!("C-1".parse::<Note>().unwrap(), Note::Cm1);
+        assert_eq!("G9".parse::<Note>().unwrap(), Note::G9);
+        assert_eq!("B#3".parse::<Note>().unwrap(), Note::C4);
+    }
+
+    #[test]
+    fn rejects_names_out_of_range_without_overflowing() {
+        for name in ["G#9", "Cb-1", "C300000000", "C-300000000", "B2147483647"] {
+            let err = name.parse::<Note>().unwrap_err().to_string();
+            assert!(err.contains("outside of C-1 to G9"), "{name}: {err}");
+        }
+        assert!("H4".parse::<Note>().is_err());
+        assert!("C".parse::<Note>().is_err());
+        assert!("C99999999999".parse::<Note>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for key in 0..=127 {
+            let note = Note::from_midi(key).unwrap();
+            assert_eq!(note.to_string().parse::<Note>().unwrap(), note);
+            assert_eq!(format!("{note:#}").parse::<Note>().unwrap(), note);
+            assert_eq!(note.midi(), key);
+        }
+        assert_eq!(Note::from_midi(128), None);
+    }
+
+    #[test]
+    fn transposes_within_range() {
+        assert_eq!(Note::C4 + Interval::Octave, Note::C5);
+        assert_eq!(Note::G9 + Interval::MajorSecond, Note::G9);
+        assert_eq!(Note::Cm1 - Interval::Octave, Note::Cm1);
+        assert_eq!(Note::C4.transpose(i32::MAX), None);
+        assert_eq!(Note::C4.transpose(i32::MIN), None);
+    }
+
+    #[test]
+    fn a4_is_440_hz() {
+        assert!((Note::A4.freq() - 440.0).abs() < 0.01);
+        assert!((Note::C0.freq() - 16.3516).abs() < 1e-4);
     }
 }
@@ -0,0 +1,451 @@
+//! Remote control over OSC (Open Sound Control) on UDP, from tools such as
+//! SuperCollider, TouchOSC or Max. The synth understands:
+//!
+//! | Address | Arguments |
+//! |---|---|
+//! | `/synth/note_on` | note as a MIDI number or a name like `"A4"`, optional velocity from 0 to 1 |
+//! | `/synth/note_off` | note |
+//! | `/synth/pitch_bend` | bend from -1 to 1 |
+//! | `/synth/mod_wheel` | amount from 0 to 1 |
+//! | `/synth/sustain` | on or off |
+//! | `/synth/waveform` | waveform name or number, same as `/synth/param/waveform` |
+//! | `/synth/param/<key>` | value of the parameter, or the name of a choice |
+//! | `/synth/undo` | none, undoes the last parameter change |
+//! | `/synth/redo` | none, redoes the last change undone |
+//! | `/synth/subscribe` | none, parameter changes and notes are sent back to the sender |
+//! | `/synth/unsubscribe` | none |
+//!
+//! Parameter changes are sent as `/synth/param/<key>` messages and notes
+//! played over OSC as `/synth/note_on` and `/synth/note_off` with the MIDI
+//! number. Invalid messages are answered with `/synth/error` and the reason.
+
+pub mod packet;
+
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Sender;
+
+use crate::{
+    events::SynthEvent,
+    history::History,
+    notes::Note,
+    osc::packet::{OscArg, OscMessage, OscPacket},
+    params::{ParamId, ParamStore},
+};
+
+/// Port the synth listens on by default.
+pub const OSC_PORT: u16 = 9000;
+/// How often parameter changes are sent out.
+const OUTPUT_INTERVAL: Duration = Duration::from_millis(20);
+/// Largest UDP payload.
+const MAX_PACKET_SIZE: usize = 65_507;
+/// Addresses changes are sent to at most, later subscriptions are refused.
+pub const MAX_TARGETS: usize = 16;
+
+const PREFIX: &str = "/synth";
+const PARAM_PREFIX: &str = "/synth/param/";
+const ERROR_ADDRESS: &str = "/synth/error";
+
+/// Turns OSC messages into synth events and parameter changes.
+#[derive(Debug, Clone)]
+pub struct OscRouter {
+    params: ParamStore,
+    tx: Sender<SynthEvent>,
+    /// Parameter changes, undone by `/synth/undo`.
+    history: Arc<Mutex<History>>,
+}
+
+impl OscRouter {
+    pub fn new(params: ParamStore, tx: Sender<SynthEvent>) -> Self {
+        Self {
+            params,
+            tx,
+            history: Arc::default(),
+        }
+    }
+
+    /// Records the parameter changes in `history`, shared with other remotes
+    /// so each undoes the changes of the others too.
+    pub fn history(mut self, history: Arc<Mutex<History>>) -> Self {
+        self.history = history;
+        self
+    }
+
+    fn set(&self, id: ParamId, value: f32) {
+        self.history.lock().unwrap().set(&self.params, id, value);
+    }
+
+    /// Handles one message, returns whether it is a synth address. Fails
+    /// when the arguments are invalid.
+    pub fn handle(&self, message: &OscMessage) -> Result<bool> {
+        let address = message.address.as_str();
+        let args = message.args.as_slice();
+        if let Some(key) = address.strip_prefix(PARAM_PREFIX) {
+            let id = ParamId::from_key(key).ok_or_else(|| anyhow!("Unknown parameter {key:?}"))?;
+            self.set(id, param_value(id, first(address, args)?)?);
+            return Ok(true);
+        }
+        let event = match address.strip_prefix(PREFIX) {
+            Some("/note_on") => SynthEvent::NoteOn {
+                note: note(first(address, args)?)?,
+                velocity: match args.get(1) {
+                    Some(velocity) => number(address, velocity)?.clamp(0.0, 1.0),
+                    None => 1.0,
+                },
+            },
+            Some("/note_off") => SynthEvent::NoteOff {
+                note: note(first(address, args)?)?,
+            },
+            Some("/pitch_bend") => {
+                SynthEvent::PitchBend(number(address, first(address, args)?)?.clamp(-1.0, 1.0))
+            }
+            Some("/mod_wheel") => {
+                SynthEvent::ModWheel(number(address, first(address, args)?)?.clamp(0.0, 1.0))
+            }
+            Some("/sustain") => SynthEvent::Sustain(number(address, first(address, args)?)? >= 0.5),
+            Some("/waveform") => {
+                let id = ParamId::Waveform;
+                self.set(id, param_value(id, first(address, args)?)?);
+                return Ok(true);
+            }
+            Some("/undo") => {
+                if !self.history.lock().unwrap().undo_params(&self.params) {
+                    return Err(anyhow!("Nothing to undo"));
+                }
+                return Ok(true);
+            }
+            Some("/redo") => {
+                if !self.history.lock().unwrap().redo_params(&self.params) {
+                    return Err(anyhow!("Nothing to redo"));
+                }
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+        self.tx.send(event)?;
+        Ok(true)
+    }
+}
+
+fn first<'a>(address: &str, args: &'a [OscArg]) -> Result<&'a OscArg> {
+    args.first()
+        .ok_or_else(|| anyhow!("{address} needs an argument"))
+}
+
+fn number(address: &str, arg: &OscArg) -> Result<f32> {
+    arg.as_f32()
+        .ok_or_else(|| anyhow!("{address} expects a number, got {arg:?}"))
+}
+
+fn note(arg: &OscArg) -> Result<Note> {
+    match arg {
+        OscArg::String(name) => name.parse().map_err(|_| anyhow!("Invalid note {name:?}")),
+        arg => {
+            let key = arg
+                .as_f32()
+                .ok_or_else(|| anyhow!("Expected a note, got {arg:?}"))?;
+            u8::try_from(key.round() as i32)
+                .ok()
+                .and_then(Note::from_midi)
+                .ok_or_else(|| anyhow!("Invalid MIDI note {key}"))
+        }
+    }
+}
+
+fn param_value(id: ParamId, arg: &OscArg) -> Result<f32> {
+    let info = id.info();
+    match arg.as_str() {
+        Some(name) => info.choice_value(name).ok_or_else(|| {
+            anyhow!(
+                "Invalid {id} {name:?}, expected one of {}",
+                info.choices.join(", ")
+            )
+        }),
+        None => arg
+            .as_f32()
+            .ok_or_else(|| anyhow!("Invalid {id} {arg:?}, expected a number")),
+    }
+}
+
+/// Message sent out when a parameter changes.
+fn param_message(id: ParamId, value: f32) -> OscMessage {
+    let info = id.info();
+    let message = OscMessage::new(&format!("{PARAM_PREFIX}{}", id.key()));
+    if info.is_toggle() {
+        message.arg(value >= 1.0)
+    } else if let Some(choice) = info.choice(value) {
+        message.arg(choice)
+    } else {
+        message.arg(value)
+    }
+}
+
+/// Notes played, as sent to the subscribers with the note as a MIDI number.
+fn note_echo(message: &OscMessage) -> Option<OscMessage> {
+    let key = note(message.args.first()?).ok()?.midi() as i32;
+    match message.address.strip_prefix(PREFIX)? {
+        "/note_on" => {
+            let velocity = message.args.get(1).and_then(OscArg::as_f32).unwrap_or(1.0);
+            Some(
+                OscMessage::new(&message.address)
+                    .arg(key)
+                    .arg(velocity.clamp(0.0, 1.0)),
+            )
+        }
+        "/note_off" => Some(OscMessage::new(&message.address).arg(key)),
+        _ => None,
+    }
+}
+
+/// Receives OSC packets on a UDP socket and sends parameter changes and
+/// notes to the subscribed addresses.
+#[derive(Debug)]
+pub struct OscServer {
+    socket: UdpSocket,
+    router: OscRouter,
+    targets: Vec<SocketAddr>,
+    /// Values last sent.
+    sent: Vec<f32>,
+    last_output: Instant,
+}
+
+impl OscServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, router: OscRouter) -> Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(OUTPUT_INTERVAL))?;
+        Ok(Self {
+            socket,
+            sent: ParamId::ALL.map(|id| router.params.get(id)).to_vec(),
+            router,
+            targets: Vec::new(),
+            last_output: Instant::now(),
+        })
+    }
+
+    /// Also sends every parameter when the server starts, then their
+    /// changes, to `target`, without it subscribing. Counts towards
+    /// [`MAX_TARGETS`].
+    pub fn send_to(mut self, target: SocketAddr) -> Self {
+        if !self.targets.contains(&target) && self.targets.len() < MAX_TARGETS {
+            self.targets.push(target);
+        }
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Waits for a packet at most [`OUTPUT_INTERVAL`] and handles it, then
+    /// sends the parameters that changed if it is time to.
+    pub fn poll(&mut self, buffer: &mut [u8]) -> Result<()> {
+        match self.socket.recv_from(buffer) {
+            Ok((len, from)) => self.receive(&buffer[..len], from)?,
+            // Timed out, or a previous datagram couldn't be delivered.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock
+                        | ErrorKind::TimedOut
+                        | ErrorKind::ConnectionRefused
+                        | ErrorKind::ConnectionReset
+                ) => {}
+            Err(err) => return Err(err.into()),
+        }
+        if self.last_output.elapsed() >= OUTPUT_INTERVAL {
+            self.last_output = Instant::now();
+            self.send_changes();
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, bytes: &[u8], from: SocketAddr) -> Result<()> {
+        let messages = match OscPacket::decode(bytes) {
+            Ok(packet) => packet.messages(),
+            Err(err) => {
+                self.reply_error(&err, from);
+                return Ok(());
+            }
+        };
+        for message in messages {
+            match message.address.strip_prefix(PREFIX) {
+                Some("/subscribe") => {
+                    if !self.targets.contains(&from) {
+                        if self.targets.len() >= MAX_TARGETS {
+                            let err = anyhow!("Too many subscribers, at most {MAX_TARGETS}");
+                            self.reply_error(&err, from);
+                            continue;
+                        }
+                        self.targets.push(from);
+                    }
+                    self.send_state(from);
+                }
+                Some("/unsubscribe") => self.targets.retain(|target| *target != from),
+                _ => match self.router.handle(&message) {
+                    Ok(_) => {
+                        if let Some(echo) = note_echo(&message) {
+                            self.send_all(&echo);
+                        }
+                    }
+                    Err(err) if err.is::<crossbeam_channel::SendError<SynthEvent>>() => {
+                        return Err(err)
+                    }
+                    Err(err) => self.reply_error(&err, from),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn reply_error(&self, err: &anyhow::Error, to: SocketAddr) {
+        let message = OscMessage::new(ERROR_ADDRESS).arg(format!("{err:#}").as_str());
+        let _ = self.socket.send_to(&message.encode(), to);
+    }
+
+    /// Sends every parameter to `to`.
+    fn send_state(&self, to: SocketAddr) {
+        for id in ParamId::ALL {
+            let message = param_message(id, self.router.params.get(id));
+            let _ = self.socket.send_to(&message.encode(), to);
+        }
+    }
+
+    fn send_changes(&mut self) {
+        for id in ParamId::ALL {
+            let value = self.router.params.get(id);
+            if value == self.sent[id.index()] {
+                continue;
+            }
+            self.sent[id.index()] = value;
+            self.send_all(&param_message(id, value));
+        }
+    }
+
+    /// Sends `message` to every target.
+    fn send_all(&self, message: &OscMessage) {
+        let bytes = message.encode();
+        for target in &self.targets {
+            let _ = self.socket.send_to(&bytes, target);
+        }
+    }
+
+    /// Handles packets until the synth stops listening to events.
+    pub fn run(&mut self) -> Result<()> {
+        for target in &self.targets {
+            self.send_state(*target);
+        }
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        loop {
+            self.poll(&mut buffer)?;
+        }
+    }
+
+    /// Runs the server from a new thread.
+    pub fn spawn(mut self) -> JoinHandle<Result<()>> {
+        std::thread::spawn(move || self.run())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server on a free loopback port, its address and the events it sends.
+    fn serve(params: &ParamStore) -> (SocketAddr, crossbeam_channel::Receiver<SynthEvent>) {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let server = OscServer::bind("127.0.0.1:0", OscRouter::new(params.clone(), tx)).unwrap();
+        let addr = server.local_addr().unwrap();
+        server.spawn();
+        (addr, rx)
+    }
+
+    fn client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    /// Skips messages until one is sent to `address`.
+    fn receive(socket: &UdpSocket, address: &str) -> OscMessage {
+        let mut buffer = [0; 1024];
+        loop {
+            let len = socket.recv(&mut buffer).unwrap();
+            let message = OscMessage::decode(&buffer[..len]).unwrap();
+            if message.address == address {
+                return message;
+            }
+        }
+    }
+
+    #[test]
+    fn plays_and_echoes_over_loopback() {
+        let params = ParamStore::new();
+        let (server, events) = serve(&params);
+        let client = client();
+        let send = |message: OscMessage| client.send_to(&message.encode(), server).unwrap();
+
+        send(OscMessage::new("/synth/subscribe"));
+        let amp = receive(&client, "/synth/param/amp");
+        assert_eq!(amp.args, [OscArg::Float(0.5)]);
+
+        send(OscMessage::new("/synth/note_on").arg("C4").arg(0.8f32));
+        let echo = receive(&client, "/synth/note_on");
+        assert_eq!(echo.args, [OscArg::Int(60), OscArg::Float(0.8)]);
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(event, SynthEvent::NoteOn { note: Note::C4, .. }));
+
+        send(OscMessage::new("/synth/param/amp").arg(0.2f32));
+        let amp = receive(&client, "/synth/param/amp");
+        assert_eq!(amp.args, [OscArg::Float(0.2)]);
+
+        send(OscMessage::new("/synth/param/nope").arg(1));
+        let error = receive(&client, ERROR_ADDRESS);
+        assert!(error.args[0].as_str().unwrap().contains("nope"));
+    }
+
+    #[test]
+    fn refuses_subscribers_past_the_limit() {
+        let params = ParamStore::new();
+        let (server, _events) = serve(&params);
+        let clients: Vec<_> = (0..=MAX_TARGETS).map(|_| client()).collect();
+        for client in &clients[..MAX_TARGETS] {
+            client
+                .send_to(&OscMessage::new("/synth/subscribe").encode(), server)
+                .unwrap();
+            receive(client, "/synth/param/amp");
+        }
+        let last = &clients[MAX_TARGETS];
+        last.send_to(&OscMessage::new("/synth/subscribe").encode(), server)
+            .unwrap();
+        receive(last, ERROR_ADDRESS);
+    }
+
+    #[test]
+    fn undoes_parameter_changes_shared_with_other_remotes() {
+        let params = ParamStore::new();
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        let history = Arc::new(Mutex::new(History::new().coalesce(Duration::ZERO)));
+        let router = OscRouter::new(params.clone(), tx).history(history.clone());
+
+        router
+            .handle(&OscMessage::new("/synth/param/amp").arg(0.2f32))
+            .unwrap();
+        history.lock().unwrap().set(&params, ParamId::Amp, 0.7);
+        assert!(router.handle(&OscMessage::new("/synth/undo")).unwrap());
+        assert_eq!(params.get(ParamId::Amp), 0.2);
+        assert!(router.handle(&OscMessage::new("/synth/undo")).unwrap());
+        assert_eq!(params.get(ParamId::Amp), 0.5);
+        assert!(router.handle(&OscMessage::new("/synth/undo")).is_err());
+        assert!(router.handle(&OscMessage::new("/synth/redo")).unwrap());
+        assert_eq!(params.get(ParamId::Amp), 0.2);
+    }
+}
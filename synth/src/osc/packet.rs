@@ -0,0 +1,325 @@
+use anyhow::{anyhow, Result};
+
+const BUNDLE_TAG: &str = "#bundle";
+
+/// Argument of an OSC message, with its type tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    /// `i`
+    Int(i32),
+    /// `f`, doubles (`d`) are read as floats too.
+    Float(f32),
+    /// `s`, symbols (`S`) are read as strings too.
+    String(String),
+    /// `b`
+    Blob(Vec<u8>),
+    /// `T` or `F`
+    Bool(bool),
+    /// `N`
+    Nil,
+}
+
+impl OscArg {
+    /// Numbers and booleans as a float.
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            OscArg::Int(value) => Some(value as f32),
+            OscArg::Float(value) => Some(value),
+            OscArg::Bool(value) => Some(value as u8 as f32),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            OscArg::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> char {
+        match self {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::String(_) => 's',
+            OscArg::Blob(_) => 'b',
+            OscArg::Bool(true) => 'T',
+            OscArg::Bool(false) => 'F',
+            OscArg::Nil => 'N',
+        }
+    }
+}
+
+impl From<i32> for OscArg {
+    fn from(value: i32) -> Self {
+        OscArg::Int(value)
+    }
+}
+
+impl From<f32> for OscArg {
+    fn from(value: f32) -> Self {
+        OscArg::Float(value)
+    }
+}
+
+impl From<&str> for OscArg {
+    fn from(value: &str) -> Self {
+        OscArg::String(value.to_string())
+    }
+}
+
+impl From<bool> for OscArg {
+    fn from(value: bool) -> Self {
+        OscArg::Bool(value)
+    }
+}
+
+/// An address, such as `/synth/note_on`, with its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg<A: Into<OscArg>>(mut self, arg: A) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_string(&mut bytes, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(OscArg::tag))
+            .collect();
+        write_string(&mut bytes, &tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::Float(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::String(value) => write_string(&mut bytes, value),
+                OscArg::Blob(blob) => {
+                    bytes.extend((blob.len() as i32).to_be_bytes());
+                    bytes.extend(blob);
+                    pad(&mut bytes);
+                }
+                OscArg::Bool(_) | OscArg::Nil => (),
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+        let address = reader.string()?;
+        if !address.starts_with('/') {
+            return Err(anyhow!("Invalid OSC address {address:?}"));
+        }
+        // Old implementations may leave out the type tags.
+        if reader.is_empty() {
+            return Ok(Self::new(&address));
+        }
+        let tags = reader.string()?;
+        let tags = tags
+            .strip_prefix(',')
+            .ok_or_else(|| anyhow!("Invalid OSC type tags {tags:?}"))?;
+        let mut args = Vec::new();
+        for tag in tags.chars() {
+            let arg = match tag {
+                'i' => OscArg::Int(i32::from_be_bytes(reader.array()?)),
+                'f' => OscArg::Float(f32::from_be_bytes(reader.array()?)),
+                'd' => OscArg::Float(f64::from_be_bytes(reader.array()?) as f32),
+                's' | 'S' => OscArg::String(reader.string()?),
+                'b' => {
+                    let len = i32::from_be_bytes(reader.array()?);
+                    let len = usize::try_from(len).map_err(|_| anyhow!("Invalid OSC blob size"))?;
+                    OscArg::Blob(reader.padded(len)?.to_vec())
+                }
+                'T' => OscArg::Bool(true),
+                'F' => OscArg::Bool(false),
+                'N' => OscArg::Nil,
+                tag => return Err(anyhow!("Unsupported OSC type tag {tag:?}")),
+            };
+            args.push(arg);
+        }
+        Ok(Self { address, args })
+    }
+}
+
+/// What a UDP datagram holds, a message or a bundle of packets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscPacket {
+    Message(OscMessage),
+    /// The time tag is ignored, bundled messages are handled when received.
+    Bundle(Vec<OscPacket>),
+}
+
+impl OscPacket {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+        if !bytes.starts_with(BUNDLE_TAG.as_bytes()) {
+            return Ok(OscPacket::Message(OscMessage::decode(bytes)?));
+        }
+        reader.string()?;
+        let _time_tag: [u8; 8] = reader.array()?;
+        let mut packets = Vec::new();
+        while !reader.is_empty() {
+            let len = i32::from_be_bytes(reader.array()?);
+            let len =
+                usize::try_from(len).map_err(|_| anyhow!("Invalid OSC bundle element size"))?;
+            packets.push(OscPacket::decode(reader.padded(len)?)?);
+        }
+        Ok(OscPacket::Bundle(packets))
+    }
+
+    /// Every message, bundles flattened.
+    pub fn messages(self) -> Vec<OscMessage> {
+        match self {
+            OscPacket::Message(message) => vec![message],
+            OscPacket::Bundle(packets) => packets.into_iter().flat_map(Self::messages).collect(),
+        }
+    }
+}
+
+/// Writes `value` null terminated and padded to 4 bytes.
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend(value.as_bytes());
+    bytes.push(0);
+    pad(bytes);
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(0);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    /// `len` bytes, skipping the padding after them.
+    fn padded(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.position.checked_add(len);
+        let bytes = end
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| anyhow!("Truncated OSC packet"))?;
+        self.position += len.next_multiple_of(4);
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.padded(N)?);
+        Ok(array)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let rest = self.bytes.get(self.position..).unwrap_or_default();
+        let len = rest
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| anyhow!("Unterminated OSC string"))?;
+        let bytes = self.padded(len + 1)?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| anyhow!("Invalid OSC string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_strings_and_blobs_to_four_bytes() {
+        let message = OscMessage::new("/a")
+            .arg(1)
+            .arg(0.5f32)
+            .arg("hi")
+            .arg(OscArg::Blob(vec![1, 2, 3]))
+            .arg(true)
+            .arg(OscArg::Nil);
+        let mut expected = b"/a\0\0,ifsbTN\0".to_vec();
+        expected.extend(1i32.to_be_bytes());
+        expected.extend(0.5f32.to_be_bytes());
+        expected.extend(b"hi\0\0");
+        expected.extend(3i32.to_be_bytes());
+        expected.extend([1, 2, 3, 0]);
+        assert_eq!(message.encode(), expected);
+        // A string filling four bytes still needs its terminator.
+        assert_eq!(OscMessage::new("/abc").encode(), b"/abc\0\0\0\0,\0\0\0");
+    }
+
+    #[test]
+    fn round_trips_every_type() {
+        let message = OscMessage::new("/synth/param/amp")
+            .arg(-7)
+            .arg(0.25f32)
+            .arg("saw")
+            .arg(OscArg::Blob(vec![9; 5]))
+            .arg(false)
+            .arg(true)
+            .arg(OscArg::Nil);
+        let decoded = OscPacket::decode(&message.encode()).unwrap();
+        assert_eq!(decoded, OscPacket::Message(message));
+    }
+
+    #[test]
+    fn reads_doubles_symbols_and_missing_type_tags() {
+        let mut bytes = b"/x\0\0,dS\0".to_vec();
+        bytes.extend(0.5f64.to_be_bytes());
+        bytes.extend(b"sym\0");
+        let message = OscMessage::decode(&bytes).unwrap();
+        assert_eq!(message.args, [OscArg::Float(0.5), OscArg::from("sym")]);
+        assert_eq!(
+            OscMessage::decode(b"/x\0\0").unwrap(),
+            OscMessage::new("/x")
+        );
+    }
+
+    #[test]
+    fn flattens_bundles() {
+        let first = OscMessage::new("/synth/note_on").arg(60);
+        let second = OscMessage::new("/synth/note_off").arg(60);
+        let mut inner = b"#bundle\0".to_vec();
+        inner.extend([0; 8]);
+        inner.extend((second.encode().len() as i32).to_be_bytes());
+        inner.extend(second.encode());
+        let mut bytes = b"#bundle\0".to_vec();
+        bytes.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        for element in [first.encode(), inner] {
+            bytes.extend((element.len() as i32).to_be_bytes());
+            bytes.extend(element);
+        }
+        let messages = OscPacket::decode(&bytes).unwrap().messages();
+        assert_eq!(messages, [first, second]);
+    }
+
+    #[test]
+    fn rejects_broken_packets() {
+        let encoded = OscMessage::new("/x").arg(1).encode();
+        for bytes in [
+            &encoded[..encoded.len() - 1],
+            b"x\0\0\0,\0\0\0",
+            b"/x\0\0i\0\0\0",
+            b"/x\0\0,q\0\0",
+            b"/x",
+            b"#bundle\0\0\0\0\0\0\0\0\0\0\0\0\x10/x\0\0",
+        ] {
+            assert!(OscPacket::decode(bytes).is_err(), "{bytes:?}");
+        }
+    }
+}
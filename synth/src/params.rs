@@ -0,0 +1,727 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    arp::{ArpPattern, ArpRate, MAX_OCTAVES},
+    glide::{GlideCurve, GlideMode},
+    mono::NotePriority,
+    notes::{Note, SHARP_NAMES},
+    tuning::Temperament,
+    waves::{
+        bank::{UnisonSettings, MAX_UNISON},
+        Wave, Waveform,
+    },
+};
+
+/// Toggle parameters are on from this value up.
+const ON: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    None,
+    Hz,
+    Cents,
+    Milliseconds,
+    Bpm,
+    Octaves,
+    /// Fraction from 0.0 to 1.0, shown as a percentage.
+    Ratio,
+}
+
+impl Unit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::None => "",
+            Unit::Hz => "Hz",
+            Unit::Cents => "ct",
+            Unit::Milliseconds => "ms",
+            Unit::Bpm => "BPM",
+            Unit::Octaves => "oct",
+            Unit::Ratio => "%",
+        }
+    }
+}
+
+/// How the audio thread moves towards a new value, to avoid zipper noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Jumps to the new value at the start of the next block.
+    None,
+    /// Exponential approach, the time constant in milliseconds.
+    OnePole(f32),
+    /// Straight ramp over the given milliseconds.
+    Linear(f32),
+}
+
+impl Smoothing {
+    /// Packed as kind in the high half and time in the low one, to fit an atomic.
+    fn to_bits(self) -> u64 {
+        let (kind, ms) = match self {
+            Smoothing::None => (0, 0.0),
+            Smoothing::OnePole(ms) => (1, ms.max(0.0)),
+            Smoothing::Linear(ms) => (2, ms.max(0.0)),
+        };
+        (kind as u64) << 32 | ms.to_bits() as u64
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        let ms = f32::from_bits(bits as u32);
+        match bits >> 32 {
+            1 => Smoothing::OnePole(ms),
+            2 => Smoothing::Linear(ms),
+            _ => Smoothing::None,
+        }
+    }
+}
+
+/// Description of a parameter, for UIs and automation to discover them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamInfo {
+    pub id: ParamId,
+    pub name: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub unit: Unit,
+    /// Values are rounded to multiples of it, `0.0` for continuous ones.
+    pub step: f32,
+    /// Names of the values of a choice parameter, from `min` on.
+    pub choices: &'static [&'static str],
+    pub smoothing: Smoothing,
+}
+
+impl ParamInfo {
+    /// Clamps and rounds `value` to one the parameter accepts.
+    pub fn normalize(&self, value: f32) -> f32 {
+        let value = if self.step > 0.0 {
+            ((value - self.min) / self.step).round() * self.step + self.min
+        } else {
+            value
+        };
+        value.clamp(self.min, self.max)
+    }
+
+    /// Off or on, stored as 0.0 or 1.0.
+    pub fn is_toggle(&self) -> bool {
+        self.choices == OFF_ON
+    }
+
+    /// Name of the value of a choice parameter.
+    pub fn choice(&self, value: f32) -> Option<&'static str> {
+        self.choices
+            .get((value - self.min).round() as usize)
+            .copied()
+    }
+
+    /// Value of a choice parameter from its name, ignoring case.
+    pub fn choice_value(&self, name: &str) -> Option<f32> {
+        self.choices
+            .iter()
+            .position(|choice| choice.eq_ignore_ascii_case(name))
+            .map(|index| self.min + index as f32)
+    }
+
+    pub fn format(&self, value: f32) -> String {
+        if let Some(choice) = self.choice(value) {
+            return choice.to_string();
+        }
+        match self.unit {
+            Unit::Ratio => format!("{:.0}%", value * 100.0),
+            unit if self.step >= 1.0 => format!("{value:.0} {}", unit.symbol()),
+            unit => format!("{value:.2} {}", unit.symbol()),
+        }
+        .trim_end()
+        .to_string()
+    }
+}
+
+/// Every parameter of the synth that can be set while it plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParamId {
+    Waveform,
+    /// MIDI note number.
+    Note,
+    Amp,
+    Detune,
+    /// Frequency of A4.
+    Reference,
+    Temperament,
+    /// Pitch class the temperament is built on, 0 is C.
+    TuningRoot,
+    GlideTime,
+    GlideCurve,
+    GlideMode,
+    GlideLegato,
+    Mono,
+    MonoPriority,
+    MonoLegato,
+    Arp,
+    ArpPattern,
+    ArpOctaves,
+    ArpRate,
+    ArpBpm,
+    ArpGate,
+    ArpSwing,
+    ArpLatch,
+    Unison,
+    UnisonSpread,
+}
+
+const OFF_ON: &[&str] = &["Off", "On"];
+
+impl ParamId {
+    pub const ALL: [ParamId; 24] = [
+        ParamId::Waveform,
+        ParamId::Note,
+        ParamId::Amp,
+        ParamId::Detune,
+        ParamId::Reference,
+        ParamId::Temperament,
+        ParamId::TuningRoot,
+        ParamId::GlideTime,
+        ParamId::GlideCurve,
+        ParamId::GlideMode,
+        ParamId::GlideLegato,
+        ParamId::Mono,
+        ParamId::MonoPriority,
+        ParamId::MonoLegato,
+        ParamId::Arp,
+        ParamId::ArpPattern,
+        ParamId::ArpOctaves,
+        ParamId::ArpRate,
+        ParamId::ArpBpm,
+        ParamId::ArpGate,
+        ParamId::ArpSwing,
+        ParamId::ArpLatch,
+        ParamId::Unison,
+        ParamId::UnisonSpread,
+    ];
+
+    /// Position in [`ParamId::ALL`].
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// Short name used to address the parameter, such as `glide_time`.
+    pub fn key(&self) -> &'static str {
+        match self {
+            ParamId::Waveform => "waveform",
+            ParamId::Note => "note",
+            ParamId::Amp => "amp",
+            ParamId::Detune => "detune",
+            ParamId::Reference => "reference",
+            ParamId::Temperament => "temperament",
+            ParamId::TuningRoot => "tuning_root",
+            ParamId::GlideTime => "glide_time",
+            ParamId::GlideCurve => "glide_curve",
+            ParamId::GlideMode => "glide_mode",
+            ParamId::GlideLegato => "glide_legato",
+            ParamId::Mono => "mono",
+            ParamId::MonoPriority => "mono_priority",
+            ParamId::MonoLegato => "mono_legato",
+            ParamId::Arp => "arp",
+            ParamId::ArpPattern => "arp_pattern",
+            ParamId::ArpOctaves => "arp_octaves",
+            ParamId::ArpRate => "arp_rate",
+            ParamId::ArpBpm => "arp_bpm",
+            ParamId::ArpGate => "arp_gate",
+            ParamId::ArpSwing => "arp_swing",
+            ParamId::ArpLatch => "arp_latch",
+            ParamId::Unison => "unison",
+            ParamId::UnisonSpread => "unison_spread",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|id| id.key() == key)
+    }
+
+    pub fn info(&self) -> ParamInfo {
+        let info = ParamInfo {
+            id: *self,
+            name: "",
+            min: 0.0,
+            max: 1.0,
+            default: 0.0,
+            unit: Unit::None,
+            step: 1.0,
+            choices: OFF_ON,
+            smoothing: Smoothing::None,
+        };
+        match self {
+            ParamId::Waveform => ParamInfo {
+                name: "Waveform",
+                max: 4.0,
+                default: 1.0,
+                choices: &["None", "Sine", "Saw", "Square", "Triangle"],
+                ..info
+            },
+            ParamId::Note => ParamInfo {
+                name: "Note",
+                max: 127.0,
+                default: 69.0,
+                choices: &[],
+                ..info
+            },
+            ParamId::Amp => ParamInfo {
+                name: "Amplitude",
+                default: 0.5,
+                unit: Unit::Ratio,
+                step: 0.0,
+                choices: &[],
+                smoothing: Smoothing::OnePole(10.0),
+                ..info
+            },
+            ParamId::Detune => ParamInfo {
+                name: "Detune",
+                min: -100.0,
+                max: 100.0,
+                unit: Unit::Cents,
+                step: 0.0,
+                choices: &[],
+                smoothing: Smoothing::Linear(20.0),
+                ..info
+            },
+            ParamId::Reference => ParamInfo {
+                name: "Reference",
+                min: 400.0,
+                max: 480.0,
+                default: 440.0,
+                unit: Unit::Hz,
+                step: 0.0,
+                choices: &[],
+                ..info
+            },
+            ParamId::Temperament => ParamInfo {
+                name: "Temperament",
+                // Past the presets, a custom or mapped temperament is kept.
+                max: Temperament::ALL.len() as f32 + 1.0,
+                choices: &[
+                    "Equal",
+                    "Just",
+                    "Pythagorean",
+                    "Meantone",
+                    "Custom",
+                    "Mapped",
+                ],
+                ..info
+            },
+            ParamId::TuningRoot => ParamInfo {
+                name: "Tuning root",
+                max: 11.0,
+                choices: &SHARP_NAMES,
+                ..info
+            },
+            ParamId::GlideTime => ParamInfo {
+                name: "Glide time",
+                max: 2000.0,
+                unit: Unit::Milliseconds,
+                choices: &[],
+                ..info
+            },
+            ParamId::GlideCurve => ParamInfo {
+                name: "Glide curve",
+                choices: &["Linear", "Exponential"],
+                ..info
+            },
+            ParamId::GlideMode => ParamInfo {
+                name: "Glide mode",
+                choices: &["Constant time", "Constant rate"],
+                ..info
+            },
+            ParamId::GlideLegato => ParamInfo {
+                name: "Glide legato only",
+                ..info
+            },
+            ParamId::Mono => ParamInfo {
+                name: "Mono",
+                ..info
+            },
+            ParamId::MonoPriority => ParamInfo {
+                name: "Note priority",
+                max: 2.0,
+                choices: &["Last", "Low", "High"],
+                ..info
+            },
+            ParamId::MonoLegato => ParamInfo {
+                name: "Mono legato",
+                default: ON,
+                ..info
+            },
+            ParamId::Arp => ParamInfo {
+                name: "Arpeggiator",
+                ..info
+            },
+            ParamId::ArpPattern => ParamInfo {
+                name: "Arp pattern",
+                max: 4.0,
+                choices: &["Up", "Down", "Up/Down", "Random", "As played"],
+                ..info
+            },
+            ParamId::ArpOctaves => ParamInfo {
+                name: "Arp octaves",
+                min: 1.0,
+                max: MAX_OCTAVES as f32,
+                default: 1.0,
+                unit: Unit::Octaves,
+                choices: &[],
+                ..info
+            },
+            ParamId::ArpRate => ParamInfo {
+                name: "Arp rate",
+                max: 5.0,
+                default: 3.0,
+                choices: &["1/4", "1/8", "1/8T", "1/16", "1/16T", "1/32"],
+                ..info
+            },
+            ParamId::ArpBpm => ParamInfo {
+                name: "Arp tempo",
+                min: 20.0,
+                max: 300.0,
+                default: 120.0,
+                unit: Unit::Bpm,
+                step: 0.0,
+                choices: &[],
+                ..info
+            },
+            ParamId::ArpGate => ParamInfo {
+                name: "Arp gate",
+                default: 0.5,
+                unit: Unit::Ratio,
+                step: 0.0,
+                choices: &[],
+                ..info
+            },
+            ParamId::ArpSwing => ParamInfo {
+                name: "Arp swing",
+                max: 0.9,
+                unit: Unit::Ratio,
+                step: 0.0,
+                choices: &[],
+                ..info
+            },
+            ParamId::ArpLatch => ParamInfo {
+                name: "Arp latch",
+                ..info
+            },
+            ParamId::Unison => ParamInfo {
+                name: "Unison voices",
+                min: 1.0,
+                max: MAX_UNISON as f32,
+                default: 1.0,
+                choices: &[],
+                ..info
+            },
+            ParamId::UnisonSpread => ParamInfo {
+                name: "Unison spread",
+                max: 100.0,
+                default: UnisonSettings::default().spread,
+                unit: Unit::Cents,
+                step: 0.0,
+                choices: &[],
+                ..info
+            },
+        }
+    }
+
+    /// Current value of the parameter in `wave`.
+    pub fn get(&self, wave: &Wave) -> f32 {
+        fn index<T: PartialEq>(values: &[T], value: &T) -> f32 {
+            values
+                .iter()
+                .position(|v| v == value)
+                .unwrap_or(values.len()) as f32
+        }
+        fn flag(value: bool) -> f32 {
+            if value {
+                ON
+            } else {
+                0.0
+            }
+        }
+        match self {
+            ParamId::Waveform => index(&Waveform::ALL, &wave.waveform),
+            ParamId::Note => wave.note.midi() as f32,
+            ParamId::Amp => wave.amp,
+            ParamId::Detune => wave.detune,
+            ParamId::Reference => wave.tuning.reference,
+            ParamId::Temperament => match &wave.tuning.temperament {
+                Temperament::Mapped(_) => Temperament::ALL.len() as f32 + 1.0,
+                temperament => index(&Temperament::ALL, temperament),
+            },
+            ParamId::TuningRoot => wave.tuning.root as f32,
+            ParamId::GlideTime => wave.glide.time,
+            ParamId::GlideCurve => index(&GlideCurve::ALL, &wave.glide.curve),
+            ParamId::GlideMode => index(&GlideMode::ALL, &wave.glide.mode),
+            ParamId::GlideLegato => flag(wave.glide.legato_only),
+            ParamId::Mono => flag(wave.mono.enabled),
+            ParamId::MonoPriority => index(&NotePriority::ALL, &wave.mono.priority),
+            ParamId::MonoLegato => flag(wave.mono.legato),
+            ParamId::Arp => flag(wave.arp.enabled),
+            ParamId::ArpPattern => index(&ArpPattern::ALL, &wave.arp.pattern),
+            ParamId::ArpOctaves => wave.arp.octaves as f32,
+            ParamId::ArpRate => index(&ArpRate::ALL, &wave.arp.rate),
+            ParamId::ArpBpm => wave.arp.bpm,
+            ParamId::ArpGate => wave.arp.gate,
+            ParamId::ArpSwing => wave.arp.swing,
+            ParamId::ArpLatch => flag(wave.arp.latch),
+            ParamId::Unison => wave.unison.voices as f32,
+            ParamId::UnisonSpread => wave.unison.spread,
+        }
+    }
+
+    /// Sets the parameter in `wave`, `value` is normalized first.
+    pub fn apply(&self, value: f32, wave: &mut Wave) {
+        fn pick<T: Copy>(values: &[T], value: f32, current: T) -> T {
+            values.get(value as usize).copied().unwrap_or(current)
+        }
+        let value = self.info().normalize(value);
+        let flag = value >= ON;
+        match self {
+            ParamId::Waveform => wave.waveform = pick(&Waveform::ALL, value, wave.waveform),
+            ParamId::Note => wave.note = Note::from_midi(value as u8).unwrap_or(wave.note),
+            ParamId::Amp => wave.amp = value,
+            ParamId::Detune => wave.detune = value,
+            ParamId::Reference => wave.tuning.reference = value,
+            ParamId::Temperament => {
+                if let Some(temperament) = Temperament::ALL.get(value as usize) {
+                    wave.tuning.temperament = temperament.clone();
+                }
+            }
+            ParamId::TuningRoot => wave.tuning.root = value as usize,
+            ParamId::GlideTime => wave.glide.time = value,
+            ParamId::GlideCurve => {
+                wave.glide.curve = pick(&GlideCurve::ALL, value, wave.glide.curve)
+            }
+            ParamId::GlideMode => wave.glide.mode = pick(&GlideMode::ALL, value, wave.glide.mode),
+            ParamId::GlideLegato => wave.glide.legato_only = flag,
+            ParamId::Mono => wave.mono.enabled = flag,
+            ParamId::MonoPriority => {
+                wave.mono.priority = pick(&NotePriority::ALL, value, wave.mono.priority)
+            }
+            ParamId::MonoLegato => wave.mono.legato = flag,
+            ParamId::Arp => wave.arp.enabled = flag,
+            ParamId::ArpPattern => {
+                wave.arp.pattern = pick(&ArpPattern::ALL, value, wave.arp.pattern)
+            }
+            ParamId::ArpOctaves => wave.arp.octaves = value as u8,
+            ParamId::ArpRate => wave.arp.rate = pick(&ArpRate::ALL, value, wave.arp.rate),
+            ParamId::ArpBpm => wave.arp.bpm = value,
+            ParamId::ArpGate => wave.arp.gate = value,
+            ParamId::ArpSwing => wave.arp.swing = value,
+            ParamId::ArpLatch => wave.arp.latch = flag,
+            ParamId::Unison => wave.unison.voices = value as usize,
+            ParamId::UnisonSpread => wave.unison.spread = value,
+        }
+    }
+}
+
+impl fmt::Display for ParamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.key())
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    /// `f32` bits.
+    value: AtomicU32,
+    /// Packed [`Smoothing`].
+    smoothing: AtomicU64,
+}
+
+/// Parameter values shared without locks between the control threads, which
+/// write them, and the audio thread, which reads them once per block.
+#[derive(Debug, Clone)]
+pub struct ParamStore(Arc<[Slot]>);
+
+impl Default for ParamStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParamStore {
+    /// Every parameter at its default value.
+    pub fn new() -> Self {
+        Self(
+            ParamId::ALL
+                .iter()
+                .map(|id| {
+                    let info = id.info();
+                    Slot {
+                        value: AtomicU32::new(info.default.to_bits()),
+                        smoothing: AtomicU64::new(info.smoothing.to_bits()),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Every parameter at its value in `wave`.
+    pub fn from_wave(wave: &Wave) -> Self {
+        let store = Self::new();
+        store.set_wave(wave);
+        store
+    }
+
+    pub fn get(&self, id: ParamId) -> f32 {
+        f32::from_bits(self.0[id.index()].value.load(Ordering::Relaxed))
+    }
+
+    /// Sets a parameter, clamped to its range and rounded to its step.
+    pub fn set(&self, id: ParamId, value: f32) {
+        if value.is_nan() {
+            return;
+        }
+        let value = id.info().normalize(value);
+        self.0[id.index()]
+            .value
+            .store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn reset(&self, id: ParamId) {
+        self.set(id, id.info().default);
+    }
+
+    pub fn smoothing(&self, id: ParamId) -> Smoothing {
+        Smoothing::from_bits(self.0[id.index()].smoothing.load(Ordering::Relaxed))
+    }
+
+    pub fn set_smoothing(&self, id: ParamId, smoothing: Smoothing) {
+        self.0[id.index()]
+            .smoothing
+            .store(smoothing.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Writes every parameter of `wave`, only the ones that differ are
+    /// picked up by the audio thread.
+    pub fn set_wave(&self, wave: &Wave) {
+        for id in ParamId::ALL {
+            self.set(id, id.get(wave));
+        }
+    }
+
+    /// `base` with every parameter applied, what the audio thread plays
+    /// once its smoothing settled.
+    pub fn wave(&self, mut base: Wave) -> Wave {
+        for id in ParamId::ALL {
+            id.apply(self.get(id), &mut base);
+        }
+        base
+    }
+}
+
+/// Moves a parameter towards its target one sample at a time, in the audio thread.
+#[derive(Debug, Clone, Copy)]
+pub struct Smoother {
+    smoothing: Smoothing,
+    sample_rate: f32,
+    value: f32,
+    target: f32,
+    /// Change per sample of a linear ramp, or the one-pole coefficient.
+    step: f32,
+    /// Samples left in a linear ramp.
+    remaining: u32,
+}
+
+impl Smoother {
+    pub fn new(sample_rate: f32, value: f32) -> Self {
+        Self {
+            smoothing: Smoothing::None,
+            sample_rate,
+            value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.value == self.target
+    }
+
+    /// Used from the next target on.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoothing = smoothing;
+    }
+
+    /// Moves towards `target`, a ramp already heading there carries on.
+    pub fn set_target(&mut self, target: f32) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        match self.smoothing {
+            Smoothing::OnePole(ms) if ms > 0.0 => {
+                self.step = f32::exp(-1000.0 / (ms * self.sample_rate));
+            }
+            Smoothing::Linear(ms) if ms > 0.0 => {
+                self.remaining = ((ms / 1000.0 * self.sample_rate) as u32).max(1);
+                self.step = (target - self.value) / self.remaining as f32;
+            }
+            _ => self.value = target,
+        }
+    }
+
+    /// Jumps to `value` without smoothing.
+    pub fn jump(&mut self, value: f32) {
+        self.value = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn advance(&mut self) -> f32 {
+        if self.value == self.target {
+            return self.value;
+        }
+        match self.smoothing {
+            Smoothing::OnePole(_) => {
+                self.value = self.target + (self.value - self.target) * self.step;
+                if (self.value - self.target).abs() <= f32::EPSILON * self.target.abs().max(1.0) {
+                    self.value = self.target;
+                }
+            }
+            Smoothing::Linear(_) if self.remaining > 1 => {
+                self.remaining -= 1;
+                // From the end of the ramp, so rounding errors don't add up.
+                self.value = self.target - self.step * self.remaining as f32;
+            }
+            _ => self.jump(self.target),
+        }
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoothing_round_trips_through_the_store() {
+        let store = ParamStore::new();
+        for smoothing in [
+            Smoothing::None,
+            Smoothing::OnePole(10.0),
+            Smoothing::OnePole(0.1),
+            Smoothing::Linear(5.3),
+            Smoothing::Linear(f32::from_bits(0x4120_0003)),
+        ] {
+            store.set_smoothing(ParamId::Amp, smoothing);
+            assert_eq!(store.smoothing(ParamId::Amp), smoothing);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! Patches are stored as TOML, with every parameter under its key:
+//!
+//! ```toml
+//! version = 1
+//! name = "Soft saw"
+//! author = "Ada"
+//! category = "Pad"
+//! tags = ["warm", "slow"]
+//!
+//! [params]
+//! waveform = "Saw"
+//! amp = 0.3
+//! glide_time = 100
+//! mono = true
+//! temperament = "Custom"
+//!
+//! [tuning]
+//! custom = [0, -29.3, 3.9, 15.6, -13.7, -2, -31.3, 2, -27.4, -15.6, 17.6, -11.7]
+//!
+//! [effects.waveshaper]
+//! curve = "Tube"
+//! drive = 4
+//! mix = 0.5
+//! oversampling = "X4"
+//!
+//! [effects.bitcrusher]
+//! bits = 8
+//! rate = 11025
+//!
+//! [effects.compressor]
+//! threshold = -18
+//! ratio = 3
+//!
+//! [effects.limiter]
+//! ceiling = -0.5
+//! ```
+//!
+//! Toggles are booleans and choices are stored by name. Parameters missing
+//! from a patch keep their current value when it is loaded. The author,
+//! category and tags are optional, they help finding patches in a library.
+//!
+//! A custom or mapped temperament has its table under `[tuning]`, either
+//! `custom` with the cents of each pitch class or `mapped` with the frequency
+//! of every MIDI key. The waveshaper, bitcrusher, EQ bands, compressor and
+//! limiter are under `[effects]`, they are set when the synth starts with the
+//! patch. A waveshaper curve may also be a `{ Table = [...] }` of points.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::{
+    effects::EffectSettings,
+    params::{ParamId, ParamStore},
+    tuning::{Temperament, MIDI_KEYS},
+    waves::Wave,
+};
+
+/// Version of the patches written, older ones are migrated when read.
+pub const PATCH_VERSION: u32 = 1;
+pub const PATCH_EXTENSION: &str = "toml";
+
+/// Upgrades a patch from the version at its index to the next one.
+const MIGRATIONS: [fn(&mut Table); PATCH_VERSION as usize] = [migrate_v0];
+
+/// Unversioned patches list the parameters at the top level.
+fn migrate_v0(table: &mut Table) {
+    let keys: Vec<String> = table
+        .keys()
+        .filter(|key| ParamId::from_key(key).is_some())
+        .cloned()
+        .collect();
+    let mut params = Table::new();
+    for key in keys {
+        if let Some(value) = table.remove(&key) {
+            params.insert(key, value);
+        }
+    }
+    table.insert("params".to_string(), Value::Table(params));
+}
+
+/// Value of a parameter as written in a patch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Flag(bool),
+    Integer(i64),
+    Number(f64),
+    Choice(String),
+}
+
+impl ParamValue {
+    /// Value as written in a patch, choices by name and toggles as booleans.
+    pub fn encode(id: ParamId, value: f32) -> Self {
+        let info = id.info();
+        if info.is_toggle() {
+            return ParamValue::Flag(value >= 1.0);
+        }
+        if let Some(choice) = info.choice(value) {
+            return ParamValue::Choice(choice.to_string());
+        }
+        if info.step >= 1.0 {
+            return ParamValue::Integer(value.round() as i64);
+        }
+        ParamValue::Number(shortest(value))
+    }
+
+    /// Value of parameter `id`, clamped to its range.
+    pub fn decode(&self, id: ParamId) -> Result<f32> {
+        let info = id.info();
+        let value = match self {
+            ParamValue::Flag(flag) => *flag as u8 as f32,
+            ParamValue::Integer(value) => *value as f32,
+            ParamValue::Number(value) => *value as f32,
+            ParamValue::Choice(name) => info.choice_value(name).ok_or_else(|| {
+                anyhow!(
+                    "Invalid {id} {name:?}, expected one of {}",
+                    info.choices.join(", ")
+                )
+            })?,
+        };
+        Ok(info.normalize(value))
+    }
+}
+
+/// `value` through its shortest decimal form, so 0.3 isn't written as
+/// 0.30000001192092896.
+fn shortest(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(value as f64)
+}
+
+/// Table of a custom or mapped temperament, which the parameters only name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TuningTable {
+    /// Offset in cents from 12-TET of each pitch class, starting at C.
+    Custom(Vec<f64>),
+    /// Frequency in Hz of every MIDI key.
+    Mapped(Vec<f64>),
+}
+
+impl TuningTable {
+    /// Table of `temperament`, `None` for the presets.
+    pub fn new(temperament: &Temperament) -> Option<Self> {
+        let values = |table: &[f32]| table.iter().copied().map(shortest).collect();
+        match temperament {
+            Temperament::Custom(offsets) => Some(TuningTable::Custom(values(offsets))),
+            Temperament::Mapped(freqs) => Some(TuningTable::Mapped(values(&freqs[..]))),
+            _ => None,
+        }
+    }
+
+    pub fn temperament(&self) -> Result<Temperament> {
+        fn table<const N: usize>(values: &[f64], name: &str) -> Result<[f32; N]> {
+            let values: Vec<f32> = values.iter().map(|value| *value as f32).collect();
+            values.try_into().map_err(|values: Vec<f32>| {
+                anyhow!(
+                    "Invalid {name} tuning, expected {N} values, found {}",
+                    values.len()
+                )
+            })
+        }
+        Ok(match self {
+            TuningTable::Custom(offsets) => Temperament::Custom(table(offsets, "custom")?),
+            TuningTable::Mapped(freqs) => {
+                let freqs: [f32; MIDI_KEYS] = table(freqs, "mapped")?;
+                if let Some(freq) = freqs.iter().find(|freq| !freq.is_finite() || **freq <= 0.0) {
+                    return Err(anyhow!("Invalid mapped tuning, frequency {freq} Hz"));
+                }
+                Temperament::Mapped(Arc::new(freqs))
+            }
+        })
+    }
+}
+
+/// A saved sound, the value of every parameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Patch {
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub author: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub category: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tuning: Option<TuningTable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects: Option<EffectSettings>,
+}
+
+impl Patch {
+    /// Patch with the current value of every parameter in `params`.
+    pub fn new(name: &str, params: &ParamStore) -> Self {
+        Self::with_values(name, ParamId::ALL.map(|id| (id, params.get(id))))
+    }
+
+    /// Patch of `wave`, with the table of its temperament.
+    pub fn from_wave(name: &str, wave: &Wave) -> Self {
+        Self::with_values(name, ParamId::ALL.map(|id| (id, id.get(wave))))
+            .temperament(&wave.tuning.temperament)
+    }
+
+    /// Saves the table of `temperament` when it is custom or mapped.
+    pub fn temperament(mut self, temperament: &Temperament) -> Self {
+        self.tuning = TuningTable::new(temperament);
+        self
+    }
+
+    pub fn effects(mut self, effects: EffectSettings) -> Self {
+        self.effects = Some(effects);
+        self
+    }
+
+    fn with_values(name: &str, values: impl IntoIterator<Item = (ParamId, f32)>) -> Self {
+        Self {
+            version: PATCH_VERSION,
+            name: name.to_string(),
+            author: String::new(),
+            category: String::new(),
+            tags: Vec::new(),
+            tuning: None,
+            effects: None,
+            params: values
+                .into_iter()
+                .map(|(id, value)| (id.key().to_string(), ParamValue::encode(id, value)))
+                .collect(),
+        }
+    }
+
+    /// Every parameter of the patch, failing on unknown or invalid ones.
+    pub fn values(&self) -> Result<Vec<(ParamId, f32)>> {
+        self.params
+            .iter()
+            .map(|(key, value)| {
+                let id =
+                    ParamId::from_key(key).ok_or_else(|| anyhow!("Unknown parameter {key:?}"))?;
+                Ok((id, value.decode(id)?))
+            })
+            .collect()
+    }
+
+    /// Sets the parameters of the patch, nothing changes if one is invalid.
+    /// The tuning table needs the whole wave, see [`Patch::apply_to_wave`].
+    pub fn apply(&self, params: &ParamStore) -> Result<()> {
+        for (id, value) in self.values()? {
+            params.set(id, value);
+        }
+        Ok(())
+    }
+
+    /// Sets the parameters of the patch in `wave`, and its tuning table
+    /// when the temperament is custom or mapped.
+    pub fn apply_to_wave(&self, wave: &mut Wave) -> Result<()> {
+        let table = self
+            .tuning
+            .as_ref()
+            .map(TuningTable::temperament)
+            .transpose()?;
+        for (id, value) in self.values()? {
+            match &table {
+                Some(table)
+                    if id == ParamId::Temperament
+                        && Temperament::ALL.get(value as usize).is_none() =>
+                {
+                    wave.tuning.temperament = table.clone();
+                }
+                _ => id.apply(value, wave),
+            }
+        }
+        Ok(())
+    }
+
+    /// Name of the file the patch is saved to, made from its name.
+    pub fn file_name(&self) -> String {
+        let stem: String = self
+            .name
+            .trim()
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let stem = stem.trim_matches('-');
+        let stem = if stem.is_empty() { "patch" } else { stem };
+        format!("{stem}.{PATCH_EXTENSION}")
+    }
+
+    /// Reads a patch, named after the file when it has no name.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut patch = Self::parse(&std::fs::read_to_string(path)?)?;
+        if patch.name.is_empty() {
+            if let Some(stem) = path.file_stem() {
+                patch.name = stem.to_string_lossy().into_owned();
+            }
+        }
+        Ok(patch)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Parses a patch of any version up to [`PATCH_VERSION`], migrating older ones.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut table: Table = text.parse().context("Invalid patch")?;
+        let version = match table.get("version") {
+            None => 0,
+            Some(Value::Integer(version)) => {
+                u32::try_from(*version).map_err(|_| anyhow!("Invalid patch version {version}"))?
+            }
+            Some(version) => return Err(anyhow!("Invalid patch version {version}")),
+        };
+        if version > PATCH_VERSION {
+            return Err(anyhow!(
+                "Patch version {version} is newer than the supported version {PATCH_VERSION}"
+            ));
+        }
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut table);
+        }
+        table.insert("version".to_string(), Value::Integer(PATCH_VERSION as i64));
+
+        let patch: Patch = table.try_into().context("Invalid patch")?;
+        patch.values()?;
+        if let Some(tuning) = &patch.tuning {
+            tuning.temperament()?;
+        }
+        Ok(patch)
+    }
+
+    /// Patch files in `dir`, sorted by name. A missing directory has none,
+    /// symbolic links and entries that can't be read are left out.
+    pub fn files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)?.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|file_type| file_type.is_file())
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension == PATCH_EXTENSION)
+            {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&toml::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        effects::{
+            distortion::{BitcrusherSettings, Curve, Oversampling, WaveshaperSettings},
+            dynamics::{CompressorSettings, LimiterSettings},
+            eq::{Band, BandKind, Slope},
+        },
+        notes::Note,
+        tuning::Tuning,
+        waves::Waveform,
+    };
+
+    fn mapped_wave() -> Wave {
+        let mut freqs = [0.0; MIDI_KEYS];
+        for (key, freq) in freqs.iter_mut().enumerate() {
+            *freq = 440.0 * f32::exp2((key as f32 - 69.0) / 19.0);
+        }
+        let mut wave = Wave::new(Waveform::Saw, Note::C4, 0.25);
+        wave.tuning = Tuning::new(440.0).temperament(Temperament::Mapped(Arc::new(freqs)));
+        wave.unison.voices = 3;
+        wave
+    }
+
+    #[test]
+    fn migrates_unversioned_patches() {
+        let patch = Patch::parse("waveform = \"Square\"\namp = 0.4\nmono = true\n").unwrap();
+        assert_eq!(patch.version, PATCH_VERSION);
+        assert_eq!(
+            patch.params.get("waveform"),
+            Some(&ParamValue::Choice("Square".to_string()))
+        );
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        patch.apply_to_wave(&mut wave).unwrap();
+        assert_eq!(wave.waveform, Waveform::Square);
+        assert_eq!(wave.amp, 0.4);
+        assert!(wave.mono.enabled);
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let wave = mapped_wave();
+        let effects = EffectSettings {
+            waveshaper: WaveshaperSettings {
+                curve: Curve::Table(vec![-1.0, -0.5, 0.5, 1.0]),
+                drive: 4.0,
+                mix: 0.5,
+                oversampling: Oversampling::X4,
+            },
+            bitcrusher: BitcrusherSettings {
+                bits: 8,
+                rate: 11025.0,
+            },
+            eq: vec![
+                Band::new(BandKind::LowCut(Slope::Db24), 30.0, 0.0, 0.7),
+                Band::new(BandKind::Peaking, 2500.0, -1.5, 1.0),
+            ],
+            compressor: CompressorSettings {
+                threshold: -18.0,
+                ratio: 3.0,
+                ..Default::default()
+            },
+            limiter: LimiterSettings {
+                ceiling: -0.5,
+                ..Default::default()
+            },
+        };
+        let patch = Patch::from_wave("Nineteen", &wave).effects(effects);
+        let dir = std::env::temp_dir().join(format!("synth-patch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(patch.file_name());
+        patch.save(&path).unwrap();
+        let opened = Patch::open(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let opened = opened.unwrap();
+        assert_eq!(opened, patch);
+
+        let mut loaded = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        opened.apply_to_wave(&mut loaded).unwrap();
+        assert_eq!(loaded, wave);
+    }
+
+    #[test]
+    fn custom_temperaments_are_saved_by_name_with_their_table() {
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 0.5);
+        let offsets = [
+            0.0, -29.3, 3.9, 15.6, -13.7, -2.0, -31.3, 2.0, -27.4, -15.6, 17.6, -11.7,
+        ];
+        wave.tuning.temperament = Temperament::Custom(offsets);
+        let text = Patch::from_wave("Werckmeister", &wave).to_string();
+        assert!(text.contains("temperament = \"Custom\""), "{text}");
+        assert!(text.contains("custom = [0.0, -29.3,"), "{text}");
+
+        let mut loaded = Wave::new(Waveform::Sin, Note::A4, 0.5);
+        Patch::parse(&text)
+            .unwrap()
+            .apply_to_wave(&mut loaded)
+            .unwrap();
+        assert_eq!(loaded.tuning.temperament, Temperament::Custom(offsets));
+
+        let text = Patch::from_wave("Nineteen", &mapped_wave()).to_string();
+        assert!(text.contains("temperament = \"Mapped\""), "{text}");
+    }
+
+    #[test]
+    fn tuning_root_is_saved_by_name() {
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 0.5);
+        wave.tuning = Tuning::new(440.0).temperament(Temperament::Just).root(7);
+        let text = Patch::from_wave("Just in G", &wave).to_string();
+        assert!(text.contains("tuning_root = \"G\""), "{text}");
+
+        let mut loaded = Wave::new(Waveform::Sin, Note::A4, 0.5);
+        Patch::parse(&text)
+            .unwrap()
+            .apply_to_wave(&mut loaded)
+            .unwrap();
+        assert_eq!(loaded.tuning, wave.tuning);
+    }
+
+    #[test]
+    fn rejects_broken_tuning_tables() {
+        for tuning in ["custom = [0, 1]", "mapped = [440]", "equal = true"] {
+            let text =
+                format!("version = 1\n[params]\ntemperament = \"Custom\"\n[tuning]\n{tuning}\n");
+            assert!(Patch::parse(&text).is_err(), "{tuning}");
+        }
+    }
+}
@@ -0,0 +1,7 @@
+/// Renders audio a whole block at a time. Work that only changes between
+/// blocks, such as picking the waveform or computing the phase increment,
+/// is done once per block, and the loops over the buffer can be vectorized.
+pub trait AudioProcessor: Send {
+    /// Overwrites `output` with the next `output.len()` samples.
+    fn process(&mut self, output: &mut [f32]);
+}
@@ -0,0 +1,342 @@
+//! Scala scale (`.scl`) and keyboard mapping (`.kbm`) files, as described in
+//! <https://www.huygens-fokker.org/scala/scl_format.html> and
+//! <https://www.huygens-fokker.org/scala/help.htm#mappings>.
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::tuning::{Temperament, Tuning, MIDI_KEYS};
+
+/// Lines that aren't comments, comments start with `!`.
+fn content_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.starts_with('!'))
+}
+
+/// Parses a pitch written either in cents (`701.955`) or as a ratio (`3/2`, `2`).
+fn parse_pitch(line: &str) -> Result<f32> {
+    let token = line
+        .split_whitespace()
+        .next()
+        .ok_or(anyhow!("Missing pitch"))?;
+    if token.contains('.') {
+        return token
+            .parse::<f32>()
+            .with_context(|| format!("Invalid cents value {token:?}"));
+    }
+    let (numerator, denominator) = token.split_once('/').unwrap_or((token, "1"));
+    let numerator: f32 = numerator
+        .parse()
+        .with_context(|| format!("Invalid ratio {token:?}"))?;
+    let denominator: f32 = denominator
+        .parse()
+        .with_context(|| format!("Invalid ratio {token:?}"))?;
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(anyhow!("Ratio {token:?} must be positive"));
+    }
+    Ok(1200.0 * (numerator / denominator).log2())
+}
+
+/// A scale with any number of degrees per period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    /// Degrees in cents above the root, the last one is the period (usually the octave).
+    pub degrees: Vec<f32>,
+}
+
+impl Scale {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = content_lines(text);
+        let description = lines
+            .next()
+            .ok_or(anyhow!("Missing scale description"))?
+            .trim()
+            .to_string();
+        let count: usize = lines
+            .next()
+            .ok_or(anyhow!("Missing number of notes"))?
+            .trim()
+            .parse()
+            .context("Invalid number of notes")?;
+        let degrees = lines
+            .take(count)
+            .map(parse_pitch)
+            .collect::<Result<Vec<_>>>()?;
+        if degrees.len() != count {
+            return Err(anyhow!(
+                "Expected {count} notes but found {}",
+                degrees.len()
+            ));
+        }
+        if count == 0 {
+            return Err(anyhow!("A scale needs at least one note"));
+        }
+        Ok(Self {
+            description,
+            degrees,
+        })
+    }
+
+    /// `divisions` equal steps per `period` cents, like 19-TET with a 1200 period.
+    pub fn equal(divisions: usize, period: f32) -> Self {
+        let divisions = divisions.max(1);
+        Self {
+            description: format!("{divisions} equal divisions of {period} cents"),
+            degrees: (1..=divisions)
+                .map(|degree| period * degree as f32 / divisions as f32)
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.degrees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.degrees.is_empty()
+    }
+
+    pub fn period(&self) -> f32 {
+        self.degrees.last().copied().unwrap_or(1200.0)
+    }
+
+    /// Cents above the root of any degree, repeating the scale every period.
+    pub fn cents(&self, degree: i32) -> f32 {
+        let len = self.len() as i32;
+        let periods = degree.div_euclid(len);
+        let step = degree.rem_euclid(len) as usize;
+        let cents = if step == 0 {
+            0.0
+        } else {
+            self.degrees[step - 1]
+        };
+        (periods as f32).mul_add(self.period(), cents)
+    }
+}
+
+/// Which scale degree each MIDI key plays and the frequency it is tuned to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    /// Keys per repetition of the mapping, 0 maps keys to consecutive degrees.
+    pub size: usize,
+    pub first_key: u8,
+    pub last_key: u8,
+    /// Key playing degree 0.
+    pub middle_key: u8,
+    pub reference_key: u8,
+    pub reference_freq: f32,
+    /// Degree reached each time the mapping repeats, 0 means the scale size.
+    pub octave_degree: i32,
+    /// Degree for each key in the mapping, `None` for keys left silent.
+    pub map: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMapping {
+    /// Consecutive keys from C4 up, with A4 at 440 Hz.
+    fn default() -> Self {
+        Self {
+            size: 0,
+            first_key: 0,
+            last_key: 127,
+            middle_key: 60,
+            reference_key: 69,
+            reference_freq: 440.0,
+            octave_degree: 0,
+            map: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMapping {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = content_lines(text).map(str::trim);
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .map(|line| line.split_whitespace().next().unwrap_or(""))
+                .ok_or(anyhow!("Missing {name}"))
+        };
+        let size: usize = field("map size")?.parse().context("Invalid map size")?;
+        let first_key = field("first key")?.parse().context("Invalid first key")?;
+        let last_key = field("last key")?.parse().context("Invalid last key")?;
+        let middle_key = field("middle key")?.parse().context("Invalid middle key")?;
+        let reference_key = field("reference key")?
+            .parse()
+            .context("Invalid reference key")?;
+        let reference_freq = field("reference frequency")?
+            .parse()
+            .context("Invalid reference frequency")?;
+        let octave_degree = field("octave degree")?
+            .parse()
+            .context("Invalid octave degree")?;
+        let map = (0..size)
+            .map(|_| match field("mapping entry")? {
+                "x" | "X" => Ok(None),
+                degree => Ok(Some(degree.parse().context("Invalid mapping entry")?)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            size,
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree,
+            map,
+        })
+    }
+
+    /// Scale degree played by `key`, `None` when the key is unmapped.
+    pub fn degree(&self, key: u8, scale: &Scale) -> Option<i32> {
+        if !(self.first_key..=self.last_key).contains(&key) {
+            return None;
+        }
+        let offset = key as i32 - self.middle_key as i32;
+        if self.size == 0 {
+            return Some(offset);
+        }
+        let size = self.size as i32;
+        let octave_degree = match self.octave_degree {
+            0 => scale.len() as i32,
+            degree => degree,
+        };
+        let degree = (*self.map.get(offset.rem_euclid(size) as usize)?)?;
+        Some(degree + offset.div_euclid(size) * octave_degree)
+    }
+
+    /// Frequency of `key`, `None` when the key is unmapped.
+    pub fn freq(&self, key: u8, scale: &Scale) -> Option<f32> {
+        let reference = self
+            .degree(self.reference_key, scale)
+            .unwrap_or(self.reference_key as i32 - self.middle_key as i32);
+        let cents = scale.cents(self.degree(key, scale)?) - scale.cents(reference);
+        Some(self.reference_freq * f32::powf(2.0, cents / 1200.0))
+    }
+
+    /// Tuning with the frequency of every MIDI key, unmapped keys are silent.
+    pub fn tuning(&self, scale: &Scale) -> Tuning {
+        let mut table = [0.0; MIDI_KEYS];
+        for (key, freq) in table.iter_mut().enumerate() {
+            *freq = self.freq(key as u8, scale).unwrap_or(0.0);
+        }
+        Tuning::new(self.reference_freq).temperament(Temperament::Mapped(Arc::new(table)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notes::Note;
+
+    const MEANTONE: &str = "! meantone.scl
+!
+Quarter-comma meantone, 5 notes
+ 5
+!
+ 193.157
+ 5/4
+ 3/2
+ 1082.892
+ 2
+";
+
+    const WHITE_KEYS: &str = "! white keys only, A4 at 432 Hz
+12
+0
+127
+60
+69
+432.0
+7
+! C to B
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-2,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn parses_cents_and_ratios() {
+        let scale = Scale::parse(MEANTONE).unwrap();
+        assert_eq!(scale.description, "Quarter-comma meantone, 5 notes");
+        assert_eq!(scale.len(), 5);
+        let expected = [193.157, 386.314, 701.955, 1082.892, 1200.0];
+        for (degree, expected) in scale.degrees.iter().zip(expected) {
+            assert_close(*degree, expected);
+        }
+        assert_close(scale.cents(0), 0.0);
+        assert_close(scale.cents(7), 1200.0 + 386.314);
+        assert_close(scale.cents(-1), -1200.0 + 1082.892);
+    }
+
+    #[test]
+    fn rejects_broken_scales() {
+        assert!(Scale::parse("Too short\n 3\n 100.0\n 2\n").is_err());
+        assert!(Scale::parse("Negative\n 1\n -3/2\n").is_err());
+        assert!(Scale::parse("Words\n 1\n fifth\n").is_err());
+        assert!(Scale::parse("Empty\n 0\n").is_err());
+    }
+
+    #[test]
+    fn equal_matches_twelve_tet() {
+        let scale = Scale::equal(12, 1200.0);
+        let tuning = KeyboardMapping::default().tuning(&scale);
+        assert_close(tuning.freq(Note::A4), 440.0);
+        assert_close(tuning.freq(Note::C4), 261.626);
+        assert_close(tuning.freq(Note::A5), 880.0);
+    }
+
+    #[test]
+    fn maps_keys_to_degrees() {
+        let scale = Scale::equal(7, 1200.0);
+        let mapping = KeyboardMapping::parse(WHITE_KEYS).unwrap();
+        assert_eq!(mapping.size, 12);
+        assert_eq!(mapping.octave_degree, 7);
+        assert_eq!(mapping.degree(60, &scale), Some(0));
+        assert_eq!(mapping.degree(61, &scale), None);
+        assert_eq!(mapping.degree(71, &scale), Some(6));
+        assert_eq!(mapping.degree(72, &scale), Some(7));
+        assert_eq!(mapping.degree(59, &scale), Some(-1));
+        assert_close(mapping.freq(69, &scale).unwrap(), 432.0);
+        assert_eq!(mapping.freq(70, &scale), None);
+
+        let tuning = mapping.tuning(&scale);
+        assert!(matches!(tuning.temperament, Temperament::Mapped(_)));
+        assert_close(tuning.freq(Note::A4), 432.0);
+        assert_eq!(tuning.freq(Note::B4b), 0.0);
+        let step = f32::powf(2.0, 1.0 / 7.0);
+        assert_close(tuning.freq(Note::B4), 432.0 * step);
+    }
+
+    #[test]
+    fn rejects_broken_mappings() {
+        assert!(KeyboardMapping::parse("2\n0\n127\n60\n69\n440.0\n0\n0\n").is_err());
+        assert!(KeyboardMapping::parse("1\n0\n127\n60\n69\n440.0\n0\ny\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n300\n60\n69\n440.0\n0\n").is_err());
+    }
+}
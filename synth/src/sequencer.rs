@@ -0,0 +1,331 @@
+pub mod pattern;
+
+use crossbeam_channel::Receiver;
+
+use crate::{
+    events::{EventSource, SynthEvent},
+    meter::Meter,
+    notes::Note,
+    sequencer::pattern::{Pattern, MAX_TRACKS},
+    transport::Position,
+};
+
+/// Steps counted before wrapping, every track length divides it.
+const CYCLE_STEPS: usize = 64;
+
+/// Changes sent to a running sequencer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SequencerCommand {
+    Play,
+    /// Stops and goes back to the first step.
+    Stop,
+    /// Replaces the pattern without losing the position.
+    Pattern(Pattern),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TrackVoice {
+    sounding: Option<Note>,
+    tied: bool,
+    /// Samples left until the sounding note is released.
+    gate_countdown: f64,
+}
+
+/// Receives the events of a track along with the track number.
+type Emit<'a> = dyn FnMut(usize, SynthEvent) + 'a;
+
+/// Plays a pattern from inside the audio thread, one sixteenth note per step.
+/// Each track plays on a voice of its own, see [`EventSource::poll_voices`].
+pub struct Sequencer {
+    pattern: Pattern,
+    commands: Option<Receiver<SequencerCommand>>,
+    sample_rate: f32,
+    playing: bool,
+    step: usize,
+    /// Samples left until the next step.
+    countdown: f64,
+    /// One per track a pattern can have, so changing patterns doesn't allocate.
+    voices: [TrackVoice; MAX_TRACKS],
+    position: Meter,
+    rng: u32,
+    /// Follows the transport instead of the pattern tempo.
+    synced: bool,
+    transport: Position,
+    /// Transport step (sixteenth note) last played when synced.
+    transport_step: Option<u64>,
+}
+
+impl Sequencer {
+    pub fn new(pattern: Pattern) -> Self {
+        Self {
+            voices: [TrackVoice::default(); MAX_TRACKS],
+            pattern,
+            commands: None,
+            sample_rate: 44100.0,
+            playing: false,
+            step: 0,
+            countdown: 1.0,
+            position: Meter::new(),
+            rng: 0x2545_F491,
+            synced: false,
+            transport: Position::default(),
+            transport_step: None,
+        }
+    }
+
+    /// Receives commands from another thread, such as the UI.
+    pub fn commands(mut self, rx: Receiver<SequencerCommand>) -> Self {
+        self.commands = Some(rx);
+        self
+    }
+
+    /// Plays and stops with the transport, at its tempo and position,
+    /// instead of the pattern tempo and the play and stop commands.
+    pub fn synced(mut self, synced: bool) -> Self {
+        self.synced = synced;
+        self
+    }
+
+    pub fn playing(mut self, playing: bool) -> Self {
+        self.playing = playing;
+        self
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Step last played, counted from the start of a 64 step cycle. Tracks
+    /// shorter than that are at this step modulo their length.
+    pub fn position(&self) -> Meter {
+        self.position.clone()
+    }
+
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32
+    }
+
+    /// Releases the notes whose gate is over.
+    fn release_gates(&mut self, emit: &mut Emit) {
+        for (track, voice) in self.voices.iter_mut().enumerate() {
+            if voice.tied {
+                continue;
+            }
+            if let Some(note) = voice.sounding {
+                voice.gate_countdown -= 1.0;
+                if voice.gate_countdown <= 0.0 {
+                    emit(track, SynthEvent::NoteOff { note });
+                    voice.sounding = None;
+                }
+            }
+        }
+    }
+
+    fn play_step(&mut self, step_length: f64, emit: &mut Emit) {
+        for track in 0..self.pattern.tracks.len().min(MAX_TRACKS) {
+            self.trigger(track, step_length, emit);
+        }
+        self.position.set(self.step as f32);
+    }
+
+    /// Plays the step the transport just reached, if any.
+    fn poll_synced(&mut self, emit: &mut Emit) {
+        let transport = self.transport;
+        if !transport.playing {
+            if self.transport_step.take().is_some() {
+                self.release_all(emit);
+            }
+            return;
+        }
+        self.release_gates(emit);
+        let step = (transport.beat * 4.0) as u64;
+        if self.transport_step == Some(step) {
+            return;
+        }
+        self.transport_step = Some(step);
+        self.step = (step % CYCLE_STEPS as u64) as usize;
+        let step_length = 60.0 / transport.bpm / 4.0 * self.sample_rate as f64;
+        self.play_step(step_length, emit);
+    }
+
+    fn release_all(&mut self, emit: &mut Emit) {
+        for (track, voice) in self.voices.iter_mut().enumerate() {
+            if let Some(note) = voice.sounding.take() {
+                emit(track, SynthEvent::NoteOff { note });
+            }
+            voice.tied = false;
+        }
+    }
+
+    fn apply(&mut self, command: SequencerCommand, emit: &mut Emit) {
+        match command {
+            SequencerCommand::Play => self.playing = true,
+            SequencerCommand::Stop => {
+                self.release_all(emit);
+                self.playing = false;
+                self.step = 0;
+                self.countdown = 1.0;
+            }
+            SequencerCommand::Pattern(pattern) => {
+                let tracks = self.voices.iter_mut().enumerate();
+                for (track, voice) in tracks.skip(pattern.tracks.len()) {
+                    if let Some(note) = voice.sounding.take() {
+                        emit(track, SynthEvent::NoteOff { note });
+                    }
+                    voice.tied = false;
+                }
+                self.pattern = pattern;
+            }
+        }
+    }
+
+    /// Plays the step of `track` due now.
+    fn trigger(&mut self, track: usize, step_length: f64, emit: &mut Emit) {
+        let steps = self.pattern.tracks[track].steps();
+        let step = steps[self.step % steps.len()];
+        let plays = step.active && (step.probability >= 1.0 || self.random() < step.probability);
+        let voice = &mut self.voices[track];
+
+        match voice.sounding {
+            // A tie into the same note keeps it sounding.
+            Some(previous) if voice.tied && plays && previous == step.note => (),
+            Some(previous) if voice.tied && plays => {
+                // Note on before note off so the oscilator slides legato.
+                emit(
+                    track,
+                    SynthEvent::NoteOn {
+                        note: step.note,
+                        velocity: step.velocity,
+                    },
+                );
+                emit(track, SynthEvent::NoteOff { note: previous });
+            }
+            Some(previous) => {
+                emit(track, SynthEvent::NoteOff { note: previous });
+                if plays {
+                    emit(
+                        track,
+                        SynthEvent::NoteOn {
+                            note: step.note,
+                            velocity: step.velocity,
+                        },
+                    );
+                }
+            }
+            None if plays => emit(
+                track,
+                SynthEvent::NoteOn {
+                    note: step.note,
+                    velocity: step.velocity,
+                },
+            ),
+            None => (),
+        }
+
+        if plays {
+            voice.sounding = Some(step.note);
+            voice.tied = step.tie;
+            voice.gate_countdown = (step_length * step.gate.clamp(0.0, 1.0) as f64).max(1.0);
+        } else {
+            voice.sounding = None;
+            voice.tied = false;
+        }
+    }
+}
+
+impl EventSource for Sequencer {
+    fn prepare(&mut self, sample_rate: f32) {
+        self.countdown *= sample_rate as f64 / self.sample_rate as f64;
+        self.sample_rate = sample_rate;
+    }
+
+    fn sync(&mut self, position: &Position) {
+        self.transport = *position;
+    }
+
+    /// Plays every track on the main oscilator.
+    fn poll(&mut self, emit: &mut dyn FnMut(SynthEvent)) {
+        self.poll_voices(&mut |_, event| emit(event));
+    }
+
+    fn poll_voices(&mut self, emit: &mut dyn FnMut(Option<usize>, SynthEvent)) {
+        let emit = &mut |track, event| emit(Some(track), event);
+        while let Some(command) = self.commands.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.apply(command, emit);
+        }
+        if self.synced {
+            self.poll_synced(emit);
+            return;
+        }
+        if !self.playing {
+            return;
+        }
+
+        self.release_gates(emit);
+        self.countdown -= 1.0;
+        if self.countdown > 0.0 {
+            return;
+        }
+        let step_length = self.pattern.step_length(self.sample_rate);
+        self.countdown += step_length;
+        self.play_step(step_length, emit);
+        self.step = (self.step + 1) % CYCLE_STEPS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequencer::pattern::{Step, Track};
+
+    /// Two tracks, each playing `notes` on their first step.
+    fn chord(notes: [Note; 2]) -> Pattern {
+        let mut pattern = Pattern {
+            bpm: 120.0,
+            tracks: vec![Track::default(), Track::default()],
+        };
+        for (track, note) in pattern.tracks.iter_mut().zip(notes) {
+            track.steps_mut()[0] = Step::new(note);
+        }
+        pattern
+    }
+
+    fn poll(sequencer: &mut Sequencer) -> Vec<(Option<usize>, SynthEvent)> {
+        let mut events = Vec::new();
+        sequencer.poll_voices(&mut |voice, event| events.push((voice, event)));
+        events
+    }
+
+    #[test]
+    fn tracks_play_on_their_own_voices() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut sequencer = Sequencer::new(chord([Note::C4, Note::E4]))
+            .commands(rx)
+            .playing(true);
+        let note_on = |note| SynthEvent::NoteOn {
+            note,
+            velocity: 0.8,
+        };
+        assert_eq!(
+            poll(&mut sequencer),
+            [(Some(0), note_on(Note::C4)), (Some(1), note_on(Note::E4))]
+        );
+
+        // Dropping the second track releases its note.
+        let mut pattern = chord([Note::C4, Note::E4]);
+        pattern.tracks.pop();
+        tx.send(SequencerCommand::Pattern(pattern)).unwrap();
+        assert_eq!(
+            poll(&mut sequencer),
+            [(Some(1), SynthEvent::NoteOff { note: Note::E4 })]
+        );
+    }
+}
@@ -0,0 +1,333 @@
+//! Patterns are stored as plain text, one step per line:
+//!
+//! ```text
+//! ! Comments start with `!`
+//! bpm 120
+//! track 16
+//! + C4 0.80 0.50 1.00
+//! - C4 0.80 0.50 1.00
+//! + E4 0.80 1.00 0.50 tie
+//! ```
+//!
+//! Each step starts with `+` when it plays or `-` for a rest, followed by the
+//! note, velocity, gate and probability, and `tie` when it is tied to the next step.
+
+use std::{fmt, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::notes::Note;
+
+pub const MAX_TRACKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    /// Rests keep their note, so a step can be muted and turned back on.
+    pub active: bool,
+    pub note: Note,
+    /// From `0.0` to `1.0`.
+    pub velocity: f32,
+    /// Fraction of the step the note is held.
+    pub gate: f32,
+    /// Holds the note into the next step, which plays legato instead of retriggering.
+    pub tie: bool,
+    /// Chance of the step playing, from `0.0` to `1.0`.
+    pub probability: f32,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            active: false,
+            note: Note::C4,
+            velocity: 0.8,
+            gate: 0.5,
+            tie: false,
+            probability: 1.0,
+        }
+    }
+}
+
+impl Step {
+    pub fn new(note: Note) -> Self {
+        Self {
+            active: true,
+            note,
+            ..Default::default()
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let mut tokens = line.split_whitespace();
+        let mut field = |name: &str| tokens.next().ok_or(anyhow!("Missing step {name}"));
+        let active = match field("state")? {
+            "+" => true,
+            "-" => false,
+            state => return Err(anyhow!("Invalid step state {state:?}, expected + or -")),
+        };
+        let note = field("note")?.parse()?;
+        let velocity = field("velocity")?.parse().context("Invalid velocity")?;
+        let gate = field("gate")?.parse().context("Invalid gate")?;
+        let probability = field("probability")?
+            .parse()
+            .context("Invalid probability")?;
+        let tie = match tokens.next() {
+            None => false,
+            Some("tie") => true,
+            Some(token) => return Err(anyhow!("Unexpected {token:?} after step")),
+        };
+        Ok(Self {
+            active,
+            note,
+            velocity,
+            gate,
+            tie,
+            probability,
+        })
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:.2} {:.2} {:.2}",
+            if self.active { '+' } else { '-' },
+            self.note,
+            self.velocity,
+            self.gate,
+            self.probability
+        )?;
+        if self.tie {
+            write!(f, " tie")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrackLength {
+    #[default]
+    Steps16,
+    Steps32,
+    Steps64,
+}
+
+impl TrackLength {
+    pub const ALL: [TrackLength; 3] = [
+        TrackLength::Steps16,
+        TrackLength::Steps32,
+        TrackLength::Steps64,
+    ];
+
+    pub fn steps(&self) -> usize {
+        match self {
+            TrackLength::Steps16 => 16,
+            TrackLength::Steps32 => 32,
+            TrackLength::Steps64 => 64,
+        }
+    }
+
+    pub fn from_steps(steps: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|length| length.steps() == steps)
+    }
+}
+
+/// A row of sixteenth note steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    steps: Vec<Step>,
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self::new(TrackLength::default())
+    }
+}
+
+impl Track {
+    pub fn new(length: TrackLength) -> Self {
+        Self {
+            steps: vec![Step::default(); length.steps()],
+        }
+    }
+
+    pub fn length(&self) -> TrackLength {
+        TrackLength::from_steps(self.steps.len()).unwrap_or_default()
+    }
+
+    /// Changes the number of steps, new steps are rests.
+    pub fn set_length(&mut self, length: TrackLength) {
+        self.steps.resize(length.steps(), Step::default());
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn steps_mut(&mut self) -> &mut [Step] {
+        &mut self.steps
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub bpm: f32,
+    pub tracks: Vec<Track>,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            tracks: vec![Track::default()],
+        }
+    }
+}
+
+impl Pattern {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('!'));
+        let bpm = lines
+            .next()
+            .and_then(|line| line.strip_prefix("bpm"))
+            .ok_or(anyhow!("Missing bpm"))?
+            .trim()
+            .parse()
+            .context("Invalid bpm")?;
+
+        let mut tracks = Vec::new();
+        while let Some(line) = lines.next() {
+            let steps: usize = line
+                .strip_prefix("track")
+                .ok_or(anyhow!("Expected a track but found {line:?}"))?
+                .trim()
+                .parse()
+                .context("Invalid track length")?;
+            let length = TrackLength::from_steps(steps)
+                .ok_or(anyhow!("Tracks have 16, 32 or 64 steps, not {steps}"))?;
+            let steps = lines
+                .by_ref()
+                .take(length.steps())
+                .map(Step::parse)
+                .collect::<Result<Vec<_>>>()?;
+            if steps.len() != length.steps() {
+                return Err(anyhow!(
+                    "Expected {} steps but found {}",
+                    length.steps(),
+                    steps.len()
+                ));
+            }
+            tracks.push(Track { steps });
+        }
+        if tracks.is_empty() || tracks.len() > MAX_TRACKS {
+            return Err(anyhow!("A pattern has 1 to {MAX_TRACKS} tracks"));
+        }
+        Ok(Self { bpm, tracks })
+    }
+
+    /// Length of a sixteenth note step in samples.
+    pub fn step_length(&self, sample_rate: f32) -> f64 {
+        60.0 / self.bpm.max(1.0) as f64 / 4.0 * sample_rate as f64
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bpm {}", self.bpm)?;
+        for track in &self.tracks {
+            writeln!(f, "track {}", track.steps.len())?;
+            for step in &track.steps {
+                writeln!(f, "{step}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text of a 16 step track, `steps` followed by rests.
+    fn track_text(steps: &[&str]) -> String {
+        let rests = std::iter::repeat_n("- C4 0.80 0.50 1.00", 16 - steps.len());
+        let lines: Vec<&str> = steps.iter().copied().chain(rests).collect();
+        format!("track 16\n{}\n", lines.join("\n"))
+    }
+
+    fn pattern_text(steps: &[&str]) -> String {
+        format!("! A comment\nbpm 98.5\n\n{}", track_text(steps))
+    }
+
+    #[test]
+    fn parses_steps() {
+        let text = pattern_text(&["+ C4 0.80 0.50 1.00", "+ E4 1 1 0.25 tie", "- G4 0.5 0.5 1"]);
+        let pattern = Pattern::parse(&text).unwrap();
+        assert_eq!(pattern.bpm, 98.5);
+        assert_eq!(pattern.tracks.len(), 1);
+        let steps = pattern.tracks[0].steps();
+        assert_eq!(steps.len(), 16);
+        assert_eq!(steps[0], Step::new(Note::C4));
+        assert_eq!(
+            steps[1],
+            Step {
+                active: true,
+                note: Note::E4,
+                velocity: 1.0,
+                gate: 1.0,
+                tie: true,
+                probability: 0.25,
+            }
+        );
+        assert!(!steps[2].active);
+        assert_eq!(steps[2].note, Note::G4);
+        assert!(steps[3..].iter().all(|step| !step.active));
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let mut pattern = Pattern {
+            bpm: 132.0,
+            tracks: vec![Track::new(TrackLength::Steps32), Track::default()],
+        };
+        pattern.tracks[0].steps_mut()[5] = Step {
+            tie: true,
+            probability: 0.5,
+            ..Step::new(Note::B3b)
+        };
+        pattern.tracks[1].steps_mut()[15] = Step::new(Note::C5);
+        assert_eq!(Pattern::parse(&pattern.to_string()).unwrap(), pattern);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let full_track = pattern_text(&[]);
+        let nine_tracks = format!("bpm 120\n{}", track_text(&[]).repeat(MAX_TRACKS + 1));
+        for text in [
+            "track 16\n".to_string(),
+            "bpm fast\n".to_string(),
+            "bpm 120\n".to_string(),
+            full_track.replace("track 16", "track 12"),
+            full_track.replacen("- C4 0.80 0.50 1.00\n", "", 1),
+            full_track.replacen("- C4", "* C4", 1),
+            full_track.replacen("- C4", "- H4", 1),
+            full_track.replacen("1.00", "1.00 slide", 1),
+            nine_tracks,
+        ] {
+            assert!(Pattern::parse(&text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,544 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl TimeSignature {
+    pub fn new(numerator: u8, denominator: u8) -> Self {
+        Self {
+            numerator: numerator.max(1),
+            denominator: denominator.max(1),
+        }
+    }
+
+    /// Length of a bar in beats (quarter notes).
+    pub fn beats_per_bar(&self) -> f64 {
+        self.numerator as f64 * 4.0 / self.denominator as f64
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoChange {
+    /// Beat (quarter note) the tempo starts at.
+    pub beat: f64,
+    pub bpm: f64,
+}
+
+/// Tempo over time, as steps at given beats.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    /// Sorted by beat, the first change is always at beat 0.
+    changes: Vec<TempoChange>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::new(120.0)
+    }
+}
+
+impl TempoMap {
+    pub fn new(bpm: f64) -> Self {
+        Self {
+            changes: vec![TempoChange {
+                beat: 0.0,
+                bpm: bpm.max(1.0),
+            }],
+        }
+    }
+
+    /// Changes the tempo from `beat` on.
+    pub fn with_change(mut self, beat: f64, bpm: f64) -> Self {
+        let beat = beat.max(0.0);
+        self.changes.retain(|change| change.beat != beat);
+        let index = self.changes.partition_point(|change| change.beat < beat);
+        self.changes.insert(
+            index,
+            TempoChange {
+                beat,
+                bpm: bpm.max(1.0),
+            },
+        );
+        self
+    }
+
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    fn segment(&self, beat: f64) -> usize {
+        self.changes
+            .partition_point(|change| change.beat <= beat)
+            .saturating_sub(1)
+    }
+
+    pub fn bpm_at(&self, beat: f64) -> f64 {
+        self.changes[self.segment(beat)].bpm
+    }
+
+    /// Time in seconds from beat 0 to `beat`.
+    pub fn seconds_at(&self, beat: f64) -> f64 {
+        let mut seconds = 0.0;
+        for (index, change) in self.changes.iter().enumerate() {
+            let end = self
+                .changes
+                .get(index + 1)
+                .map_or(beat, |next| next.beat.min(beat));
+            if end <= change.beat {
+                break;
+            }
+            seconds += (end - change.beat) * 60.0 / change.bpm;
+        }
+        seconds
+    }
+
+    /// Beat reached `seconds` after beat 0.
+    pub fn beat_at(&self, seconds: f64) -> f64 {
+        let mut start = 0.0;
+        for (index, change) in self.changes.iter().enumerate() {
+            let beat = change.beat + (seconds - start) * change.bpm / 60.0;
+            match self.changes.get(index + 1) {
+                Some(next) if beat >= next.beat => {
+                    start += (next.beat - change.beat) * 60.0 / change.bpm;
+                }
+                _ => return beat,
+            }
+        }
+        0.0
+    }
+}
+
+/// Where the transport is, as seen on a single frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    pub playing: bool,
+    /// Frames since beat 0.
+    pub frame: u64,
+    /// Beats (quarter notes) since the start, fractions included.
+    pub beat: f64,
+    pub bpm: f64,
+    pub time_signature: TimeSignature,
+    /// Time until the frame is heard, as last measured by the audio callback.
+    pub latency: Duration,
+}
+
+impl Position {
+    /// Beats played while a frame goes through the output latency.
+    pub fn latency_beats(&self) -> f64 {
+        self.latency.as_secs_f64() * self.bpm / 60.0
+    }
+
+    /// Bar, counted from 0.
+    pub fn bar(&self) -> u64 {
+        (self.beat / self.time_signature.beats_per_bar()) as u64
+    }
+
+    /// Beat within the bar, counted from 0.
+    pub fn beat_in_bar(&self) -> f64 {
+        self.beat % self.time_signature.beats_per_bar()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TransportCommand {
+    Play,
+    Stop,
+    Locate(f64),
+    TempoMap(TempoMap),
+    FollowBpm(Option<f64>),
+    TimeSignature(TimeSignature),
+}
+
+/// Position published by the audio thread for the control thread.
+#[derive(Debug, Default)]
+struct Shared {
+    playing: AtomicBool,
+    frame: AtomicU64,
+    /// `f64` bits.
+    beat: AtomicU64,
+    /// `f64` bits.
+    bpm: AtomicU64,
+    /// Numerator in the high byte, denominator in the low one.
+    time_signature: AtomicU32,
+    latency_nanos: AtomicU64,
+}
+
+/// Musical time, advanced one frame at a time by the audio engine.
+#[derive(Debug)]
+pub struct Transport {
+    sample_rate: f64,
+    tempo_map: TempoMap,
+    /// Tempo of a clock followed instead of the tempo map, which is kept
+    /// for when the clock goes away.
+    follow_bpm: Option<f64>,
+    time_signature: TimeSignature,
+    playing: bool,
+    frame: u64,
+    beat: f64,
+    latency: Duration,
+    tx: Sender<TransportCommand>,
+    rx: Receiver<TransportCommand>,
+    shared: Arc<Shared>,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport {
+    /// Stopped at beat 0, 120 BPM in 4/4.
+    pub fn new() -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let transport = Self {
+            sample_rate: 44100.0,
+            tempo_map: TempoMap::default(),
+            follow_bpm: None,
+            time_signature: TimeSignature::default(),
+            playing: false,
+            frame: 0,
+            beat: 0.0,
+            latency: Duration::ZERO,
+            tx,
+            rx,
+            shared: Arc::default(),
+        };
+        transport.publish();
+        transport
+    }
+
+    pub fn tempo_map(mut self, tempo_map: TempoMap) -> Self {
+        self.set_tempo_map(tempo_map);
+        self
+    }
+
+    pub fn time_signature(mut self, time_signature: TimeSignature) -> Self {
+        self.time_signature = time_signature;
+        self.publish();
+        self
+    }
+
+    /// Handle to control and follow the transport from other threads.
+    pub fn handle(&self) -> TransportHandle {
+        TransportHandle {
+            tx: self.tx.clone(),
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    pub fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate as f64;
+        self.locate(self.beat);
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            playing: self.playing,
+            frame: self.frame,
+            beat: self.beat,
+            bpm: self.bpm(),
+            time_signature: self.time_signature,
+            latency: self.latency,
+        }
+    }
+
+    /// Tempo at the current beat.
+    pub fn bpm(&self) -> f64 {
+        self.follow_bpm
+            .unwrap_or_else(|| self.tempo_map.bpm_at(self.beat))
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    /// Pauses, keeping the position.
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Moves to `beat`.
+    pub fn locate(&mut self, beat: f64) {
+        let beat = beat.max(0.0);
+        self.frame = (self.tempo_map.seconds_at(beat) * self.sample_rate).round() as u64;
+        self.beat = beat;
+    }
+
+    pub fn set_tempo_map(&mut self, tempo_map: TempoMap) {
+        self.tempo_map = tempo_map;
+        // Keep the musical position, the time it falls on moves with the tempo.
+        self.locate(self.beat);
+    }
+
+    /// Plays at `bpm` instead of the tempo map, to follow an external clock.
+    /// `None` goes back to the tempo map, which is left untouched.
+    pub fn follow_bpm(&mut self, bpm: Option<f64>) {
+        self.follow_bpm = bpm.map(|bpm| bpm.max(1.0));
+        if bpm.is_none() {
+            self.locate(self.beat);
+        }
+    }
+
+    /// Applies the commands sent from other threads and publishes the
+    /// position, once per audio block. `latency` is the time until the
+    /// block is heard.
+    pub fn update(&mut self, latency: Option<Duration>) {
+        while let Ok(command) = self.rx.try_recv() {
+            match command {
+                TransportCommand::Play => self.play(),
+                TransportCommand::Stop => self.stop(),
+                TransportCommand::Locate(beat) => self.locate(beat),
+                TransportCommand::TempoMap(tempo_map) => self.set_tempo_map(tempo_map),
+                TransportCommand::FollowBpm(bpm) => self.follow_bpm(bpm),
+                TransportCommand::TimeSignature(time_signature) => {
+                    self.time_signature = time_signature
+                }
+            }
+        }
+        if let Some(latency) = latency {
+            self.latency = latency;
+            self.shared
+                .latency_nanos
+                .store(latency.as_nanos() as u64, Ordering::Relaxed);
+        }
+        self.publish();
+    }
+
+    /// Moves forward by `frames` when playing.
+    pub fn advance(&mut self, frames: u64) {
+        if !self.playing {
+            return;
+        }
+        self.frame += frames;
+        match self.follow_bpm {
+            Some(bpm) => self.beat += frames as f64 / self.sample_rate * bpm / 60.0,
+            // Derived from the frame count so rounding errors don't add up.
+            None => self.beat = self.tempo_map.beat_at(self.frame as f64 / self.sample_rate),
+        }
+    }
+
+    fn publish(&self) {
+        let shared = &self.shared;
+        shared.playing.store(self.playing, Ordering::Relaxed);
+        shared.frame.store(self.frame, Ordering::Relaxed);
+        shared.beat.store(self.beat.to_bits(), Ordering::Relaxed);
+        let bpm = self.bpm();
+        shared.bpm.store(bpm.to_bits(), Ordering::Relaxed);
+        let time_signature =
+            (self.time_signature.numerator as u32) << 8 | self.time_signature.denominator as u32;
+        shared
+            .time_signature
+            .store(time_signature, Ordering::Relaxed);
+    }
+}
+
+/// Controls a transport running in the audio thread and reads its position.
+#[derive(Debug, Clone)]
+pub struct TransportHandle {
+    tx: Sender<TransportCommand>,
+    shared: Arc<Shared>,
+}
+
+impl TransportHandle {
+    fn send(&self, command: TransportCommand) -> Result<()> {
+        self.tx
+            .send(command)
+            .map_err(|_| anyhow!("The transport is no longer running"))
+    }
+
+    pub fn play(&self) -> Result<()> {
+        self.send(TransportCommand::Play)
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.send(TransportCommand::Stop)
+    }
+
+    pub fn locate(&self, beat: f64) -> Result<()> {
+        self.send(TransportCommand::Locate(beat))
+    }
+
+    /// Replaces the tempo map with a constant tempo.
+    pub fn set_bpm(&self, bpm: f64) -> Result<()> {
+        self.send(TransportCommand::TempoMap(TempoMap::new(bpm)))
+    }
+
+    pub fn set_tempo_map(&self, tempo_map: TempoMap) -> Result<()> {
+        self.send(TransportCommand::TempoMap(tempo_map))
+    }
+
+    /// Plays at `bpm` instead of the tempo map until `None`, see
+    /// [`Transport::follow_bpm`].
+    pub fn follow_bpm(&self, bpm: Option<f64>) -> Result<()> {
+        self.send(TransportCommand::FollowBpm(bpm))
+    }
+
+    pub fn set_time_signature(&self, time_signature: TimeSignature) -> Result<()> {
+        self.send(TransportCommand::TimeSignature(time_signature))
+    }
+
+    /// Position at the start of the last audio block.
+    pub fn position(&self) -> Position {
+        let shared = &self.shared;
+        let time_signature = shared.time_signature.load(Ordering::Relaxed);
+        Position {
+            playing: shared.playing.load(Ordering::Relaxed),
+            frame: shared.frame.load(Ordering::Relaxed),
+            beat: f64::from_bits(shared.beat.load(Ordering::Relaxed)),
+            bpm: f64::from_bits(shared.bpm.load(Ordering::Relaxed)),
+            time_signature: TimeSignature::new((time_signature >> 8) as u8, time_signature as u8),
+            latency: self.latency(),
+        }
+    }
+
+    /// Time between the audio callback and the output reaching the speakers.
+    pub fn latency(&self) -> Duration {
+        Duration::from_nanos(self.shared.latency_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Beat being heard right now, behind the position by the output latency.
+    pub fn audible_beat(&self) -> f64 {
+        let position = self.position();
+        if !position.playing {
+            return position.beat;
+        }
+        (position.beat - position.latency_beats()).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 120 BPM, slowing to 60 at beat 8 and speeding up to 240 at beat 16.
+    fn tempo_map() -> TempoMap {
+        TempoMap::new(120.0)
+            .with_change(16.0, 240.0)
+            .with_change(8.0, 60.0)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn converts_beats_and_seconds_across_tempo_changes() {
+        let map = tempo_map();
+        assert_eq!(map.changes().len(), 3);
+        for (beat, seconds) in [
+            (0.0, 0.0),
+            (4.0, 2.0),
+            (8.0, 4.0),
+            (10.0, 6.0),
+            (16.0, 12.0),
+            (20.0, 13.0),
+        ] {
+            assert!(close(map.seconds_at(beat), seconds), "beat {beat}");
+            assert!(close(map.beat_at(seconds), beat), "{seconds} s");
+        }
+        assert_eq!(map.bpm_at(7.9), 120.0);
+        assert_eq!(map.bpm_at(8.0), 60.0);
+        assert_eq!(map.bpm_at(100.0), 240.0);
+    }
+
+    #[test]
+    fn beats_round_trip_through_seconds() {
+        let map = tempo_map();
+        for step in 0..100 {
+            let beat = step as f64 * 0.37;
+            assert!(
+                close(map.beat_at(map.seconds_at(beat)), beat),
+                "beat {beat}"
+            );
+        }
+    }
+
+    #[test]
+    fn counts_bars_and_beats_in_the_time_signature() {
+        assert_eq!(TimeSignature::new(4, 4).beats_per_bar(), 4.0);
+        assert_eq!(TimeSignature::new(6, 8).beats_per_bar(), 3.0);
+        assert_eq!(TimeSignature::new(7, 4).beats_per_bar(), 7.0);
+        assert_eq!(TimeSignature::new(0, 0), TimeSignature::new(1, 1));
+
+        let position = Position {
+            beat: 7.5,
+            time_signature: TimeSignature::new(6, 8),
+            ..Default::default()
+        };
+        assert_eq!(position.bar(), 2);
+        assert_eq!(position.beat_in_bar(), 1.5);
+        let position = Position {
+            time_signature: TimeSignature::new(4, 4),
+            ..position
+        };
+        assert_eq!(position.bar(), 1);
+        assert_eq!(position.beat_in_bar(), 3.5);
+    }
+
+    #[test]
+    fn advances_only_while_playing() {
+        let mut transport = Transport::new().tempo_map(tempo_map());
+        transport.prepare(1000.0);
+        transport.advance(1000);
+        assert_eq!(transport.position().frame, 0);
+
+        transport.play();
+        transport.advance(1000);
+        assert_eq!(transport.position().frame, 1000);
+        assert!(close(transport.position().beat, 2.0));
+        // Into the slower tempo, a second is a single beat.
+        transport.advance(4000);
+        assert!(close(transport.position().beat, 9.0));
+        assert_eq!(transport.bpm(), 60.0);
+
+        transport.stop();
+        transport.advance(1000);
+        assert_eq!(transport.position().frame, 5000);
+    }
+
+    #[test]
+    fn locates_to_the_frame_of_a_beat() {
+        let mut transport = Transport::new().tempo_map(tempo_map());
+        transport.prepare(1000.0);
+        transport.locate(10.0);
+        assert_eq!(transport.position().frame, 6000);
+        transport.locate(-1.0);
+        assert_eq!(transport.position().frame, 0);
+        assert_eq!(transport.position().beat, 0.0);
+
+        // Following a clock, then going back to the tempo map.
+        transport.locate(4.0);
+        transport.play();
+        transport.follow_bpm(Some(60.0));
+        transport.advance(1000);
+        assert!(close(transport.position().beat, 5.0));
+        transport.follow_bpm(None);
+        assert_eq!(transport.position().frame, 2500);
+    }
+}
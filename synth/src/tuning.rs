@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use crate::notes::Note;
+
+/// Number of MIDI keys, one per [`Note`].
+pub const MIDI_KEYS: usize = 128;
+
+const JUST_RATIOS: [f32; 12] = [
+    1.0,
+    16.0 / 15.0,
+    9.0 / 8.0,
+    6.0 / 5.0,
+    5.0 / 4.0,
+    4.0 / 3.0,
+    45.0 / 32.0,
+    3.0 / 2.0,
+    8.0 / 5.0,
+    5.0 / 3.0,
+    9.0 / 5.0,
+    15.0 / 8.0,
+];
+
+const PYTHAGOREAN_RATIOS: [f32; 12] = [
+    1.0,
+    256.0 / 243.0,
+    9.0 / 8.0,
+    32.0 / 27.0,
+    81.0 / 64.0,
+    4.0 / 3.0,
+    729.0 / 512.0,
+    3.0 / 2.0,
+    128.0 / 81.0,
+    27.0 / 16.0,
+    16.0 / 9.0,
+    243.0 / 128.0,
+];
+
+/// Fifth of quarter-comma meantone, a quarter of a syntonic comma narrower than pure.
+const MEANTONE_FIFTH: f32 = 696.578_4;
+
+/// Number of fifths from the root to each degree, the wolf fifth sits between G# and Eb.
+const MEANTONE_FIFTHS: [i32; 12] = [0, 7, 2, -3, 4, -1, 6, 1, 8, 3, -2, 5];
+
+fn ratio_cents(ratio: f32) -> f32 {
+    1200.0 * ratio.log2()
+}
+
+/// How the octave is divided into twelve notes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Temperament {
+    /// 12-TET, every semitone is 100 cents.
+    #[default]
+    Equal,
+    /// 5-limit just intonation built on the tuning root.
+    Just,
+    /// Stacked pure fifths from the tuning root.
+    Pythagorean,
+    /// Quarter-comma meantone from the tuning root.
+    Meantone,
+    /// Offset in cents from 12-TET for each pitch class, starting at C. The root is ignored.
+    Custom([f32; 12]),
+    /// Frequency in Hz of every MIDI key, usually built from a Scala scale and
+    /// keyboard mapping. Both the root and the reference are ignored. The
+    /// table is shared, so cloning the temperament doesn't allocate.
+    Mapped(Arc<[f32; MIDI_KEYS]>),
+}
+
+impl Temperament {
+    pub const ALL: [Temperament; 4] = [
+        Temperament::Equal,
+        Temperament::Just,
+        Temperament::Pythagorean,
+        Temperament::Meantone,
+    ];
+
+    /// Whether it is one of [`Temperament::ALL`], rather than custom or mapped.
+    pub fn is_preset(&self) -> bool {
+        !matches!(self, Temperament::Custom(_) | Temperament::Mapped(_))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Temperament::Equal => "Equal",
+            Temperament::Just => "Just",
+            Temperament::Pythagorean => "Pythagorean",
+            Temperament::Meantone => "Meantone",
+            Temperament::Custom(_) => "Custom",
+            Temperament::Mapped(_) => "Mapped",
+        }
+    }
+
+    /// Offset in cents from 12-TET of the note `degree` semitones above the root.
+    fn deviation(&self, degree: usize) -> f32 {
+        let equal = 100.0 * degree as f32;
+        match self {
+            Temperament::Equal => 0.0,
+            Temperament::Just => ratio_cents(JUST_RATIOS[degree]) - equal,
+            Temperament::Pythagorean => ratio_cents(PYTHAGOREAN_RATIOS[degree]) - equal,
+            Temperament::Meantone => {
+                (MEANTONE_FIFTHS[degree] as f32 * MEANTONE_FIFTH).rem_euclid(1200.0) - equal
+            }
+            Temperament::Custom(offsets) => offsets[degree],
+            Temperament::Mapped(_) => 0.0,
+        }
+    }
+}
+
+/// Maps notes to frequencies. The reference note is always tuned to the
+/// reference frequency, the temperament only moves the other notes around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    /// Frequency of A4 in Hz.
+    pub reference: f32,
+    pub temperament: Temperament,
+    /// Pitch class the temperament is built on, 0 is C and 11 is B.
+    pub root: usize,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            reference: 440.0,
+            temperament: Temperament::Equal,
+            root: 0,
+        }
+    }
+}
+
+impl Tuning {
+    pub fn new(reference: f32) -> Self {
+        Self {
+            reference,
+            ..Default::default()
+        }
+    }
+
+    pub fn temperament(mut self, temperament: Temperament) -> Self {
+        self.temperament = temperament;
+        self
+    }
+
+    pub fn root(mut self, root: usize) -> Self {
+        self.root = root % 12;
+        self
+    }
+
+    /// Offset in cents from 12-TET of a pitch class.
+    pub fn deviation(&self, pitch_class: usize) -> f32 {
+        match &self.temperament {
+            Temperament::Custom(offsets) => offsets[pitch_class % 12],
+            temperament => temperament.deviation((pitch_class + 12 - self.root % 12) % 12),
+        }
+    }
+
+    /// Pitch in cents relative to A4 = 440 Hz.
+    pub fn cents(&self, note: Note) -> f32 {
+        if let Temperament::Mapped(_) = self.temperament {
+            return ratio_cents(self.freq(note) / 440.0);
+        }
+        let reference = ratio_cents(self.reference / 440.0);
+        let semitones = note as i32 - Note::A4 as i32;
+        let deviation = self.deviation(note.pitch_class()) - self.deviation(Note::A4.pitch_class());
+        100.0f32.mul_add(semitones as f32, reference + deviation)
+    }
+
+    /// Picks the temperament at `index` in [`Temperament::ALL`], or just past
+    /// them the custom or mapped one in `kept`. A custom or mapped
+    /// temperament left is put in `kept` in turn, so it can be picked again.
+    /// Returns the temperament replaced, the audio thread hands it over
+    /// rather than freeing a table.
+    pub fn pick_temperament(
+        &mut self,
+        index: usize,
+        kept: &mut Temperament,
+    ) -> Option<Temperament> {
+        match Temperament::ALL.get(index) {
+            Some(preset) => {
+                if !self.temperament.is_preset() {
+                    std::mem::swap(&mut self.temperament, kept);
+                }
+                Some(std::mem::replace(&mut self.temperament, preset.clone()))
+            }
+            None if self.temperament.is_preset() && !kept.is_preset() => {
+                std::mem::swap(&mut self.temperament, kept);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn freq(&self, note: Note) -> f32 {
+        match &self.temperament {
+            Temperament::Mapped(table) => table[note.midi() as usize],
+            _ => 440.0 * f32::powf(2.0, self.cents(note) / 1200.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapped() -> Temperament {
+        Temperament::Mapped(Arc::new([220.0; MIDI_KEYS]))
+    }
+
+    #[test]
+    fn cycles_back_to_the_mapped_table() {
+        let mut tuning = Tuning::new(440.0).temperament(mapped());
+        let mut kept = Temperament::Equal;
+        tuning.pick_temperament(0, &mut kept);
+        assert_eq!(tuning.temperament, Temperament::Equal);
+        assert_eq!(kept, mapped());
+        tuning.pick_temperament(2, &mut kept);
+        assert_eq!(tuning.temperament, Temperament::Pythagorean);
+        tuning.pick_temperament(Temperament::ALL.len(), &mut kept);
+        assert_eq!(tuning.temperament, mapped());
+        assert_eq!(tuning.freq(Note::C4), 220.0);
+        assert!(kept.is_preset());
+    }
+
+    #[test]
+    fn keeps_presets_without_a_table() {
+        let mut tuning = Tuning::new(440.0);
+        let mut kept = Temperament::Equal;
+        tuning.pick_temperament(1, &mut kept);
+        tuning.pick_temperament(Temperament::ALL.len(), &mut kept);
+        assert_eq!(tuning.temperament, Temperament::Just);
+        assert_eq!(kept, Temperament::Equal);
+    }
+
+    #[test]
+    fn deviations_move_with_the_root() {
+        let in_c = Tuning::new(440.0).temperament(Temperament::Just);
+        let in_d = in_c.clone().root(2);
+        assert_eq!(in_d.deviation(2), 0.0);
+        for degree in 0..12 {
+            assert_eq!(in_d.deviation((degree + 2) % 12), in_c.deviation(degree));
+        }
+        // The major third above the root is the flat just one in either key.
+        assert!((in_c.deviation(4) + 13.7).abs() < 0.1);
+        assert!((in_d.deviation(6) + 13.7).abs() < 0.1);
+
+        // A root set directly past B wraps around instead of underflowing.
+        let mut wrapped = in_d.clone();
+        wrapped.root = 14;
+        assert_eq!(wrapped.deviation(1), in_d.deviation(1));
+    }
+}
@@ -1,10 +1,34 @@
+pub mod bank;
+
 use std::f32::consts::PI;
 
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{
+    arp::{ArpSettings, Arpeggiator},
+    events::SynthEvent,
+    glide::{Glide, GlideSettings},
+    meter::Meter,
+    mono::{MonoSettings, NoteStack},
+    notes::Note,
+    params::{ParamId, ParamStore, Smoother, Smoothing},
+    processor::AudioProcessor,
+    tuning::{Temperament, Tuning},
+    waves::bank::{OscilatorBank, UnisonSettings, MAX_UNISON},
+};
 
-use crate::notes::Note;
+/// Pitch bend range in semitones used by new oscilators.
+const DEFAULT_BEND_RANGE: f32 = 2.0;
+/// Vibrato applied with the mod wheel fully up, in semitones.
+const VIBRATO_DEPTH: f32 = 0.5;
+const VIBRATO_RATE: f32 = 5.5;
+/// Ramp of the gate when a note starts or stops, short enough to sound
+/// immediate but long enough not to click.
+const GATE_RAMP: Smoothing = Smoothing::Linear(2.0);
+/// Frames rendered by the unison bank between two retunings.
+const UNISON_CHUNK: usize = 32;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Waveform {
     None,
     Sin,
@@ -13,18 +37,113 @@ pub enum Waveform {
     Triangle,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Waveform {
+    pub const ALL: [Waveform; 5] = [
+        Waveform::None,
+        Waveform::Sin,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Triangle,
+    ];
+
+    /// Samples the waveform at `phase`, measured in cycles, between -1.0 and 1.0.
+    #[inline]
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Sin => Self::sin(phase),
+            Waveform::Saw => Self::saw(phase),
+            Waveform::Square => Self::square(phase),
+            Waveform::Triangle => Self::triangle(phase),
+            Waveform::None => 0.0,
+        }
+    }
+
+    #[inline]
+    fn sin(phase: f32) -> f32 {
+        f32::sin(2.0 * PI * phase)
+    }
+
+    #[inline]
+    fn saw(phase: f32) -> f32 {
+        let zero_one = (phase + 0.5).fract();
+        zero_one.mul_add(2.0, -1.0)
+    }
+
+    #[inline]
+    fn square(phase: f32) -> f32 {
+        let zero_one = (phase + 0.5).fract();
+        zero_one.mul_add(2.0, -1.0).signum()
+    }
+
+    #[inline]
+    fn triangle(phase: f32) -> f32 {
+        let zero_one = (phase + 0.75).fract();
+        zero_one.mul_add(2.0, -1.0).abs().mul_add(2.0, -1.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Wave {
     pub waveform: Waveform,
+    /// Note held on the keyboard, an oscilator plays it as a key press and
+    /// keeps the note it is playing apart from the wave.
     pub note: Note,
     pub amp: f32,
+    pub tuning: Tuning,
+    /// Offset in cents added on top of the tuned note.
+    pub detune: f32,
+    /// Frequency in Hz played instead of the note when set.
+    pub fixed_freq: Option<f32>,
+    pub glide: GlideSettings,
+    pub mono: MonoSettings,
+    pub arp: ArpSettings,
+    pub unison: UnisonSettings,
 }
 
 pub struct Oscilator {
     sample_rate: f32,
-    curr_sample: f32,
+    phase: f32,
     wave: Wave,
     rx: Option<Receiver<Wave>>,
+    params: Option<ParamStore>,
+    /// Values of the parameters read at the start of the last block.
+    param_values: [f32; ParamId::ALL.len()],
+    amp: Smoother,
+    /// Detune in cents, applied on top of the glide.
+    detune: Smoother,
+    /// Detune in cents set by the events, added to the one of the wave.
+    event_detune: f32,
+    /// Frequency in Hz set by the events, played instead of the note and
+    /// the fixed frequency of the wave.
+    event_freq: Option<f32>,
+    events: Option<Receiver<SynthEvent>>,
+    /// Frequency played, published at the end of each block.
+    frequency: Option<Meter>,
+    /// Level of the note playing, opened at its velocity by a note on and
+    /// closed by a note off.
+    gate: Smoother,
+    /// Note playing, picked by the note events, the mono priority or the
+    /// arpeggiator. The note of the wave is only the one held on the keyboard.
+    note: Note,
+    /// Custom or mapped temperament while a preset one is picked, to go back to it.
+    kept: Temperament,
+    /// Takes the mapped temperaments no longer used, to free their tables
+    /// outside the audio thread.
+    retired: Option<Sender<Temperament>>,
+    bend: f32,
+    bend_range: f32,
+    modulation: f32,
+    vibrato_phase: f32,
+    sustain: bool,
+    sustained: bool,
+    glide: Glide,
+    /// Pitch in octaves (log2 of the frequency) while gliding between notes.
+    pitch: f32,
+    /// Notes held down in mono mode.
+    held: NoteStack,
+    arp: Arpeggiator,
+    /// Unison voices, with room for [`MAX_UNISON`] of them.
+    unison: OscilatorBank,
 }
 
 impl Wave {
@@ -33,68 +152,798 @@ impl Wave {
             waveform,
             note,
             amp,
+            tuning: Tuning::default(),
+            detune: 0.0,
+            fixed_freq: None,
+            glide: GlideSettings::default(),
+            mono: MonoSettings::default(),
+            arp: ArpSettings::default(),
+            unison: UnisonSettings::default(),
         }
     }
 
-    pub fn sample(&self, t: f32) -> f32 {
-        match self.waveform {
-            Waveform::Sin => self.sample_sin(t),
-            Waveform::Saw => self.sample_saw(t),
-            Waveform::Square => self.sample_square(t),
-            Waveform::Triangle => self.sample_triangle(t),
-            Waveform::None => 0.0,
-        }
+    /// Detunes by a number of semitones, fractions included.
+    pub fn detune_semitones(&mut self, semitones: f32) {
+        self.detune = semitones * 100.0;
+    }
+
+    pub fn freq(&self) -> f32 {
+        self.fixed_freq
+            .unwrap_or_else(|| self.tuning.freq(self.note) * f32::powf(2.0, self.detune / 1200.0))
+    }
+
+    /// Distance in cents between the frequency played and the note in 12-TET at A4 = 440 Hz.
+    pub fn cents(&self) -> f32 {
+        1200.0 * (self.freq() / self.note.freq()).log2()
     }
 
-    fn sample_sin(&self, t: f32) -> f32 {
-        self.amp * f32::sin(self.note.freq() * 2.0 * PI * t)
+    pub fn sample(&self, t: f32) -> f32 {
+        self.sample_phase(t * self.freq())
     }
 
-    fn sample_saw(&self, t: f32) -> f32 {
-        let zero_one = t.mul_add(self.note.freq(), 0.5) % 1.0;
-        self.amp * zero_one.mul_add(2.0, -1.0)
+    /// Samples the wave at `phase`, measured in cycles.
+    pub fn sample_phase(&self, phase: f32) -> f32 {
+        self.amp * self.shape(phase)
     }
 
-    fn sample_square(&self, t: f32) -> f32 {
-        let zero_one = t.mul_add(self.note.freq(), 0.5) % 1.0;
-        self.amp * zero_one.mul_add(2.0, -1.0).signum()
+    /// Samples the waveform at `phase`, between -1.0 and 1.0 whatever the amplitude.
+    pub fn shape(&self, phase: f32) -> f32 {
+        self.waveform.sample(phase)
     }
 
-    fn sample_triangle(&self, t: f32) -> f32 {
-        let zero_one = t.mul_add(self.note.freq(), 0.75) % 1.0;
-        self.amp * zero_one.mul_add(2.0, -1.0).abs().mul_add(2.0, -1.0)
+    /// Pitch in octaves (log2 of the frequency) of `note` before detune.
+    fn pitch(&self, note: Note) -> f32 {
+        self.fixed_freq
+            .unwrap_or_else(|| self.tuning.freq(note))
+            .log2()
     }
 }
 
 impl Oscilator {
     pub fn new(sample_rate: u32, wave: Wave) -> Self {
-        Self {
+        let pitch = wave.pitch(wave.note);
+        let note = wave.note;
+        let mut glide = Glide::new(sample_rate as f32, pitch);
+        glide.settings = wave.glide;
+        let mut amp = Smoother::new(sample_rate as f32, wave.amp);
+        amp.set_smoothing(ParamId::Amp.info().smoothing);
+        let mut detune = Smoother::new(sample_rate as f32, wave.detune);
+        detune.set_smoothing(ParamId::Detune.info().smoothing);
+        let mut gate = Smoother::new(sample_rate as f32, 1.0);
+        gate.set_smoothing(GATE_RAMP);
+        let mut unison = OscilatorBank::new(sample_rate, wave.waveform);
+        unison.reserve(MAX_UNISON);
+        let mut oscilator = Self {
             sample_rate: sample_rate as f32,
-            curr_sample: 0.0,
+            phase: 0.0,
             wave,
             rx: None,
+            params: None,
+            param_values: [f32::NAN; ParamId::ALL.len()],
+            amp,
+            detune,
+            event_detune: 0.0,
+            event_freq: None,
+            events: None,
+            frequency: None,
+            gate,
+            note,
+            kept: Temperament::Equal,
+            retired: None,
+            bend: 0.0,
+            bend_range: DEFAULT_BEND_RANGE,
+            modulation: 0.0,
+            vibrato_phase: 0.0,
+            sustain: false,
+            sustained: false,
+            glide,
+            pitch,
+            held: NoteStack::new(),
+            arp: Arpeggiator::new(sample_rate as f32),
+            unison,
+        };
+        oscilator.restack_unison();
+        oscilator
+    }
+
+    pub fn wave(&self) -> &Wave {
+        &self.wave
+    }
+
+    /// Note playing, or last played once released.
+    pub fn note(&self) -> Note {
+        self.note
+    }
+
+    /// Frequency currently played, with detune, pitch bend and vibrato applied.
+    pub fn freq(&self) -> f32 {
+        let vibrato = self.modulation * VIBRATO_DEPTH * f32::sin(2.0 * PI * self.vibrato_phase);
+        let detune = match self.event_freq.or(self.wave.fixed_freq) {
+            Some(_) => 0.0,
+            None => self.detune.value() / 100.0,
+        };
+        let semitones = self.bend * self.bend_range + vibrato + detune;
+        f32::exp2(self.pitch + semitones / 12.0)
+    }
+
+    /// Glides towards the frequency of the wave when it changed. `legato`
+    /// tells whether the previous note is still held.
+    fn retarget(&mut self, legato: bool) {
+        self.glide.settings = self.wave.glide;
+        let target = match self.event_freq {
+            Some(freq) => freq.log2(),
+            None => self.wave.pitch(self.note),
+        };
+        if target != self.glide.target() {
+            self.glide.set_target(target, legato);
         }
     }
 
     fn inc_sample(&mut self) {
-        self.curr_sample += 1.0;
-        self.curr_sample %= self.wave.note.freq().recip() * self.sample_rate;
+        self.pitch = self.glide.advance();
+        self.detune.advance();
+        self.phase = (self.phase + self.freq() / self.sample_rate).fract();
+        if self.modulation > 0.0 {
+            self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / self.sample_rate).fract();
+        }
+    }
+
+    /// Whether a note is playing or held by the sustain pedal.
+    fn is_gated(&self) -> bool {
+        self.gate.target() > 0.0
+    }
+
+    /// Replaces the settings, amplitude and detune move to theirs smoothly.
+    fn set_wave(&mut self, wave: Wave) {
+        let released = self.wave.note;
+        let arp = self.wave.arp.enabled;
+        let previous = std::mem::replace(&mut self.wave, wave).tuning.temperament;
+        if !previous.is_preset() && self.wave.tuning.temperament.is_preset() {
+            let kept = std::mem::replace(&mut self.kept, previous);
+            self.retire(kept);
+        } else {
+            self.retire(previous);
+        }
+        self.wave_changed(released, arp);
+    }
+
+    /// Hands a mapped temperament over to be freed outside the audio thread.
+    /// Without anything to take it, or with too many handed over at once, it
+    /// is freed here.
+    fn retire(&self, temperament: Temperament) {
+        if let (Temperament::Mapped(_), Some(retired)) = (&temperament, &self.retired) {
+            let _ = retired.try_send(temperament);
+        }
+    }
+
+    /// Catches up with the wave after it changed, `released` is its previous
+    /// keyboard note and `arp` whether the arpeggiator was on. A different
+    /// keyboard note is played as if its key was pressed, and the previous
+    /// one released, the note playing is otherwise left to the events.
+    fn wave_changed(&mut self, released: Note, arp: bool) {
+        if !self.wave.mono.enabled {
+            self.held.clear();
+        }
+        if arp && !self.wave.arp.enabled {
+            if let Some(event) = self.arp.stop() {
+                self.play(event);
+            }
+        }
+        self.restack_unison();
+        self.amp.set_target(self.wave.amp);
+        self.detune.set_target(self.wave.detune + self.event_detune);
+        self.retarget(self.is_gated());
+        let note = self.wave.note;
+        if note != released {
+            self.handle_event(SynthEvent::NoteOn {
+                note,
+                velocity: 1.0,
+            });
+            self.handle_event(SynthEvent::NoteOff { note: released });
+        }
+    }
+
+    /// Matches the unison bank to the waveform and number of voices of the
+    /// wave, within the room reserved for it.
+    fn restack_unison(&mut self) {
+        self.unison.set_waveform(self.wave.waveform);
+        let voices = self.wave.unison.voices.min(MAX_UNISON);
+        if voices > 1 && voices != self.unison.len() {
+            self.unison.clear();
+            self.unison
+                .add_unison(self.freq(), voices, self.wave.unison.spread, 1.0);
+        }
+    }
+
+    /// Picks up the waves and parameters changed since the last block. They
+    /// are applied in place, a mapped temperament switched away from is kept
+    /// aside or handed over rather than freed in the audio thread.
+    pub fn start_block(&mut self) {
+        if let Some(wave) = self.rx.as_ref().and_then(|rx| rx.try_iter().last()) {
+            self.set_wave(wave);
+        }
+        let Some(params) = &self.params else {
+            return;
+        };
+        self.amp.set_smoothing(params.smoothing(ParamId::Amp));
+        self.detune.set_smoothing(params.smoothing(ParamId::Detune));
+        let values = ParamId::ALL.map(|id| params.get(id));
+        let released = self.wave.note;
+        let arp = self.wave.arp.enabled;
+        let mut changed = false;
+        for (id, value) in ParamId::ALL.into_iter().zip(values) {
+            if value == self.param_values[id.index()] {
+                continue;
+            }
+            self.param_values[id.index()] = value;
+            match id {
+                ParamId::Temperament => {
+                    let index = id.info().normalize(value) as usize;
+                    let replaced = self.wave.tuning.pick_temperament(index, &mut self.kept);
+                    if let Some(replaced) = replaced {
+                        self.retire(replaced);
+                    }
+                }
+                id => id.apply(value, &mut self.wave),
+            }
+            changed = true;
+        }
+        if changed {
+            self.wave_changed(released, arp);
+        }
     }
 
     pub fn sample(&mut self) -> f32 {
-        match &self.rx {
-            Some(rx) => {
-                if let Ok(wave) = rx.try_recv() {
-                    self.wave = wave;
+        self.receive_events();
+        self.tick()
+    }
+
+    fn receive_events(&mut self) {
+        while let Some(event) = self.events.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.handle_event(event);
+        }
+    }
+
+    /// Next sample, with everything that can change between samples updated.
+    fn tick(&mut self) -> f32 {
+        if self.wave.arp.enabled {
+            for event in self.arp.advance(&self.wave.arp).into_iter().flatten() {
+                self.play(event);
+            }
+        }
+        self.inc_sample();
+        self.gate.advance() * self.amp.advance() * self.wave.shape(self.phase)
+    }
+
+    /// Nothing changes the pitch or the shape from one sample to the next.
+    fn is_steady(&self) -> bool {
+        !self.wave.arp.enabled
+            && !self.glide.is_gliding()
+            && self.modulation == 0.0
+            && self.detune.is_settled()
+            && self.gate.is_settled()
+    }
+
+    /// Renders a steady block with the phase increment computed once, and
+    /// the waveform picked once instead of on every sample.
+    fn render_steady(&mut self, output: &mut [f32], shape: impl Fn(f32) -> f32) {
+        self.pitch = self.glide.advance();
+        let increment = self.freq() / self.sample_rate;
+        let mut phase = self.phase;
+        if self.amp.is_settled() {
+            let gain = self.gate.value() * self.amp.value();
+            for sample in output.iter_mut() {
+                phase = (phase + increment).fract();
+                *sample = gain * shape(phase);
+            }
+        } else {
+            for sample in output.iter_mut() {
+                phase = (phase + increment).fract();
+                *sample = self.gate.value() * self.amp.advance() * shape(phase);
+            }
+        }
+        self.phase = phase;
+    }
+
+    /// Renders the unison voices through the bank, retuned to the pitch every
+    /// [`UNISON_CHUNK`] frames. Gate and amplitude still change on every sample.
+    fn render_unison(&mut self, output: &mut [f32]) {
+        for chunk in output.chunks_mut(UNISON_CHUNK) {
+            let mut gains = [0.0; UNISON_CHUNK];
+            for gain in &mut gains[..chunk.len()] {
+                if self.wave.arp.enabled {
+                    for event in self.arp.advance(&self.wave.arp).into_iter().flatten() {
+                        self.play(event);
+                    }
+                }
+                self.inc_sample();
+                *gain = self.gate.advance() * self.amp.advance();
+            }
+            self.unison
+                .tune_unison(self.freq(), self.wave.unison.spread);
+            self.unison.render(chunk);
+            for (sample, gain) in chunk.iter_mut().zip(gains) {
+                *sample *= gain;
+            }
+        }
+    }
+
+    pub fn handle_event(&mut self, event: SynthEvent) {
+        match event {
+            SynthEvent::NoteOn { note, velocity } if self.wave.arp.enabled => {
+                self.arp.press(note, velocity, &self.wave.arp);
+            }
+            SynthEvent::NoteOff { note } if self.wave.arp.enabled => {
+                self.arp.release(note, &self.wave.arp);
+            }
+            SynthEvent::NoteOn { .. } | SynthEvent::NoteOff { .. } => self.play(event),
+            SynthEvent::PitchBend(bend) => self.bend = bend.clamp(-1.0, 1.0),
+            SynthEvent::BendRange(semitones) => self.bend_range = semitones,
+            SynthEvent::Detune(cents) => {
+                self.event_detune = cents;
+                self.detune.set_target(self.wave.detune + cents);
+            }
+            SynthEvent::Frequency(freq) => {
+                self.event_freq = freq;
+                self.retarget(self.is_gated());
+            }
+            SynthEvent::ModWheel(modulation) => self.modulation = modulation.clamp(0.0, 1.0),
+            SynthEvent::Sustain(sustain) => {
+                self.sustain = sustain;
+                if !sustain && self.sustained {
+                    self.sustained = false;
+                    self.gate.set_target(0.0);
+                }
+            }
+        }
+    }
+
+    /// Plays a note on or off, after the arpeggiator.
+    fn play(&mut self, event: SynthEvent) {
+        match event {
+            SynthEvent::NoteOn { note, velocity } if self.wave.mono.enabled => {
+                self.held.press(note, velocity);
+                self.play_held();
+            }
+            SynthEvent::NoteOn { note, velocity } => {
+                let legato = self.is_gated();
+                self.note = note;
+                self.gate.set_target(velocity.clamp(0.0, 1.0));
+                self.sustained = false;
+                self.retarget(legato);
+            }
+            SynthEvent::NoteOff { note } if self.wave.mono.enabled => {
+                self.held.release(note);
+                if self.held.is_empty() {
+                    self.release();
+                } else {
+                    self.play_held();
                 }
             }
+            SynthEvent::NoteOff { note } if note == self.note => self.release(),
             _ => (),
         }
-        self.inc_sample();
-        self.wave.sample(self.curr_sample / self.sample_rate)
     }
 
+    fn release(&mut self) {
+        if self.sustain {
+            self.sustained = true;
+        } else {
+            self.gate.set_target(0.0);
+        }
+    }
+
+    /// Plays the held note picked by the mono priority, falling back to
+    /// previously held notes as the others are released.
+    fn play_held(&mut self) {
+        let Some((note, velocity)) = self.held.select(self.wave.mono.priority) else {
+            return;
+        };
+        let sounding = self.is_gated();
+        self.sustained = false;
+        if sounding && note == self.note {
+            return;
+        }
+        if !sounding || !self.wave.mono.legato {
+            // Retrigger, the new note starts over instead of continuing the previous one.
+            self.phase = 0.0;
+            self.gate.set_target(velocity.clamp(0.0, 1.0));
+        }
+        self.note = note;
+        self.retarget(sounding);
+    }
+
+    /// Stops the note playing right away, whatever the sustain pedal, until
+    /// the next note on.
+    pub fn stop(&mut self) {
+        self.held.clear();
+        self.sustained = false;
+        self.gate.jump(0.0);
+    }
+
+    /// Nothing is playing or about to, rendering would only give silence.
+    pub fn is_idle(&self) -> bool {
+        !self.wave.arp.enabled && !self.is_gated() && self.gate.is_settled()
+    }
+
+    /// Takes the settings of `wave` but keeps its own keyboard note, for
+    /// voices following the main oscilator. Only a tuning that changed is
+    /// cloned, which shares a mapped table rather than copying it, so
+    /// following doesn't allocate.
+    pub fn follow(&mut self, wave: &Wave) {
+        let settings = Wave {
+            note: self.wave.note,
+            tuning: Tuning::default(),
+            ..*wave
+        };
+        let tuning_changed = self.wave.tuning != wave.tuning;
+        if !tuning_changed
+            && settings
+                == (Wave {
+                    tuning: Tuning::default(),
+                    ..self.wave
+                })
+        {
+            return;
+        }
+        let tuning = if tuning_changed {
+            wave.tuning.clone()
+        } else {
+            std::mem::take(&mut self.wave.tuning)
+        };
+        self.set_wave(Wave { tuning, ..settings });
+    }
+
+    /// Arpeggiates at `bpm`, the transport tempo while it plays, or at the
+    /// tempo of the wave for `None`.
+    pub fn follow_tempo(&mut self, bpm: Option<f32>) {
+        self.arp.follow_tempo(bpm);
+    }
+
+    /// Pitch bend range in semitones for a full bend in either direction.
+    pub fn set_bend_range(&mut self, semitones: f32) {
+        self.bend_range = semitones;
+    }
+
+    /// Replaces the whole wave with the last one received, at the start of each block.
     pub fn add_receiver(&mut self, rx: Receiver<Wave>) {
         self.rx = Some(rx);
     }
+
+    /// Follows the parameters in `params`, which override the wave from the
+    /// next block on.
+    pub fn add_params(&mut self, params: ParamStore) {
+        self.param_values = [f32::NAN; ParamId::ALL.len()];
+        self.params = Some(params);
+    }
+
+    /// Plays the events received on `rx`. They are picked up at the start of
+    /// each block, so they land up to a block late, 5.3 ms for 256 frames at
+    /// 48 kHz. Events from an [`EventSource`](crate::events::EventSource) in
+    /// the engine are sample accurate instead.
+    pub fn add_event_receiver(&mut self, rx: Receiver<SynthEvent>) {
+        self.events = Some(rx);
+    }
+
+    /// Sends the mapped temperaments it stops using to `tx`, so their tables
+    /// are freed by whoever receives them rather than in the audio thread.
+    pub fn add_retired_sender(&mut self, tx: Sender<Temperament>) {
+        self.retired = Some(tx);
+    }
+
+    /// Publishes the frequency played, with bend, vibrato and detune, after each block.
+    pub fn add_frequency_meter(&mut self, meter: Meter) {
+        self.frequency = Some(meter);
+    }
+}
+
+impl AudioProcessor for Oscilator {
+    fn process(&mut self, output: &mut [f32]) {
+        self.receive_events();
+        if self.wave.unison.is_enabled() {
+            self.render_unison(output);
+        } else if !self.is_steady() {
+            output.iter_mut().for_each(|sample| *sample = self.tick());
+        } else {
+            match self.wave.waveform {
+                Waveform::Sin => self.render_steady(output, Waveform::sin),
+                Waveform::Saw => self.render_steady(output, Waveform::saw),
+                Waveform::Square => self.render_steady(output, Waveform::square),
+                Waveform::Triangle => self.render_steady(output, Waveform::triangle),
+                Waveform::None => self.render_steady(output, |_| 0.0),
+            }
+        }
+        if let Some(meter) = &self.frequency {
+            meter.set(self.freq());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{mono::NotePriority, tuning::MIDI_KEYS};
+
+    fn note_on(note: Note) -> SynthEvent {
+        SynthEvent::NoteOn {
+            note,
+            velocity: 1.0,
+        }
+    }
+
+    fn render(oscilator: &mut Oscilator, frames: usize) -> f32 {
+        let mut block = vec![0.0; frames];
+        oscilator.process(&mut block);
+        block
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    fn mono_oscilator() -> (Oscilator, Wave, ParamStore) {
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        wave.mono = MonoSettings::new(NotePriority::Last);
+        let params = ParamStore::from_wave(&wave);
+        let mut oscilator = Oscilator::new(48000, wave.clone());
+        oscilator.add_params(params.clone());
+        oscilator.start_block();
+        (oscilator, wave, params)
+    }
+
+    #[test]
+    fn unison_voices_follow_the_parameters() {
+        let wave = Wave::new(Waveform::Saw, Note::A4, 0.5);
+        let params = ParamStore::from_wave(&wave);
+        let mut oscilator = Oscilator::new(48000, wave);
+        oscilator.add_params(params.clone());
+        oscilator.start_block();
+        let single = render(&mut oscilator, 4800);
+
+        params.set(ParamId::Unison, 5.0);
+        oscilator.start_block();
+        assert_eq!(oscilator.unison.len(), 5);
+        let stacked = render(&mut oscilator, 4800);
+        assert!(stacked > 0.1 && stacked <= single + 1e-3, "peak {stacked}");
+
+        params.set(ParamId::Unison, 1.0);
+        oscilator.start_block();
+        assert!((render(&mut oscilator, 4800) - single).abs() < 1e-3);
+    }
+
+    #[test]
+    fn parameter_edits_keep_the_mono_note() {
+        let (mut oscilator, mut wave, params) = mono_oscilator();
+        oscilator.handle_event(note_on(Note::C4));
+        oscilator.handle_event(note_on(Note::E4));
+        assert_eq!(oscilator.note(), Note::E4);
+
+        // Every edit in the TUI sends its whole wave, note included.
+        wave.waveform = Waveform::Saw;
+        wave.amp = 0.5;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        assert_eq!(oscilator.note(), Note::E4);
+        render(&mut oscilator, 16);
+        assert!((oscilator.freq() - Note::E4.freq()).abs() < 0.01);
+
+        oscilator.handle_event(SynthEvent::NoteOff { note: Note::E4 });
+        assert_eq!(oscilator.note(), Note::C4);
+    }
+
+    #[test]
+    fn whole_waves_keep_the_mono_note() {
+        let (mut oscilator, mut wave, _) = mono_oscilator();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        oscilator.add_receiver(rx);
+        oscilator.handle_event(note_on(Note::C4));
+        wave.waveform = Waveform::Square;
+        tx.send(wave.clone()).unwrap();
+        oscilator.start_block();
+        assert_eq!(oscilator.note(), Note::C4);
+        assert!(render(&mut oscilator, 256) > 0.5);
+    }
+
+    #[test]
+    fn keyboard_note_is_pressed_and_released_in_mono() {
+        let (mut oscilator, mut wave, params) = mono_oscilator();
+        oscilator.handle_event(note_on(Note::C4));
+        wave.note = Note::B4;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        assert_eq!(oscilator.note(), Note::B4);
+        wave.note = Note::C5;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        assert_eq!(oscilator.note(), Note::C5);
+        // B4 was released when the keyboard moved on, C4 is still held.
+        oscilator.handle_event(SynthEvent::NoteOff { note: Note::C5 });
+        assert_eq!(oscilator.note(), Note::C4);
+    }
+
+    /// Notes played by the arpeggiator over `frames`, one per step.
+    fn arpeggio(oscilator: &mut Oscilator, frames: usize) -> Vec<Note> {
+        let mut notes: Vec<Note> = Vec::new();
+        for _ in 0..frames / 64 {
+            render(oscilator, 64);
+            if oscilator.is_gated() && notes.last() != Some(&oscilator.note()) {
+                notes.push(oscilator.note());
+            }
+        }
+        notes
+    }
+
+    #[test]
+    fn parameter_edits_keep_the_arpeggio() {
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        wave.arp.enabled = true;
+        wave.arp.bpm = 300.0;
+        let params = ParamStore::from_wave(&wave);
+        let mut oscilator = Oscilator::new(48000, wave.clone());
+        oscilator.add_params(params.clone());
+        oscilator.start_block();
+        oscilator.handle_event(note_on(Note::C4));
+        oscilator.handle_event(note_on(Note::E4));
+        oscilator.handle_event(note_on(Note::G4));
+        assert_eq!(
+            arpeggio(&mut oscilator, 48000)[..3],
+            [Note::C4, Note::E4, Note::G4]
+        );
+
+        for amp in [0.9, 0.8, 0.7, 0.6] {
+            wave.amp = amp;
+            params.set_wave(&wave);
+            oscilator.start_block();
+            let notes = arpeggio(&mut oscilator, 9600);
+            assert!(!notes.is_empty());
+            assert!(notes
+                .iter()
+                .all(|note| [Note::C4, Note::E4, Note::G4].contains(note)));
+        }
+
+        // The keyboard note joins the arpeggio.
+        wave.note = Note::B4;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        assert!(arpeggio(&mut oscilator, 48000).contains(&Note::B4));
+    }
+
+    #[test]
+    fn turning_the_arpeggiator_off_releases_its_note() {
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        wave.arp.enabled = true;
+        wave.arp.gate = 1.0;
+        let mut oscilator = Oscilator::new(48000, wave.clone());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        oscilator.add_receiver(rx);
+        oscilator.handle_event(note_on(Note::C4));
+        render(&mut oscilator, 256);
+        assert!(oscilator.is_gated());
+        wave.arp.enabled = false;
+        tx.send(wave.clone()).unwrap();
+        oscilator.start_block();
+        assert!(!oscilator.is_gated());
+    }
+
+    #[test]
+    fn mapped_tables_are_shared_and_handed_over() {
+        let table = |freq| Temperament::Mapped(Arc::new([freq; MIDI_KEYS]));
+        let mut wave = Wave::new(Waveform::Sin, Note::A4, 1.0);
+        wave.tuning.temperament = table(220.0);
+        let mut oscilator = Oscilator::new(48000, wave.clone());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        oscilator.add_receiver(rx);
+        let (s_retired, r_retired) = crossbeam_channel::bounded(4);
+        oscilator.add_retired_sender(s_retired);
+
+        // A voice following the oscilator shares its table.
+        let mut voice = Oscilator::new(48000, Wave::new(Waveform::Sin, Note::A4, 1.0));
+        voice.follow(oscilator.wave());
+        match (&voice.wave().tuning.temperament, &wave.tuning.temperament) {
+            (Temperament::Mapped(a), Temperament::Mapped(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("The voice didn't follow the mapped temperament"),
+        }
+
+        // A new table hands the previous one over.
+        wave.tuning.temperament = table(330.0);
+        tx.send(wave.clone()).unwrap();
+        oscilator.start_block();
+        assert_eq!(r_retired.try_recv(), Ok(table(220.0)));
+
+        // A preset keeps the table aside, and picking another preset then
+        // leaves nothing to hand over.
+        let params = ParamStore::from_wave(&wave);
+        oscilator.add_params(params.clone());
+        params.set(ParamId::Temperament, 1.0);
+        oscilator.start_block();
+        params.set(ParamId::Temperament, 2.0);
+        oscilator.start_block();
+        assert!(r_retired.try_recv().is_err());
+        assert_eq!(oscilator.kept, table(330.0));
+    }
+
+    #[test]
+    fn event_detune_and_frequency_outlast_wave_updates() {
+        let (mut oscilator, mut wave, params) = mono_oscilator();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        oscilator.add_receiver(rx);
+        oscilator.handle_event(note_on(Note::A4));
+        oscilator.handle_event(SynthEvent::Detune(100.0));
+        wave.amp = 0.5;
+        params.set_wave(&wave);
+        oscilator.start_block();
+        render(&mut oscilator, 48000);
+        assert!((oscilator.freq() - Note::B4b.freq()).abs() < 0.01);
+
+        oscilator.handle_event(SynthEvent::Frequency(Some(300.0)));
+        wave.waveform = Waveform::Saw;
+        tx.send(wave.clone()).unwrap();
+        oscilator.start_block();
+        render(&mut oscilator, 48000);
+        assert!((oscilator.freq() - 300.0).abs() < 0.01);
+
+        oscilator.handle_event(SynthEvent::Frequency(None));
+        render(&mut oscilator, 48000);
+        assert!((oscilator.freq() - Note::B4b.freq()).abs() < 0.01);
+    }
+
+    #[test]
+    fn frequency_meter_includes_the_bend() {
+        let mut oscilator = Oscilator::new(48000, Wave::new(Waveform::Sin, Note::A4, 1.0));
+        let meter = Meter::new();
+        oscilator.add_frequency_meter(meter.clone());
+        oscilator.handle_event(SynthEvent::PitchBend(1.0));
+        render(&mut oscilator, 64);
+        assert!((meter.get() - Note::B4.freq()).abs() < 0.01);
+    }
+
+    /// `value` of the oscilator after each of the next `frames`.
+    fn smoothed(
+        oscilator: &mut Oscilator,
+        frames: usize,
+        value: fn(&Oscilator) -> f32,
+    ) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                oscilator.tick();
+                value(oscilator)
+            })
+            .collect()
+    }
+
+    /// Largest change from one frame to the next, starting from `from`.
+    fn largest_step(from: f32, values: &[f32]) -> f32 {
+        let mut previous = from;
+        values.iter().fold(0.0, |largest: f32, &value| {
+            let step = (value - previous).abs();
+            previous = value;
+            largest.max(step)
+        })
+    }
+
+    #[test]
+    fn smoothed_parameters_reach_their_target_in_time() {
+        let (mut oscilator, mut wave, params) = mono_oscilator();
+        params.set_smoothing(ParamId::Amp, Smoothing::Linear(20.0));
+        wave.amp = 0.5;
+        wave.detune = 50.0;
+        params.set_wave(&wave);
+        oscilator.start_block();
+
+        // 20 ms at 48 kHz in even steps.
+        let amp = smoothed(&mut oscilator, 960, |oscilator| oscilator.amp.value());
+        assert!(amp[958] > 0.5);
+        assert_eq!(amp[959], 0.5);
+        assert!(largest_step(1.0, &amp) <= 0.5 / 960.0 * 1.001);
+
+        let (mut oscilator, _, params) = mono_oscilator();
+        params.set_smoothing(ParamId::Detune, Smoothing::OnePole(5.0));
+        params.set_wave(&wave);
+        oscilator.start_block();
+        // A 5 ms time constant is within 1% after 25 ms, the first step is the largest.
+        let detune = smoothed(&mut oscilator, 1200, |oscilator| oscilator.detune.value());
+        assert!((detune[239] / 50.0 - (1.0 - (-1.0f32).exp())).abs() < 0.01);
+        assert!((detune[1199] - 50.0).abs() < 0.5);
+        assert!(largest_step(0.0, &detune) <= detune[0] * 1.0001);
+    }
 }
@@ -0,0 +1,672 @@
+use std::f32::consts::TAU;
+
+use crate::waves::Waveform;
+
+/// Voices are stored in groups of this many, the widest lanes used.
+const MAX_LANES: usize = 8;
+
+/// Most voices in a unison stack.
+pub const MAX_UNISON: usize = 16;
+
+/// Largest difference between [`fast_sin`] and `f32::sin`, over a whole cycle.
+pub const FAST_SIN_MAX_ERROR: f32 = 1e-6;
+
+// Taylor series of sin(x), accurate to f32 precision up to a quarter cycle.
+const SIN_3: f32 = -1.0 / 6.0;
+const SIN_5: f32 = 1.0 / 120.0;
+const SIN_7: f32 = -1.0 / 5040.0;
+const SIN_9: f32 = 1.0 / 362_880.0;
+const SIN_11: f32 = -1.0 / 39_916_800.0;
+
+/// Polynomial approximation of `sin(2π phase)`, with `phase` in cycles. Off
+/// by at most [`FAST_SIN_MAX_ERROR`].
+#[inline]
+pub fn fast_sin(phase: f32) -> f32 {
+    // Within half a cycle of 0, then folded to within a quarter cycle.
+    let phase = phase - phase.round();
+    let phase = if phase.abs() > 0.25 {
+        0.5f32.copysign(phase) - phase
+    } else {
+        phase
+    };
+    let x = TAU * phase;
+    let x2 = x * x;
+    let poly = SIN_11
+        .mul_add(x2, SIN_9)
+        .mul_add(x2, SIN_7)
+        .mul_add(x2, SIN_5)
+        .mul_add(x2, SIN_3)
+        .mul_add(x2, 1.0);
+    x * poly
+}
+
+/// Copies of each note, detuned around it and rendered by an [`OscilatorBank`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnisonSettings {
+    /// `1` plays the note alone, up to [`MAX_UNISON`].
+    pub voices: usize,
+    /// Cents between the lowest and the highest voice.
+    pub spread: f32,
+}
+
+impl Default for UnisonSettings {
+    fn default() -> Self {
+        Self {
+            voices: 1,
+            spread: 20.0,
+        }
+    }
+}
+
+impl UnisonSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.voices > 1
+    }
+}
+
+/// Instruction set the bank renders with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimdLevel {
+    /// One voice at a time, works everywhere.
+    Scalar,
+    /// Four voices at a time.
+    Sse2,
+    /// Eight voices at a time.
+    Avx2,
+}
+
+impl SimdLevel {
+    pub const ALL: [SimdLevel; 3] = [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2];
+
+    /// Best level the running CPU supports.
+    pub fn detect() -> Self {
+        [SimdLevel::Avx2, SimdLevel::Sse2]
+            .into_iter()
+            .find(SimdLevel::is_supported)
+            .unwrap_or(SimdLevel::Scalar)
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            SimdLevel::Scalar => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            _ => false,
+        }
+    }
+}
+
+/// Phases, increments and gains of every voice, padded with silent voices
+/// to a whole number of lanes.
+#[derive(Debug, Clone, Default)]
+struct Voices {
+    phases: Vec<f32>,
+    /// Cycles per sample.
+    increments: Vec<f32>,
+    gains: Vec<f32>,
+}
+
+/// Many oscilators sharing a waveform, such as unison voices or a dense
+/// chord, rendered several at a time with SIMD and mixed together.
+#[derive(Debug, Clone)]
+pub struct OscilatorBank {
+    sample_rate: f32,
+    waveform: Waveform,
+    simd: SimdLevel,
+    voices: Voices,
+    len: usize,
+}
+
+impl OscilatorBank {
+    /// Empty bank using the best SIMD level of the CPU.
+    pub fn new(sample_rate: u32, waveform: Waveform) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            waveform,
+            simd: SimdLevel::detect(),
+            voices: Voices::default(),
+            len: 0,
+        }
+    }
+
+    /// Renders with `simd`, or without SIMD when the CPU doesn't support it.
+    pub fn simd(mut self, simd: SimdLevel) -> Self {
+        self.simd = if simd.is_supported() {
+            simd
+        } else {
+            SimdLevel::Scalar
+        };
+        self
+    }
+
+    pub fn simd_level(&self) -> SimdLevel {
+        self.simd
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Makes room for `voices` voices, adding up to that many afterwards
+    /// doesn't allocate.
+    pub fn reserve(&mut self, voices: usize) {
+        let padded = voices.next_multiple_of(MAX_LANES);
+        for values in [
+            &mut self.voices.phases,
+            &mut self.voices.increments,
+            &mut self.voices.gains,
+        ] {
+            values.reserve(padded.saturating_sub(values.len()));
+        }
+    }
+
+    /// Adds a voice, returns its index.
+    pub fn add_voice(&mut self, freq: f32, gain: f32) -> usize {
+        if self.len == self.voices.phases.len() {
+            let padded = self.len + MAX_LANES;
+            self.voices.phases.resize(padded, 0.0);
+            self.voices.increments.resize(padded, 0.0);
+            self.voices.gains.resize(padded, 0.0);
+        }
+        let voice = self.len;
+        self.len += 1;
+        self.set_freq(voice, freq);
+        self.set_gain(voice, gain);
+        voice
+    }
+
+    /// Adds `voices` voices spread evenly over `spread` cents around `freq`,
+    /// each at `gain / voices`, starting at different phases.
+    pub fn add_unison(&mut self, freq: f32, voices: usize, spread: f32, gain: f32) {
+        for index in 0..voices {
+            let voice = self.add_voice(
+                freq * unison_ratio(index, voices, spread),
+                gain / voices as f32,
+            );
+            self.voices.phases[voice] = index as f32 / voices as f32;
+        }
+    }
+
+    /// Moves every voice, added by a single [`add_unison`](Self::add_unison),
+    /// to `spread` cents around `freq`. Their phases and gains are kept.
+    pub fn tune_unison(&mut self, freq: f32, spread: f32) {
+        let voices = self.len;
+        for index in 0..voices {
+            self.set_freq(index, freq * unison_ratio(index, voices, spread));
+        }
+    }
+
+    pub fn set_freq(&mut self, voice: usize, freq: f32) {
+        if voice < self.len {
+            self.voices.increments[voice] = (freq / self.sample_rate).clamp(0.0, 0.5);
+        }
+    }
+
+    pub fn set_gain(&mut self, voice: usize, gain: f32) {
+        if voice < self.len {
+            self.voices.gains[voice] = gain;
+        }
+    }
+
+    /// Removes every voice, their memory is kept for the next ones.
+    pub fn clear(&mut self) {
+        self.voices.phases.clear();
+        self.voices.increments.clear();
+        self.voices.gains.clear();
+        self.len = 0;
+    }
+
+    /// Overwrites `output` with the sum of every voice.
+    pub fn render(&mut self, output: &mut [f32]) {
+        output.fill(0.0);
+        if self.waveform == Waveform::None {
+            return;
+        }
+        match self.simd {
+            // SAFETY: `simd` only holds levels the CPU supports, see `simd()`.
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx2 => unsafe { x86::render_avx2(self.waveform, &mut self.voices, output) },
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Sse2 => unsafe { x86::render_sse2(self.waveform, &mut self.voices, output) },
+            _ => render_scalar(self.waveform, &mut self.voices, output),
+        }
+    }
+}
+
+/// Frequency ratio of voice `index` out of `voices` spread over `spread` cents.
+fn unison_ratio(index: usize, voices: usize, spread: f32) -> f32 {
+    if voices > 1 {
+        f32::exp2(spread * (index as f32 / (voices - 1) as f32 - 0.5) / 1200.0)
+    } else {
+        1.0
+    }
+}
+
+fn render_scalar(waveform: Waveform, voices: &mut Voices, output: &mut [f32]) {
+    match waveform {
+        Waveform::Sin => render_voices(voices, output, fast_sin),
+        Waveform::Saw => render_voices(voices, output, Waveform::saw),
+        Waveform::Square => render_voices(voices, output, Waveform::square),
+        Waveform::Triangle => render_voices(voices, output, Waveform::triangle),
+        Waveform::None => (),
+    }
+}
+
+/// One voice after the other, `shape` is inlined into the loop.
+fn render_voices(voices: &mut Voices, output: &mut [f32], shape: impl Fn(f32) -> f32) {
+    for ((phase, increment), gain) in voices
+        .phases
+        .iter_mut()
+        .zip(&voices.increments)
+        .zip(&voices.gains)
+    {
+        for sample in output.iter_mut() {
+            *phase += increment;
+            if *phase >= 1.0 {
+                *phase -= 1.0;
+            }
+            *sample += gain * shape(*phase);
+        }
+    }
+}
+
+/// Operations on a register of [`WIDTH`](Lanes::WIDTH) voices. The shapes
+/// rendered with them match the scalar ones in `waves.rs`, with the sine
+/// replaced by [`fast_sin`].
+trait Lanes: Copy {
+    const WIDTH: usize;
+    fn splat(value: f32) -> Self;
+    /// Loads the first [`WIDTH`](Lanes::WIDTH) values.
+    fn load(values: &[f32]) -> Self;
+    fn store(self, values: &mut [f32]);
+    fn add(self, other: Self) -> Self;
+    fn sub(self, other: Self) -> Self;
+    fn mul(self, other: Self) -> Self;
+    /// `self * a + b`.
+    fn mul_add(self, a: Self, b: Self) -> Self;
+    fn abs(self) -> Self;
+    /// `self` with the sign of `sign`.
+    fn copysign(self, sign: Self) -> Self;
+    /// All bits set in the lanes where `self >= other`.
+    fn ge(self, other: Self) -> Self;
+    fn gt(self, other: Self) -> Self;
+    /// `then` in the lanes set in `mask`, `otherwise` in the others.
+    fn select(mask: Self, then: Self, otherwise: Self) -> Self;
+    fn sum(self) -> f32;
+}
+
+#[inline(always)]
+fn render_lanes<L: Lanes>(waveform: Waveform, voices: &mut Voices, output: &mut [f32]) {
+    let one = L::splat(1.0);
+    let minus_one = L::splat(-1.0);
+    let two = L::splat(2.0);
+    let half = L::splat(0.5);
+    let wrap = move |phase: L| L::select(phase.ge(one), phase.sub(one), phase);
+    match waveform {
+        Waveform::Sin => run(voices, output, |phase: L| {
+            // Phases are within a cycle, first moved to within half a cycle of 0.
+            let phase = L::select(phase.ge(half), phase.sub(one), phase);
+            let folded = half.copysign(phase).sub(phase);
+            let phase = L::select(phase.abs().gt(L::splat(0.25)), folded, phase);
+            let x = phase.mul(L::splat(TAU));
+            let x2 = x.mul(x);
+            let poly = L::splat(SIN_11)
+                .mul_add(x2, L::splat(SIN_9))
+                .mul_add(x2, L::splat(SIN_7))
+                .mul_add(x2, L::splat(SIN_5))
+                .mul_add(x2, L::splat(SIN_3))
+                .mul_add(x2, one);
+            x.mul(poly)
+        }),
+        Waveform::Saw => run(voices, output, |phase: L| {
+            wrap(phase.add(half)).mul_add(two, minus_one)
+        }),
+        Waveform::Square => run(voices, output, |phase: L| {
+            let saw = wrap(phase.add(half)).mul_add(two, minus_one);
+            L::select(saw.ge(L::splat(0.0)), one, minus_one)
+        }),
+        Waveform::Triangle => run(voices, output, |phase: L| {
+            let zero_one = wrap(phase.add(L::splat(0.75)));
+            zero_one
+                .mul_add(two, minus_one)
+                .abs()
+                .mul_add(two, minus_one)
+        }),
+        Waveform::None => (),
+    }
+}
+
+/// [`WIDTH`](Lanes::WIDTH) voices at a time, `shape` is inlined into the loop.
+#[inline(always)]
+fn run<L: Lanes>(voices: &mut Voices, output: &mut [f32], shape: impl Fn(L) -> L) {
+    let one = L::splat(1.0);
+    for ((phases, increments), gains) in voices
+        .phases
+        .chunks_exact_mut(L::WIDTH)
+        .zip(voices.increments.chunks_exact(L::WIDTH))
+        .zip(voices.gains.chunks_exact(L::WIDTH))
+    {
+        let mut phase = L::load(phases);
+        let increment = L::load(increments);
+        let gain = L::load(gains);
+        for sample in output.iter_mut() {
+            phase = phase.add(increment);
+            phase = L::select(phase.ge(one), phase.sub(one), phase);
+            *sample += shape(phase).mul(gain).sum();
+        }
+        phase.store(phases);
+    }
+}
+
+/// SSE2 and AVX2 with `std::arch`. The rendering functions are compiled
+/// with the features enabled and the lanes inlined into them.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use super::{render_lanes, Lanes, Voices, Waveform};
+
+    /// Renders four voices at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support SSE2.
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn render_sse2(waveform: Waveform, voices: &mut Voices, output: &mut [f32]) {
+        render_lanes::<Sse2>(waveform, voices, output)
+    }
+
+    /// Renders eight voices at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2 and FMA.
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn render_avx2(waveform: Waveform, voices: &mut Voices, output: &mut [f32]) {
+        render_lanes::<Avx2>(waveform, voices, output)
+    }
+
+    // SAFETY, for every `unsafe` below: the lanes are only used inlined into
+    // the functions above, which are only called when the CPU supports their
+    // features. Loads and stores check the length of their slice first.
+
+    #[derive(Clone, Copy)]
+    struct Sse2(__m128);
+
+    impl Lanes for Sse2 {
+        const WIDTH: usize = 4;
+
+        #[inline(always)]
+        fn splat(value: f32) -> Self {
+            Self(unsafe { _mm_set1_ps(value) })
+        }
+
+        #[inline(always)]
+        fn load(values: &[f32]) -> Self {
+            let values = &values[..Self::WIDTH];
+            Self(unsafe { _mm_loadu_ps(values.as_ptr()) })
+        }
+
+        #[inline(always)]
+        fn store(self, values: &mut [f32]) {
+            let values = &mut values[..Self::WIDTH];
+            unsafe { _mm_storeu_ps(values.as_mut_ptr(), self.0) }
+        }
+
+        #[inline(always)]
+        fn add(self, other: Self) -> Self {
+            Self(unsafe { _mm_add_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn sub(self, other: Self) -> Self {
+            Self(unsafe { _mm_sub_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn mul(self, other: Self) -> Self {
+            Self(unsafe { _mm_mul_ps(self.0, other.0) })
+        }
+
+        /// Not fused, SSE2 has no FMA.
+        #[inline(always)]
+        fn mul_add(self, a: Self, b: Self) -> Self {
+            self.mul(a).add(b)
+        }
+
+        #[inline(always)]
+        fn abs(self) -> Self {
+            Self(unsafe { _mm_andnot_ps(_mm_set1_ps(-0.0), self.0) })
+        }
+
+        #[inline(always)]
+        fn copysign(self, sign: Self) -> Self {
+            unsafe {
+                let mask = _mm_set1_ps(-0.0);
+                Self(_mm_or_ps(
+                    _mm_andnot_ps(mask, self.0),
+                    _mm_and_ps(mask, sign.0),
+                ))
+            }
+        }
+
+        #[inline(always)]
+        fn ge(self, other: Self) -> Self {
+            Self(unsafe { _mm_cmpge_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn gt(self, other: Self) -> Self {
+            Self(unsafe { _mm_cmpgt_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn select(mask: Self, then: Self, otherwise: Self) -> Self {
+            unsafe {
+                Self(_mm_or_ps(
+                    _mm_and_ps(mask.0, then.0),
+                    _mm_andnot_ps(mask.0, otherwise.0),
+                ))
+            }
+        }
+
+        #[inline(always)]
+        fn sum(self) -> f32 {
+            let mut lanes = [0.0; 4];
+            self.store(&mut lanes);
+            lanes.iter().sum()
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    struct Avx2(__m256);
+
+    impl Lanes for Avx2 {
+        const WIDTH: usize = 8;
+
+        #[inline(always)]
+        fn splat(value: f32) -> Self {
+            Self(unsafe { _mm256_set1_ps(value) })
+        }
+
+        #[inline(always)]
+        fn load(values: &[f32]) -> Self {
+            let values = &values[..Self::WIDTH];
+            Self(unsafe { _mm256_loadu_ps(values.as_ptr()) })
+        }
+
+        #[inline(always)]
+        fn store(self, values: &mut [f32]) {
+            let values = &mut values[..Self::WIDTH];
+            unsafe { _mm256_storeu_ps(values.as_mut_ptr(), self.0) }
+        }
+
+        #[inline(always)]
+        fn add(self, other: Self) -> Self {
+            Self(unsafe { _mm256_add_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn sub(self, other: Self) -> Self {
+            Self(unsafe { _mm256_sub_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn mul(self, other: Self) -> Self {
+            Self(unsafe { _mm256_mul_ps(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn mul_add(self, a: Self, b: Self) -> Self {
+            Self(unsafe { _mm256_fmadd_ps(self.0, a.0, b.0) })
+        }
+
+        #[inline(always)]
+        fn abs(self) -> Self {
+            Self(unsafe { _mm256_andnot_ps(_mm256_set1_ps(-0.0), self.0) })
+        }
+
+        #[inline(always)]
+        fn copysign(self, sign: Self) -> Self {
+            unsafe {
+                let mask = _mm256_set1_ps(-0.0);
+                Self(_mm256_or_ps(
+                    _mm256_andnot_ps(mask, self.0),
+                    _mm256_and_ps(mask, sign.0),
+                ))
+            }
+        }
+
+        #[inline(always)]
+        fn ge(self, other: Self) -> Self {
+            Self(unsafe { _mm256_cmp_ps::<_CMP_GE_OQ>(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn gt(self, other: Self) -> Self {
+            Self(unsafe { _mm256_cmp_ps::<_CMP_GT_OQ>(self.0, other.0) })
+        }
+
+        #[inline(always)]
+        fn select(mask: Self, then: Self, otherwise: Self) -> Self {
+            Self(unsafe { _mm256_blendv_ps(otherwise.0, then.0, mask.0) })
+        }
+
+        #[inline(always)]
+        fn sum(self) -> f32 {
+            let mut lanes = [0.0; 8];
+            self.store(&mut lanes);
+            lanes.iter().sum()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn render(simd: SimdLevel, waveform: Waveform) -> (SimdLevel, Vec<f32>) {
+        let mut bank = OscilatorBank::new(SAMPLE_RATE, waveform).simd(simd);
+        bank.add_unison(110.0, 21, 30.0, 1.0);
+        let mut output = vec![0.0; 4800];
+        bank.render(&mut output);
+        (bank.simd_level(), output)
+    }
+
+    #[test]
+    fn fast_sin_is_within_its_bound() {
+        const STEPS: u32 = 1 << 20;
+        let max_error = (0..STEPS)
+            .map(|step| {
+                let phase = step as f32 / STEPS as f32;
+                (fast_sin(phase) - f32::sin(TAU * phase)).abs()
+            })
+            .fold(0.0, f32::max);
+        assert!(max_error <= FAST_SIN_MAX_ERROR, "off by {max_error:e}");
+    }
+
+    #[test]
+    fn simd_levels_match_scalar() {
+        for waveform in Waveform::ALL {
+            let (_, scalar) = render(SimdLevel::Scalar, waveform);
+            for simd in SimdLevel::ALL.into_iter().filter(SimdLevel::is_supported) {
+                let (level, output) = render(simd, waveform);
+                assert_eq!(level, simd);
+                let diff = scalar
+                    .iter()
+                    .zip(&output)
+                    .map(|(a, b)| (a - b).abs())
+                    .fold(0.0, f32::max);
+                assert!(diff < 1e-5, "{waveform:?} {simd:?} off by {diff:e}");
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_levels_fall_back_to_scalar() {
+        for simd in SimdLevel::ALL {
+            let level = OscilatorBank::new(SAMPLE_RATE, Waveform::Sin)
+                .simd(simd)
+                .simd_level();
+            let expected = if simd.is_supported() {
+                simd
+            } else {
+                SimdLevel::Scalar
+            };
+            assert_eq!(level, expected);
+        }
+    }
+
+    #[test]
+    fn reserved_voices_are_added_in_place() {
+        let mut bank = OscilatorBank::new(SAMPLE_RATE, Waveform::Saw);
+        bank.reserve(MAX_UNISON);
+        let phases = bank.voices.phases.as_ptr();
+        for voices in [MAX_UNISON, 3, MAX_UNISON] {
+            bank.clear();
+            bank.add_unison(220.0, voices, 20.0, 1.0);
+            assert_eq!(bank.len(), voices);
+            assert_eq!(bank.voices.phases.as_ptr(), phases);
+        }
+    }
+
+    #[test]
+    fn unison_is_retuned_around_the_new_frequency() {
+        let mut bank = OscilatorBank::new(SAMPLE_RATE, Waveform::Saw);
+        bank.add_unison(220.0, 3, 20.0, 1.0);
+        bank.tune_unison(440.0, 1200.0);
+        let freqs: Vec<f32> = bank.voices.increments[..3]
+            .iter()
+            .map(|increment| increment * SAMPLE_RATE as f32)
+            .collect();
+        for (freq, expected) in freqs
+            .iter()
+            .zip([440.0 / 2f32.sqrt(), 440.0, 440.0 * 2f32.sqrt()])
+        {
+            assert!(
+                (freq - expected).abs() < 1e-2,
+                "{freq} instead of {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,618 @@
+//! Control panel for a browser on the same machine, behind the `web`
+//! feature. `GET /` serves the page, `GET /api/params` every parameter as
+//! JSON and `/ws` upgrades to a WebSocket speaking JSON messages tagged by
+//! `type`:
+//!
+//! | Direction | Type | Fields |
+//! |---|---|---|
+//! | to the browser | `state` | `params`, each with its key, name, range, unit, choices and value |
+//! | to the browser | `params` | `values` of the parameters that changed, by key |
+//! | to the browser | `frame` | `title`, `scope` samples, gain reductions in dB and `transport` |
+//! | to the browser | `error` | `message` |
+//! | to the synth | `set` | `id` (parameter key), `value` (number, bool or choice name) |
+//! | to the synth | `note_on` | `note` (name or MIDI number), `velocity` (0 to 1, optional) |
+//! | to the synth | `note_off` | `note` |
+//! | to the synth | `undo` | none, undoes the last parameter change |
+//! | to the synth | `redo` | none, redoes the last change undone |
+//!
+//! The WebSocket only accepts pages served by the panel itself, from
+//! `http://127.0.0.1:<port>` or `http://localhost:<port>`.
+//!
+//! Frames carry what the terminal UI draws: the wave over four periods of
+//! A4 and the compressor and limiter meters.
+
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+use crate::{
+    events::SynthEvent,
+    history::History,
+    meter::Meter,
+    notes::Note,
+    params::{ParamId, ParamStore},
+    patch::ParamValue,
+    transport::TransportHandle,
+    waves::Wave,
+};
+
+/// Port the panel is served on by default.
+pub const WEB_PORT: u16 = 8080;
+/// How often frames and parameter changes are pushed.
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+/// Samples of the wave in a frame.
+const SCOPE_SAMPLES: usize = 256;
+/// Longest request head read before giving up on a client.
+const MAX_HEAD_SIZE: usize = 8192;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+#[derive(Debug, Serialize)]
+struct ParamState {
+    key: &'static str,
+    name: &'static str,
+    min: f32,
+    max: f32,
+    step: f32,
+    unit: &'static str,
+    choices: &'static [&'static str],
+    value: ParamValue,
+}
+
+#[derive(Debug, Serialize)]
+struct TransportState {
+    playing: bool,
+    bar: u64,
+    beat: f64,
+    bpm: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Update {
+    State {
+        params: Vec<ParamState>,
+    },
+    Params {
+        values: BTreeMap<&'static str, ParamValue>,
+    },
+    Frame {
+        title: String,
+        scope: Vec<f32>,
+        compressor_db: Option<f32>,
+        limiter_db: Option<f32>,
+        transport: Option<TransportState>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A note given by name, like `"A4"`, or MIDI number.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum NoteArg {
+    Midi(u8),
+    Name(String),
+}
+
+impl NoteArg {
+    fn note(&self) -> Result<Note> {
+        match self {
+            NoteArg::Midi(key) => {
+                Note::from_midi(*key).ok_or_else(|| anyhow!("Invalid MIDI note {key}"))
+            }
+            NoteArg::Name(name) => name.parse().map_err(|_| anyhow!("Invalid note {name:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Set {
+        id: String,
+        value: ParamValue,
+    },
+    NoteOn {
+        note: NoteArg,
+        velocity: Option<f32>,
+    },
+    NoteOff {
+        note: NoteArg,
+    },
+    Undo,
+    Redo,
+}
+
+/// What the panel shows and controls.
+#[derive(Debug, Clone)]
+pub struct WebPanel {
+    params: ParamStore,
+    tx: Sender<SynthEvent>,
+    /// Settings the parameters don't cover, such as the tuning.
+    wave: Wave,
+    compressor: Option<Meter>,
+    limiter: Option<Meter>,
+    transport: Option<TransportHandle>,
+    /// Parameter changes, undone by `undo`.
+    history: Arc<Mutex<History>>,
+}
+
+impl WebPanel {
+    pub fn new(params: ParamStore, tx: Sender<SynthEvent>, wave: Wave) -> Self {
+        Self {
+            params,
+            tx,
+            wave,
+            compressor: None,
+            limiter: None,
+            transport: None,
+            history: Arc::default(),
+        }
+    }
+
+    /// Records the parameter changes in `history`, shared with other remotes
+    /// so each undoes the changes of the others too.
+    pub fn history(mut self, history: Arc<Mutex<History>>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Gain reduction of the compressor, in dB.
+    pub fn compressor(mut self, meter: Meter) -> Self {
+        self.compressor = Some(meter);
+        self
+    }
+
+    /// Gain reduction of the limiter, in dB.
+    pub fn limiter(mut self, meter: Meter) -> Self {
+        self.limiter = Some(meter);
+        self
+    }
+
+    pub fn transport(mut self, transport: TransportHandle) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Handles one message from the browser. Fails when it is invalid.
+    pub fn handle(&self, text: &str) -> Result<()> {
+        let command: Command =
+            serde_json::from_str(text).map_err(|err| anyhow!("Invalid message: {err}"))?;
+        let event = match command {
+            Command::Set { id, value } => {
+                let id =
+                    ParamId::from_key(&id).ok_or_else(|| anyhow!("Unknown parameter {id:?}"))?;
+                let value = value.decode(id)?;
+                self.history.lock().unwrap().set(&self.params, id, value);
+                return Ok(());
+            }
+            Command::Undo => {
+                if !self.history.lock().unwrap().undo_params(&self.params) {
+                    return Err(anyhow!("Nothing to undo"));
+                }
+                return Ok(());
+            }
+            Command::Redo => {
+                if !self.history.lock().unwrap().redo_params(&self.params) {
+                    return Err(anyhow!("Nothing to redo"));
+                }
+                return Ok(());
+            }
+            Command::NoteOn { note, velocity } => SynthEvent::NoteOn {
+                note: note.note()?,
+                velocity: velocity.unwrap_or(1.0).clamp(0.0, 1.0),
+            },
+            Command::NoteOff { note } => SynthEvent::NoteOff { note: note.note()? },
+        };
+        self.tx.send(event)?;
+        Ok(())
+    }
+
+    fn state(&self) -> Update {
+        let params = ParamId::ALL
+            .into_iter()
+            .map(|id| {
+                let info = id.info();
+                ParamState {
+                    key: id.key(),
+                    name: info.name,
+                    min: info.min,
+                    max: info.max,
+                    step: info.step,
+                    unit: info.unit.symbol(),
+                    choices: info.choices,
+                    value: ParamValue::encode(id, self.params.get(id)),
+                }
+            })
+            .collect();
+        Update::State { params }
+    }
+
+    fn frame(&self) -> Update {
+        let wave = self.params.wave(self.wave.clone());
+        let period = Note::A4.freq().recip();
+        let delta = 4.0 * period / (SCOPE_SAMPLES - 1) as f32;
+        let transport = self.transport.as_ref().map(|transport| {
+            let position = transport.position();
+            TransportState {
+                playing: position.playing,
+                bar: position.bar() + 1,
+                beat: position.beat_in_bar().floor() + 1.0,
+                bpm: position.bpm,
+            }
+        });
+        Update::Frame {
+            title: format!(
+                "{:?}, {} ({:.2} Hz, {:+.1} cents), {:.2?}, A4 = {} Hz {}",
+                wave.waveform,
+                wave.note,
+                wave.freq(),
+                wave.cents(),
+                wave.amp,
+                wave.tuning.reference,
+                wave.tuning.temperament.name()
+            ),
+            scope: (0..SCOPE_SAMPLES)
+                .map(|i| wave.sample(i as f32 * delta))
+                .collect(),
+            compressor_db: self.compressor.as_ref().map(Meter::get),
+            limiter_db: self.limiter.as_ref().map(Meter::get),
+            transport,
+        }
+    }
+
+    /// Parameters that changed since `sent`, which is updated.
+    fn changes(&self, sent: &mut [f32]) -> Option<Update> {
+        let mut values = BTreeMap::new();
+        for id in ParamId::ALL {
+            let value = self.params.get(id);
+            if value != sent[id.index()] {
+                sent[id.index()] = value;
+                values.insert(id.key(), ParamValue::encode(id, value));
+            }
+        }
+        (!values.is_empty()).then_some(Update::Params { values })
+    }
+}
+
+/// Serves the panel over HTTP, each connection from its own thread.
+#[derive(Debug)]
+pub struct WebServer {
+    listener: TcpListener,
+    panel: WebPanel,
+}
+
+impl WebServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, panel: WebPanel) -> Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            panel,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections, a failed one is logged and skipped.
+    pub fn run(&self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Couldn't accept a web connection: {err}");
+                    // Out of file descriptors, give the open ones time to close.
+                    std::thread::sleep(FRAME_INTERVAL);
+                    continue;
+                }
+            };
+            let panel = self.panel.clone();
+            std::thread::spawn(move || serve_connection(&panel, stream));
+        }
+        Ok(())
+    }
+
+    /// Runs the server from a new thread.
+    pub fn spawn(self) -> JoinHandle<Result<()>> {
+        std::thread::spawn(move || self.run())
+    }
+}
+
+/// Reads the request head one byte at a time, so nothing sent after it is
+/// consumed.
+fn read_head(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_SIZE {
+            return Err(anyhow!("Request head too long"));
+        }
+        if stream.read(&mut byte)? == 0 {
+            return Err(anyhow!("Connection closed"));
+        }
+        head.push(byte[0]);
+    }
+    Ok(head)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+fn serve_connection(panel: &WebPanel, mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(HEAD_TIMEOUT))?;
+    let head = read_head(&mut stream)?;
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut request = httparse::Request::new(&mut headers);
+    if request
+        .parse(&head)
+        .map_or(true, |status| status.is_partial())
+    {
+        return respond(&mut stream, "400 Bad Request", "text/plain", "Bad request");
+    }
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .and_then(|header| std::str::from_utf8(header.value).ok())
+    };
+    let websocket_key = header("Upgrade")
+        .filter(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+        .and(header("Sec-WebSocket-Key"))
+        .map(|key| derive_accept_key(key.trim().as_bytes()));
+    match (request.method, request.path) {
+        (Some("GET"), Some("/")) => respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            INDEX_HTML,
+        ),
+        (Some("GET"), Some("/api/params")) => respond(
+            &mut stream,
+            "200 OK",
+            "application/json",
+            &serde_json::to_string(&panel.state())?,
+        ),
+        // Pages from other sites could otherwise drive the synth through the
+        // browser, which connects WebSockets from any origin.
+        (Some("GET"), Some("/ws"))
+            if !header("Origin").is_some_and(|origin| {
+                is_local_origin(origin, stream.local_addr().map_or(0, |addr| addr.port()))
+            }) =>
+        {
+            respond(
+                &mut stream,
+                "403 Forbidden",
+                "text/plain",
+                "Forbidden origin",
+            )
+        }
+        (Some("GET"), Some("/ws")) => match websocket_key {
+            Some(accept) => {
+                write!(
+                    stream,
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+                )?;
+                stream.set_read_timeout(Some(FRAME_INTERVAL))?;
+                serve_websocket(
+                    panel,
+                    WebSocket::from_raw_socket(stream, Role::Server, None),
+                )
+            }
+            None => respond(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                "Expected a WebSocket upgrade",
+            ),
+        },
+        (Some("GET"), _) => respond(&mut stream, "404 Not Found", "text/plain", "Not found"),
+        _ => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed",
+        ),
+    }
+}
+
+/// Whether `origin` is the panel served on `port` of this machine.
+fn is_local_origin(origin: &str, port: u16) -> bool {
+    ["127.0.0.1", "localhost"]
+        .iter()
+        .any(|host| origin == format!("http://{host}:{port}"))
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, update: &Update) -> Result<()> {
+    socket.send(Message::text(serde_json::to_string(update)?))?;
+    Ok(())
+}
+
+/// Sends the state, then handles messages while pushing a frame and the
+/// parameter changes every [`FRAME_INTERVAL`], until the browser leaves.
+fn serve_websocket(panel: &WebPanel, mut socket: WebSocket<TcpStream>) -> Result<()> {
+    send(&mut socket, &panel.state())?;
+    let mut sent = ParamId::ALL.map(|id| panel.params.get(id));
+    let mut last_frame = Instant::now();
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if let Err(err) = panel.handle(&text) {
+                    let message = format!("{err:#}");
+                    send(&mut socket, &Update::Error { message })?;
+                }
+            }
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                return Ok(())
+            }
+            Err(err) => return Err(err.into()),
+        }
+        if socket.can_write() && last_frame.elapsed() >= FRAME_INTERVAL {
+            last_frame = Instant::now();
+            if let Some(changes) = panel.changes(&mut sent) {
+                send(&mut socket, &changes)?;
+            }
+            send(&mut socket, &panel.frame())?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tungstenite::{client::IntoClientRequest, stream::MaybeTlsStream};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::waves::Waveform;
+
+    /// A panel on a free loopback port, its address and the events it sends.
+    fn serve(params: &ParamStore) -> (SocketAddr, crossbeam_channel::Receiver<SynthEvent>) {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let wave = Wave::new(Waveform::Sin, Note::A4, 0.3);
+        let server =
+            WebServer::bind("127.0.0.1:0", WebPanel::new(params.clone(), tx, wave)).unwrap();
+        let addr = server.local_addr().unwrap();
+        server.spawn();
+        (addr, rx)
+    }
+
+    /// Status line and body of a plain HTTP request.
+    fn request(addr: SocketAddr, method: &str, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{method} {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    /// A WebSocket to the panel, or the HTTP status refusing it.
+    fn connect(
+        addr: SocketAddr,
+        origin: Option<&str>,
+    ) -> std::result::Result<WebSocket<MaybeTlsStream<TcpStream>>, u16> {
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert("Origin", origin.parse().unwrap());
+        }
+        let socket = match tungstenite::connect(request) {
+            Ok((socket, _)) => socket,
+            Err(tungstenite::Error::Http(response)) => return Err(response.status().as_u16()),
+            Err(err) => panic!("{err}"),
+        };
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+        }
+        Ok(socket)
+    }
+
+    /// Skips messages until one of type `kind`.
+    fn receive(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>, kind: &str) -> serde_json::Value {
+        loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if message["type"] == kind {
+                    return message;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_only_local_origins_on_its_port() {
+        assert!(is_local_origin("http://127.0.0.1:8080", 8080));
+        assert!(is_local_origin("http://localhost:8080", 8080));
+        assert!(!is_local_origin("http://localhost:8081", 8080));
+        assert!(!is_local_origin("https://localhost:8080", 8080));
+        assert!(!is_local_origin("http://localhost.example.com:8080", 8080));
+        assert!(!is_local_origin("null", 8080));
+    }
+
+    #[test]
+    fn serves_the_page_and_the_parameters() {
+        let (addr, _events) = serve(&ParamStore::new());
+        let (status, page) = request(addr, "GET", "/");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(page.contains("<canvas"));
+        let (status, body) = request(addr, "GET", "/api/params");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            state["params"].as_array().unwrap().len(),
+            ParamId::ALL.len()
+        );
+        assert_eq!(request(addr, "GET", "/missing").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            request(addr, "POST", "/").0,
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+    }
+
+    #[test]
+    fn refuses_websockets_from_other_origins() {
+        let (addr, _events) = serve(&ParamStore::new());
+        let other_port = format!("http://localhost:{}", addr.port() + 1);
+        for origin in [None, Some("http://example.com"), Some(other_port.as_str())] {
+            assert_eq!(connect(addr, origin).err(), Some(403), "{origin:?}");
+        }
+    }
+
+    #[test]
+    fn controls_the_synth_over_a_websocket() {
+        let params = ParamStore::new();
+        let (addr, events) = serve(&params);
+        let origin = format!("http://localhost:{}", addr.port());
+        let mut socket = connect(addr, Some(&origin)).unwrap();
+        let state = receive(&mut socket, "state");
+        assert_eq!(
+            state["params"].as_array().unwrap().len(),
+            ParamId::ALL.len()
+        );
+
+        let messages = [
+            json!({ "type": "set", "id": "waveform", "value": "saw" }),
+            json!({ "type": "set", "id": "mono", "value": true }),
+            json!({ "type": "note_on", "note": "C4", "velocity": 0.8 }),
+            json!({ "type": "set", "id": "volume", "value": 1 }),
+        ];
+        for message in messages {
+            socket.send(Message::text(message.to_string())).unwrap();
+        }
+        let error = receive(&mut socket, "error");
+        assert!(error["message"].as_str().unwrap().contains("volume"));
+        let frame = receive(&mut socket, "frame");
+        assert_eq!(frame["scope"].as_array().unwrap().len(), SCOPE_SAMPLES);
+
+        assert_eq!(params.get(ParamId::Waveform), 2.0);
+        assert_eq!(params.get(ParamId::Mono), 1.0);
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(event, SynthEvent::NoteOn { note: Note::C4, .. }));
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use synth::patch::PATCH_EXTENSION;
+
+use crate::{keys::KeyBindings, theme::Theme};
+
+/// Directory of the config file in `$XDG_CONFIG_HOME`.
+const CONFIG_DIR: &str = "rust-synth";
+const CONFIG_FILE: &str = "config.toml";
+
+pub const USAGE: &str = "Usage: tui [options] [file...]
+
+Files are told apart by extension: a patch (.toml) sets the initial sound, a
+Scala scale (.scl) or keyboard mapping (.kbm) the tuning, a pattern (.seq) the
+sequencer, and anything else is played as a MIDI file.
+
+Options:
+    --config PATH          Config file, instead of $XDG_CONFIG_HOME/rust-synth/config.toml
+    --host NAME            Audio host, such as ALSA or JACK
+    --device NAME          Output device
+    --sample-rate HZ       Sample rate
+    --buffer-size FRAMES   Frames per buffer
+    --channels N           Output channels
+    --patch PATH           Initial patch
+    --theme NAME           default, monochrome or neon
+    --list-devices         List the audio hosts and their output devices
+    -h, --help             Show this help
+
+The config file takes the same settings, key bindings and theme colors:
+
+    patch = \"patches/soft-saw.toml\"
+
+    [audio]
+    host = \"ALSA\"
+    device = \"default\"
+    sample_rate = 48000
+    buffer_size = 512
+    channels = 2
+
+    [keys]
+    quit = \"q\"
+    arp = \"F2\"
+
+    [theme]
+    name = \"neon\"
+    highlight = \"#ff8800\"";
+
+/// Audio settings, the device picks those left out.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    patch: Option<PathBuf>,
+    audio: AudioConfig,
+    keys: BTreeMap<String, String>,
+    theme: ThemeConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ThemeConfig {
+    name: Option<String>,
+    #[serde(flatten)]
+    colors: BTreeMap<String, String>,
+}
+
+/// Settings from the config file, overridden by the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub audio: AudioConfig,
+    pub patch: Option<PathBuf>,
+    pub keys: KeyBindings,
+    pub theme: Theme,
+    /// Files given as arguments, handled by their extension.
+    pub files: Vec<String>,
+    pub list_devices: bool,
+    pub help: bool,
+}
+
+/// Default config file, `$XDG_CONFIG_HOME/rust-synth/config.toml` or
+/// `~/.config/rust-synth/config.toml`.
+pub fn config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str, unit: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid {flag} {value:?}, expected a number of {unit}"))
+}
+
+impl Config {
+    /// Reads the config file, then applies the command line `args` over it.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config_file = None;
+        let mut audio = AudioConfig::default();
+        let mut patch = None;
+        let mut theme = None;
+        let mut config = Config::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") && arg != "-h" {
+                config.files.push(arg);
+                continue;
+            }
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| anyhow!("{flag} needs a value\n\n{USAGE}"))
+            };
+            match flag.as_str() {
+                "--config" => config_file = Some(PathBuf::from(value()?)),
+                "--host" => audio.host = Some(value()?),
+                "--device" => audio.device = Some(value()?),
+                "--sample-rate" => audio.sample_rate = Some(number(&flag, &value()?, "Hz")?),
+                "--buffer-size" => audio.buffer_size = Some(number(&flag, &value()?, "frames")?),
+                "--channels" => audio.channels = Some(number(&flag, &value()?, "channels")?),
+                "--patch" => patch = Some(PathBuf::from(value()?)),
+                "--theme" => theme = Some(value()?),
+                "--list-devices" => config.list_devices = true,
+                "-h" | "--help" => config.help = true,
+                _ => return Err(anyhow!("Unknown option {flag}\n\n{USAGE}")),
+            }
+        }
+
+        // Only a config file asked for has to exist.
+        let file = match config_file {
+            Some(path) => Some(read_file(&path)?),
+            None => match config_path() {
+                Some(path) if path.exists() => Some(read_file(&path)?),
+                _ => None,
+            },
+        };
+        let (file, dir) = file.unwrap_or_default();
+
+        config.audio = AudioConfig {
+            host: audio.host.or(file.audio.host),
+            device: audio.device.or(file.audio.device),
+            sample_rate: audio.sample_rate.or(file.audio.sample_rate),
+            buffer_size: audio.buffer_size.or(file.audio.buffer_size),
+            channels: audio.channels.or(file.audio.channels),
+        };
+        // A patch given as a file replaces the one of the config file, but
+        // can't be given along with --patch.
+        let patch_files = config
+            .files
+            .iter()
+            .filter(|file| {
+                Path::new(file)
+                    .extension()
+                    .is_some_and(|ext| ext == PATCH_EXTENSION)
+            })
+            .count();
+        if patch_files > 1 || (patch_files == 1 && patch.is_some()) {
+            return Err(anyhow!(
+                "Only one patch can be given, with --patch or as a file\n\n{USAGE}"
+            ));
+        }
+        // Paths in the config file are relative to it.
+        if patch_files == 0 {
+            config.patch = patch.or(file.patch.map(|path| dir.join(path)));
+        }
+        config.keys = KeyBindings::with_overrides(&file.keys)?;
+        config.theme = match theme.or(file.theme.name) {
+            Some(name) => Theme::named(&name)?,
+            None => Theme::default(),
+        }
+        .with_colors(&file.theme.colors)?;
+        Ok(config)
+    }
+}
+
+/// Parsed config file and the directory it is in.
+fn read_file(path: &Path) -> Result<(ConfigFile, PathBuf)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read the config file {}", path.display()))?;
+    let file =
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok((file, dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+    use ratatui::style::Color;
+
+    use super::*;
+    use crate::keys::Action;
+
+    /// Loads `args` over a config file holding `text`.
+    fn load(name: &str, text: &str, args: &[&str]) -> Result<Config> {
+        let dir = std::env::temp_dir().join(format!("tui-config-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+        std::fs::write(&path, text).unwrap();
+        let args = ["--config", path.to_str().unwrap()]
+            .into_iter()
+            .chain(args.iter().copied())
+            .map(String::from);
+        let config = Config::load(args);
+        std::fs::remove_dir_all(&dir).unwrap();
+        config
+    }
+
+    #[test]
+    fn command_line_wins_over_the_config_file() {
+        let text = r##"
+            patch = "patches/soft-saw.toml"
+
+            [audio]
+            host = "ALSA"
+            sample_rate = 44100
+
+            [keys]
+            quit = "q"
+
+            [theme]
+            name = "neon"
+            highlight = "#ff8800"
+        "##;
+        let config = load(
+            "override",
+            text,
+            &["--sample-rate=48000", "--channels", "1"],
+        )
+        .unwrap();
+        assert_eq!(config.audio.host.as_deref(), Some("ALSA"));
+        assert_eq!(config.audio.sample_rate, Some(48000));
+        assert_eq!(config.audio.channels, Some(1));
+        assert!(config.patch.unwrap().ends_with("patches/soft-saw.toml"));
+        assert_eq!(config.keys.key(Action::Quit), KeyCode::Char('q'));
+        assert_eq!(config.theme.wave, Color::LightGreen);
+        assert_eq!(config.theme.highlight, Color::Rgb(0xff, 0x88, 0x00));
+
+        let config = load("theme", text, &["--theme", "monochrome"]).unwrap();
+        assert_eq!(config.theme.wave, Color::Reset);
+    }
+
+    #[test]
+    fn takes_one_patch() {
+        let text = r#"patch = "soft-saw.toml""#;
+        let config = load("file", text, &["bass.toml", "song.mid"]).unwrap();
+        assert_eq!(config.patch, None);
+        assert_eq!(config.files, ["bass.toml", "song.mid"]);
+        assert!(load("both", "", &["--patch", "a.toml", "b.toml"]).is_err());
+        assert!(load("two", "", &["a.toml", "b.toml"]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(load("option", "", &["--volume", "1"]).is_err());
+        assert!(load("value", "", &["--host"]).is_err());
+        assert!(load("number", "", &["--sample-rate", "fast"]).is_err());
+        assert!(load("field", "volume = 1", &[]).is_err());
+        assert!(load("audio", "[audio]\nrate = 1", &[]).is_err());
+        assert!(load("key", "[keys]\nquit = \"Tab\"", &[]).is_err());
+        assert!(load("theme", "[theme]\nname = \"pastel\"", &[]).is_err());
+        assert!(Config::load([
+            "--config".to_string(),
+            "/nonexistent/config.toml".to_string()
+        ])
+        .is_err());
+    }
+}
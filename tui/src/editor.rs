@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+
+use crossterm::event::KeyCode;
+use synth::sequencer::pattern::{Pattern, Step, Track, TrackLength, MAX_TRACKS};
+
+use crate::input::cycle;
+
+/// Gate lengths cycled through with `g`.
+const GATES: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+/// Probabilities cycled through with `p`.
+const PROBABILITIES: [f32; 4] = [1.0, 0.75, 0.5, 0.25];
+const BPM_RANGE: (f32, f32) = (40.0, 300.0);
+/// Keys [`SequencerEditor::handle_key`] uses, which the global key
+/// bindings can't take.
+pub const EDITOR_KEYS: [KeyCode; 20] = [
+    KeyCode::Right,
+    KeyCode::Left,
+    KeyCode::Down,
+    KeyCode::Up,
+    KeyCode::Char(' '),
+    KeyCode::Enter,
+    KeyCode::Char('='),
+    KeyCode::Char('-'),
+    KeyCode::Char('.'),
+    KeyCode::Char(','),
+    KeyCode::Char('g'),
+    KeyCode::Char('t'),
+    KeyCode::Char('p'),
+    KeyCode::Char('l'),
+    KeyCode::Char('n'),
+    KeyCode::Char('x'),
+    KeyCode::Char(']'),
+    KeyCode::Char('['),
+    KeyCode::Char('s'),
+    KeyCode::Char('o'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Synth,
+    Sequencer,
+    Presets,
+}
+
+impl Page {
+    /// Page shown after this one with `<Tab>`.
+    pub fn next(self) -> Self {
+        match self {
+            Page::Synth => Page::Sequencer,
+            Page::Sequencer => Page::Presets,
+            Page::Presets => Page::Synth,
+        }
+    }
+}
+
+/// Pattern being edited in the sequencer page, with the selected step.
+#[derive(Debug, Clone)]
+pub struct SequencerEditor {
+    pub pattern: Pattern,
+    pub track: usize,
+    pub step: usize,
+    pub playing: bool,
+    /// File the pattern is saved to and loaded from.
+    pub path: PathBuf,
+    /// Result of the last save or load.
+    pub status: String,
+}
+
+/// What the editor did with a key press.
+#[derive(Debug, PartialEq)]
+pub enum EditorAction {
+    None,
+    /// The pattern changed and should be sent to the sequencer.
+    Edit,
+    Play,
+    Stop,
+}
+
+impl SequencerEditor {
+    pub fn new(pattern: Pattern, path: PathBuf) -> Self {
+        Self {
+            pattern,
+            track: 0,
+            step: 0,
+            playing: false,
+            path,
+            status: String::new(),
+        }
+    }
+
+    pub fn selected(&self) -> &Step {
+        &self.pattern.tracks[self.track].steps()[self.step]
+    }
+
+    fn selected_mut(&mut self) -> &mut Step {
+        &mut self.pattern.tracks[self.track].steps_mut()[self.step]
+    }
+
+    fn clamp_cursor(&mut self) {
+        self.track = self.track.min(self.pattern.tracks.len() - 1);
+        let steps = self.pattern.tracks[self.track].steps().len();
+        self.step = self.step.min(steps - 1);
+    }
+
+    pub fn handle_key(&mut self, code: KeyCode) -> EditorAction {
+        let steps = self.pattern.tracks[self.track].steps().len();
+        match code {
+            KeyCode::Right => self.step = (self.step + 1) % steps,
+            KeyCode::Left => self.step = (self.step + steps - 1) % steps,
+            KeyCode::Down => {
+                self.track = (self.track + 1) % self.pattern.tracks.len();
+                self.clamp_cursor();
+            }
+            KeyCode::Up => {
+                let tracks = self.pattern.tracks.len();
+                self.track = (self.track + tracks - 1) % tracks;
+                self.clamp_cursor();
+            }
+            KeyCode::Char(' ') => {
+                self.playing = !self.playing;
+                return if self.playing {
+                    EditorAction::Play
+                } else {
+                    EditorAction::Stop
+                };
+            }
+            KeyCode::Enter => {
+                let step = self.selected_mut();
+                step.active = !step.active;
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('=') => {
+                let step = self.selected_mut();
+                step.note = step.note.transpose(1).unwrap_or(step.note);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('-') => {
+                let step = self.selected_mut();
+                step.note = step.note.transpose(-1).unwrap_or(step.note);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('.') => {
+                let step = self.selected_mut();
+                step.velocity = (step.velocity + 0.1).clamp(0.1, 1.0);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char(',') => {
+                let step = self.selected_mut();
+                step.velocity = (step.velocity - 0.1).clamp(0.1, 1.0);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('g') => {
+                let step = self.selected_mut();
+                step.gate = cycle(&GATES, step.gate);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('t') => {
+                let step = self.selected_mut();
+                step.tie = !step.tie;
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('p') => {
+                let step = self.selected_mut();
+                step.probability = cycle(&PROBABILITIES, step.probability);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('l') => {
+                let track = &mut self.pattern.tracks[self.track];
+                track.set_length(cycle(&TrackLength::ALL, track.length()));
+                self.clamp_cursor();
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('n') if self.pattern.tracks.len() < MAX_TRACKS => {
+                self.pattern.tracks.push(Track::default());
+                self.track = self.pattern.tracks.len() - 1;
+                self.clamp_cursor();
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('x') if self.pattern.tracks.len() > 1 => {
+                self.pattern.tracks.remove(self.track);
+                self.clamp_cursor();
+                return EditorAction::Edit;
+            }
+            KeyCode::Char(']') => {
+                let (min, max) = BPM_RANGE;
+                self.pattern.bpm = (self.pattern.bpm + 5.0).clamp(min, max);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('[') => {
+                let (min, max) = BPM_RANGE;
+                self.pattern.bpm = (self.pattern.bpm - 5.0).clamp(min, max);
+                return EditorAction::Edit;
+            }
+            KeyCode::Char('s') => {
+                self.status = match self.pattern.save(&self.path) {
+                    Ok(()) => format!("Saved to {}", self.path.display()),
+                    Err(err) => format!("Couldn't save {}: {err:#}", self.path.display()),
+                };
+            }
+            KeyCode::Char('o') => match Pattern::open(&self.path) {
+                Ok(pattern) => {
+                    self.pattern = pattern;
+                    self.clamp_cursor();
+                    self.status = format!("Loaded {}", self.path.display());
+                    return EditorAction::Edit;
+                }
+                Err(err) => {
+                    self.status = format!("Couldn't load {}: {err:#}", self.path.display());
+                }
+            },
+            _ => (),
+        }
+        EditorAction::None
+    }
+}
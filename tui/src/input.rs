@@ -1,16 +1,39 @@
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, Result};
 use crossbeam_channel::{SendError, Sender, TrySendError};
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{self, Event, KeyEvent, KeyEventKind};
 use synth::{
+    arp::{ArpPattern, ArpRate, MAX_OCTAVES},
+    effects::{
+        distortion::{Curve, DistortionControl, Oversampling, MAX_CRUSH_BITS, MAX_CRUSH_RATE},
+        EffectSettings,
+    },
+    events::SynthEvent,
+    glide::{GlideCurve, GlideMode},
+    history::{Edit, History},
+    mono::NotePriority,
+    notes::Note,
+    params::ParamStore,
+    patch::Patch,
+    sequencer::SequencerCommand,
+    transport::TransportHandle,
+    tuning::Temperament,
     waves::{Wave, Waveform},
-    Note,
+};
+
+use crate::{
+    editor::{EditorAction, Page, SequencerEditor},
+    keys::{Action, KeyBindings},
+    presets::{PresetAction, PresetBrowser},
+    ui::View,
 };
 
 #[derive(Debug)]
 pub struct MultiSender<T>(Vec<Sender<T>>);
 
 #[allow(dead_code)]
-impl<T: Copy> MultiSender<T> {
+impl<T: Clone> MultiSender<T> {
     pub fn new() -> MultiSender<T> {
         MultiSender(Vec::new())
     }
@@ -24,14 +47,45 @@ impl<T: Copy> MultiSender<T> {
     }
 
     pub fn try_send(&self, msg: T) -> Vec<Result<(), TrySendError<T>>> {
-        self.0.iter().map(|s| s.try_send(msg)).collect()
+        self.0.iter().map(|s| s.try_send(msg.clone())).collect()
     }
 
     pub fn send(&self, msg: T) -> Vec<Result<(), SendError<T>>> {
-        self.0.iter().map(|s| s.send(msg)).collect()
+        self.0.iter().map(|s| s.send(msg.clone())).collect()
     }
 }
 
+/// Glide times in milliseconds cycled through with `g`.
+const GLIDE_TIMES: [f32; 5] = [0.0, 50.0, 100.0, 250.0, 500.0];
+
+/// Arpeggiator gate lengths cycled through with `h`.
+const ARP_GATES: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+/// Arpeggiator swing amounts cycled through with `w`.
+const ARP_SWINGS: [f32; 4] = [0.0, 0.1, 0.2, 1.0 / 3.0];
+const ARP_BPM_RANGE: (f32, f32) = (40.0, 300.0);
+/// Unison voices cycled through with `u`.
+const UNISON_VOICES: [usize; 5] = [1, 2, 3, 5, 7];
+/// Unison spreads in cents cycled through with `i`.
+const UNISON_SPREADS: [f32; 4] = [10.0, 20.0, 40.0, 80.0];
+/// Waveshaper drives cycled through with `j`.
+const SHAPER_DRIVES: [f32; 5] = [1.0, 2.0, 4.0, 8.0, 16.0];
+/// Bitcrusher depths cycled through with `b`, the first one is clean.
+const CRUSH_BITS: [u32; 5] = [MAX_CRUSH_BITS, 12, 8, 6, 4];
+/// Bitcrusher rates in Hz cycled through with `n`, the first one is clean.
+const CRUSH_RATES: [f32; 5] = [MAX_CRUSH_RATE, 22050.0, 11025.0, 6000.0, 3000.0];
+
+/// How long a patch is heard when auditioned from the presets page.
+const AUDITION_TIME: Duration = Duration::from_millis(1500);
+
+/// Next value after `current` in `values`, wrapping around.
+pub fn cycle<T: Copy + PartialEq>(values: &[T], current: T) -> T {
+    let index = values
+        .iter()
+        .position(|value| *value == current)
+        .unwrap_or(0);
+    values[(index + 1) % values.len()]
+}
+
 #[derive(Debug)]
 enum ControlFlow {
     Continue,
@@ -41,88 +95,505 @@ enum ControlFlow {
 #[derive(Debug)]
 pub struct Input {
     wave: Wave,
+    /// Parameters followed by the audio thread.
+    params: ParamStore,
+    /// Sends the wave to be drawn.
     tx: MultiSender<Wave>,
+    page: Page,
+    editor: SequencerEditor,
+    presets: PresetBrowser,
+    /// Edits of the synth parameters, to undo them.
+    history: History,
+    sequencer: Sender<SequencerCommand>,
+    transport: TransportHandle,
+    /// Sends the page, editor and preset state to be drawn.
+    view: Sender<View>,
+    /// Plays notes on the synth, to audition patches.
+    events: Option<Sender<SynthEvent>>,
+    /// When the patch being auditioned stops, and the note it plays.
+    audition: Option<(Instant, Note)>,
+    /// Custom or mapped temperament while a preset one is picked.
+    kept: Temperament,
+    keys: KeyBindings,
+    /// Effects the synth started with and the distortion set since, saved
+    /// with the patches.
+    effects: EffectSettings,
+    /// Waveshaper and bitcrusher settings followed by the audio thread.
+    distortion: Option<DistortionControl>,
 }
 
 impl Input {
-    pub fn new(tx: MultiSender<Wave>) -> Self {
+    pub fn new(
+        wave: Wave,
+        params: ParamStore,
+        tx: MultiSender<Wave>,
+        initial: View,
+        sequencer: Sender<SequencerCommand>,
+        transport: TransportHandle,
+        view: Sender<View>,
+    ) -> Self {
         Self {
-            wave: Wave::new(Waveform::None, Note::A4, 0.3),
+            wave,
+            params,
             tx,
+            page: initial.page,
+            editor: initial.editor,
+            presets: initial.presets,
+            history: initial.history,
+            sequencer,
+            transport,
+            view,
+            events: None,
+            audition: None,
+            kept: Temperament::Equal,
+            keys: KeyBindings::default(),
+            effects: EffectSettings::default(),
+            distortion: None,
         }
     }
 
+    /// Auditions patches with notes sent to `events`, without it the current
+    /// note is heard with the patch.
+    pub fn events(mut self, events: Sender<SynthEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn effects(mut self, effects: EffectSettings) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    /// Changes the waveshaper and bitcrusher of the output through `control`.
+    pub fn distortion(mut self, control: DistortionControl) -> Self {
+        self.distortion = Some(control);
+        self
+    }
+
+    pub fn keys(mut self, keys: KeyBindings) -> Self {
+        self.keys = keys;
+        self
+    }
+
     pub fn handle(&mut self) -> Result<()> {
         loop {
+            if let Some((end, _)) = self.audition {
+                let timeout = end.saturating_duration_since(Instant::now());
+                if !event::poll(timeout).unwrap_or(false) {
+                    self.end_audition()?;
+                    continue;
+                }
+            }
             let Ok(event) = event::read() else {
                 continue;
             };
             let result = match event {
-                Event::Key(key_event) => self.handle_key(key_event),
+                Event::Key(key_event) => {
+                    if key_event.kind == KeyEventKind::Press {
+                        self.end_audition()?;
+                    }
+                    self.edit(key_event)
+                }
                 Event::Resize(_, _) => self.send_wave(),
                 _ => Ok(ControlFlow::Continue),
             }?;
-            match result {
-                ControlFlow::Quit => break,
-                _ => (),
+            if let ControlFlow::Quit = result {
+                break;
             }
         }
         Ok(())
     }
 
+    /// Handles a key, recording the parameters it changed in the history.
+    fn edit(&mut self, key_event: KeyEvent) -> Result<ControlFlow> {
+        // Parameters may have been changed remotely since the last key.
+        self.wave = self.params.wave(self.wave.clone());
+        if self.page == Page::Synth && key_event.kind == KeyEventKind::Press {
+            match self.keys.action(key_event.code) {
+                Some(Action::Undo) => return self.undo(),
+                Some(Action::Redo) => return self.redo(),
+                _ => (),
+            }
+        }
+        let before = self.wave.clone();
+        let flow = self.handle_key(key_event)?;
+        let edit = Edit::between(&before, &self.wave);
+        if !edit.is_empty() {
+            self.history.push(edit);
+            self.send_view()?;
+        }
+        Ok(flow)
+    }
+
+    fn undo(&mut self) -> Result<ControlFlow> {
+        if let Some(edit) = self.history.undo() {
+            edit.revert_wave(&mut self.wave);
+            self.send_wave()?;
+        }
+        self.send_view()
+    }
+
+    fn redo(&mut self) -> Result<ControlFlow> {
+        if let Some(edit) = self.history.redo() {
+            edit.apply_to_wave(&mut self.wave);
+            self.send_wave()?;
+        }
+        self.send_view()
+    }
+
     fn handle_key(&mut self, key_event: KeyEvent) -> Result<ControlFlow> {
+        // Keys typed into a name or search are text, even the global ones.
+        let typing = self.page == Page::Presets && self.presets.is_typing();
+        let action = self.keys.action(key_event.code).filter(|_| !typing);
         match key_event {
+            KeyEvent {
+                kind: KeyEventKind::Press,
+                ..
+            } if action == Some(Action::NextPage) => {
+                self.page = self.page.next();
+                self.send_view()
+            }
             KeyEvent {
                 code,
                 kind: KeyEventKind::Press,
                 ..
-            } => match code {
-                KeyCode::Char('1') => {
+            } if self.page == Page::Presets && action != Some(Action::Quit) => {
+                match self.presets.handle_key(code) {
+                    PresetAction::None => (),
+                    PresetAction::Load(patch) => match patch.apply_to_wave(&mut self.wave) {
+                        Ok(()) => {
+                            self.send_wave()?;
+                            if let Some(effects) = &patch.effects {
+                                self.load_distortion(effects);
+                                self.send_distortion()?;
+                                if *effects != self.effects {
+                                    self.presets.status +=
+                                        ", its effects are set on the next start";
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            self.presets.status = format!("Couldn't load {}: {err:#}", patch.name)
+                        }
+                    },
+                    PresetAction::Audition(patch) => self.audition(&patch)?,
+                    PresetAction::SaveAs(name) => {
+                        self.presets.save(
+                            Patch::from_wave(&name, &self.wave).effects(self.effects.clone()),
+                        );
+                    }
+                }
+                self.send_view()
+            }
+            KeyEvent {
+                code,
+                kind: KeyEventKind::Press,
+                ..
+            } if self.page == Page::Sequencer && action != Some(Action::Quit) => {
+                match self.editor.handle_key(code) {
+                    EditorAction::None => (),
+                    EditorAction::Edit => {
+                        self.sequencer
+                            .send(SequencerCommand::Pattern(self.editor.pattern.clone()))
+                            .map_err(|_| anyhow!("Error while sending to the sequencer"))?;
+                        self.transport.set_bpm(self.editor.pattern.bpm as f64)?;
+                    }
+                    EditorAction::Play => self.transport.play()?,
+                    EditorAction::Stop => {
+                        self.transport.stop()?;
+                        self.transport.locate(0.0)?;
+                    }
+                }
+                self.send_view()
+            }
+            KeyEvent {
+                kind: KeyEventKind::Press,
+                ..
+            } => match action {
+                Some(Action::WaveNone) => {
                     self.wave.waveform = Waveform::None;
                     self.send_wave()
                 }
-                KeyCode::Char('2') => {
+                Some(Action::WaveSine) => {
                     self.wave.waveform = Waveform::Sin;
                     self.send_wave()
                 }
-                KeyCode::Char('3') => {
+                Some(Action::WaveSaw) => {
                     self.wave.waveform = Waveform::Saw;
                     self.send_wave()
                 }
-                KeyCode::Char('4') => {
+                Some(Action::WaveSquare) => {
                     self.wave.waveform = Waveform::Square;
                     self.send_wave()
                 }
-                KeyCode::Char('5') => {
+                Some(Action::WaveTriangle) => {
                     self.wave.waveform = Waveform::Triangle;
                     self.send_wave()
                 }
-                KeyCode::Right => {
+                Some(Action::PitchUp) => {
                     self.wave.note = self.wave.note.next_note();
                     self.send_wave()
                 }
-                KeyCode::Left => {
+                Some(Action::PitchDown) => {
                     self.wave.note = self.wave.note.prev_note();
                     self.send_wave()
                 }
-                KeyCode::Up => {
+                Some(Action::AmpUp) => {
                     self.wave.amp = (self.wave.amp + 0.01).clamp(0.0, 1.0);
                     self.send_wave()
                 }
-                KeyCode::Down => {
+                Some(Action::AmpDown) => {
                     self.wave.amp = (self.wave.amp - 0.01).clamp(0.0, 1.0);
                     self.send_wave()
                 }
-                KeyCode::Esc => Ok(ControlFlow::Quit),
+                Some(Action::Temperament) => {
+                    // The presets, then the custom or mapped temperament loaded if any.
+                    let tuning = &mut self.wave.tuning;
+                    let count = match tuning.temperament.is_preset() && self.kept.is_preset() {
+                        true => Temperament::ALL.len(),
+                        false => Temperament::ALL.len() + 1,
+                    };
+                    let current = Temperament::ALL
+                        .iter()
+                        .position(|t| *t == tuning.temperament)
+                        .unwrap_or(Temperament::ALL.len());
+                    tuning.pick_temperament((current + 1) % count, &mut self.kept);
+                    self.send_wave()
+                }
+                Some(Action::ReferenceUp) => {
+                    self.wave.tuning.reference = (self.wave.tuning.reference + 1.0).min(480.0);
+                    self.send_wave()
+                }
+                Some(Action::ReferenceDown) => {
+                    self.wave.tuning.reference = (self.wave.tuning.reference - 1.0).max(400.0);
+                    self.send_wave()
+                }
+                Some(Action::FineTuneUp) => {
+                    self.wave.detune = (self.wave.detune + 1.0).clamp(-100.0, 100.0);
+                    self.send_wave()
+                }
+                Some(Action::FineTuneDown) => {
+                    self.wave.detune = (self.wave.detune - 1.0).clamp(-100.0, 100.0);
+                    self.send_wave()
+                }
+                Some(Action::FineTuneReset) => {
+                    self.wave.detune = 0.0;
+                    self.send_wave()
+                }
+                Some(Action::GlideTime) => {
+                    let glide = &mut self.wave.glide;
+                    let current = GLIDE_TIMES
+                        .iter()
+                        .position(|time| *time == glide.time)
+                        .unwrap_or(0);
+                    glide.time = GLIDE_TIMES[(current + 1) % GLIDE_TIMES.len()];
+                    self.send_wave()
+                }
+                Some(Action::GlideCurve) => {
+                    self.wave.glide.curve = match self.wave.glide.curve {
+                        GlideCurve::Linear => GlideCurve::Exponential,
+                        GlideCurve::Exponential => GlideCurve::Linear,
+                    };
+                    self.send_wave()
+                }
+                Some(Action::GlideMode) => {
+                    self.wave.glide.mode = match self.wave.glide.mode {
+                        GlideMode::ConstantTime => GlideMode::ConstantRate,
+                        GlideMode::ConstantRate => GlideMode::ConstantTime,
+                    };
+                    self.send_wave()
+                }
+                Some(Action::GlideLegato) => {
+                    self.wave.glide.legato_only = !self.wave.glide.legato_only;
+                    self.send_wave()
+                }
+                Some(Action::Mono) => {
+                    self.wave.mono.enabled = !self.wave.mono.enabled;
+                    self.send_wave()
+                }
+                Some(Action::NotePriority) => {
+                    let priorities = NotePriority::ALL;
+                    let current = priorities
+                        .iter()
+                        .position(|priority| *priority == self.wave.mono.priority)
+                        .unwrap_or(0);
+                    self.wave.mono.priority = priorities[(current + 1) % priorities.len()];
+                    self.send_wave()
+                }
+                Some(Action::MonoLegato) => {
+                    self.wave.mono.legato = !self.wave.mono.legato;
+                    self.send_wave()
+                }
+                Some(Action::Arp) => {
+                    self.wave.arp.enabled = !self.wave.arp.enabled;
+                    self.send_wave()
+                }
+                Some(Action::ArpPattern) => {
+                    self.wave.arp.pattern = cycle(&ArpPattern::ALL, self.wave.arp.pattern);
+                    self.send_wave()
+                }
+                Some(Action::ArpOctaves) => {
+                    self.wave.arp.octaves = self.wave.arp.octaves % MAX_OCTAVES + 1;
+                    self.send_wave()
+                }
+                Some(Action::ArpRate) => {
+                    self.wave.arp.rate = cycle(&ArpRate::ALL, self.wave.arp.rate);
+                    self.send_wave()
+                }
+                Some(Action::ArpTempoUp) => {
+                    let (min, max) = ARP_BPM_RANGE;
+                    self.wave.arp.bpm = (self.wave.arp.bpm + 5.0).clamp(min, max);
+                    self.send_wave()
+                }
+                Some(Action::ArpTempoDown) => {
+                    let (min, max) = ARP_BPM_RANGE;
+                    self.wave.arp.bpm = (self.wave.arp.bpm - 5.0).clamp(min, max);
+                    self.send_wave()
+                }
+                Some(Action::ArpGate) => {
+                    self.wave.arp.gate = cycle(&ARP_GATES, self.wave.arp.gate);
+                    self.send_wave()
+                }
+                Some(Action::ArpSwing) => {
+                    self.wave.arp.swing = cycle(&ARP_SWINGS, self.wave.arp.swing);
+                    self.send_wave()
+                }
+                Some(Action::ArpLatch) => {
+                    self.wave.arp.latch = !self.wave.arp.latch;
+                    self.send_wave()
+                }
+                Some(Action::Unison) => {
+                    self.wave.unison.voices = cycle(&UNISON_VOICES, self.wave.unison.voices);
+                    self.send_wave()
+                }
+                Some(Action::UnisonSpread) => {
+                    self.wave.unison.spread = cycle(&UNISON_SPREADS, self.wave.unison.spread);
+                    self.send_wave()
+                }
+                Some(Action::ShaperCurve) => {
+                    // Off, then each built-in curve, then off again.
+                    let shaper = &mut self.effects.waveshaper;
+                    let current = Curve::BUILT_IN.iter().position(|c| *c == shaper.curve);
+                    match current.map(|index| index + 1) {
+                        _ if shaper.mix <= 0.0 => {
+                            shaper.curve = Curve::BUILT_IN[0].clone();
+                            shaper.mix = 1.0;
+                        }
+                        Some(next) if next < Curve::BUILT_IN.len() => {
+                            shaper.curve = Curve::BUILT_IN[next].clone();
+                        }
+                        _ => shaper.mix = 0.0,
+                    }
+                    self.send_distortion()
+                }
+                Some(Action::ShaperDrive) => {
+                    let shaper = &mut self.effects.waveshaper;
+                    shaper.drive = cycle(&SHAPER_DRIVES, shaper.drive);
+                    self.send_distortion()
+                }
+                Some(Action::Oversampling) => {
+                    let shaper = &mut self.effects.waveshaper;
+                    shaper.oversampling = cycle(&Oversampling::ALL, shaper.oversampling);
+                    self.send_distortion()
+                }
+                Some(Action::CrushBits) => {
+                    let crusher = &mut self.effects.bitcrusher;
+                    crusher.bits = cycle(&CRUSH_BITS, crusher.bits);
+                    self.send_distortion()
+                }
+                Some(Action::CrushRate) => {
+                    let crusher = &mut self.effects.bitcrusher;
+                    crusher.rate = cycle(&CRUSH_RATES, crusher.rate);
+                    self.send_distortion()
+                }
+                Some(Action::Quit) => Ok(ControlFlow::Quit),
                 _ => Ok(ControlFlow::Continue),
             },
             _ => Ok(ControlFlow::Continue),
         }
     }
 
+    fn send_view(&self) -> Result<ControlFlow> {
+        self.view
+            .send(View {
+                page: self.page,
+                editor: self.editor.clone(),
+                presets: self.presets.clone(),
+                history: self.history.clone(),
+                waveshaper: self.effects.waveshaper.clone(),
+                bitcrusher: self.effects.bitcrusher,
+            })
+            .map(|_| ControlFlow::Continue)
+            .map_err(|_| anyhow!("Error while sending the view"))
+    }
+
+    /// Sends the waveshaper and bitcrusher settings to the audio thread and
+    /// to be drawn.
+    fn send_distortion(&self) -> Result<ControlFlow> {
+        if let Some(control) = &self.distortion {
+            control.set_waveshaper(&self.effects.waveshaper);
+            control.set_bitcrusher(&self.effects.bitcrusher);
+        }
+        self.send_view()
+    }
+
+    /// Plays the waveshaper and bitcrusher of a loaded patch. A table curve
+    /// other than the one the waveshaper was built with waits for the next
+    /// start, like the rest of the effects.
+    fn load_distortion(&mut self, effects: &EffectSettings) {
+        let waveshaper = &effects.waveshaper;
+        let table = matches!(waveshaper.curve, Curve::Table(_));
+        if !table || waveshaper.curve == self.effects.waveshaper.curve {
+            self.effects.waveshaper = waveshaper.clone();
+        }
+        self.effects.bitcrusher = effects.bitcrusher;
+    }
+
+    /// Plays a note with `patch` until [`AUDITION_TIME`] has passed or a key
+    /// is pressed, then goes back to the current sound.
+    fn audition(&mut self, patch: &Patch) -> Result<()> {
+        let mut wave = self.wave.clone();
+        if let Err(err) = patch.apply_to_wave(&mut wave) {
+            self.presets.status = format!("Couldn't audition {}: {err:#}", patch.name);
+            return Ok(());
+        }
+        let note = wave.note;
+        self.show_wave(wave)?;
+        if let Some(events) = &self.events {
+            events
+                .send(SynthEvent::NoteOn {
+                    note,
+                    velocity: 1.0,
+                })
+                .map_err(|_| anyhow!("Error while sending the audition note"))?;
+        }
+        self.audition = Some((Instant::now() + AUDITION_TIME, note));
+        Ok(())
+    }
+
+    /// Releases the note of the patch auditioned and goes back to the current sound.
+    fn end_audition(&mut self) -> Result<()> {
+        let Some((_, note)) = self.audition.take() else {
+            return Ok(());
+        };
+        if let Some(events) = &self.events {
+            events
+                .send(SynthEvent::NoteOff { note })
+                .map_err(|_| anyhow!("Error while sending the audition note"))?;
+        }
+        self.send_wave()?;
+        Ok(())
+    }
+
     fn send_wave(&self) -> Result<ControlFlow> {
+        self.show_wave(self.wave.clone())
+    }
+
+    /// Sends `wave` to the audio thread and to be drawn.
+    fn show_wave(&self, wave: Wave) -> Result<ControlFlow> {
+        self.params.set_wave(&wave);
         self.tx
-            .send(self.wave)
+            .send(wave)
             .iter()
             .all(|res| !res.is_err())
             .then_some(ControlFlow::Continue)
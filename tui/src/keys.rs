@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use crossterm::event::KeyCode;
+
+use crate::{editor::EDITOR_KEYS, presets::PRESET_KEYS};
+
+/// Something a key does on the synth page, or on every page for
+/// [`Action::NextPage`] and [`Action::Quit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    NextPage,
+    Quit,
+    Undo,
+    Redo,
+    WaveNone,
+    WaveSine,
+    WaveSaw,
+    WaveSquare,
+    WaveTriangle,
+    PitchUp,
+    PitchDown,
+    FineTuneUp,
+    FineTuneDown,
+    FineTuneReset,
+    AmpUp,
+    AmpDown,
+    Temperament,
+    ReferenceUp,
+    ReferenceDown,
+    GlideTime,
+    GlideCurve,
+    GlideMode,
+    GlideLegato,
+    Mono,
+    NotePriority,
+    MonoLegato,
+    Arp,
+    ArpPattern,
+    ArpOctaves,
+    ArpRate,
+    ArpTempoUp,
+    ArpTempoDown,
+    ArpGate,
+    ArpSwing,
+    ArpLatch,
+    Unison,
+    UnisonSpread,
+    ShaperCurve,
+    ShaperDrive,
+    Oversampling,
+    CrushBits,
+    CrushRate,
+}
+
+impl Action {
+    pub const ALL: [Action; 42] = [
+        Action::NextPage,
+        Action::Quit,
+        Action::Undo,
+        Action::Redo,
+        Action::WaveNone,
+        Action::WaveSine,
+        Action::WaveSaw,
+        Action::WaveSquare,
+        Action::WaveTriangle,
+        Action::PitchUp,
+        Action::PitchDown,
+        Action::FineTuneUp,
+        Action::FineTuneDown,
+        Action::FineTuneReset,
+        Action::AmpUp,
+        Action::AmpDown,
+        Action::Temperament,
+        Action::ReferenceUp,
+        Action::ReferenceDown,
+        Action::GlideTime,
+        Action::GlideCurve,
+        Action::GlideMode,
+        Action::GlideLegato,
+        Action::Mono,
+        Action::NotePriority,
+        Action::MonoLegato,
+        Action::Arp,
+        Action::ArpPattern,
+        Action::ArpOctaves,
+        Action::ArpRate,
+        Action::ArpTempoUp,
+        Action::ArpTempoDown,
+        Action::ArpGate,
+        Action::ArpSwing,
+        Action::ArpLatch,
+        Action::Unison,
+        Action::UnisonSpread,
+        Action::ShaperCurve,
+        Action::ShaperDrive,
+        Action::Oversampling,
+        Action::CrushBits,
+        Action::CrushRate,
+    ];
+
+    /// Name used in the `[keys]` table of the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::NextPage => "next_page",
+            Action::Quit => "quit",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::WaveNone => "wave_none",
+            Action::WaveSine => "wave_sine",
+            Action::WaveSaw => "wave_saw",
+            Action::WaveSquare => "wave_square",
+            Action::WaveTriangle => "wave_triangle",
+            Action::PitchUp => "pitch_up",
+            Action::PitchDown => "pitch_down",
+            Action::FineTuneUp => "fine_tune_up",
+            Action::FineTuneDown => "fine_tune_down",
+            Action::FineTuneReset => "fine_tune_reset",
+            Action::AmpUp => "amp_up",
+            Action::AmpDown => "amp_down",
+            Action::Temperament => "temperament",
+            Action::ReferenceUp => "reference_up",
+            Action::ReferenceDown => "reference_down",
+            Action::GlideTime => "glide_time",
+            Action::GlideCurve => "glide_curve",
+            Action::GlideMode => "glide_mode",
+            Action::GlideLegato => "glide_legato",
+            Action::Mono => "mono",
+            Action::NotePriority => "note_priority",
+            Action::MonoLegato => "mono_legato",
+            Action::Arp => "arp",
+            Action::ArpPattern => "arp_pattern",
+            Action::ArpOctaves => "arp_octaves",
+            Action::ArpRate => "arp_rate",
+            Action::ArpTempoUp => "arp_tempo_up",
+            Action::ArpTempoDown => "arp_tempo_down",
+            Action::ArpGate => "arp_gate",
+            Action::ArpSwing => "arp_swing",
+            Action::ArpLatch => "arp_latch",
+            Action::Unison => "unison",
+            Action::UnisonSpread => "unison_spread",
+            Action::ShaperCurve => "shaper_curve",
+            Action::ShaperDrive => "shaper_drive",
+            Action::Oversampling => "oversampling",
+            Action::CrushBits => "crush_bits",
+            Action::CrushRate => "crush_rate",
+        }
+    }
+
+    fn default_key(&self) -> KeyCode {
+        match self {
+            Action::NextPage => KeyCode::Tab,
+            Action::Quit => KeyCode::Esc,
+            Action::Undo => KeyCode::Char('z'),
+            Action::Redo => KeyCode::Char('y'),
+            Action::WaveNone => KeyCode::Char('1'),
+            Action::WaveSine => KeyCode::Char('2'),
+            Action::WaveSaw => KeyCode::Char('3'),
+            Action::WaveSquare => KeyCode::Char('4'),
+            Action::WaveTriangle => KeyCode::Char('5'),
+            Action::PitchUp => KeyCode::Right,
+            Action::PitchDown => KeyCode::Left,
+            Action::FineTuneUp => KeyCode::Char('.'),
+            Action::FineTuneDown => KeyCode::Char(','),
+            Action::FineTuneReset => KeyCode::Char('0'),
+            Action::AmpUp => KeyCode::Up,
+            Action::AmpDown => KeyCode::Down,
+            Action::Temperament => KeyCode::Char('t'),
+            Action::ReferenceUp => KeyCode::Char('='),
+            Action::ReferenceDown => KeyCode::Char('-'),
+            Action::GlideTime => KeyCode::Char('g'),
+            Action::GlideCurve => KeyCode::Char('c'),
+            Action::GlideMode => KeyCode::Char('r'),
+            Action::GlideLegato => KeyCode::Char('l'),
+            Action::Mono => KeyCode::Char('m'),
+            Action::NotePriority => KeyCode::Char('p'),
+            Action::MonoLegato => KeyCode::Char('e'),
+            Action::Arp => KeyCode::Char('a'),
+            Action::ArpPattern => KeyCode::Char('s'),
+            Action::ArpOctaves => KeyCode::Char('o'),
+            Action::ArpRate => KeyCode::Char('d'),
+            Action::ArpTempoUp => KeyCode::Char(']'),
+            Action::ArpTempoDown => KeyCode::Char('['),
+            Action::ArpGate => KeyCode::Char('h'),
+            Action::ArpSwing => KeyCode::Char('w'),
+            Action::ArpLatch => KeyCode::Char('x'),
+            Action::Unison => KeyCode::Char('u'),
+            Action::UnisonSpread => KeyCode::Char('i'),
+            Action::ShaperCurve => KeyCode::Char('k'),
+            Action::ShaperDrive => KeyCode::Char('j'),
+            Action::Oversampling => KeyCode::Char('v'),
+            Action::CrushBits => KeyCode::Char('b'),
+            Action::CrushRate => KeyCode::Char('n'),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// Keys with a name rather than the character they type.
+const NAMED_KEYS: [(&str, KeyCode); 15] = [
+    ("Tab", KeyCode::Tab),
+    ("Esc", KeyCode::Esc),
+    ("Enter", KeyCode::Enter),
+    ("Space", KeyCode::Char(' ')),
+    ("Backspace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+];
+
+/// Key from its name, a single character like `a`, a named key like
+/// `Space` or a function key like `F5`.
+pub fn parse_key(name: &str) -> Result<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(char), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(char));
+    }
+    if let Some((_, code)) = NAMED_KEYS
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+    {
+        return Ok(*code);
+    }
+    name.strip_prefix(['F', 'f'])
+        .and_then(|number| number.parse().ok())
+        .filter(|number| (1..=12).contains(number))
+        .map(KeyCode::F)
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown key {name:?}, expected a character, F1 to F12 or one of {}",
+                NAMED_KEYS.map(|(key, _)| key).join(", ")
+            )
+        })
+}
+
+/// Name of `code` as shown in the controls.
+pub fn key_name(code: KeyCode) -> String {
+    if let Some((name, _)) = NAMED_KEYS.iter().find(|(_, key)| *key == code) {
+        return name.to_string();
+    }
+    match code {
+        KeyCode::Char(char) => char.to_string(),
+        KeyCode::F(number) => format!("F{number}"),
+        code => format!("{code:?}"),
+    }
+}
+
+/// Key of every [`Action`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    keys: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL.iter().map(Action::default_key).collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Default bindings with the keys given by action name replaced. Fails
+    /// for unknown actions or keys, for a key bound twice, and for a global
+    /// action on a key the sequencer or presets page uses.
+    pub fn with_overrides(overrides: &BTreeMap<String, String>) -> Result<Self> {
+        let mut bindings = Self::default();
+        for (name, key) in overrides {
+            let action = Action::from_name(name).ok_or_else(|| {
+                anyhow!(
+                    "Unknown action {name:?} in [keys], expected one of {}",
+                    Action::ALL.map(|action| action.name()).join(", ")
+                )
+            })?;
+            bindings.keys[action as usize] = parse_key(key)?;
+        }
+        for (index, action) in Action::ALL.iter().enumerate() {
+            let key = bindings.keys[index];
+            if let Some(other) = Action::ALL[index + 1..]
+                .iter()
+                .find(|other| bindings.key(**other) == key)
+            {
+                return Err(anyhow!(
+                    "The key {} is bound to both {} and {}",
+                    key_name(key),
+                    action.name(),
+                    other.name()
+                ));
+            }
+        }
+        for action in [Action::NextPage, Action::Quit] {
+            let key = bindings.key(action);
+            let page = if EDITOR_KEYS.contains(&key) {
+                "sequencer"
+            } else if PRESET_KEYS.contains(&key) {
+                "presets"
+            } else {
+                continue;
+            };
+            return Err(anyhow!(
+                "The key {} of {} is used by the {page} page",
+                key_name(key),
+                action.name()
+            ));
+        }
+        Ok(bindings)
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys[action as usize]
+    }
+
+    pub fn action(&self, code: KeyCode) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|action| self.key(*action) == code)
+    }
+
+    /// Control hint like `<a>: Arp On/Off`.
+    pub fn hint(&self, action: Action, text: &str) -> String {
+        format!("<{}>: {text}", key_name(self.key(action)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(keys: &[(&str, &str)]) -> Result<KeyBindings> {
+        let keys = keys
+            .iter()
+            .map(|(action, key)| (action.to_string(), key.to_string()))
+            .collect();
+        KeyBindings::with_overrides(&keys)
+    }
+
+    #[test]
+    fn parses_and_names_keys() {
+        for (name, code) in [
+            ("a", KeyCode::Char('a')),
+            ("Space", KeyCode::Char(' ')),
+            ("pageup", KeyCode::PageUp),
+            ("F12", KeyCode::F(12)),
+        ] {
+            assert_eq!(parse_key(name).unwrap(), code);
+        }
+        assert_eq!(key_name(KeyCode::Char(' ')), "Space");
+        assert_eq!(key_name(KeyCode::F(5)), "F5");
+        for name in ["", "F13", "F0", "Shift"] {
+            assert!(parse_key(name).is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(overrides(&[]).unwrap(), KeyBindings::default());
+    }
+
+    #[test]
+    fn overrides_keys_by_action_name() {
+        let bindings = overrides(&[("quit", "q"), ("arp", "F2")]).unwrap();
+        assert_eq!(bindings.key(Action::Quit), KeyCode::Char('q'));
+        assert_eq!(bindings.action(KeyCode::F(2)), Some(Action::Arp));
+        assert_eq!(bindings.action(KeyCode::Char('a')), None);
+    }
+
+    #[test]
+    fn rejects_unknown_actions_and_keys_bound_twice() {
+        assert!(overrides(&[("explode", "e")]).is_err());
+        assert!(overrides(&[("arp", "m")]).is_err());
+        // The key taken from arp is free for mono.
+        assert!(overrides(&[("arp", "F2"), ("mono", "a")]).is_ok());
+    }
+
+    #[test]
+    fn keeps_the_page_keys_from_global_actions() {
+        for (action, key) in [
+            ("next_page", "s"),
+            ("next_page", "Enter"),
+            ("quit", "f"),
+            ("quit", "/"),
+            ("quit", "Space"),
+            ("next_page", "g"),
+            ("quit", "Left"),
+        ] {
+            assert!(overrides(&[(action, key)]).is_err(), "{action} {key}");
+        }
+        // Synth page actions may share keys with the other pages.
+        assert!(overrides(&[("quit", "q"), ("mono", "f")]).is_ok());
+    }
+}
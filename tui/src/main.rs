@@ -1,41 +1,334 @@
+mod config;
+mod editor;
 mod input;
+mod keys;
+mod presets;
 mod terminal;
+mod theme;
 mod ui;
 
-use anyhow::Result;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use config::{Config, USAGE};
+use editor::{Page, SequencerEditor};
 use input::{Input, MultiSender};
+use presets::PresetBrowser;
+#[cfg(feature = "web")]
+use synth::web::{WebPanel, WebServer, WEB_PORT};
 use synth::{
+    effects::{
+        distortion::{
+            Bitcrusher, Curve, DistortionControl, Waveshaper, MAX_CRUSH_BITS, MAX_CRUSH_RATE,
+        },
+        dynamics::{Compressor, Limiter},
+        eq::Equalizer,
+        Chain,
+    },
+    history::History,
+    meter::Meter,
+    midi::{
+        clock::{ClockFollower, ClockGenerator, CLOCK_QUEUE},
+        smf::{MidiFile, SmfPlayer},
+        MidiInputPort, MidiOutputPort, MidiRouter,
+    },
+    osc::{OscRouter, OscServer, OSC_PORT},
+    params::ParamStore,
+    patch::{Patch, PATCH_EXTENSION},
+    scala::{KeyboardMapping, Scale},
+    sequencer::{pattern::Pattern, Sequencer},
+    transport::{TempoMap, Transport},
     waves::{Wave, Waveform},
     AudioDevice, Note, StreamTrait, Synth,
 };
 use terminal::{restore_terminal, setup_terminal};
-use ui::ui;
+use ui::{eq_response, ui, Look, Meters, View};
+
+const METER_REFRESH: Duration = Duration::from_millis(50);
+const MIDI_PORT_NAME: &str = "rust-synth in";
+const MIDI_CLOCK_PORT_NAME: &str = "rust-synth clock";
+/// Where the sequencer pattern is saved when no pattern file is given.
+const DEFAULT_PATTERN_PATH: &str = "pattern.seq";
+/// Directory browsed for patches when no patch file is given.
+const DEFAULT_PATCH_DIR: &str = "patches";
 
 fn main() -> Result<()> {
-    let device = AudioDevice::default()?;
-    let mut synth = Synth::new(device)?
-        .channels(2)?
-        .buffer_size(512)?
-        .sample_rate(44100)?;
+    let config = Config::load(std::env::args().skip(1))?;
+    if config.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    if config.list_devices {
+        return list_devices();
+    }
+
+    // A MIDI file given as argument is played along with the keyboard, Scala
+    // scale (.scl) and keyboard mapping (.kbm) files replace the tuning and
+    // a sequencer pattern (.seq) is loaded into the sequencer page. A patch
+    // (.toml) sets the initial sound, the presets page browses its directory.
+    let mut midi_file = None;
+    let mut scale = None;
+    let mut mapping = KeyboardMapping::default();
+    let mut pattern = Pattern::default();
+    let mut pattern_path = PathBuf::from(DEFAULT_PATTERN_PATH);
+    let mut patch = None;
+    let mut patch_dir = PathBuf::from(DEFAULT_PATCH_DIR);
+    if let Some(path) = &config.patch {
+        patch = Some(
+            Patch::open(path)
+                .with_context(|| format!("Couldn't open the patch {}", path.display()))?,
+        );
+        if let Some(dir) = path.parent() {
+            patch_dir = dir.to_path_buf();
+        }
+    }
+    for arg in &config.files {
+        match Path::new(&arg).extension().and_then(OsStr::to_str) {
+            Some(PATCH_EXTENSION) => {
+                patch = Some(Patch::open(arg).with_context(|| format!("Couldn't open {arg}"))?);
+                if let Some(dir) = Path::new(arg).parent() {
+                    patch_dir = dir.to_path_buf();
+                }
+            }
+            Some("scl") => {
+                scale = Some(Scale::open(arg).with_context(|| format!("Couldn't open {arg}"))?)
+            }
+            Some("kbm") => {
+                mapping =
+                    KeyboardMapping::open(arg).with_context(|| format!("Couldn't open {arg}"))?
+            }
+            Some("seq") => {
+                pattern = Pattern::open(arg).with_context(|| format!("Couldn't open {arg}"))?;
+                pattern_path = PathBuf::from(arg);
+            }
+            _ => {
+                midi_file =
+                    Some(MidiFile::open(arg).with_context(|| format!("Couldn't open {arg}"))?)
+            }
+        }
+    }
+
+    // The effects of the patch are set up once, before the synth starts.
+    let effects = patch
+        .as_ref()
+        .and_then(|patch| patch.effects.clone())
+        .unwrap_or_default();
+    // Except the waveshaper and bitcrusher, which follow the synth page.
+    let distortion = DistortionControl::new(&effects.waveshaper, &effects.bitcrusher);
+    let waveshaper = Waveshaper::new(Curve::SoftClip, 1.0)
+        .settings(effects.waveshaper.clone())
+        .control(distortion.clone());
+    let bitcrusher = Bitcrusher::new(MAX_CRUSH_BITS, MAX_CRUSH_RATE)
+        .settings(effects.bitcrusher)
+        .control(distortion.clone());
+    let eq = Equalizer::new(effects.eq.clone());
+    let compressor = Compressor::new().settings(effects.compressor);
+    let limiter = Limiter::new().settings(effects.limiter);
+
+    let (s_sequencer, r_sequencer) = crossbeam_channel::unbounded();
+    let transport = Transport::new().tempo_map(TempoMap::new(pattern.bpm as f64));
+    let transport_handle = transport.handle();
+
+    // MIDI is optional, keep playing from the keyboard when no input is
+    // available. The transport follows MIDI clock coming in, and sends its
+    // own clock out of a virtual port.
+    let (s_events, r_events) = crossbeam_channel::unbounded();
+    let (s_clock, r_clock) = crossbeam_channel::unbounded();
+    let router = MidiRouter::new(s_events.clone()).clock(s_clock);
+    let _midi_inputs = [
+        MidiInputPort::open(0, router.clone()),
+        MidiInputPort::open_virtual(MIDI_PORT_NAME, router),
+    ];
+    ClockFollower::new(transport_handle.clone()).spawn(r_clock);
+    let (s_clock_out, r_clock_out) = crossbeam_channel::bounded(CLOCK_QUEUE);
+    let clock_out = MidiOutputPort::open_virtual(MIDI_CLOCK_PORT_NAME)
+        .map(|port| port.forward_clock(r_clock_out))
+        .is_ok();
+    let sequencer = Sequencer::new(pattern.clone())
+        .commands(r_sequencer)
+        .synced(true);
+    let mut view = View {
+        page: Page::Synth,
+        editor: SequencerEditor::new(pattern, pattern_path),
+        presets: PresetBrowser::new(patch_dir),
+        history: History::new(),
+        waveshaper: effects.waveshaper.clone(),
+        bitcrusher: effects.bitcrusher,
+    };
+    let mut meters = Meters {
+        compressor: compressor.gain_reduction(),
+        limiter: limiter.gain_reduction(),
+        sequencer: sequencer.position(),
+        frequency: Meter::new(),
+        transport: transport_handle.clone(),
+        remote_errors: Vec::new(),
+    };
+
+    let mut wave = Wave::new(Waveform::None, Note::A4, 0.3);
+    if let Some(scale) = scale {
+        wave.tuning = mapping.tuning(&scale);
+    }
+    if let Some(patch) = patch {
+        patch.apply_to_wave(&mut wave)?;
+    }
+    // A MIDI file needs a waveform to be heard, it plays alone until a key
+    // is pressed.
+    if midi_file.is_some() && wave.waveform == Waveform::None {
+        wave.waveform = Waveform::Sin;
+    }
+    let params = ParamStore::from_wave(&wave);
+
+    // OSC is optional too, other tools on this machine can play notes and
+    // set parameters, and subscribe to the changes. Remotes share one
+    // history, undoing on one undoes the changes made on the others.
+    let remote_history = Arc::new(Mutex::new(History::new()));
+    let mut remotes = Vec::new();
+    match OscServer::bind(
+        ("127.0.0.1", OSC_PORT),
+        OscRouter::new(params.clone(), s_events.clone()).history(remote_history.clone()),
+    ) {
+        Ok(server) => remotes.push(("OSC", server.spawn())),
+        Err(err) => meters
+            .remote_errors
+            .push(format!("OSC is off, port {OSC_PORT}: {err:#}")),
+    }
 
-    let wave = Wave::new(Waveform::None, Note::A4, 0.3);
+    // The browser panel mirrors the synth page, it is built with the `web`
+    // feature.
+    #[cfg(feature = "web")]
+    match WebServer::bind(
+        ("127.0.0.1", WEB_PORT),
+        WebPanel::new(params.clone(), s_events.clone(), wave.clone())
+            .compressor(meters.compressor.clone())
+            .limiter(meters.limiter.clone())
+            .transport(meters.transport.clone())
+            .history(remote_history.clone()),
+    ) {
+        Ok(server) => remotes.push(("Web panel", server.spawn())),
+        Err(err) => meters
+            .remote_errors
+            .push(format!("Web panel is off, port {WEB_PORT}: {err:#}")),
+    }
+
+    // Settings left out are the device defaults.
+    let audio = &config.audio;
+    let device = AudioDevice::open(audio.host.as_deref(), audio.device.as_deref())
+        .context("Couldn't open the audio device, see --list-devices")?;
+    let mut synth = Synth::new(device)?;
+    if let Some(channels) = audio.channels {
+        synth = synth.channels(channels)?;
+    }
+    if let Some(sample_rate) = audio.sample_rate {
+        synth = synth.sample_rate(sample_rate)?;
+    }
+    if let Some(buffer_size) = audio.buffer_size {
+        synth = synth.buffer_size(buffer_size)?;
+    }
+    let eq_curve = eq_response(&eq, synth.config().sample_rate.0 as f32, 128);
+    synth = synth
+        .effects(
+            Chain::new()
+                .with(waveshaper)
+                .with(bitcrusher)
+                .with(eq)
+                .with(compressor)
+                .with(limiter),
+        )
+        .events(r_events)
+        .source(sequencer)
+        .transport(transport)
+        .params(params.clone())
+        .frequency(meters.frequency.clone());
+    if clock_out {
+        synth = synth.source(ClockGenerator::new(s_clock_out));
+    }
+    if let Some(midi_file) = midi_file {
+        synth = synth.source(SmfPlayer::new(midi_file)).silent();
+    }
 
-    let (s_stream, r_stream) = crossbeam_channel::unbounded();
     let (s_main, r_main) = crossbeam_channel::unbounded();
-    let multi_s = MultiSender::from(&[s_stream, s_main]);
-    let stream = synth.new_output_stream_chan::<f32>(wave, r_stream.clone())?;
+    let (s_view, r_view) = crossbeam_channel::unbounded();
+    let multi_s = MultiSender::from(&[s_main]);
+    let stream = synth.new_output_stream::<f32>(wave.clone())?;
     stream.play()?;
 
+    let look = Look {
+        keys: config.keys,
+        theme: config.theme,
+    };
     let mut terminal = setup_terminal()?;
-    terminal.draw(|frame| ui(frame, wave))?;
+    terminal.draw(|frame| ui(frame, &wave, &meters, &eq_curve, &view, &look))?;
 
-    std::thread::spawn(move || Input::new(multi_s).handle());
+    let input_view = view.clone();
+    let input_keys = look.keys.clone();
+    let input_params = params.clone();
+    let input_wave = wave.clone();
+    std::thread::spawn(move || {
+        Input::new(
+            input_wave,
+            input_params,
+            multi_s,
+            input_view,
+            s_sequencer,
+            transport_handle,
+            s_view,
+        )
+        .events(s_events)
+        .keys(input_keys)
+        .effects(effects)
+        .distortion(distortion)
+        .handle()
+    });
 
-    while let Ok(wave) = r_main.recv() {
-        terminal.draw(|frame| ui(frame, wave))?;
+    loop {
+        crossbeam_channel::select! {
+            recv(r_main) -> new_wave => match new_wave {
+                Ok(new_wave) => wave = new_wave,
+                Err(_) => break,
+            },
+            recv(r_view) -> new_view => {
+                if let Ok(new_view) = new_view {
+                    view = new_view;
+                }
+            },
+            default(METER_REFRESH) => (),
+        }
+        // Parameters may also be changed remotely.
+        wave = params.wave(wave);
+        for (name, remote) in remotes.extract_if(.., |(_, remote)| remote.is_finished()) {
+            meters.remote_errors.push(match remote.join() {
+                Ok(Ok(())) => format!("{name} stopped"),
+                Ok(Err(err)) => format!("{name} stopped: {err:#}"),
+                Err(_) => format!("{name} crashed"),
+            });
+        }
+        terminal.draw(|frame| ui(frame, &wave, &meters, &eq_curve, &view, &look))?;
     }
 
     restore_terminal(&mut terminal)?;
 
     Ok(())
 }
+
+/// Prints the audio hosts and their output devices, for `--host` and
+/// `--device`.
+fn list_devices() -> Result<()> {
+    for host in AudioDevice::hosts() {
+        println!("{host}");
+        match AudioDevice::output_devices(host) {
+            Ok(devices) if devices.is_empty() => println!("    no output devices"),
+            Ok(devices) => {
+                for device in devices {
+                    println!("    {device}");
+                }
+            }
+            Err(err) => println!("    unavailable: {err:#}"),
+        }
+    }
+    Ok(())
+}
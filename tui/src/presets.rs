@@ -0,0 +1,520 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use crossterm::event::KeyCode;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use synth::patch::Patch;
+
+/// Favorite patches of a library, one path relative to it per line.
+const FAVORITES_FILE: &str = "favorites.txt";
+/// Keys [`PresetBrowser::handle_key`] uses when nothing is typed, which
+/// the global key bindings can't take.
+pub const PRESET_KEYS: [KeyCode; 10] = [
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Enter,
+    KeyCode::Char(' '),
+    KeyCode::Char('/'),
+    KeyCode::Char('f'),
+    KeyCode::Char('v'),
+    KeyCode::Char('c'),
+    KeyCode::Char('r'),
+    KeyCode::Char('s'),
+];
+
+/// A patch file found in the library, with its metadata.
+#[derive(Debug, Clone)]
+pub struct PresetEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub author: String,
+    /// Category of the patch, or the subdirectory it is in.
+    pub category: String,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    /// Why the file couldn't be read, it is listed anyway.
+    pub error: Option<String>,
+}
+
+impl PresetEntry {
+    fn open(path: PathBuf, folder: String) -> Self {
+        match Patch::open(&path) {
+            Ok(patch) => Self {
+                path,
+                name: patch.name,
+                author: patch.author,
+                category: if patch.category.is_empty() {
+                    folder
+                } else {
+                    patch.category
+                },
+                tags: patch.tags,
+                favorite: false,
+                error: None,
+            },
+            Err(err) => Self {
+                name: file_stem(&path),
+                path,
+                author: String::new(),
+                category: folder,
+                tags: Vec::new(),
+                favorite: false,
+                error: Some(format!("{err:#}")),
+            },
+        }
+    }
+
+    /// Score of the entry for the search terms, `None` if it doesn't match.
+    /// Terms starting with `#` must be one of the tags, the others are
+    /// fuzzy matched against the name, author, category and tags.
+    fn score(&self, matcher: &SkimMatcherV2, query: &str) -> Option<i64> {
+        let text = format!(
+            "{} {} {} {}",
+            self.name,
+            self.author,
+            self.category,
+            self.tags.join(" ")
+        );
+        query
+            .split_whitespace()
+            .try_fold(0, |score, term| match term.strip_prefix('#') {
+                Some(tag) => self
+                    .tags
+                    .iter()
+                    .any(|other| other.eq_ignore_ascii_case(tag))
+                    .then_some(score),
+                None => matcher
+                    .fuzzy_match(&text, term)
+                    .map(|term_score| score + term_score),
+            })
+    }
+}
+
+/// Patches of a library directory and its subdirectories, filtered by a
+/// search, a category and favorites.
+#[derive(Debug, Clone)]
+pub struct PresetBrowser {
+    pub dir: PathBuf,
+    pub entries: Vec<PresetEntry>,
+    /// Indices of the entries shown, best matches first.
+    pub visible: Vec<usize>,
+    /// Index in `visible`.
+    pub selected: usize,
+    pub query: String,
+    /// Whether keys go to the search query.
+    pub searching: bool,
+    pub favorites_only: bool,
+    /// Only entries of this category are shown.
+    pub category: Option<String>,
+    /// Name typed for save as, `None` when not saving.
+    pub naming: Option<String>,
+    /// Patch saved as the name of an existing one, until replacing it is
+    /// confirmed.
+    pub overwriting: Option<Patch>,
+    /// Result of the last action.
+    pub status: String,
+}
+
+/// What the browser did with a key press.
+#[derive(Debug)]
+pub enum PresetAction {
+    None,
+    Load(Patch),
+    /// Play a note with the patch, then go back to the current sound.
+    Audition(Patch),
+    /// Save the current sound under this name.
+    SaveAs(String),
+}
+
+impl PresetBrowser {
+    pub fn new(dir: PathBuf) -> Self {
+        let mut browser = Self {
+            dir,
+            entries: Vec::new(),
+            visible: Vec::new(),
+            selected: 0,
+            query: String::new(),
+            searching: false,
+            favorites_only: false,
+            category: None,
+            naming: None,
+            overwriting: None,
+            status: String::new(),
+        };
+        browser.rescan();
+        browser
+    }
+
+    /// Whether keys are typed into the search or the save as name, or
+    /// answer whether to replace a patch.
+    pub fn is_typing(&self) -> bool {
+        self.searching || self.naming.is_some() || self.overwriting.is_some()
+    }
+
+    pub fn selected(&self) -> Option<&PresetEntry> {
+        self.visible
+            .get(self.selected)
+            .map(|&index| &self.entries[index])
+    }
+
+    /// Categories of every entry, sorted.
+    pub fn categories(&self) -> Vec<&str> {
+        let categories: BTreeSet<&str> = self
+            .entries
+            .iter()
+            .map(|entry| entry.category.as_str())
+            .filter(|category| !category.is_empty())
+            .collect();
+        categories.into_iter().collect()
+    }
+
+    /// Indexes the library again.
+    pub fn rescan(&mut self) {
+        let selected = self.selected().map(|entry| entry.path.clone());
+        self.entries.clear();
+        let mut skipped = Vec::new();
+        if let Err(err) = scan(&self.dir, String::new(), &mut self.entries, &mut skipped) {
+            self.status = format!("Couldn't scan {}: {err:#}", self.dir.display());
+        } else if !skipped.is_empty() {
+            let skipped: Vec<String> = skipped
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            self.status = format!("Couldn't read {}", skipped.join(", "));
+        }
+        let favorites = std::fs::read_to_string(self.dir.join(FAVORITES_FILE)).unwrap_or_default();
+        for line in favorites.lines() {
+            let path = self.dir.join(line.trim());
+            for entry in self.entries.iter_mut().filter(|entry| entry.path == path) {
+                entry.favorite = true;
+            }
+        }
+        self.filter();
+        if let Some(path) = selected {
+            self.select(&path);
+        }
+    }
+
+    /// Updates the entries shown after the search or the filters changed.
+    fn filter(&mut self) {
+        let matcher = SkimMatcherV2::default().ignore_case();
+        let mut scored: Vec<(i64, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !self.favorites_only || entry.favorite)
+            .filter(|(_, entry)| {
+                self.category
+                    .as_ref()
+                    .is_none_or(|category| entry.category == *category)
+            })
+            .filter_map(|(index, entry)| Some((entry.score(&matcher, &self.query)?, index)))
+            .collect();
+        // Stable, so equal scores stay in library order.
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.visible = scored.into_iter().map(|(_, index)| index).collect();
+        self.selected = self.selected.min(self.visible.len().saturating_sub(1));
+    }
+
+    fn select(&mut self, path: &Path) {
+        if let Some(position) = self
+            .visible
+            .iter()
+            .position(|&index| self.entries[index].path == path)
+        {
+            self.selected = position;
+        }
+    }
+
+    /// Saves `patch` in the library and selects it, once confirmed if it
+    /// replaces a file. A patch without metadata gets the one of the
+    /// selected patch.
+    pub fn save(&mut self, mut patch: Patch) {
+        if let Some(entry) = self.selected() {
+            if patch.author.is_empty() && patch.category.is_empty() && patch.tags.is_empty() {
+                patch.author = entry.author.clone();
+                patch.category = entry.category.clone();
+                patch.tags = entry.tags.clone();
+            }
+        }
+        if self.dir.join(patch.file_name()).exists() {
+            self.overwriting = Some(patch);
+            return;
+        }
+        self.write(patch);
+    }
+
+    fn write(&mut self, patch: Patch) {
+        let path = self.dir.join(patch.file_name());
+        let result = std::fs::create_dir_all(&self.dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| patch.save(&path));
+        let status = match result {
+            Ok(()) => format!("Saved {} to {}", patch.name, path.display()),
+            Err(err) => format!("Couldn't save {}: {err:#}", path.display()),
+        };
+        self.rescan();
+        self.select(&path);
+        self.status = status;
+    }
+
+    fn toggle_favorite(&mut self) {
+        let Some(&index) = self.visible.get(self.selected) else {
+            return;
+        };
+        let entry = &mut self.entries[index];
+        entry.favorite = !entry.favorite;
+        self.status = format!(
+            "{} {} favorites",
+            entry.name,
+            if entry.favorite {
+                "added to"
+            } else {
+                "removed from"
+            }
+        );
+        if let Err(err) = self.save_favorites() {
+            self.status = format!("Couldn't save favorites: {err:#}");
+        }
+        self.filter();
+    }
+
+    fn save_favorites(&self) -> Result<()> {
+        let favorites: String = self
+            .entries
+            .iter()
+            .filter(|entry| entry.favorite)
+            .filter_map(|entry| entry.path.strip_prefix(&self.dir).ok())
+            .map(|path| format!("{}\n", path.display()))
+            .collect();
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.dir.join(FAVORITES_FILE), favorites)?;
+        Ok(())
+    }
+
+    /// Opens the selected patch.
+    fn open_selected(&mut self) -> Option<Patch> {
+        let entry = self.selected()?;
+        match Patch::open(&entry.path) {
+            Ok(patch) => Some(patch),
+            Err(err) => {
+                self.status = format!("Couldn't load {}: {err:#}", entry.path.display());
+                None
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, code: KeyCode) -> PresetAction {
+        if let Some(patch) = self.overwriting.take() {
+            match code {
+                KeyCode::Enter => self.write(patch),
+                _ => self.status = format!("{} not saved", patch.name),
+            }
+            return PresetAction::None;
+        }
+        if let Some(name) = &mut self.naming {
+            match code {
+                KeyCode::Char(c) => name.push(c),
+                KeyCode::Backspace => {
+                    name.pop();
+                }
+                KeyCode::Enter if !name.trim().is_empty() => {
+                    let name = name.trim().to_string();
+                    self.naming = None;
+                    return PresetAction::SaveAs(name);
+                }
+                KeyCode::Esc => self.naming = None,
+                _ => (),
+            }
+            return PresetAction::None;
+        }
+
+        let count = self.visible.len().max(1);
+        match code {
+            KeyCode::Down => self.selected = (self.selected + 1) % count,
+            KeyCode::Up => self.selected = (self.selected + count - 1) % count,
+            KeyCode::Char(c) if self.searching => {
+                self.query.push(c);
+                self.selected = 0;
+                self.filter();
+            }
+            KeyCode::Backspace if self.searching => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Enter if self.searching => self.searching = false,
+            KeyCode::Esc if self.searching => {
+                self.searching = false;
+                self.query.clear();
+                self.filter();
+            }
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Char('f') => self.toggle_favorite(),
+            KeyCode::Char('v') => {
+                self.favorites_only = !self.favorites_only;
+                self.filter();
+            }
+            KeyCode::Char('c') => {
+                let categories = self.categories();
+                let next = match &self.category {
+                    None => categories.first(),
+                    Some(current) => categories
+                        .iter()
+                        .position(|category| category == current)
+                        .and_then(|position| categories.get(position + 1)),
+                };
+                self.category = next.map(|category| category.to_string());
+                self.filter();
+            }
+            KeyCode::Char('r') => {
+                self.rescan();
+                self.status = format!("{} patches in {}", self.entries.len(), self.dir.display());
+            }
+            KeyCode::Char('s') => {
+                let name = self.selected().map(|entry| entry.name.clone());
+                self.naming = Some(name.unwrap_or_default());
+            }
+            KeyCode::Char(' ') => {
+                if let Some(patch) = self.open_selected() {
+                    self.status = format!("Auditioning {}", patch.name);
+                    return PresetAction::Audition(patch);
+                }
+            }
+            KeyCode::Enter => {
+                if let Some(patch) = self.open_selected() {
+                    self.status = format!("Loaded {}", patch.name);
+                    return PresetAction::Load(patch);
+                }
+            }
+            _ => (),
+        }
+        PresetAction::None
+    }
+}
+
+/// Adds the patches in `dir` and its subdirectories to `entries`, sorted by
+/// folder then file name. `folder` is the path of `dir` in the library.
+/// Symbolic links aren't followed, so a link to a parent can't loop, and
+/// subdirectories that can't be read are added to `skipped`.
+fn scan(
+    dir: &Path,
+    folder: String,
+    entries: &mut Vec<PresetEntry>,
+    skipped: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for path in Patch::files(dir)? {
+        entries.push(PresetEntry::open(path, folder.clone()));
+    }
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut subdirs = Vec::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            subdirs.push(entry.path());
+        }
+    }
+    subdirs.sort();
+    for subdir in subdirs {
+        let name = subdir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let folder = if folder.is_empty() {
+            name
+        } else {
+            format!("{folder}/{name}")
+        };
+        if scan(&subdir, folder, entries, skipped).is_err() {
+            skipped.push(subdir);
+        }
+    }
+    Ok(())
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use synth::{
+        notes::Note,
+        waves::{Wave, Waveform},
+    };
+
+    use super::*;
+
+    /// Empty library in a directory of its own, removed by the caller.
+    fn library(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("presets-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn scan_skips_links_and_unreadable_folders() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = library("scan");
+        let wave = Wave::new(Waveform::Saw, Note::A4, 0.5);
+        for folder in ["Bass", "Locked"] {
+            std::fs::create_dir_all(dir.join(folder)).unwrap();
+            Patch::from_wave(folder, &wave)
+                .save(dir.join(folder).join("patch.toml"))
+                .unwrap();
+        }
+        // A link back to the library would otherwise be scanned forever.
+        symlink(&dir, dir.join("Bass").join("Loop")).unwrap();
+        symlink(dir.join("Bass").join("patch.toml"), dir.join("linked.toml")).unwrap();
+        let locked = dir.join("Locked");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root, the folder is then read like the others.
+        let readable = std::fs::read_dir(&locked).is_ok();
+
+        let browser = PresetBrowser::new(dir.clone());
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<&str> = browser
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        if readable {
+            assert_eq!(names, ["Bass", "Locked"]);
+        } else {
+            assert_eq!(names, ["Bass"]);
+            assert!(browser.status.contains("Locked"), "{}", browser.status);
+        }
+    }
+
+    #[test]
+    fn saving_over_a_patch_asks_first() {
+        let dir = library("overwrite");
+        let mut browser = PresetBrowser::new(dir.clone());
+        let wave = Wave::new(Waveform::Saw, Note::A4, 0.5);
+        browser.save(Patch::from_wave("Lead", &wave));
+        let path = dir.join("lead.toml");
+        let saved = std::fs::read_to_string(&path).unwrap();
+
+        let louder = Wave { amp: 0.8, ..wave };
+        browser.save(Patch::from_wave("Lead", &louder));
+        assert!(browser.is_typing());
+        browser.handle_key(KeyCode::Esc);
+        assert!(!browser.is_typing());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+
+        browser.save(Patch::from_wave("Lead", &louder));
+        browser.handle_key(KeyCode::Enter);
+        let replaced = Patch::open(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(replaced.unwrap(), Patch::from_wave("Lead", &louder));
+    }
+}
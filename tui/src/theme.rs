@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use ratatui::style::Color;
+
+/// Colors of the interface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    /// Wave chart line.
+    pub wave: Color,
+    /// EQ response line.
+    pub eq: Color,
+    /// Gain reduction gauges.
+    pub gauge: Color,
+    /// Step being played and the active preset filters.
+    pub highlight: Color,
+    /// Preset categories.
+    pub info: Color,
+    /// Tags and edits that were undone.
+    pub muted: Color,
+    /// Presets that failed to load and remotes that aren't running.
+    pub error: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            wave: Color::Reset,
+            eq: Color::Reset,
+            gauge: Color::Reset,
+            highlight: Color::Yellow,
+            info: Color::Cyan,
+            muted: Color::DarkGray,
+            error: Color::Red,
+        }
+    }
+}
+
+impl Theme {
+    pub const NAMES: [&'static str; 3] = ["default", "monochrome", "neon"];
+
+    pub fn named(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "default" => Ok(Self::default()),
+            "monochrome" => Ok(Self {
+                wave: Color::Reset,
+                eq: Color::Reset,
+                gauge: Color::Reset,
+                highlight: Color::White,
+                info: Color::Gray,
+                muted: Color::DarkGray,
+                error: Color::White,
+            }),
+            "neon" => Ok(Self {
+                wave: Color::LightGreen,
+                eq: Color::LightMagenta,
+                gauge: Color::LightCyan,
+                highlight: Color::LightYellow,
+                info: Color::LightCyan,
+                muted: Color::DarkGray,
+                error: Color::LightRed,
+            }),
+            _ => Err(anyhow!(
+                "Unknown theme {name:?}, expected one of {}",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+
+    /// Theme with the colors given by name replaced, as color names like
+    /// `light-blue`, 256 color indices or `#rrggbb`.
+    pub fn with_colors(mut self, colors: &BTreeMap<String, String>) -> Result<Self> {
+        for (name, value) in colors {
+            let color = value.parse().map_err(|_| {
+                anyhow!(
+                    "Invalid color {value:?} for {name} in [theme], expected a name like \
+                     light-blue, an index from 0 to 255 or #rrggbb"
+                )
+            })?;
+            *match name.as_str() {
+                "wave" => &mut self.wave,
+                "eq" => &mut self.eq,
+                "gauge" => &mut self.gauge,
+                "highlight" => &mut self.highlight,
+                "info" => &mut self.info,
+                "muted" => &mut self.muted,
+                "error" => &mut self.error,
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown color {name:?} in [theme], expected one of name, wave, eq, gauge, highlight, info, muted, error"
+                    ))
+                }
+            } = color;
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_colors_of_a_named_theme() {
+        let colors = BTreeMap::from([
+            ("wave".to_string(), "light-blue".to_string()),
+            ("eq".to_string(), "42".to_string()),
+            ("error".to_string(), "#ff0000".to_string()),
+        ]);
+        let theme = Theme::named("Neon").unwrap().with_colors(&colors).unwrap();
+        assert_eq!(theme.wave, Color::LightBlue);
+        assert_eq!(theme.eq, Color::Indexed(42));
+        assert_eq!(theme.error, Color::Rgb(0xff, 0, 0));
+        assert_eq!(theme.gauge, Color::LightCyan);
+    }
+
+    #[test]
+    fn rejects_unknown_themes_and_colors() {
+        assert!(Theme::named("pastel").is_err());
+        let unknown = BTreeMap::from([("background".to_string(), "red".to_string())]);
+        assert!(Theme::default().with_colors(&unknown).is_err());
+        let invalid = BTreeMap::from([("wave".to_string(), "#ff00".to_string())]);
+        assert!(Theme::default().with_colors(&invalid).is_err());
+    }
+}
@@ -1,71 +1,657 @@
 use ratatui::{
     prelude::{Backend, Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
     symbols,
-    text::Span,
-    widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, List, ListItem},
+    text::{Line, Span},
+    widgets::{
+        Axis, Block, Borders, Chart, Dataset, GraphType, LineGauge, List, ListItem, ListState,
+        Paragraph,
+    },
     Frame,
 };
-use synth::{waves::Wave, Note};
+use synth::{
+    effects::{
+        distortion::{
+            BitcrusherSettings, Oversampling, WaveshaperSettings, MAX_CRUSH_BITS, MAX_CRUSH_RATE,
+        },
+        eq::Equalizer,
+    },
+    history::History,
+    meter::Meter,
+    transport::TransportHandle,
+    waves::Wave,
+    Note,
+};
+
+use crate::{
+    editor::{Page, SequencerEditor},
+    keys::{key_name, Action, KeyBindings},
+    presets::PresetBrowser,
+    theme::Theme,
+};
+
+/// Gain reduction shown in the meters is scaled against this many dB.
+const MAX_GAIN_REDUCTION: f32 = 24.0;
+
+/// Frequency range shown by the EQ chart, which uses a logarithmic axis.
+const EQ_FREQ_RANGE: (f64, f64) = (20.0, 20000.0);
+const EQ_DB_RANGE: f64 = 24.0;
+
+pub struct Meters {
+    pub compressor: Meter,
+    pub limiter: Meter,
+    /// Step played by the sequencer.
+    pub sequencer: Meter,
+    /// Frequency played, bend, vibrato and detune included.
+    pub frequency: Meter,
+    pub transport: TransportHandle,
+    /// Why OSC or the browser panel aren't running.
+    pub remote_errors: Vec<String>,
+}
+
+/// Key bindings and colors, set once at startup.
+#[derive(Debug, Clone, Default)]
+pub struct Look {
+    pub keys: KeyBindings,
+    pub theme: Theme,
+}
+
+/// Page shown and the state of the pages edited from the input thread.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub page: Page,
+    pub editor: SequencerEditor,
+    pub presets: PresetBrowser,
+    pub history: History,
+    pub waveshaper: WaveshaperSettings,
+    pub bitcrusher: BitcrusherSettings,
+}
+
+pub fn ui<B: Backend>(
+    f: &mut Frame<B>,
+    wave: &Wave,
+    meters: &Meters,
+    eq_curve: &[(f64, f64)],
+    view: &View,
+    look: &Look,
+) {
+    match view.page {
+        Page::Synth => synth_page(f, wave, meters, eq_curve, view, look),
+        Page::Sequencer => sequencer_page(f, meters, &view.editor, look),
+        Page::Presets => presets_page(f, &view.presets, look),
+    }
+}
 
-pub fn ui<B: Backend>(f: &mut Frame<B>, wave: Wave) {
+fn synth_page<B: Backend>(
+    f: &mut Frame<B>,
+    wave: &Wave,
+    meters: &Meters,
+    eq_curve: &[(f64, f64)],
+    view: &View,
+    look: &Look,
+) {
+    let Look { keys, theme } = look;
     let areas = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
-        .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(70),
+                Constraint::Length(3),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
         .split(f.size());
 
     let period = Note::A4.freq().recip() as f64;
     let data = wave_to_data(wave, period, 256);
+    let (freq, closest, cents) = played_pitch(wave, &meters.frequency);
     let dataset = new_dataset(
         &data,
-        format!("{:?}, {:?}, {:.2?}", wave.waveform, wave.note, wave.amp),
+        format!(
+            "{:?}, {} ({:.2} Hz, {} {:+.1} cents), {:.2?}, A4 = {} Hz {}",
+            wave.waveform,
+            wave.note,
+            freq,
+            closest,
+            cents,
+            wave.amp,
+            wave.tuning.reference,
+            wave.tuning.temperament.name()
+        ),
+        theme.wave,
     );
     let wave_widget = chart_wave(dataset, [0.0, 4.0 * period]);
 
+    let charts = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Percentage(55),
+                Constraint::Percentage(25),
+                Constraint::Percentage(20),
+            ]
+            .as_ref(),
+        )
+        .split(areas[0]);
+    let eq_dataset = new_dataset(eq_curve, "Response".to_string(), theme.eq);
+    let eq_widget = chart_eq(eq_dataset);
+    let history_widget = history_list(&view.history, &meters.remote_errors, look);
+
     let controls = Layout::default()
         .direction(Direction::Horizontal)
         .margin(0)
         .constraints(
             [
-                Constraint::Ratio(1, 3),
-                Constraint::Ratio(1, 3),
-                Constraint::Ratio(1, 3),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
+                Constraint::Ratio(1, 8),
             ]
             .as_ref(),
         )
+        .split(areas[2]);
+
+    let gauges = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(0)
+        .constraints([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)].as_ref())
         .split(areas[1]);
+    let compressor_gauge = gain_reduction_gauge(&meters.compressor, "Compressor GR", theme);
+    let limiter_gauge = gain_reduction_gauge(&meters.limiter, "Limiter GR", theme);
 
     let wave_control_items = [
-        ListItem::new("<1>: None"),
-        ListItem::new("<2>: Sine"),
-        ListItem::new("<3>: Saw"),
-        ListItem::new("<4>: Square"),
-        ListItem::new("<5>: Triangle"),
+        ListItem::new(keys.hint(Action::WaveNone, "None")),
+        ListItem::new(keys.hint(Action::WaveSine, "Sine")),
+        ListItem::new(keys.hint(Action::WaveSaw, "Saw")),
+        ListItem::new(keys.hint(Action::WaveSquare, "Square")),
+        ListItem::new(keys.hint(Action::WaveTriangle, "Triangle")),
     ];
     let wave_controls = controls_list(&wave_control_items, "Waves");
 
     let note_control_items = [
-        ListItem::new("<Right>: Inc. Pitch"),
-        ListItem::new("<Left>: Dec. Pitch"),
+        ListItem::new(keys.hint(Action::PitchUp, "Inc. Pitch")),
+        ListItem::new(keys.hint(Action::PitchDown, "Dec. Pitch")),
+        ListItem::new(keys.hint(Action::FineTuneUp, "Inc. Fine Tune")),
+        ListItem::new(keys.hint(Action::FineTuneDown, "Dec. Fine Tune")),
+        ListItem::new(keys.hint(Action::FineTuneReset, "Reset Fine Tune")),
     ];
     let note_controls = controls_list(&note_control_items, "Note");
 
     let amp_control_items = [
-        ListItem::new("<Up>: Inc. Amplitude"),
-        ListItem::new("<Down>: Dec. Amplitude"),
+        ListItem::new(keys.hint(Action::AmpUp, "Inc. Amplitude")),
+        ListItem::new(keys.hint(Action::AmpDown, "Dec. Amplitude")),
+        ListItem::new(keys.hint(Action::NextPage, "Next Page")),
     ];
     let amp_controls = controls_list(&amp_control_items, "Amplitude");
 
-    f.render_widget(wave_widget, areas[0]);
+    let tuning_control_items = [
+        ListItem::new(keys.hint(Action::Temperament, "Temperament")),
+        ListItem::new(keys.hint(Action::ReferenceUp, "Inc. A4")),
+        ListItem::new(keys.hint(Action::ReferenceDown, "Dec. A4")),
+    ];
+    let tuning_controls = controls_list(&tuning_control_items, "Tuning");
+
+    let glide = wave.glide;
+    let glide_title = format!(
+        "Glide {}ms {:?} {:?}{}",
+        glide.time,
+        glide.curve,
+        glide.mode,
+        if glide.legato_only { " Legato" } else { "" }
+    );
+    let glide_control_items = [
+        ListItem::new(keys.hint(Action::GlideTime, "Glide Time")),
+        ListItem::new(keys.hint(Action::GlideCurve, "Curve")),
+        ListItem::new(keys.hint(Action::GlideMode, "Time/Rate")),
+        ListItem::new(keys.hint(Action::GlideLegato, "Legato Only")),
+    ];
+    let glide_controls = controls_list(&glide_control_items, &glide_title);
+
+    let mono = wave.mono;
+    let mut voice_title = if mono.enabled {
+        format!(
+            "Mono {:?} {}",
+            mono.priority,
+            if mono.legato { "Legato" } else { "Retrigger" }
+        )
+    } else {
+        "Voice".to_string()
+    };
+    let unison = wave.unison;
+    if unison.is_enabled() {
+        voice_title += &format!(" Unison x{} {}c", unison.voices, unison.spread);
+    }
+    let voice_control_items = [
+        ListItem::new(keys.hint(Action::Mono, "Mono")),
+        ListItem::new(keys.hint(Action::NotePriority, "Note Priority")),
+        ListItem::new(keys.hint(Action::MonoLegato, "Legato/Retrigger")),
+        ListItem::new(keys.hint(Action::Unison, "Unison")),
+        ListItem::new(keys.hint(Action::UnisonSpread, "Unison Spread")),
+    ];
+    let voice_controls = controls_list(&voice_control_items, &voice_title);
+
+    let arp = wave.arp;
+    // The transport tempo wins while it plays.
+    let position = meters.transport.position();
+    let arp_bpm = match position.playing {
+        true => position.bpm as f32,
+        false => arp.bpm,
+    };
+    let arp_title = if arp.enabled {
+        format!(
+            "Arp {:?} x{} {} {} BPM",
+            arp.pattern,
+            arp.octaves,
+            arp.rate.name(),
+            arp_bpm
+        )
+    } else {
+        "Arp".to_string()
+    };
+    let arp_control_items = [
+        ListItem::new(keys.hint(Action::Arp, "Arp On/Off")),
+        ListItem::new(keys.hint(Action::ArpPattern, "Pattern")),
+        ListItem::new(keys.hint(Action::ArpOctaves, "Octaves")),
+        ListItem::new(keys.hint(Action::ArpRate, "Rate")),
+        ListItem::new(format!(
+            "<{}/{}>: Tempo",
+            key_name(keys.key(Action::ArpTempoDown)),
+            key_name(keys.key(Action::ArpTempoUp))
+        )),
+        ListItem::new(keys.hint(Action::ArpGate, &format!("Gate {:.0}%", arp.gate * 100.0))),
+        ListItem::new(keys.hint(
+            Action::ArpSwing,
+            &format!("Swing {:.0}%", arp.swing * 100.0),
+        )),
+        ListItem::new(keys.hint(
+            Action::ArpLatch,
+            &format!("Latch {}", if arp.latch { "On" } else { "Off" }),
+        )),
+    ];
+    let arp_controls = controls_list(&arp_control_items, &arp_title);
+
+    let (shaper, crusher) = (&view.waveshaper, &view.bitcrusher);
+    let shaper_curve = match shaper.mix > 0.0 {
+        true => shaper.curve.name(),
+        false => "Off",
+    };
+    let oversampling = match shaper.oversampling {
+        Oversampling::None => "Off".to_string(),
+        oversampling => format!("x{}", oversampling.factor()),
+    };
+    let crush_bits = match crusher.bits >= MAX_CRUSH_BITS {
+        true => "Off".to_string(),
+        false => crusher.bits.to_string(),
+    };
+    let crush_rate = match crusher.rate >= MAX_CRUSH_RATE {
+        true => "Off".to_string(),
+        false => format!("{} Hz", crusher.rate),
+    };
+    let distortion_control_items = [
+        ListItem::new(keys.hint(Action::ShaperCurve, &format!("Shaper {shaper_curve}"))),
+        ListItem::new(keys.hint(Action::ShaperDrive, &format!("Drive x{}", shaper.drive))),
+        ListItem::new(keys.hint(
+            Action::Oversampling,
+            &format!("Oversampling {oversampling}"),
+        )),
+        ListItem::new(keys.hint(Action::CrushBits, &format!("Bits {crush_bits}"))),
+        ListItem::new(keys.hint(Action::CrushRate, &format!("Rate {crush_rate}"))),
+    ];
+    let distortion_controls = controls_list(&distortion_control_items, "Distortion");
+
+    f.render_widget(wave_widget, charts[0]);
+    f.render_widget(eq_widget, charts[1]);
+    f.render_widget(history_widget, charts[2]);
+    f.render_widget(compressor_gauge, gauges[0]);
+    f.render_widget(limiter_gauge, gauges[1]);
     f.render_widget(wave_controls, controls[0]);
     f.render_widget(note_controls, controls[1]);
     f.render_widget(amp_controls, controls[2]);
+    f.render_widget(tuning_controls, controls[3]);
+    f.render_widget(glide_controls, controls[4]);
+    f.render_widget(voice_controls, controls[5]);
+    f.render_widget(arp_controls, controls[6]);
+    f.render_widget(distortion_controls, controls[7]);
+}
+
+fn sequencer_page<B: Backend>(
+    f: &mut Frame<B>,
+    meters: &Meters,
+    editor: &SequencerEditor,
+    look: &Look,
+) {
+    let areas = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints(
+            [
+                Constraint::Min(0),
+                Constraint::Length(4),
+                Constraint::Length(9),
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+
+    let position = meters.transport.position();
+    let playhead = position.playing.then_some(meters.sequencer.get() as usize);
+    let title = format!(
+        "Sequencer, bar {} beat {:.0}, {} BPM {}, {:.0?} latency{}",
+        position.bar() + 1,
+        position.beat_in_bar().floor() + 1.0,
+        position.bpm,
+        position.time_signature,
+        meters.transport.latency(),
+        if position.playing { ", playing" } else { "" }
+    );
+    f.render_widget(
+        Paragraph::new(step_grid(editor, playhead, &look.theme))
+            .block(Block::default().title(title).borders(Borders::ALL)),
+        areas[0],
+    );
+
+    let step = editor.selected();
+    let details = vec![
+        Line::from(format!(
+            "Track {}/{}, {} steps, step {}: {}{}, velocity {:.0}%, gate {:.0}%, probability {:.0}%{}",
+            editor.track + 1,
+            editor.pattern.tracks.len(),
+            editor.pattern.tracks[editor.track].steps().len(),
+            editor.step + 1,
+            step.note,
+            if step.active { "" } else { " (rest)" },
+            step.velocity * 100.0,
+            step.gate * 100.0,
+            step.probability * 100.0,
+            if step.tie { ", tied" } else { "" }
+        )),
+        Line::from(editor.status.as_str()),
+    ];
+    f.render_widget(
+        Paragraph::new(details).block(Block::default().title("Step").borders(Borders::ALL)),
+        areas[1],
+    );
+
+    let controls = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Ratio(1, 3),
+                Constraint::Ratio(1, 3),
+                Constraint::Ratio(1, 3),
+            ]
+            .as_ref(),
+        )
+        .split(areas[2]);
+    let step_control_items = [
+        ListItem::new("<Arrows>: Select Step"),
+        ListItem::new("<Enter>: Step On/Off"),
+        ListItem::new("<=/->: Note"),
+        ListItem::new("<./,>: Velocity"),
+        ListItem::new("<g>: Gate"),
+        ListItem::new("<t>: Tie"),
+        ListItem::new("<p>: Probability"),
+    ];
+    let track_control_items = [
+        ListItem::new("<l>: Track Length"),
+        ListItem::new("<n>: New Track"),
+        ListItem::new("<x>: Delete Track"),
+    ];
+    let pattern_control_items = [
+        ListItem::new("<Space>: Play/Stop"),
+        ListItem::new("<[/]>: Tempo"),
+        ListItem::new("<s>: Save"),
+        ListItem::new("<o>: Load"),
+        ListItem::new(look.keys.hint(Action::NextPage, "Next Page")),
+    ];
+    f.render_widget(controls_list(&step_control_items, "Step"), controls[0]);
+    f.render_widget(controls_list(&track_control_items, "Track"), controls[1]);
+    f.render_widget(
+        controls_list(&pattern_control_items, "Pattern"),
+        controls[2],
+    );
+}
+
+fn presets_page<B: Backend>(f: &mut Frame<B>, presets: &PresetBrowser, look: &Look) {
+    let theme = &look.theme;
+    let areas = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints(
+            [
+                Constraint::Length(3),
+                Constraint::Min(0),
+                Constraint::Length(4),
+                Constraint::Length(7),
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+
+    let mut filters = Vec::new();
+    if let Some(category) = &presets.category {
+        filters.push(format!("category {category}"));
+    }
+    if presets.favorites_only {
+        filters.push("favorites".to_string());
+    }
+    let cursor = if presets.searching { "_" } else { "" };
+    let search = Line::from(vec![
+        Span::raw(format!("/{}{cursor}", presets.query)),
+        Span::styled(
+            format!("  {}", filters.join(", ")),
+            Style::default().fg(theme.highlight),
+        ),
+    ]);
+    f.render_widget(
+        Paragraph::new(search).block(Block::default().title("Search").borders(Borders::ALL)),
+        areas[0],
+    );
+
+    let items: Vec<ListItem> = presets
+        .visible
+        .iter()
+        .map(|&index| {
+            let entry = &presets.entries[index];
+            let mut spans = vec![
+                Span::raw(if entry.favorite { "★ " } else { "  " }),
+                Span::styled(
+                    entry.name.as_str(),
+                    match entry.error {
+                        Some(_) => Style::default().fg(theme.error),
+                        None => Style::default(),
+                    },
+                ),
+            ];
+            if !entry.category.is_empty() {
+                spans.push(Span::styled(
+                    format!("  [{}]", entry.category),
+                    Style::default().fg(theme.info),
+                ));
+            }
+            for tag in &entry.tags {
+                spans.push(Span::styled(
+                    format!(" #{tag}"),
+                    Style::default().fg(theme.muted),
+                ));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    let title = format!(
+        "Presets in {} ({}/{})",
+        presets.dir.display(),
+        presets.visible.len(),
+        presets.entries.len()
+    );
+    let list = List::new(items)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(presets.selected));
+    f.render_stateful_widget(list, areas[1], &mut state);
+
+    let details = match (&presets.overwriting, &presets.naming, presets.selected()) {
+        (Some(patch), _, _) => vec![
+            Line::from(format!(
+                "Replace {}?",
+                presets.dir.join(patch.file_name()).display()
+            )),
+            Line::from("<Enter>: Replace, any other key: Cancel"),
+        ],
+        (None, Some(name), _) => vec![
+            Line::from(format!("Save as: {name}_")),
+            Line::from("<Enter>: Save, <Esc>: Cancel"),
+        ],
+        (None, None, Some(entry)) => vec![
+            Line::from(match (&entry.error, entry.author.as_str()) {
+                (Some(error), _) => format!("{}: {error}", entry.path.display()),
+                (None, "") => entry.path.display().to_string(),
+                (None, author) => format!("{} by {author}", entry.path.display()),
+            }),
+            Line::from(presets.status.as_str()),
+        ],
+        (None, None, None) if presets.entries.is_empty() => vec![
+            Line::from("No patches yet, save one with <s>"),
+            Line::from(presets.status.as_str()),
+        ],
+        (None, None, None) => vec![
+            Line::from("No patches match"),
+            Line::from(presets.status.as_str()),
+        ],
+    };
+    f.render_widget(
+        Paragraph::new(details).block(Block::default().title("Patch").borders(Borders::ALL)),
+        areas[2],
+    );
+
+    let controls = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(0)
+        .constraints([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)].as_ref())
+        .split(areas[3]);
+    let browse_control_items = [
+        ListItem::new("<Up/Down>: Select Patch"),
+        ListItem::new("<Enter>: Load"),
+        ListItem::new("<Space>: Audition"),
+        ListItem::new("<s>: Save As"),
+        ListItem::new(look.keys.hint(Action::NextPage, "Next Page")),
+    ];
+    let library_control_items = [
+        ListItem::new("</>: Search, #tag for tags"),
+        ListItem::new("<f>: Favorite"),
+        ListItem::new("<v>: Favorites Only"),
+        ListItem::new("<c>: Category"),
+        ListItem::new("<r>: Rescan"),
+    ];
+    f.render_widget(controls_list(&browse_control_items, "Browse"), controls[0]);
+    f.render_widget(
+        controls_list(&library_control_items, "Library"),
+        controls[1],
+    );
+}
+
+/// One line per track, steps grouped by beat. The selected step is
+/// highlighted and the step being played is drawn in yellow.
+fn step_grid(
+    editor: &SequencerEditor,
+    playhead: Option<usize>,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for (index, track) in editor.pattern.tracks.iter().enumerate() {
+        let steps = track.steps();
+        let mut spans = vec![Span::raw(format!("{:>2} ", index + 1))];
+        for (position, step) in steps.iter().enumerate() {
+            if position > 0 && position % 4 == 0 {
+                spans.push(Span::raw(" "));
+            }
+            let symbol = match (step.active, step.tie) {
+                (false, _) => "·",
+                (true, false) => "■",
+                (true, true) => "»",
+            };
+            let mut style = Style::default();
+            if playhead.is_some_and(|playhead| playhead % steps.len() == position) {
+                style = style.fg(theme.highlight);
+            }
+            if index == editor.track && position == editor.step {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            spans.push(Span::styled(symbol, style));
+        }
+        lines.push(Line::from(spans));
+    }
+    lines
+}
+
+/// Edits to redo dimmed above the ones to undo, newest first. The next
+/// edit to undo is in bold.
+/// Edits, most recent first, under the errors of the remotes.
+fn history_list(history: &History, errors: &[String], look: &Look) -> List<'static> {
+    let errors = errors
+        .iter()
+        .map(|error| ListItem::new(error.clone()).style(Style::default().fg(look.theme.error)));
+    let undone = history
+        .undone()
+        .iter()
+        .map(|edit| ListItem::new(edit.to_string()).style(Style::default().fg(look.theme.muted)));
+    let done = history
+        .done()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, edit)| {
+            let style = if index == 0 {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            ListItem::new(edit.to_string()).style(style)
+        });
+    List::new(errors.chain(undone).chain(done).collect::<Vec<_>>()).block(
+        Block::default()
+            .title(format!(
+                "History {} {}",
+                look.keys.hint(Action::Undo, "Undo"),
+                look.keys.hint(Action::Redo, "Redo")
+            ))
+            .borders(Borders::ALL),
+    )
 }
 
 fn controls_list<'a>(items: &'a [ListItem], title: &'a str) -> List<'a> {
     List::new(items).block(Block::default().title(title).borders(Borders::ALL))
 }
 
+/// Frequency played, with the closest note in 12-TET at A4 = 440 Hz and the
+/// distance to it in cents. The wave's own frequency until the stream reports one.
+fn played_pitch(wave: &Wave, meter: &Meter) -> (f32, Note, f32) {
+    let freq = match meter.get() {
+        freq if freq > 0.0 => freq,
+        _ => wave.freq(),
+    };
+    let key = 12.0f32.mul_add((freq / 440.0).log2(), 69.0);
+    let closest = Note::from_midi(key.round().clamp(0.0, 127.0) as u8).unwrap_or(wave.note);
+    (freq, closest, 1200.0 * (freq / closest.freq()).log2())
+}
+
+fn gain_reduction_gauge<'a>(meter: &Meter, title: &'a str, theme: &Theme) -> LineGauge<'a> {
+    let reduction = meter.get().max(0.0);
+    LineGauge::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .gauge_style(Style::default().fg(theme.gauge))
+        .label(format!("-{reduction:.1} dB"))
+        .ratio((reduction / MAX_GAIN_REDUCTION).min(1.0) as f64)
+}
+
 fn chart_wave(dataset: Dataset, range: [f64; 2]) -> Chart {
     Chart::new(vec![dataset])
         .block(Block::default().title("Wave").borders(Borders::ALL))
@@ -81,7 +667,45 @@ fn chart_wave(dataset: Dataset, range: [f64; 2]) -> Chart {
         )
 }
 
-fn wave_to_data(wave: Wave, period: f64, n_samples: u32) -> Vec<(f64, f64)> {
+fn chart_eq(dataset: Dataset) -> Chart {
+    Chart::new(vec![dataset])
+        .block(Block::default().title("EQ").borders(Borders::ALL))
+        .x_axis(
+            Axis::default()
+                .bounds([EQ_FREQ_RANGE.0.log10(), EQ_FREQ_RANGE.1.log10()])
+                .labels(
+                    ["20", "200", "2k", "20k"]
+                        .iter()
+                        .cloned()
+                        .map(Span::from)
+                        .collect(),
+                ),
+        )
+        .y_axis(
+            Axis::default().bounds([-EQ_DB_RANGE, EQ_DB_RANGE]).labels(
+                ["-24", "0", "+24"]
+                    .iter()
+                    .cloned()
+                    .map(Span::from)
+                    .collect(),
+            ),
+        )
+}
+
+/// Samples the EQ response at `n_points` log-spaced frequencies, as `(log10(freq), dB)`.
+pub fn eq_response(eq: &Equalizer, sample_rate: f32, n_points: u32) -> Vec<(f64, f64)> {
+    let (low, high) = (EQ_FREQ_RANGE.0.log10(), EQ_FREQ_RANGE.1.log10());
+    let delta = (high - low) / (n_points - 1) as f64;
+    (0..n_points)
+        .map(|i| {
+            let x = low + i as f64 * delta;
+            let db = eq.magnitude_db(sample_rate, 10.0_f64.powf(x) as f32) as f64;
+            (x, db.clamp(-EQ_DB_RANGE, EQ_DB_RANGE))
+        })
+        .collect()
+}
+
+fn wave_to_data(wave: &Wave, period: f64, n_samples: u32) -> Vec<(f64, f64)> {
     let delta = 4.0 * period / (n_samples - 1) as f64;
     (0..n_samples)
         .map(|i| {
@@ -91,10 +715,11 @@ fn wave_to_data(wave: Wave, period: f64, n_samples: u32) -> Vec<(f64, f64)> {
         .collect::<Vec<_>>()
 }
 
-fn new_dataset<'a>(data: &'a [(f64, f64)], name: String) -> Dataset<'a> {
+fn new_dataset<'a>(data: &'a [(f64, f64)], name: String, color: Color) -> Dataset<'a> {
     Dataset::default()
         .name(name)
+        .style(Style::default().fg(color))
         .marker(symbols::Marker::Braille)
         .graph_type(GraphType::Line)
-        .data(&data)
+        .data(data)
 }